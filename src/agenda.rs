@@ -0,0 +1,257 @@
+use crate::{deadline, Todo};
+use chrono::{DateTime, Days, NaiveDate, TimeZone, Utc};
+use serde::Serialize;
+use std::cmp::{Ordering, Reverse};
+
+/// The furthest ahead `group` gives each day its own group.
+pub const MAX_DAYS_AHEAD: u32 = 366;
+
+/// Which part of the agenda a group is. Serialized as `"overdue"`,
+/// `{"day": "YYYY-MM-DD"}`, `"later"` or `"no_deadline"`, and ordered that
+/// way too.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Bucket {
+    Overdue,
+    Day(NaiveDate),
+    Later,
+    NoDeadline,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct AgendaGroup {
+    pub bucket: Bucket,
+    pub todos: Vec<Todo>,
+}
+
+/// The incomplete `todos` laid out as an agenda seen from `now` in `tz`:
+/// the overdue ones, then one group per day from today to `days_ahead` days
+/// on (capped at `MAX_DAYS_AHEAD`), then everything later, then those
+/// without a readable deadline. Every group is present, empty or not.
+///
+/// Overdue means the deadline has passed, as for the overdue filter, so a
+/// deadline earlier today is overdue while a date-only one for today isn't
+/// until midnight. Within a group todos are ordered by deadline, date-only
+/// ones after those with a time on the same day, then by priority, highest
+/// first.
+pub fn group<Tz: TimeZone>(
+    todos: Vec<Todo>,
+    now: DateTime<Utc>,
+    tz: &Tz,
+    days_ahead: u32,
+) -> Vec<AgendaGroup> {
+    let today = now.with_timezone(tz).date_naive();
+    let horizon = today + Days::new(days_ahead.min(MAX_DAYS_AHEAD).into());
+    let mut groups: Vec<AgendaGroup> = std::iter::once(Bucket::Overdue)
+        .chain(
+            today
+                .iter_days()
+                .take_while(|day| *day <= horizon)
+                .map(Bucket::Day),
+        )
+        .chain([Bucket::Later, Bucket::NoDeadline])
+        .map(|bucket| AgendaGroup {
+            bucket,
+            todos: Vec::new(),
+        })
+        .collect();
+
+    let mut todos: Vec<(Option<DateTime<Tz>>, Todo)> = todos
+        .into_iter()
+        .filter(|todo| !todo.completed)
+        .map(|todo| {
+            let due = todo
+                .deadline
+                .as_deref()
+                .and_then(|d| deadline::deadline_in(d, tz));
+            (due, todo)
+        })
+        .collect();
+    todos.sort_by_key(|(due, todo)| (due.is_none(), due.clone(), Reverse(todo.priority)));
+
+    for (due, todo) in todos {
+        let bucket = match due {
+            None => Bucket::NoDeadline,
+            Some(due) if due.with_timezone(&Utc).cmp(&now) == Ordering::Less => Bucket::Overdue,
+            Some(due) if due.date_naive() <= horizon => Bucket::Day(due.date_naive()),
+            Some(_) => Bucket::Later,
+        };
+        let at = groups
+            .binary_search_by_key(&bucket, |group| group.bucket)
+            .expect("every bucket has a group");
+        groups[at].todos.push(todo);
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_todo;
+    use chrono::FixedOffset;
+
+    fn todo(id: u64, deadline: Option<&str>, priority: u8) -> Todo {
+        Todo {
+            deadline: deadline.map(String::from),
+            priority,
+            ..test_todo(id, &format!("todo {}", id))
+        }
+    }
+
+    fn warsaw() -> FixedOffset {
+        FixedOffset::east_opt(2 * 3600).unwrap()
+    }
+
+    /// `value` given as Warsaw local time.
+    fn at(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(&format!("{}+02:00", value))
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn day(value: &str) -> Bucket {
+        Bucket::Day(NaiveDate::parse_from_str(value, "%Y-%m-%d").unwrap())
+    }
+
+    /// The ids in each non-empty group.
+    fn layout(todos: Vec<Todo>, now: DateTime<Utc>, days_ahead: u32) -> Vec<(Bucket, Vec<u64>)> {
+        group(todos, now, &warsaw(), days_ahead)
+            .into_iter()
+            .filter(|group| !group.todos.is_empty())
+            .map(|group| (group.bucket, group.todos.iter().map(|t| t.id).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn test_empty_agenda_has_every_group() {
+        let groups = group(Vec::new(), at("2024-05-01T12:00:00"), &warsaw(), 2);
+        let buckets: Vec<Bucket> = groups.iter().map(|g| g.bucket).collect();
+        assert_eq!(
+            buckets,
+            vec![
+                Bucket::Overdue,
+                day("2024-05-01"),
+                day("2024-05-02"),
+                day("2024-05-03"),
+                Bucket::Later,
+                Bucket::NoDeadline,
+            ]
+        );
+        assert!(groups.iter().all(|g| g.todos.is_empty()));
+        assert_eq!(
+            group(Vec::new(), at("2024-05-01T12:00:00"), &warsaw(), 0).len(),
+            4
+        );
+    }
+
+    #[test]
+    fn test_just_before_midnight() {
+        let now = at("2024-05-01T23:59:30");
+        let todos = vec![
+            todo(1, Some("2024-05-01T21:59:00+00:00"), 0),
+            todo(2, Some("2024-05-01T21:59:45+00:00"), 0),
+            todo(3, Some("2024-05-01"), 0),
+            todo(4, Some("2024-05-01T22:00:30+00:00"), 0),
+        ];
+        assert_eq!(
+            layout(todos, now, 1),
+            vec![
+                (Bucket::Overdue, vec![1]),
+                (day("2024-05-01"), vec![2, 3]),
+                (day("2024-05-02"), vec![4]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_just_after_midnight() {
+        let now = at("2024-05-02T00:00:10");
+        let todos = vec![
+            todo(1, Some("2024-05-01"), 0),
+            todo(2, Some("2024-05-02"), 0),
+            todo(3, Some("2024-05-01T22:00:30+00:00"), 0),
+        ];
+        assert_eq!(
+            layout(todos, now, 0),
+            vec![(Bucket::Overdue, vec![1]), (day("2024-05-02"), vec![3, 2])]
+        );
+    }
+
+    #[test]
+    fn test_timed_deadline_groups_by_local_day() {
+        // 23:30 UTC on 1 May is 01:30 on 2 May in Warsaw.
+        let todos = vec![todo(1, Some("2024-05-01T23:30:00+00:00"), 0)];
+        assert_eq!(
+            layout(todos, at("2024-05-01T12:00:00"), 3),
+            vec![(day("2024-05-02"), vec![1])]
+        );
+    }
+
+    #[test]
+    fn test_horizon() {
+        let now = at("2024-05-01T12:00:00");
+        let todos = vec![
+            todo(1, Some("2024-05-03"), 0),
+            todo(2, Some("2024-05-04"), 0),
+            todo(3, Some("2025-01-01"), 0),
+        ];
+        assert_eq!(
+            layout(todos.clone(), now, 2),
+            vec![(day("2024-05-03"), vec![1]), (Bucket::Later, vec![2, 3])]
+        );
+        assert_eq!(
+            layout(todos.clone(), now, 0),
+            vec![(Bucket::Later, vec![1, 2, 3])]
+        );
+        let capped = group(todos, now, &warsaw(), u32::MAX);
+        assert_eq!(capped.len(), MAX_DAYS_AHEAD as usize + 4);
+        assert_eq!(capped[capped.len() - 2].bucket, Bucket::Later);
+    }
+
+    #[test]
+    fn test_order_within_a_day() {
+        let now = at("2024-05-01T08:00:00");
+        let todos = vec![
+            todo(1, Some("2024-05-01"), 3),
+            todo(2, Some("2024-05-01T15:00:00+00:00"), 5),
+            todo(3, Some("2024-05-01T07:00:00+00:00"), 0),
+            todo(4, Some("2024-05-01T07:00:00+00:00"), 3),
+            todo(5, Some("2024-05-01"), 4),
+        ];
+        assert_eq!(
+            layout(todos, now, 0),
+            vec![(day("2024-05-01"), vec![4, 3, 2, 5, 1])]
+        );
+    }
+
+    #[test]
+    fn test_overdue_order_and_no_deadline_group() {
+        let now = at("2024-05-01T12:00:00");
+        let mut done = todo(6, Some("2024-04-01"), 5);
+        done.completed = true;
+        let todos = vec![
+            todo(1, None, 0),
+            todo(2, Some("2024-04-29"), 0),
+            todo(3, Some("someday"), 4),
+            todo(4, Some("2024-04-20"), 1),
+            todo(5, None, 2),
+            done,
+        ];
+        assert_eq!(
+            layout(todos, now, 1),
+            vec![
+                (Bucket::Overdue, vec![4, 2]),
+                (Bucket::NoDeadline, vec![3, 5, 1]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bucket_serialization() {
+        let buckets = [Bucket::Overdue, day("2024-05-01"), Bucket::NoDeadline];
+        assert_eq!(
+            serde_json::to_string(&buckets).unwrap(),
+            r#"["overdue",{"day":"2024-05-01"},"no_deadline"]"#
+        );
+    }
+}
@@ -0,0 +1,381 @@
+use crate::{db_get_setting, db_set_setting};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use rusqlite::Connection;
+use serde::Serialize;
+use std::fmt;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Settings key holding the passphrase hash; no row means no lock.
+pub const APP_LOCK_HASH_SETTING: &str = "app_lock_hash";
+/// Settings key holding how many idle minutes lock the app; 0 never does.
+pub const AUTO_LOCK_SETTING: &str = "auto_lock_minutes";
+pub const DEFAULT_AUTO_LOCK_MINUTES: u32 = 10;
+pub const MAX_AUTO_LOCK_MINUTES: u32 = 24 * 60;
+/// Emitted when the app locks, so every window can hide the list.
+pub const APP_LOCKED_EVENT: &str = "app-locked";
+/// How often the auto-lock is checked between commands.
+pub const TICK_INTERVAL: Duration = Duration::from_secs(15);
+/// How long a wrong passphrase takes to be turned down, to slow guessing.
+pub const UNLOCK_FAILURE_DELAY: Duration = Duration::from_millis(500);
+
+/// Commands that work while the app is locked: unlocking, reading the
+/// settings the window needs before it has anything to show, and switching
+/// to another profile, which has a lock of its own.
+pub const ALLOWED_WHILE_LOCKED: &[&str] = &[
+    "unlock",
+    "lock",
+    "get_app_lock_status",
+    "get_settings",
+    "get_runtime_info",
+    "get_encryption_status",
+    "unlock_database",
+    "list_profiles",
+    "switch_profile",
+];
+/// Commands the frontend polls on a timer. They don't count as activity, or
+/// an open window would never lock.
+pub const PASSIVE_COMMANDS: &[&str] = &["get_pending_reminders", "get_app_lock_status"];
+
+const SALT_LEN: usize = 16;
+
+/// Why an app lock command failed. Serialized as `{"kind", "message"}` like
+/// the other typed command errors.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+#[serde(tag = "kind", content = "message", rename_all = "snake_case")]
+pub enum AppLockError {
+    /// The app is locked and `unlock` hasn't succeeded yet.
+    Locked,
+    WrongPassphrase,
+    EmptyPassphrase,
+    /// No passphrase has been set with `set_app_lock`.
+    NotSet,
+}
+
+impl fmt::Display for AppLockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppLockError::Locked => write!(f, "the app is locked"),
+            AppLockError::WrongPassphrase => write!(f, "wrong passphrase"),
+            AppLockError::EmptyPassphrase => write!(f, "the passphrase is empty"),
+            AppLockError::NotSet => write!(f, "no app lock passphrase is set"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct LockState {
+    enabled: bool,
+    locked: bool,
+    auto_lock_after: Option<Duration>,
+    last_activity: Instant,
+}
+
+impl LockState {
+    /// Locks if the app has been idle for longer than the auto-lock allows.
+    /// True if this call locked it.
+    fn expire(&mut self, now: Instant) -> bool {
+        let idle = now.saturating_duration_since(self.last_activity);
+        match self.auto_lock_after {
+            Some(after) if self.enabled && !self.locked && idle >= after => {
+                self.locked = true;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Whether the app is locked, and when it last saw a command. Every method
+/// takes the current time so tests can move the clock.
+#[derive(Debug)]
+pub struct AppLock(Mutex<LockState>);
+
+impl Default for AppLock {
+    fn default() -> Self {
+        AppLock(Mutex::new(LockState {
+            enabled: false,
+            locked: false,
+            auto_lock_after: None,
+            last_activity: Instant::now(),
+        }))
+    }
+}
+
+fn auto_lock_after(minutes: u32) -> Option<Duration> {
+    (minutes > 0).then(|| Duration::from_secs(u64::from(minutes) * 60))
+}
+
+impl AppLock {
+    /// Applies the stored settings; `locked` only takes effect if a
+    /// passphrase is set.
+    pub fn configure(&self, enabled: bool, auto_lock_minutes: u32, locked: bool, now: Instant) {
+        *self.0.lock().unwrap() = LockState {
+            enabled,
+            locked: enabled && locked,
+            auto_lock_after: auto_lock_after(auto_lock_minutes),
+            last_activity: now,
+        };
+    }
+
+    pub fn set_auto_lock(&self, minutes: u32) {
+        self.0.lock().unwrap().auto_lock_after = auto_lock_after(minutes);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.0.lock().unwrap().enabled
+    }
+
+    pub fn is_locked(&self, now: Instant) -> bool {
+        let mut state = self.0.lock().unwrap();
+        state.expire(now);
+        state.locked
+    }
+
+    /// Locks now. False if there is no passphrase to lock with.
+    pub fn lock(&self) -> bool {
+        let mut state = self.0.lock().unwrap();
+        state.locked = state.enabled;
+        state.enabled
+    }
+
+    /// Locks if the auto-lock is due. True if this call locked it.
+    pub fn tick(&self, now: Instant) -> bool {
+        self.0.lock().unwrap().expire(now)
+    }
+
+    /// `Locked` if `command` has to wait for the app to be unlocked;
+    /// otherwise notes the activity, unless `command` is passive.
+    pub fn check(&self, command: &str, now: Instant) -> Result<(), AppLockError> {
+        let mut state = self.0.lock().unwrap();
+        state.expire(now);
+        if state.locked && !ALLOWED_WHILE_LOCKED.contains(&command) {
+            return Err(AppLockError::Locked);
+        }
+        if !PASSIVE_COMMANDS.contains(&command) {
+            state.last_activity = now;
+        }
+        Ok(())
+    }
+
+    /// Unlocks if `passphrase` matches `stored`. A wrong one is turned down
+    /// only after `UNLOCK_FAILURE_DELAY`.
+    pub fn unlock(
+        &self,
+        stored: Option<&str>,
+        passphrase: &str,
+        now: Instant,
+    ) -> Result<(), AppLockError> {
+        check_passphrase(passphrase, stored.ok_or(AppLockError::NotSet)?)?;
+        let mut state = self.0.lock().unwrap();
+        state.locked = false;
+        state.last_activity = now;
+        Ok(())
+    }
+}
+
+/// `WrongPassphrase` after `UNLOCK_FAILURE_DELAY` unless `passphrase` matches
+/// `stored`.
+fn check_passphrase(passphrase: &str, stored: &str) -> Result<(), AppLockError> {
+    if !verify(passphrase, stored) {
+        thread::sleep(UNLOCK_FAILURE_DELAY);
+        return Err(AppLockError::WrongPassphrase);
+    }
+    Ok(())
+}
+
+/// The stored passphrase hash, if a lock is set.
+pub fn stored_hash(conn: &Connection) -> Option<String> {
+    db_get_setting(conn, APP_LOCK_HASH_SETTING)
+}
+
+/// Stores the hash of `passphrase`, or removes the lock for `None`. Once a
+/// lock is set, `current` has to be its passphrase.
+pub fn set_passphrase(
+    conn: &Connection,
+    current: Option<&str>,
+    passphrase: Option<&str>,
+) -> Result<(), AppLockError> {
+    if let Some(stored) = stored_hash(conn) {
+        check_passphrase(current.unwrap_or_default(), &stored)?;
+    }
+    match passphrase {
+        Some("") => Err(AppLockError::EmptyPassphrase),
+        Some(passphrase) => {
+            db_set_setting(conn, APP_LOCK_HASH_SETTING, &hash_passphrase(passphrase));
+            Ok(())
+        }
+        None => {
+            conn.execute(
+                "DELETE FROM settings WHERE key = ?1",
+                [APP_LOCK_HASH_SETTING],
+            )
+            .expect("Failed to remove app lock");
+            Ok(())
+        }
+    }
+}
+
+pub fn auto_lock_minutes(conn: &Connection) -> u32 {
+    db_get_setting(conn, AUTO_LOCK_SETTING)
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_AUTO_LOCK_MINUTES)
+}
+
+pub fn check_auto_lock_minutes(minutes: u32) -> Result<(), String> {
+    if minutes > MAX_AUTO_LOCK_MINUTES {
+        return Err(format!(
+            "Auto-lock must be at most {} minutes",
+            MAX_AUTO_LOCK_MINUTES
+        ));
+    }
+    Ok(())
+}
+
+pub fn set_auto_lock_minutes(conn: &Connection, minutes: u32) {
+    db_set_setting(conn, AUTO_LOCK_SETTING, &minutes.to_string());
+}
+
+/// `passphrase` hashed with Argon2id and a random salt, as a PHC string
+/// (`$argon2id$v=19$m=...,t=...,p=...$salt$hash`) that carries its own
+/// parameters, so they can be raised later without breaking stored hashes.
+pub fn hash_passphrase(passphrase: &str) -> String {
+    hash_with(&Argon2::default(), passphrase)
+}
+
+fn hash_with(argon2: &Argon2, passphrase: &str) -> String {
+    let mut salt = [0u8; SALT_LEN];
+    getrandom::getrandom(&mut salt).expect("Failed to read random bytes");
+    let salt = SaltString::encode_b64(&salt).expect("Failed to encode salt");
+    argon2
+        .hash_password(passphrase.as_bytes(), &salt)
+        .expect("Failed to hash passphrase")
+        .to_string()
+}
+
+/// Whether `passphrase` is the one `stored` was made from. A stored value
+/// that isn't a PHC string never matches.
+pub fn verify(passphrase: &str, stored: &str) -> bool {
+    PasswordHash::new(stored).is_ok_and(|hash| {
+        Argon2::default()
+            .verify_password(passphrase.as_bytes(), &hash)
+            .is_ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::setup_test_db;
+    use argon2::{Algorithm, Params, Version};
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    /// A hash cheap enough for unoptimized test builds; `verify` reads the
+    /// parameters back from the string.
+    fn quick_hash(passphrase: &str) -> String {
+        let params = Params::new(Params::MIN_M_COST, 1, 1, None).unwrap();
+        let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, params);
+        hash_with(&argon2, passphrase)
+    }
+
+    #[test]
+    fn test_hash_and_verify() {
+        let stored = quick_hash("open sesame");
+        assert!(stored.starts_with("$argon2id$"), "{}", stored);
+        assert!(verify("open sesame", &stored));
+        assert!(!verify("open sesame!", &stored));
+        assert!(!verify("open sesame", &stored.replace("t=1", "t=2")));
+        assert!(!verify("open sesame", "plain text"));
+        assert_ne!(quick_hash("same"), quick_hash("same"));
+    }
+
+    #[test]
+    fn test_wrong_passphrase_is_delayed() {
+        let lock = AppLock::default();
+        let now = Instant::now();
+        lock.configure(true, 10, true, now);
+        let stored = quick_hash("open sesame");
+
+        let started = Instant::now();
+        assert_eq!(
+            lock.unlock(Some(&stored), "let me in", now),
+            Err(AppLockError::WrongPassphrase)
+        );
+        assert!(started.elapsed() >= UNLOCK_FAILURE_DELAY);
+        assert!(lock.is_locked(now));
+        assert_eq!(
+            lock.unlock(None, "open sesame", now),
+            Err(AppLockError::NotSet)
+        );
+
+        lock.unlock(Some(&stored), "open sesame", now).unwrap();
+        assert!(!lock.is_locked(now));
+    }
+
+    #[test]
+    fn test_changing_the_lock_needs_the_current_passphrase() {
+        let conn = setup_test_db();
+        let stored = quick_hash("open sesame");
+        db_set_setting(&conn, APP_LOCK_HASH_SETTING, &stored);
+
+        for current in [None, Some("let me in")] {
+            let started = Instant::now();
+            assert_eq!(
+                set_passphrase(&conn, current, None),
+                Err(AppLockError::WrongPassphrase)
+            );
+            assert!(started.elapsed() >= UNLOCK_FAILURE_DELAY);
+            assert_eq!(stored_hash(&conn), Some(stored.clone()));
+        }
+
+        set_passphrase(&conn, Some("open sesame"), None).unwrap();
+        assert_eq!(stored_hash(&conn), None);
+    }
+
+    #[test]
+    fn test_auto_lock_after_idle_minutes() {
+        let lock = AppLock::default();
+        let t0 = Instant::now();
+        lock.configure(true, 10, false, t0);
+
+        lock.check("get_todos", t0 + 9 * MINUTE).unwrap();
+        // Polling doesn't keep it awake.
+        lock.check("get_pending_reminders", t0 + 12 * MINUTE)
+            .unwrap();
+        assert!(!lock.tick(t0 + 18 * MINUTE));
+        assert!(lock.tick(t0 + 19 * MINUTE));
+        assert!(!lock.tick(t0 + 20 * MINUTE));
+        assert_eq!(
+            lock.check("get_todos", t0 + 20 * MINUTE),
+            Err(AppLockError::Locked)
+        );
+
+        // Without a passphrase, or with the auto-lock off, it never locks.
+        lock.configure(false, 10, false, t0);
+        assert!(!lock.is_locked(t0 + 60 * MINUTE));
+        lock.configure(true, 0, false, t0);
+        assert!(!lock.is_locked(t0 + 60 * 24 * MINUTE));
+        assert!(lock.lock());
+        assert!(lock.is_locked(t0));
+    }
+
+    #[test]
+    fn test_allowed_commands_while_locked() {
+        let lock = AppLock::default();
+        let now = Instant::now();
+        lock.configure(true, 10, true, now);
+        for command in ["get_settings", "unlock", "get_app_lock_status"] {
+            assert_eq!(lock.check(command, now), Ok(()), "{}", command);
+        }
+        for command in ["get_todos", "delete_todo", "export_bundle"] {
+            assert_eq!(lock.check(command, now), Err(AppLockError::Locked));
+        }
+        assert_eq!(
+            serde_json::to_value(AppLockError::Locked).unwrap(),
+            serde_json::json!({"kind": "locked"})
+        );
+    }
+}
@@ -0,0 +1,224 @@
+use crate::{db_get_setting, db_set_setting};
+use rusqlite::Connection;
+use serde::Serialize;
+use std::fmt;
+use tauri::{AppHandle, Manager, Runtime};
+use tauri_plugin_autostart::AutoLaunchManager;
+
+/// Settings key holding whether the app should start at login.
+pub const AUTOSTART_SETTING: &str = "autostart";
+/// Passed to login launches so the app starts in the tray.
+pub const AUTOSTART_ARG: &str = "--autostart";
+
+/// Why the login item could not be read or changed. Serialized as
+/// `{"kind", "message"}` like the other typed command errors.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+#[serde(tag = "kind", content = "message", rename_all = "snake_case")]
+pub enum AutostartError {
+    /// There is no login-item support for this install (e.g. the plugin
+    /// couldn't resolve a stable path for a portable build).
+    Unavailable(String),
+    /// The OS refused to add or remove the login item.
+    Rejected(String),
+}
+
+impl fmt::Display for AutostartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AutostartError::Unavailable(msg) => write!(f, "autostart unavailable: {}", msg),
+            AutostartError::Rejected(msg) => write!(f, "autostart rejected: {}", msg),
+        }
+    }
+}
+
+/// The OS's list of programs launched at login. The app goes through the
+/// autostart plugin; tests use an in-memory flag.
+pub trait LoginItem {
+    fn is_enabled(&self) -> Result<bool, AutostartError>;
+    fn set_enabled(&self, enabled: bool) -> Result<(), AutostartError>;
+}
+
+impl<R: Runtime> LoginItem for AppHandle<R> {
+    fn is_enabled(&self) -> Result<bool, AutostartError> {
+        let manager = self
+            .try_state::<AutoLaunchManager>()
+            .ok_or_else(|| AutostartError::Unavailable("not set up".to_string()))?;
+        manager
+            .is_enabled()
+            .map_err(|e| AutostartError::Unavailable(e.to_string()))
+    }
+
+    fn set_enabled(&self, enabled: bool) -> Result<(), AutostartError> {
+        let manager = self
+            .try_state::<AutoLaunchManager>()
+            .ok_or_else(|| AutostartError::Unavailable("not set up".to_string()))?;
+        let result = if enabled {
+            manager.enable()
+        } else {
+            manager.disable()
+        };
+        result.map_err(|e| AutostartError::Rejected(e.to_string()))
+    }
+}
+
+/// What the user asked for and what the OS will actually do at next login.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+pub struct AutostartStatus {
+    pub stored: bool,
+    pub effective: bool,
+}
+
+fn stored(conn: &Connection) -> Option<bool> {
+    db_get_setting(conn, AUTOSTART_SETTING).map(|v| v == "true")
+}
+
+fn store(conn: &Connection, enabled: bool) {
+    db_set_setting(conn, AUTOSTART_SETTING, &enabled.to_string());
+}
+
+pub fn status(conn: &Connection, os: &impl LoginItem) -> Result<AutostartStatus, AutostartError> {
+    Ok(AutostartStatus {
+        stored: stored(conn).unwrap_or(false),
+        effective: os.is_enabled()?,
+    })
+}
+
+/// Changes the login item and saves the preference. Nothing is saved if the
+/// OS refuses.
+pub fn set_autostart(
+    conn: &Connection,
+    os: &impl LoginItem,
+    enabled: bool,
+) -> Result<AutostartStatus, AutostartError> {
+    os.set_enabled(enabled)?;
+    store(conn, enabled);
+    status(conn, os)
+}
+
+/// Brings the stored preference and the OS in line at startup. The stored
+/// preference wins, except that a login item the user removed themselves
+/// (stored on, OS off) stays removed and the preference follows. Before
+/// the preference was ever set, whatever the OS has is adopted.
+pub fn reconcile(
+    conn: &Connection,
+    os: &impl LoginItem,
+) -> Result<AutostartStatus, AutostartError> {
+    let effective = os.is_enabled()?;
+    match stored(conn) {
+        Some(true) if !effective => store(conn, false),
+        Some(false) if effective => os.set_enabled(false)?,
+        None => store(conn, effective),
+        _ => {}
+    }
+    status(conn, os)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::setup_test_db;
+    use std::cell::Cell;
+
+    #[derive(Default)]
+    struct FakeLoginItem {
+        enabled: Cell<bool>,
+        refuse: bool,
+    }
+
+    impl LoginItem for FakeLoginItem {
+        fn is_enabled(&self) -> Result<bool, AutostartError> {
+            Ok(self.enabled.get())
+        }
+
+        fn set_enabled(&self, enabled: bool) -> Result<(), AutostartError> {
+            if self.refuse {
+                return Err(AutostartError::Rejected("access denied".to_string()));
+            }
+            self.enabled.set(enabled);
+            Ok(())
+        }
+    }
+
+    fn os(enabled: bool) -> FakeLoginItem {
+        FakeLoginItem {
+            enabled: Cell::new(enabled),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_set_autostart_registers_and_persists() {
+        let conn = setup_test_db();
+        let os = os(false);
+
+        let status = set_autostart(&conn, &os, true).unwrap();
+
+        assert_eq!(
+            status,
+            AutostartStatus {
+                stored: true,
+                effective: true
+            }
+        );
+        assert!(os.enabled.get());
+    }
+
+    #[test]
+    fn test_rejected_registration_is_not_persisted() {
+        let conn = setup_test_db();
+        let os = FakeLoginItem {
+            refuse: true,
+            ..Default::default()
+        };
+
+        let err = set_autostart(&conn, &os, true).unwrap_err();
+
+        assert!(matches!(err, AutostartError::Rejected(_)));
+        assert_eq!(stored(&conn), None);
+    }
+
+    #[test]
+    fn test_reconcile_respects_manual_removal() {
+        let conn = setup_test_db();
+        store(&conn, true);
+
+        let status = reconcile(&conn, &os(false)).unwrap();
+
+        assert!(!status.stored && !status.effective);
+        assert_eq!(stored(&conn), Some(false));
+    }
+
+    #[test]
+    fn test_reconcile_removes_unwanted_login_item() {
+        let conn = setup_test_db();
+        store(&conn, false);
+        let os = os(true);
+
+        let status = reconcile(&conn, &os).unwrap();
+
+        assert!(!status.stored && !status.effective);
+        assert!(!os.enabled.get());
+    }
+
+    #[test]
+    fn test_reconcile_adopts_os_state_when_unset() {
+        let conn = setup_test_db();
+
+        let status = reconcile(&conn, &os(true)).unwrap();
+
+        assert!(status.stored && status.effective);
+        assert_eq!(stored(&conn), Some(true));
+    }
+
+    #[test]
+    fn test_reconcile_leaves_matching_state_alone() {
+        let conn = setup_test_db();
+        store(&conn, true);
+        let os = FakeLoginItem {
+            enabled: Cell::new(true),
+            refuse: true,
+        };
+
+        assert!(reconcile(&conn, &os).unwrap().effective);
+    }
+}
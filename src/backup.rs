@@ -0,0 +1,449 @@
+use crate::{db_get_setting, db_set_setting, encryption, init_db, run_migrations, MIGRATIONS};
+use chrono::{DateTime, Local};
+use rusqlite::{Connection, DatabaseName, OpenFlags};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub const ROLLING_BACKUP_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+pub const DEFAULT_BACKUPS_TO_KEEP: usize = 7;
+pub const MAX_BACKUPS_TO_KEEP: usize = 50;
+/// Settings key holding how many rolling backups to keep.
+pub const BACKUPS_TO_KEEP_SETTING: &str = "backups_to_keep";
+const BACKUP_PREFIX: &str = "todos-";
+const BACKUP_EXTENSION: &str = ".db";
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct BackupInfo {
+    pub name: String,
+    pub size_bytes: u64,
+}
+
+/// Copies the live database to `dest` using SQLite's online backup API, so the
+/// copy is consistent even while the app keeps using the connection. SQLCipher
+/// refuses that API, so an encrypted database, open under `passphrase`, is
+/// exported instead, and the copy is encrypted with the same passphrase.
+pub fn backup_database(
+    conn: &Connection,
+    dest: &Path,
+    passphrase: Option<&str>,
+) -> Result<(), String> {
+    let Some(passphrase) = passphrase else {
+        return conn
+            .backup(DatabaseName::Main, dest, None)
+            .map_err(|e| e.to_string());
+    };
+    // The export writes into a new file only.
+    if dest.exists() {
+        fs::remove_file(dest).map_err(|e| e.to_string())?;
+    }
+    encryption::export(conn, dest, passphrase).map_err(|e| e.to_string())
+}
+
+/// Checks that `path` is a SQLite database created by this app: it must open,
+/// have a `todos` table, and carry a schema version we know how to migrate.
+/// An encrypted one is opened with `passphrase`. Returns it open.
+pub fn validate_database(path: &Path, passphrase: Option<&str>) -> Result<Connection, String> {
+    if !path.is_file() {
+        return Err(format!("{} is not a file", path.display()));
+    }
+    let conn = match passphrase {
+        Some(passphrase) if encryption::is_encrypted(path) => {
+            encryption::open(path, passphrase).map_err(|e| e.to_string())?
+        }
+        _ => Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .map_err(|e| e.to_string())?,
+    };
+    let version: usize = conn
+        .query_row("PRAGMA user_version", [], |row| row.get(0))
+        .map_err(|_| "Not a SQLite database".to_string())?;
+    if version == 0 || version > MIGRATIONS.len() {
+        return Err(format!("Unsupported schema version {}", version));
+    }
+    let has_todos: bool = conn
+        .query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'todos')",
+            [],
+            |row| row.get(0),
+        )
+        .map_err(|e| e.to_string())?;
+    if !has_todos {
+        return Err("Database has no todos table".to_string());
+    }
+    Ok(conn)
+}
+
+/// Where the current database is kept before a restore overwrites it.
+fn pre_restore_path(conn: &Connection) -> Option<PathBuf> {
+    let path = conn.path().filter(|p| !p.is_empty())?;
+    Some(PathBuf::from(format!("{}.pre-restore.bak", path)))
+}
+
+/// Replaces the contents of `conn` with the database at `src`, after
+/// validating it and saving the current data next to the database file as
+/// `.pre-restore.bak`. The restore goes through the backup API, so the
+/// connection stays open and other handles never see a half-copied file.
+/// An encrypted database, open under `passphrase`, gets `src` encrypted with
+/// it and swapped in whole instead; `src` may be plaintext or encrypted with
+/// the same passphrase.
+pub fn restore_database(
+    conn: &mut Connection,
+    src: &Path,
+    passphrase: Option<&str>,
+) -> Result<(), String> {
+    let source = validate_database(src, passphrase)?;
+    if let Some(bak) = pre_restore_path(conn) {
+        backup_database(conn, &bak, passphrase)?;
+    }
+    match passphrase {
+        Some(passphrase) => {
+            let replaced = encryption::replace_with(conn, &source, passphrase);
+            // `conn` may be a new connection, whether or not that worked.
+            init_db(conn);
+            replaced.map_err(|e| e.to_string())?
+        }
+        None => {
+            drop(source);
+            conn.restore(
+                DatabaseName::Main,
+                src,
+                None::<fn(rusqlite::backup::Progress)>,
+            )
+            .map_err(|e| e.to_string())?
+        }
+    }
+    run_migrations(conn).map_err(|e| e.to_string())
+}
+
+pub fn backups_to_keep(conn: &Connection) -> usize {
+    db_get_setting(conn, BACKUPS_TO_KEEP_SETTING)
+        .and_then(|v| v.parse().ok())
+        .filter(|&n| n > 0)
+        .unwrap_or(DEFAULT_BACKUPS_TO_KEEP)
+}
+
+pub fn check_backups_to_keep(keep: usize) -> Result<(), String> {
+    if keep == 0 {
+        return Err("At least one backup must be kept".to_string());
+    }
+    if keep > MAX_BACKUPS_TO_KEEP {
+        return Err(format!(
+            "At most {} backups can be kept",
+            MAX_BACKUPS_TO_KEEP
+        ));
+    }
+    Ok(())
+}
+
+pub fn set_backups_to_keep(conn: &Connection, keep: usize) -> Result<(), String> {
+    check_backups_to_keep(keep)?;
+    db_set_setting(conn, BACKUPS_TO_KEEP_SETTING, &keep.to_string());
+    Ok(())
+}
+
+/// Rolling backups are named `todos-YYYYMMDD-HHMMSS.db`, so sorting by name
+/// sorts by age.
+fn backup_file_name(now: DateTime<Local>) -> String {
+    format!(
+        "{}{}{}",
+        BACKUP_PREFIX,
+        now.format("%Y%m%d-%H%M%S"),
+        BACKUP_EXTENSION
+    )
+}
+
+fn is_backup_name(name: &str) -> bool {
+    name.strip_prefix(BACKUP_PREFIX)
+        .and_then(|rest| rest.strip_suffix(BACKUP_EXTENSION))
+        .is_some_and(|stamp| {
+            stamp.len() == 15 && stamp.chars().all(|c| c.is_ascii_digit() || c == '-')
+        })
+}
+
+/// Lists the rolling backups in `dir`, newest first. A missing directory just
+/// means no backups have been taken yet.
+pub fn list_backups(dir: &Path) -> Result<Vec<BackupInfo>, String> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut backups = Vec::new();
+    for entry in fs::read_dir(dir).map_err(|e| e.to_string())? {
+        let entry = entry.map_err(|e| e.to_string())?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if is_backup_name(&name) {
+            let size_bytes = entry.metadata().map_err(|e| e.to_string())?.len();
+            backups.push(BackupInfo { name, size_bytes });
+        }
+    }
+    backups.sort_by(|a, b| b.name.cmp(&a.name));
+    Ok(backups)
+}
+
+/// Deletes all but the `keep` newest backups, returning the removed names.
+pub fn prune_backups(dir: &Path, keep: usize) -> Result<Vec<String>, String> {
+    let mut removed = Vec::new();
+    for old in list_backups(dir)?.into_iter().skip(keep) {
+        fs::remove_file(dir.join(&old.name)).map_err(|e| e.to_string())?;
+        removed.push(old.name);
+    }
+    Ok(removed)
+}
+
+/// A stamp of every row change `conn` has made since it was opened, never
+/// zero. Unlike file times it sees writes still sitting in the WAL.
+fn change_mark(conn: &Connection) -> rusqlite::Result<i64> {
+    conn.query_row("SELECT total_changes() % 2147483647 + 1", [], |row| {
+        row.get(0)
+    })
+}
+
+/// The `change_mark` of `conn`'s last rolling backup, kept as the user
+/// version of its temp database: setting that is not a row change, and a
+/// reopened or swapped-in connection starts without one.
+fn backup_mark(conn: &Connection) -> rusqlite::Result<Option<i64>> {
+    let mark: i64 = conn.query_row("PRAGMA temp.user_version", [], |row| row.get(0))?;
+    Ok(Some(mark).filter(|&mark| mark != 0))
+}
+
+fn set_backup_mark(conn: &Connection) -> rusqlite::Result<()> {
+    let mark = change_mark(conn)?;
+    conn.execute_batch(&format!("PRAGMA temp.user_version = {}", mark))
+}
+
+/// Whether the database changed after the newest backup in `dir` was
+/// written. A connection that hasn't made a backup yet, e.g. right after
+/// launch, compares the database and WAL file times instead.
+fn changed_since_last_backup(
+    conn: &Connection,
+    db_path: &Path,
+    dir: &Path,
+) -> Result<bool, String> {
+    let Some(newest) = list_backups(dir)?.into_iter().next() else {
+        return Ok(true);
+    };
+    if let Some(mark) = backup_mark(conn).map_err(|e| e.to_string())? {
+        return Ok(mark != change_mark(conn).map_err(|e| e.to_string())?);
+    }
+    let modified = |path: &Path| {
+        fs::metadata(path)
+            .and_then(|m| m.modified())
+            .map_err(|e| e.to_string())
+    };
+    let wal = PathBuf::from(format!("{}-wal", db_path.display()));
+    let mut written = modified(db_path)?;
+    if wal.exists() {
+        written = written.max(modified(&wal)?);
+    }
+    Ok(written > modified(&dir.join(newest.name))?)
+}
+
+/// Backs the database up into `dir` and prunes old copies down to `keep`.
+/// Does nothing and returns `None` when the database hasn't changed since the
+/// newest backup, or when it isn't backed by a file.
+pub fn rolling_backup(
+    conn: &Connection,
+    dir: &Path,
+    keep: usize,
+    now: DateTime<Local>,
+    passphrase: Option<&str>,
+) -> Result<Option<String>, String> {
+    let Some(db_path) = conn.path().filter(|p| !p.is_empty()) else {
+        return Ok(None);
+    };
+    if !changed_since_last_backup(conn, Path::new(db_path), dir)? {
+        return Ok(None);
+    }
+    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    let name = backup_file_name(now);
+    backup_database(conn, &dir.join(&name), passphrase)?;
+    set_backup_mark(conn).map_err(|e| e.to_string())?;
+    prune_backups(dir, keep)?;
+    Ok(Some(name))
+}
+
+/// Restores one of the rolling backups in `dir` by name.
+pub fn restore_backup(
+    conn: &mut Connection,
+    dir: &Path,
+    name: &str,
+    passphrase: Option<&str>,
+) -> Result<(), String> {
+    if !is_backup_name(name) {
+        return Err(format!("'{}' is not a backup name", name));
+    }
+    restore_database(conn, &dir.join(name), passphrase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db_add_todo, db_get_todos};
+    use chrono::TimeZone;
+
+    fn open_db(path: &Path) -> Connection {
+        let conn = Connection::open(path).unwrap();
+        init_db(&conn);
+        conn
+    }
+
+    #[test]
+    fn test_backup_opens_independently_with_identical_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        let conn = open_db(&dir.path().join("todos.db"));
+        db_add_todo(&conn, "One".to_string(), Some("2024-05-01".to_string()));
+        db_add_todo(&conn, "Two".to_string(), None);
+        let dest = dir.path().join("backup.db");
+
+        backup_database(&conn, &dest, None).unwrap();
+
+        let copy = Connection::open(&dest).unwrap();
+        assert_eq!(db_get_todos(&copy), db_get_todos(&conn));
+        assert!(validate_database(&dest, None).is_ok());
+    }
+
+    #[test]
+    fn test_restore_rejects_non_sqlite_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut conn = open_db(&dir.path().join("todos.db"));
+        db_add_todo(&conn, "Keep me".to_string(), None);
+        let bogus = dir.path().join("notes.txt");
+        std::fs::write(&bogus, "definitely not a database").unwrap();
+
+        assert!(restore_database(&mut conn, &bogus, None).is_err());
+        assert!(restore_database(&mut conn, &dir.path().join("missing.db"), None).is_err());
+        assert_eq!(db_get_todos(&conn)[0].title, "Keep me");
+    }
+
+    #[test]
+    fn test_restore_replaces_data_and_keeps_previous_copy() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("todos.db");
+        let mut conn = open_db(&db_path);
+        db_add_todo(&conn, "Current".to_string(), None);
+        let src_path = dir.path().join("other.db");
+        let src = open_db(&src_path);
+        db_add_todo(&src, "Restored".to_string(), None);
+        drop(src);
+
+        restore_database(&mut conn, &src_path, None).unwrap();
+
+        assert_eq!(db_get_todos(&conn)[0].title, "Restored");
+        let bak = Connection::open(dir.path().join("todos.db.pre-restore.bak")).unwrap();
+        assert_eq!(db_get_todos(&bak)[0].title, "Current");
+    }
+
+    #[cfg(feature = "sqlcipher")]
+    #[test]
+    fn test_encrypted_database_backs_up_and_restores() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("todos.db");
+        let mut conn = open_db(&db_path);
+        db_add_todo(&conn, "Backed up".to_string(), None);
+        encryption::enable_encryption(&mut conn, Some(&db_path), "hunter2").unwrap();
+        init_db(&conn);
+        let backups = dir.path().join("backups");
+        let now = Local.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+
+        let name = rolling_backup(&conn, &backups, 7, now, Some("hunter2"))
+            .unwrap()
+            .unwrap();
+        assert!(encryption::is_encrypted(&backups.join(&name)));
+        assert!(validate_database(&backups.join(&name), None).is_err());
+        db_add_todo(&conn, "After the backup".to_string(), None);
+
+        restore_backup(&mut conn, &backups, &name, Some("hunter2")).unwrap();
+
+        let titles: Vec<String> = db_get_todos(&conn).into_iter().map(|t| t.title).collect();
+        assert_eq!(titles, ["Backed up"]);
+        assert!(encryption::is_encrypted(&db_path));
+        let bak = dir.path().join("todos.db.pre-restore.bak");
+        assert_eq!(
+            db_get_todos(&encryption::open(&bak, "hunter2").unwrap()).len(),
+            2
+        );
+    }
+
+    fn touch_backup(dir: &Path, name: &str) {
+        fs::create_dir_all(dir).unwrap();
+        fs::write(dir.join(name), "x").unwrap();
+    }
+
+    #[test]
+    fn test_backup_names_sort_chronologically() {
+        let earlier = Local.with_ymd_and_hms(2024, 5, 1, 9, 0, 0).unwrap();
+        let later = Local.with_ymd_and_hms(2024, 12, 1, 8, 0, 0).unwrap();
+
+        assert_eq!(backup_file_name(earlier), "todos-20240501-090000.db");
+        assert!(backup_file_name(earlier) < backup_file_name(later));
+        assert!(is_backup_name("todos-20240501-090000.db"));
+        assert!(!is_backup_name("todos.db"));
+        assert!(!is_backup_name("../todos-20240501-090000.db"));
+    }
+
+    #[test]
+    fn test_prune_keeps_newest_backups() {
+        let dir = tempfile::tempdir().unwrap();
+        for day in 1..=9 {
+            touch_backup(dir.path(), &format!("todos-2024050{}-120000.db", day));
+        }
+        touch_backup(dir.path(), "unrelated.db");
+
+        let removed = prune_backups(dir.path(), 7).unwrap();
+
+        assert_eq!(
+            removed,
+            vec!["todos-20240502-120000.db", "todos-20240501-120000.db"]
+        );
+        let names: Vec<String> = list_backups(dir.path())
+            .unwrap()
+            .into_iter()
+            .map(|b| b.name)
+            .collect();
+        assert_eq!(names.len(), 7);
+        assert_eq!(names[0], "todos-20240509-120000.db");
+        assert!(dir.path().join("unrelated.db").exists());
+    }
+
+    #[test]
+    fn test_rolling_backup_skips_unchanged_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("todos.db");
+        let backups = dir.path().join("backups");
+        let conn = open_db(&db_path);
+        db_add_todo(&conn, "One".to_string(), None);
+        let now = Local.with_ymd_and_hms(2024, 5, 1, 9, 0, 0).unwrap();
+
+        let first = rolling_backup(&conn, &backups, 7, now, None).unwrap();
+        assert_eq!(first, Some("todos-20240501-090000.db".to_string()));
+
+        let later = now + chrono::Duration::hours(1);
+        assert_eq!(
+            rolling_backup(&conn, &backups, 7, later, None).unwrap(),
+            None
+        );
+
+        // Still in the WAL, and within the file timestamps' resolution.
+        db_add_todo(&conn, "Two".to_string(), None);
+
+        let second = rolling_backup(&conn, &backups, 7, later, None).unwrap();
+        assert_eq!(second, Some("todos-20240501-100000.db".to_string()));
+        assert_eq!(list_backups(&backups).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_backups_to_keep_reads_setting() {
+        let dir = tempfile::tempdir().unwrap();
+        let conn = open_db(&dir.path().join("todos.db"));
+        assert_eq!(backups_to_keep(&conn), DEFAULT_BACKUPS_TO_KEEP);
+
+        set_backups_to_keep(&conn, 3).unwrap();
+        assert_eq!(backups_to_keep(&conn), 3);
+        assert!(set_backups_to_keep(&conn, 0).is_err());
+        assert!(set_backups_to_keep(&conn, MAX_BACKUPS_TO_KEEP + 1).is_err());
+        assert_eq!(backups_to_keep(&conn), 3);
+
+        crate::db_set_setting(&conn, BACKUPS_TO_KEEP_SETTING, "0");
+        assert_eq!(backups_to_keep(&conn), DEFAULT_BACKUPS_TO_KEEP);
+    }
+}
@@ -0,0 +1,204 @@
+use crate::{db_get_setting, db_get_todos, db_set_setting, deadline, Filter, Todo};
+use chrono::{DateTime, Local};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, Runtime};
+
+/// Settings key holding which todos the taskbar/dock badge counts.
+pub const BADGE_MODE_SETTING: &str = "badge_mode";
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BadgeMode {
+    /// Incomplete todos due by the end of today, overdue ones included.
+    #[default]
+    DueToday,
+    Overdue,
+    AllActive,
+    Off,
+}
+
+impl BadgeMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            BadgeMode::DueToday => "due_today",
+            BadgeMode::Overdue => "overdue",
+            BadgeMode::AllActive => "all_active",
+            BadgeMode::Off => "off",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        [
+            BadgeMode::DueToday,
+            BadgeMode::Overdue,
+            BadgeMode::AllActive,
+            BadgeMode::Off,
+        ]
+        .into_iter()
+        .find(|mode| mode.as_str() == value)
+    }
+}
+
+pub fn badge_mode(conn: &Connection) -> BadgeMode {
+    db_get_setting(conn, BADGE_MODE_SETTING)
+        .and_then(|v| BadgeMode::parse(&v))
+        .unwrap_or_default()
+}
+
+pub fn set_badge_mode(conn: &Connection, mode: BadgeMode) {
+    db_set_setting(conn, BADGE_MODE_SETTING, mode.as_str());
+}
+
+/// The number to show, or `None` when the badge is off. Uses the same
+/// deadline rules as the list filters: `Filter::Overdue` for overdue, and
+/// the deadline's local day for due today (or earlier).
+pub fn badge_count(todos: &[Todo], mode: BadgeMode, now: DateTime<Local>) -> Option<u64> {
+    let counts = |todo: &&Todo| match mode {
+        BadgeMode::Off => false,
+        BadgeMode::Overdue => Filter::Overdue.matches(todo, now),
+        BadgeMode::AllActive => Filter::Active.matches(todo, now),
+        BadgeMode::DueToday => {
+            !todo.completed
+                && todo
+                    .deadline
+                    .as_deref()
+                    .and_then(|d| deadline::deadline_day(d, &Local))
+                    .is_some_and(|day| day <= now.date_naive())
+        }
+    };
+    (mode != BadgeMode::Off).then(|| todos.iter().filter(counts).count() as u64)
+}
+
+/// Puts a count on the app icon. The app sets the main window's badge;
+/// tests record the calls instead.
+pub trait Badge {
+    /// `None` removes the badge.
+    fn set_badge(&self, count: Option<u64>) -> Result<(), String>;
+}
+
+impl<R: Runtime> Badge for AppHandle<R> {
+    fn set_badge(&self, count: Option<u64>) -> Result<(), String> {
+        let Some(window) = self.get_webview_window(crate::tray::MAIN_WINDOW) else {
+            return Ok(());
+        };
+        // Windows has no numeric badge, only an overlay image, so it gets a
+        // plain dot while anything is counted.
+        #[cfg(target_os = "windows")]
+        let result = window.set_overlay_icon(
+            count.map(|_| tauri::image::Image::new_owned(overlay_dot(), DOT_SIZE, DOT_SIZE)),
+        );
+        #[cfg(not(target_os = "windows"))]
+        let result = window.set_badge_count(count.map(|n| n as i64));
+        result.map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(target_os = "windows")]
+const DOT_SIZE: u32 = 16;
+
+/// A red filled circle as RGBA pixels.
+#[cfg(target_os = "windows")]
+fn overlay_dot() -> Vec<u8> {
+    let r = DOT_SIZE as f32 / 2.0;
+    (0..DOT_SIZE * DOT_SIZE)
+        .flat_map(|i| {
+            let (x, y) = (
+                (i % DOT_SIZE) as f32 + 0.5 - r,
+                (i / DOT_SIZE) as f32 + 0.5 - r,
+            );
+            let alpha = if x * x + y * y <= r * r { 255 } else { 0 };
+            [220, 38, 38, alpha]
+        })
+        .collect()
+}
+
+/// Recounts according to the saved mode and updates the badge. A count of
+/// zero clears it.
+pub fn refresh(conn: &Connection, badge: &impl Badge, now: DateTime<Local>) -> Result<(), String> {
+    let count = badge_count(&db_get_todos(conn), badge_mode(conn), now).filter(|&n| n > 0);
+    badge.set_badge(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db_add_todo, db_update_todo, setup_test_db};
+    use chrono::TimeZone;
+    use std::cell::RefCell;
+
+    #[derive(Default)]
+    struct RecordingBadge(RefCell<Vec<Option<u64>>>);
+
+    impl Badge for RecordingBadge {
+        fn set_badge(&self, count: Option<u64>) -> Result<(), String> {
+            self.0.borrow_mut().push(count);
+            Ok(())
+        }
+    }
+
+    fn noon() -> DateTime<Local> {
+        Local.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap()
+    }
+
+    /// One overdue, two more due later today, one tomorrow, one unscheduled
+    /// and a completed overdue todo.
+    fn fixture(conn: &Connection) {
+        for deadline in [
+            Some("2024-04-30T09:00"),
+            Some("2024-05-01T18:00"),
+            Some("2024-05-01"),
+            Some("2024-05-02T09:00"),
+            None,
+        ] {
+            db_add_todo(conn, "Todo".to_string(), deadline.map(String::from));
+        }
+        let done = db_add_todo(conn, "Done".to_string(), Some("2024-04-29".to_string()));
+        db_update_todo(conn, done.id, None, Some(true), None, None);
+    }
+
+    #[test]
+    fn test_count_per_mode() {
+        let conn = setup_test_db();
+        fixture(&conn);
+        let todos = db_get_todos(&conn);
+
+        assert_eq!(badge_count(&todos, BadgeMode::DueToday, noon()), Some(3));
+        assert_eq!(badge_count(&todos, BadgeMode::Overdue, noon()), Some(1));
+        assert_eq!(badge_count(&todos, BadgeMode::AllActive, noon()), Some(5));
+        assert_eq!(badge_count(&todos, BadgeMode::Off, noon()), None);
+    }
+
+    #[test]
+    fn test_mode_setting_defaults_to_due_today() {
+        let conn = setup_test_db();
+        assert_eq!(badge_mode(&conn), BadgeMode::DueToday);
+
+        set_badge_mode(&conn, BadgeMode::AllActive);
+        assert_eq!(badge_mode(&conn), BadgeMode::AllActive);
+
+        db_set_setting(&conn, BADGE_MODE_SETTING, "sparkles");
+        assert_eq!(badge_mode(&conn), BadgeMode::DueToday);
+    }
+
+    #[test]
+    fn test_refresh_sets_and_clears_badge() {
+        let conn = setup_test_db();
+        let badge = RecordingBadge::default();
+        refresh(&conn, &badge, noon()).unwrap();
+
+        fixture(&conn);
+        refresh(&conn, &badge, noon()).unwrap();
+        set_badge_mode(&conn, BadgeMode::Off);
+        refresh(&conn, &badge, noon()).unwrap();
+
+        assert_eq!(badge.0.borrow().as_slice(), [None, Some(3), None]);
+    }
+
+    #[test]
+    fn test_mode_serializes_snake_case() {
+        let json = serde_json::to_string(&BadgeMode::AllActive).unwrap();
+        assert_eq!(json, r#""all_active""#);
+        assert_eq!(BadgeMode::parse("all_active"), Some(BadgeMode::AllActive));
+    }
+}
@@ -0,0 +1,250 @@
+use crate::TODO_COLUMNS;
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+
+/// The bundle format this build writes. `import_bundle` reads this and
+/// anything older, and refuses newer bundles rather than guess at them.
+pub const BUNDLE_VERSION: u64 = 1;
+
+/// Everything needed to rebuild the todo list on another device. Rows are
+/// kept as stored, timestamps in UTC, so nothing shifts with the time zone
+/// of either machine.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Bundle {
+    pub version: u64,
+    pub todos: Vec<BundleTodo>,
+    pub tags: Vec<BundleTag>,
+    pub todo_tags: Vec<BundleTodoTag>,
+}
+
+/// One row of `todos`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct BundleTodo {
+    pub id: u64,
+    pub title: String,
+    pub completed: bool,
+    pub created_at: Option<String>,
+    pub deadline: Option<String>,
+    pub description: Option<String>,
+    pub priority: u8,
+    pub completed_at: Option<String>,
+    pub pinned: bool,
+    pub color: Option<String>,
+    pub estimate_minutes: Option<u32>,
+    pub version: u64,
+    pub reminder_minutes: Option<u32>,
+    pub updated_at: Option<String>,
+    /// Missing from bundles written before todos had one; a new one is made
+    /// on import.
+    #[serde(default)]
+    pub uuid: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct BundleTag {
+    pub id: u64,
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct BundleTodoTag {
+    pub todo_id: u64,
+    pub tag_id: u64,
+}
+
+/// The todos, tags and their links as stored.
+pub fn read_bundle(conn: &Connection) -> rusqlite::Result<Bundle> {
+    let todos = conn
+        .prepare(&format!(
+            "SELECT {}, uuid FROM todos ORDER BY id",
+            TODO_COLUMNS
+        ))?
+        .query_map([], |row| {
+            Ok(BundleTodo {
+                id: row.get(0)?,
+                title: row.get(1)?,
+                completed: row.get(2)?,
+                created_at: row.get(3)?,
+                deadline: row.get(4)?,
+                description: row.get(5)?,
+                priority: row.get(6)?,
+                completed_at: row.get(7)?,
+                pinned: row.get(8)?,
+                color: row.get(9)?,
+                estimate_minutes: row.get(10)?,
+                version: row.get(11)?,
+                reminder_minutes: row.get(12)?,
+                updated_at: row.get(13)?,
+                uuid: row.get(14)?,
+            })
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    let tags = conn
+        .prepare("SELECT id, name FROM tags ORDER BY id")?
+        .query_map([], |row| {
+            Ok(BundleTag {
+                id: row.get(0)?,
+                name: row.get(1)?,
+            })
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    let todo_tags = conn
+        .prepare("SELECT todo_id, tag_id FROM todo_tags ORDER BY todo_id, tag_id")?
+        .query_map([], |row| {
+            Ok(BundleTodoTag {
+                todo_id: row.get(0)?,
+                tag_id: row.get(1)?,
+            })
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(Bundle {
+        version: BUNDLE_VERSION,
+        todos,
+        tags,
+        todo_tags,
+    })
+}
+
+/// The todos, tags and the links between them as one JSON document.
+/// Settings, history and the archive stay behind.
+pub fn export_bundle(conn: &Connection) -> Result<String, String> {
+    let bundle = read_bundle(conn).map_err(|e| e.to_string())?;
+    serde_json::to_string_pretty(&bundle).map_err(|e| e.to_string())
+}
+
+/// Reads `json` as a bundle, checking the version before anything else so a
+/// newer format gets a clear answer instead of a confusing parse error.
+pub fn parse_bundle(json: &str) -> Result<Bundle, String> {
+    let value: Value =
+        serde_json::from_str(json).map_err(|e| format!("Not a todo bundle: {}", e))?;
+    match value.get("version").and_then(Value::as_u64) {
+        None | Some(0) => return Err("Not a todo bundle: it has no version".to_string()),
+        Some(version) if version > BUNDLE_VERSION => {
+            return Err(format!(
+                "This bundle is format version {}, but this app only reads up to version {}; update the app to import it",
+                version, BUNDLE_VERSION
+            ))
+        }
+        Some(_) => {}
+    }
+    let bundle: Bundle =
+        serde_json::from_value(value).map_err(|e| format!("Not a todo bundle: {}", e))?;
+    let todo_ids: HashSet<u64> = bundle.todos.iter().map(|t| t.id).collect();
+    let tag_ids: HashSet<u64> = bundle.tags.iter().map(|t| t.id).collect();
+    if let Some(link) = bundle
+        .todo_tags
+        .iter()
+        .find(|link| !todo_ids.contains(&link.todo_id) || !tag_ids.contains(&link.tag_id))
+    {
+        return Err(format!(
+            "The bundle tags todo {} with tag {}, but doesn't have both",
+            link.todo_id, link.tag_id
+        ));
+    }
+    Ok(bundle)
+}
+
+/// Replaces the todos, tags and their links with those in `json`, all in one
+/// transaction, so a bundle that fails part way leaves the list as it was.
+pub fn import_bundle(conn: &Connection, json: &str) -> Result<(), String> {
+    let bundle = parse_bundle(json)?;
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    write_bundle(&tx, &bundle).map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())
+}
+
+fn write_bundle(conn: &Connection, bundle: &Bundle) -> rusqlite::Result<()> {
+    conn.execute_batch("DELETE FROM todo_tags; DELETE FROM todos; DELETE FROM tags;")?;
+    let mut insert_todo = conn.prepare(&format!(
+        "INSERT INTO todos ({}, uuid)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+        TODO_COLUMNS
+    ))?;
+    for todo in &bundle.todos {
+        insert_todo.execute(params![
+            todo.id,
+            todo.title,
+            todo.completed,
+            todo.created_at,
+            todo.deadline,
+            todo.description,
+            todo.priority,
+            todo.completed_at,
+            todo.pinned,
+            todo.color,
+            todo.estimate_minutes,
+            todo.version,
+            todo.reminder_minutes,
+            todo.updated_at,
+            todo.uuid,
+        ])?;
+    }
+    let mut insert_tag = conn.prepare("INSERT INTO tags (id, name) VALUES (?1, ?2)")?;
+    for tag in &bundle.tags {
+        insert_tag.execute(params![tag.id, tag.name])?;
+    }
+    let mut insert_link =
+        conn.prepare("INSERT INTO todo_tags (todo_id, tag_id) VALUES (?1, ?2)")?;
+    for link in &bundle.todo_tags {
+        insert_link.execute(params![link.todo_id, link.tag_id])?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db_add_tag, db_add_todo, db_get_tags, db_get_todos, db_set_completed_bulk, db_set_pinned,
+        setup_test_db,
+    };
+
+    #[test]
+    fn test_bundle_round_trip() {
+        let conn = setup_test_db();
+        let first = db_add_todo(
+            &conn,
+            "Pack boxes".to_string(),
+            Some("2024-06-01".to_string()),
+        );
+        let second = db_add_todo(&conn, "Cancel internet".to_string(), None);
+        db_add_todo(&conn, "Hand in keys".to_string(), None);
+        db_add_tag(&conn, first.id, "move");
+        db_add_tag(&conn, second.id, "move");
+        db_add_tag(&conn, second.id, "admin");
+        db_set_completed_bulk(&conn, &[second.id], true);
+        db_set_pinned(&conn, first.id, true);
+        let json = export_bundle(&conn).unwrap();
+
+        let fresh = setup_test_db();
+        db_add_todo(&fresh, "Already here".to_string(), None);
+        import_bundle(&fresh, &json).unwrap();
+        assert_eq!(db_get_todos(&fresh), db_get_todos(&conn));
+        assert_eq!(
+            db_get_tags(&fresh, second.id),
+            db_get_tags(&conn, second.id)
+        );
+        assert_eq!(export_bundle(&fresh).unwrap(), json);
+    }
+
+    #[test]
+    fn test_bundle_version_is_checked() {
+        let conn = setup_test_db();
+        db_add_todo(&conn, "Keep me".to_string(), None);
+        let before = db_get_todos(&conn);
+
+        let newer = r#"{"version": 2, "todos": [], "tags": [], "todo_tags": [], "lists": []}"#;
+        let err = import_bundle(&conn, newer).unwrap_err();
+        assert!(err.contains("format version 2"), "{}", err);
+        assert!(import_bundle(&conn, r#"{"todos": []}"#)
+            .unwrap_err()
+            .contains("no version"));
+        let dangling = r#"{"version": 1, "todos": [], "tags": [], "todo_tags": [{"todo_id": 1, "tag_id": 1}]}"#;
+        assert!(import_bundle(&conn, dangling)
+            .unwrap_err()
+            .contains("tags todo 1 with tag 1"));
+        assert_eq!(db_get_todos(&conn), before);
+    }
+}
@@ -0,0 +1,173 @@
+use crate::autostart::AUTOSTART_ARG;
+use crate::db_location::Overrides;
+use crate::{validate_new_todo, validation, NewTodo, MAX_TITLE_CHARS};
+use std::path::PathBuf;
+
+pub const USAGE: &str =
+    "usage: tauri_basic_example [--add TITLE [--deadline DEADLINE]] [--no-gui] \
+                         [--db-path PATH | --ephemeral]";
+
+/// What the command line asked for. Launches without `--add` (including the
+/// ones the OS makes for deep links) parse to the default.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CliArgs {
+    pub add: Option<NewTodo>,
+    /// Add the todo and exit instead of opening the window.
+    pub no_gui: bool,
+    /// Launched at login: stay in the tray instead of showing the window.
+    pub autostart: bool,
+    /// `--db-path` and `--ephemeral`, for this run only.
+    pub database: Overrides,
+}
+
+/// Parses the arguments after the program name. Values come either as the
+/// next argument (`--add "Buy milk"`) or inline (`--add="Buy milk"`); the
+/// shell has already removed the quotes. Unrecognized arguments are ignored
+/// because the OS passes its own (deep-link URLs, `-psn_…` on macOS).
+pub fn parse_args(args: impl IntoIterator<Item = String>) -> Result<CliArgs, String> {
+    let mut title = None;
+    let mut deadline = None;
+    let mut no_gui = false;
+    let mut autostart = false;
+    let mut database = Overrides::default();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => {
+                (flag.to_string(), Some(value.to_string()))
+            }
+            _ => (arg, None),
+        };
+        let mut value = |name: &str| {
+            inline
+                .clone()
+                .or_else(|| args.next())
+                .filter(|v| !v.starts_with("--"))
+                .ok_or_else(|| format!("{} needs a value", name))
+        };
+        match flag.as_str() {
+            "--add" => title = Some(value("--add")?),
+            "--deadline" => deadline = Some(value("--deadline")?),
+            "--no-gui" => no_gui = true,
+            AUTOSTART_ARG => autostart = true,
+            "--db-path" => database.db_path = Some(PathBuf::from(value("--db-path")?)),
+            "--ephemeral" => database.ephemeral = true,
+            _ => {}
+        }
+    }
+
+    let add = match title {
+        // The database isn't open yet, so this is checked against the
+        // default length limit rather than the setting.
+        Some(title) => {
+            let new = NewTodo {
+                title,
+                deadline,
+                ..NewTodo::default()
+            };
+            Some(
+                validate_new_todo(new, MAX_TITLE_CHARS)
+                    .map_err(|errors| validation::describe(&errors))?,
+            )
+        }
+        None if deadline.is_some() => return Err("--deadline needs --add".to_string()),
+        None => None,
+    };
+    Ok(CliArgs {
+        add,
+        no_gui,
+        autostart,
+        database,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<CliArgs, String> {
+        parse_args(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn test_no_arguments() {
+        assert_eq!(parse(&[]).unwrap(), CliArgs::default());
+    }
+
+    #[test]
+    fn test_add_with_deadline() {
+        let args = parse(&["--add", "Buy milk", "--deadline", "2024-06-01"]).unwrap();
+
+        let new = args.add.unwrap();
+        assert_eq!(new.title, "Buy milk");
+        assert_eq!(new.deadline.as_deref(), Some("2024-06-01"));
+        assert!(!args.no_gui);
+    }
+
+    #[test]
+    fn test_quoting() {
+        let new = parse(&["--add=Say \"hi\" = wave"]).unwrap().add.unwrap();
+        assert_eq!(new.title, "Say \"hi\" = wave");
+
+        let new = parse(&["--add", "  padded  ", "--deadline=2024-06-01T09:30"])
+            .unwrap()
+            .add
+            .unwrap();
+        assert_eq!(new.title, "padded");
+        assert_eq!(
+            new.deadline,
+            crate::deadline::normalize("2024-06-01T09:30", &chrono::Local)
+        );
+    }
+
+    #[test]
+    fn test_missing_title() {
+        assert!(parse(&["--add"]).is_err());
+        assert!(parse(&["--add", "--no-gui"]).is_err());
+        assert!(parse(&["--add", "   "]).is_err());
+        assert!(parse(&["--deadline", "2024-06-01"]).is_err());
+    }
+
+    #[test]
+    fn test_invalid_deadline() {
+        let err = parse(&["--add", "Milk", "--deadline", "whenever"]).unwrap_err();
+        assert!(err.contains("whenever"));
+        assert!(parse(&["--add", "Milk", "--deadline"]).is_err());
+    }
+
+    #[test]
+    fn test_no_gui_and_unknown_arguments() {
+        let args = parse(&[
+            "todoapp://add?title=x",
+            "--no-gui",
+            "--add",
+            "Milk",
+            "-psn_0_1",
+        ])
+        .unwrap();
+
+        assert!(args.no_gui);
+        assert_eq!(args.add.unwrap().title, "Milk");
+    }
+
+    #[test]
+    fn test_autostart_flag() {
+        assert!(parse(&["--autostart"]).unwrap().autostart);
+        assert!(!parse(&["--no-gui"]).unwrap().autostart);
+    }
+
+    #[test]
+    fn test_database_flags() {
+        let args = parse(&["--db-path", "/tmp/e2e.db", "--no-gui"]).unwrap();
+        assert_eq!(args.database.db_path, Some(PathBuf::from("/tmp/e2e.db")));
+        assert!(!args.database.ephemeral);
+
+        assert!(parse(&["--ephemeral"]).unwrap().database.ephemeral);
+        assert_eq!(
+            parse(&["--db-path=/tmp/x.db"]).unwrap().database.db_path,
+            Some(PathBuf::from("/tmp/x.db"))
+        );
+        assert!(parse(&["--db-path"]).is_err());
+    }
+}
@@ -0,0 +1,501 @@
+use crate::{encryption, init_db, RETRY_DELAY, WRITE_ATTEMPTS};
+use rusqlite::backup::{Backup, Progress, StepResult};
+use rusqlite::{Connection, DatabaseName, OpenFlags};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+/// Where the database lives when no other location has been chosen. Relative
+/// to the working directory, as it always has been.
+pub const DEFAULT_DB_PATH: &str = "todos.db";
+pub const DB_FILE_NAME: &str = "todos.db";
+/// File in the app data dir holding the directory the database was moved to.
+const POINTER_FILE: &str = "database-location";
+const PAGES_PER_STEP: i32 = 64;
+/// Environment variables matching the `--db-path` and `--ephemeral` flags.
+pub const DB_PATH_ENV: &str = "TODOS_DB_PATH";
+pub const EPHEMERAL_ENV: &str = "TODOS_EPHEMERAL";
+
+fn pointer_path(app_dir: &Path) -> PathBuf {
+    app_dir.join(POINTER_FILE)
+}
+
+/// The database file the pointer file names, if there is a readable one.
+fn read_pointer(app_dir: &Path) -> Option<PathBuf> {
+    fs::read_to_string(pointer_path(app_dir))
+        .ok()
+        .map(|dir| dir.trim().to_string())
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| Path::new(&dir).join(DB_FILE_NAME))
+}
+
+/// A one-run choice of database, from the command line or the environment.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Overrides {
+    pub db_path: Option<PathBuf>,
+    /// Use an in-memory database that is gone when the app exits.
+    pub ephemeral: bool,
+}
+
+impl Overrides {
+    /// Reads `DB_PATH_ENV` and `EPHEMERAL_ENV` through `var`. Empty values
+    /// count as unset; `EPHEMERAL_ENV` is on for anything but `0`/`false`.
+    pub fn from_env(var: impl Fn(&str) -> Option<String>) -> Self {
+        let var = |name| var(name).filter(|v| !v.trim().is_empty());
+        Overrides {
+            db_path: var(DB_PATH_ENV).map(PathBuf::from),
+            ephemeral: var(EPHEMERAL_ENV)
+                .is_some_and(|v| !matches!(v.trim().to_ascii_lowercase().as_str(), "0" | "false")),
+        }
+    }
+}
+
+/// Where the database for this run came from.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DbSource {
+    Flag,
+    Env,
+    /// Moved there with `move_database`.
+    Pointer,
+    Default,
+    /// The database of a profile other than the default one.
+    Profile,
+}
+
+/// The database this run uses. `path` is `None` for an ephemeral, in-memory
+/// database.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct DbChoice {
+    pub source: DbSource,
+    pub ephemeral: bool,
+    pub path: Option<PathBuf>,
+}
+
+impl DbChoice {
+    /// Whether this is the user's own database, rather than one picked for
+    /// a single run. Only then is it backed up, moved or switched away from.
+    pub fn is_persistent_default(&self) -> bool {
+        matches!(
+            self.source,
+            DbSource::Pointer | DbSource::Default | DbSource::Profile
+        )
+    }
+
+    pub fn open(&self) -> rusqlite::Result<Connection> {
+        match &self.path {
+            Some(path) => Connection::open(path),
+            None => Connection::open_in_memory(),
+        }
+    }
+}
+
+/// Picks the database: command-line flags, then environment variables, then
+/// the pointer file, then `DEFAULT_DB_PATH`. At each level `ephemeral` wins
+/// over a path.
+pub fn choose(flags: &Overrides, env: &Overrides, pointer: Option<PathBuf>) -> DbChoice {
+    for (overrides, source) in [(flags, DbSource::Flag), (env, DbSource::Env)] {
+        if overrides.ephemeral {
+            return DbChoice {
+                source,
+                ephemeral: true,
+                path: None,
+            };
+        }
+        if let Some(path) = &overrides.db_path {
+            return DbChoice {
+                source,
+                ephemeral: false,
+                path: Some(path.clone()),
+            };
+        }
+    }
+    let (source, path) = match pointer {
+        Some(path) => (DbSource::Pointer, path),
+        None => (DbSource::Default, PathBuf::from(DEFAULT_DB_PATH)),
+    };
+    DbChoice {
+        source,
+        ephemeral: false,
+        path: Some(path),
+    }
+}
+
+/// `choose` with the real environment and pointer file.
+pub fn choose_at_startup(flags: &Overrides, app_dir: &Path) -> DbChoice {
+    let env = Overrides::from_env(|name| std::env::var(name).ok());
+    choose(flags, &env, read_pointer(app_dir))
+}
+
+/// The database of the default profile: where the pointer file says, or
+/// `DEFAULT_DB_PATH`.
+pub fn main_database(app_dir: &Path) -> DbChoice {
+    choose(
+        &Overrides::default(),
+        &Overrides::default(),
+        read_pointer(app_dir),
+    )
+}
+
+/// Writes the pointer to a temporary file and renames it over the old one,
+/// so a crash leaves either the old or the new location, never half of one.
+fn write_pointer(app_dir: &Path, db_dir: &Path) -> Result<(), String> {
+    fs::create_dir_all(app_dir).map_err(|e| e.to_string())?;
+    let tmp = app_dir.join(format!("{}.tmp", POINTER_FILE));
+    fs::write(&tmp, db_dir.to_string_lossy().as_bytes()).map_err(|e| e.to_string())?;
+    fs::rename(&tmp, pointer_path(app_dir)).map_err(|e| e.to_string())
+}
+
+/// Row count of every table, in name order.
+fn row_counts(conn: &Connection) -> Result<Vec<(String, i64)>, String> {
+    let tables: Vec<String> = conn
+        .prepare(
+            "SELECT name FROM sqlite_master
+             WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name",
+        )
+        .and_then(|mut stmt| {
+            stmt.query_map([], |row| row.get(0))?
+                .collect::<rusqlite::Result<_>>()
+        })
+        .map_err(|e| e.to_string())?;
+    tables
+        .into_iter()
+        .map(|table| {
+            let count = conn
+                .query_row(&format!("SELECT COUNT(*) FROM \"{}\"", table), [], |row| {
+                    row.get(0)
+                })
+                .map_err(|e| e.to_string())?;
+            Ok((table, count))
+        })
+        .collect()
+}
+
+/// Copies `conn` to `dest` a few pages at a time through the backup API,
+/// calling `on_step` after each step that leaves pages to copy. Reading
+/// through the connection picks up anything still in the WAL, so the
+/// `-wal`/`-shm` files never need copying themselves. Gives up once
+/// `WRITE_ATTEMPTS` steps in a row find the database busy.
+fn copy_database(
+    conn: &Connection,
+    dest: &Path,
+    mut on_step: impl FnMut(Progress) -> Result<(), String>,
+) -> Result<(), String> {
+    let mut dest = Connection::open(dest).map_err(|e| e.to_string())?;
+    let backup = Backup::new_with_names(conn, DatabaseName::Main, &mut dest, DatabaseName::Main)
+        .map_err(|e| e.to_string())?;
+    let mut busy = 0;
+    loop {
+        match backup.step(PAGES_PER_STEP).map_err(|e| e.to_string())? {
+            StepResult::Done => return Ok(()),
+            StepResult::More => {
+                busy = 0;
+                on_step(backup.progress())?;
+            }
+            StepResult::Busy | StepResult::Locked => {
+                busy += 1;
+                if busy >= WRITE_ATTEMPTS {
+                    return Err("The database stayed busy, try moving it again".to_string());
+                }
+                thread::sleep(RETRY_DELAY);
+            }
+            other => return Err(format!("Copying the database stopped: {:?}", other)),
+        }
+    }
+}
+
+/// Removes a database file together with its `-wal` and `-shm` files.
+pub fn remove_database_files(path: &Path) {
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = fs::remove_file(format!("{}{}", path.display(), suffix));
+    }
+}
+
+/// Copies the database into `new_dir`, checks the copy, points `app_dir`
+/// at it and swaps `conn` over. Until the swap nothing the app uses has
+/// changed, so any failure leaves the old database open and in place; a
+/// partial copy is removed. The old file is deleted afterwards unless
+/// `keep_old` is set. Returns the new database path.
+///
+/// An encrypted database, open under `passphrase`, is exported in one go
+/// rather than copied in steps, as SQLCipher refuses the backup API.
+pub fn move_database(
+    conn: &mut Connection,
+    app_dir: &Path,
+    new_dir: &Path,
+    keep_old: bool,
+    passphrase: Option<&str>,
+    on_step: impl FnMut(Progress) -> Result<(), String>,
+) -> Result<PathBuf, String> {
+    if !new_dir.is_dir() {
+        return Err(format!("{} is not a directory", new_dir.display()));
+    }
+    let old_path = conn.path().filter(|p| !p.is_empty()).map(PathBuf::from);
+    let new_path = new_dir.join(DB_FILE_NAME);
+    if new_path.exists() {
+        return Err(format!("{} already exists", new_path.display()));
+    }
+
+    let copy = match passphrase {
+        Some(passphrase) => {
+            encryption::export(conn, &new_path, passphrase).map_err(|e| e.to_string())
+        }
+        None => copy_database(conn, &new_path, on_step),
+    };
+    let open = |flags| match passphrase {
+        Some(passphrase) => encryption::open(&new_path, passphrase).map_err(|e| e.to_string()),
+        None => Connection::open_with_flags(&new_path, flags).map_err(|e| e.to_string()),
+    };
+    let copied = copy.and_then(|()| {
+        let copy = open(OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        if row_counts(&copy)? != row_counts(conn)? {
+            return Err("The copy doesn't match the database".to_string());
+        }
+        let reopened = open(OpenFlags::default())?;
+        init_db(&reopened);
+        write_pointer(app_dir, new_dir)?;
+        Ok(reopened)
+    });
+    let reopened = match copied {
+        Ok(reopened) => reopened,
+        Err(e) => {
+            remove_database_files(&new_path);
+            return Err(e);
+        }
+    };
+
+    // Closes the old connection.
+    *conn = reopened;
+    if let (Some(old_path), false) = (old_path, keep_old) {
+        remove_database_files(&old_path);
+    }
+    Ok(new_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db_add_todo, db_get_todos, db_set_setting};
+
+    fn open_db(path: &Path) -> Connection {
+        let conn = Connection::open(path).unwrap();
+        init_db(&conn);
+        conn
+    }
+
+    /// A database big enough to take several backup steps.
+    fn fixture(path: &Path) -> Connection {
+        let conn = open_db(path);
+        let padding = "x".repeat(2000);
+        for i in 0..200 {
+            db_add_todo(&conn, format!("Todo {} {}", i, padding), None);
+        }
+        db_set_setting(&conn, "theme", "dark");
+        conn
+    }
+
+    #[test]
+    fn test_no_pointer_without_pointer_file() {
+        let app_dir = tempfile::tempdir().unwrap();
+        assert_eq!(read_pointer(app_dir.path()), None);
+
+        fs::write(pointer_path(app_dir.path()), "  \n").unwrap();
+        assert_eq!(read_pointer(app_dir.path()), None);
+    }
+
+    #[test]
+    fn test_choose_precedence() {
+        let pointer = Some(PathBuf::from("/data/todos.db"));
+        let flag_path = Overrides {
+            db_path: Some(PathBuf::from("/tmp/e2e.db")),
+            ..Overrides::default()
+        };
+        let env_ephemeral = Overrides {
+            ephemeral: true,
+            ..Overrides::default()
+        };
+        let none = Overrides::default();
+
+        let choice = choose(&flag_path, &env_ephemeral, pointer.clone());
+        assert_eq!(choice.source, DbSource::Flag);
+        assert_eq!(choice.path, flag_path.db_path);
+
+        let choice = choose(&none, &env_ephemeral, pointer.clone());
+        assert_eq!(
+            choice,
+            DbChoice {
+                source: DbSource::Env,
+                ephemeral: true,
+                path: None
+            }
+        );
+        assert!(!choice.is_persistent_default());
+
+        let both = Overrides {
+            ephemeral: true,
+            ..flag_path.clone()
+        };
+        assert!(choose(&both, &none, None).ephemeral);
+
+        let choice = choose(&none, &none, pointer.clone());
+        assert_eq!(choice.source, DbSource::Pointer);
+        assert_eq!(choice.path, pointer);
+        assert!(choice.is_persistent_default());
+
+        let choice = choose(&none, &none, None);
+        assert_eq!(choice.source, DbSource::Default);
+        assert_eq!(choice.path, Some(PathBuf::from(DEFAULT_DB_PATH)));
+    }
+
+    #[test]
+    fn test_overrides_from_env() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            Overrides::from_env(move |name| {
+                vars.iter()
+                    .find(|(k, _)| *k == name)
+                    .map(|(_, v)| v.to_string())
+            })
+        };
+
+        assert_eq!(env(&[]), Overrides::default());
+        assert_eq!(
+            env(&[(DB_PATH_ENV, "/tmp/x.db"), (EPHEMERAL_ENV, "0")]),
+            Overrides {
+                db_path: Some(PathBuf::from("/tmp/x.db")),
+                ephemeral: false
+            }
+        );
+        assert!(env(&[(EPHEMERAL_ENV, "1")]).ephemeral);
+        assert!(!env(&[(EPHEMERAL_ENV, "False")]).ephemeral);
+        assert_eq!(env(&[(DB_PATH_ENV, " ")]).db_path, None);
+    }
+
+    #[test]
+    fn test_ephemeral_choice_opens_in_memory() {
+        let choice = choose(
+            &Overrides {
+                ephemeral: true,
+                ..Overrides::default()
+            },
+            &Overrides::default(),
+            None,
+        );
+        let conn = choice.open().unwrap();
+        assert_eq!(conn.path(), Some(""));
+    }
+
+    #[test]
+    fn test_move_between_directories() {
+        let old_dir = tempfile::tempdir().unwrap();
+        let new_dir = tempfile::tempdir().unwrap();
+        let app_dir = tempfile::tempdir().unwrap();
+        let old_path = old_dir.path().join(DB_FILE_NAME);
+        let mut conn = fixture(&old_path);
+        let before = db_get_todos(&conn);
+
+        let new_path = move_database(
+            &mut conn,
+            app_dir.path(),
+            new_dir.path(),
+            false,
+            None,
+            |_| Ok(()),
+        )
+        .unwrap();
+
+        assert_eq!(new_path, new_dir.path().join(DB_FILE_NAME));
+        assert_eq!(conn.path().map(PathBuf::from), Some(new_path.clone()));
+        assert_eq!(db_get_todos(&conn), before);
+        assert_eq!(read_pointer(app_dir.path()), Some(new_path.clone()));
+        assert!(!old_path.exists());
+
+        db_add_todo(&conn, "After the move".to_string(), None);
+        drop(conn);
+        assert_eq!(db_get_todos(&open_db(&new_path)).len(), before.len() + 1);
+    }
+
+    #[test]
+    fn test_move_can_keep_old_file() {
+        let old_dir = tempfile::tempdir().unwrap();
+        let new_dir = tempfile::tempdir().unwrap();
+        let app_dir = tempfile::tempdir().unwrap();
+        let old_path = old_dir.path().join(DB_FILE_NAME);
+        let mut conn = fixture(&old_path);
+
+        move_database(
+            &mut conn,
+            app_dir.path(),
+            new_dir.path(),
+            true,
+            None,
+            |_| Ok(()),
+        )
+        .unwrap();
+
+        assert_eq!(db_get_todos(&open_db(&old_path)).len(), 200);
+    }
+
+    #[test]
+    fn test_failure_mid_copy_keeps_old_database_in_use() {
+        let old_dir = tempfile::tempdir().unwrap();
+        let new_dir = tempfile::tempdir().unwrap();
+        let app_dir = tempfile::tempdir().unwrap();
+        let old_path = old_dir.path().join(DB_FILE_NAME);
+        let mut conn = fixture(&old_path);
+        let mut steps = 0;
+
+        let err = move_database(
+            &mut conn,
+            app_dir.path(),
+            new_dir.path(),
+            false,
+            None,
+            |p| {
+                assert!(p.remaining > 0);
+                steps += 1;
+                Err("disk full".to_string())
+            },
+        )
+        .unwrap_err();
+
+        assert_eq!(err, "disk full");
+        assert_eq!(steps, 1);
+        assert_eq!(conn.path().map(PathBuf::from), Some(old_path.clone()));
+        assert_eq!(db_get_todos(&conn).len(), 200);
+        assert!(old_path.exists());
+        assert!(!new_dir.path().join(DB_FILE_NAME).exists());
+        assert_eq!(read_pointer(app_dir.path()), None);
+    }
+
+    #[test]
+    fn test_refuses_existing_target() {
+        let old_dir = tempfile::tempdir().unwrap();
+        let new_dir = tempfile::tempdir().unwrap();
+        let app_dir = tempfile::tempdir().unwrap();
+        let mut conn = fixture(&old_dir.path().join(DB_FILE_NAME));
+        let target = new_dir.path().join(DB_FILE_NAME);
+        fs::write(&target, "someone else's data").unwrap();
+
+        assert!(move_database(
+            &mut conn,
+            app_dir.path(),
+            new_dir.path(),
+            false,
+            None,
+            |_| Ok(())
+        )
+        .is_err());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "someone else's data");
+        assert!(move_database(
+            &mut conn,
+            app_dir.path(),
+            &new_dir.path().join("missing"),
+            false,
+            None,
+            |_| Ok(())
+        )
+        .is_err());
+        assert_eq!(db_get_todos(&conn).len(), 200);
+    }
+}
@@ -0,0 +1,309 @@
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, SecondsFormat, TimeZone, Utc};
+use std::cmp::Ordering;
+
+/// Reads a stored deadline as an instant in `tz`. Deadlines are stored in
+/// one of two forms:
+///
+/// * `YYYY-MM-DD` for a date-only deadline, which lasts until the end of
+///   that day in the user's time zone, whatever zone it is read in later;
+/// * an RFC 3339 timestamp in UTC for a deadline with a time.
+///
+/// Times without an offset (`YYYY-MM-DDTHH:MM[:SS]`, what a `datetime-local`
+/// input sends) are read as local to `tz`, and other offsets are accepted
+/// too. Returns `None` for anything else, and for a local time that doesn't
+/// exist because the clocks skipped it.
+pub fn deadline_in<Tz: TimeZone>(value: &str, tz: &Tz) -> Option<DateTime<Tz>> {
+    if let Ok(dt) = DateTime::parse_from_rfc3339(value) {
+        return Some(dt.with_timezone(tz));
+    }
+    let naive = NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M"))
+        .ok()
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .ok()
+                .and_then(|d| d.and_hms_opt(23, 59, 59))
+        })?;
+    tz.from_local_datetime(&naive).earliest()
+}
+
+/// How `deadline` compares with the instant `now`: `Less` once it has
+/// passed. Every overdue and due-soon check goes through this, so they all
+/// agree on when a deadline is up.
+pub fn deadline_cmp<Tz: TimeZone>(deadline: &str, now: DateTime<Utc>, tz: &Tz) -> Option<Ordering> {
+    deadline_in(deadline, tz).map(|d| d.with_timezone(&Utc).cmp(&now))
+}
+
+/// The calendar day in `tz` that `deadline` falls on.
+pub fn deadline_day<Tz: TimeZone>(deadline: &str, tz: &Tz) -> Option<NaiveDate> {
+    deadline_in(deadline, tz).map(|d| d.date_naive())
+}
+
+/// `dt` the way a deadline with a time is stored: RFC 3339 in UTC, to the
+/// second.
+pub fn to_stored<Tz: TimeZone>(dt: DateTime<Tz>) -> String {
+    dt.with_timezone(&Utc)
+        .to_rfc3339_opts(SecondsFormat::Secs, false)
+}
+
+/// A deadline as it should be stored: dates unchanged, times converted to
+/// UTC (times without an offset being local to `tz`). `None` if it can't be
+/// read.
+pub fn normalize<Tz: TimeZone>(value: &str, tz: &Tz) -> Option<String> {
+    if NaiveDate::parse_from_str(value, "%Y-%m-%d").is_ok() {
+        return Some(value.to_string());
+    }
+    deadline_in(value, tz).map(to_stored)
+}
+
+/// `value` moved by `days` calendar days. Dates stay dates, and times keep
+/// their time of day in `tz`, across a daylight-saving change too. `None` if
+/// it can't be read or the new local time doesn't exist.
+pub fn shift_days<Tz: TimeZone>(value: &str, days: i64, tz: &Tz) -> Option<String> {
+    let delta = Duration::try_days(days)?;
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return date
+            .checked_add_signed(delta)
+            .map(|d| d.format("%Y-%m-%d").to_string());
+    }
+    let local = deadline_in(value, tz)?
+        .naive_local()
+        .checked_add_signed(delta)?;
+    tz.from_local_datetime(&local).earliest().map(to_stored)
+}
+
+/// Where a todo stands against its deadline.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DeadlineStatus {
+    /// Incomplete and the deadline has passed.
+    pub overdue: bool,
+    /// Incomplete and the deadline falls on today's date in `tz`, passed or
+    /// not.
+    pub due_today: bool,
+    /// Calendar days in `tz` from today to the deadline's day; negative once
+    /// past. Set for completed todos too.
+    pub days_until: Option<i64>,
+}
+
+pub fn status<Tz: TimeZone>(
+    deadline: Option<&str>,
+    completed: bool,
+    now: DateTime<Utc>,
+    tz: &Tz,
+) -> DeadlineStatus {
+    let Some(deadline) = deadline else {
+        return DeadlineStatus::default();
+    };
+    let today = now.with_timezone(tz).date_naive();
+    let day = deadline_day(deadline, tz);
+    DeadlineStatus {
+        overdue: !completed && deadline_cmp(deadline, now, tz) == Some(Ordering::Less),
+        due_today: !completed && day == Some(today),
+        days_until: day.map(|day| (day - today).num_days()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{FixedOffset, MappedLocalTime};
+
+    fn utc(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn offset(hours: i32) -> FixedOffset {
+        FixedOffset::east_opt(hours * 3600).unwrap()
+    }
+
+    /// Central European time around the 2024 spring change: +01:00 until
+    /// 01:00 UTC on 31 March, when local clocks skip from 02:00 to 03:00,
+    /// and +02:00 after.
+    #[derive(Debug, Clone, Copy)]
+    struct SpringForward;
+
+    impl SpringForward {
+        fn change() -> NaiveDateTime {
+            NaiveDate::from_ymd_opt(2024, 3, 31)
+                .unwrap()
+                .and_hms_opt(1, 0, 0)
+                .unwrap()
+        }
+    }
+
+    impl TimeZone for SpringForward {
+        type Offset = FixedOffset;
+
+        fn from_offset(_: &FixedOffset) -> Self {
+            SpringForward
+        }
+
+        fn offset_from_local_date(&self, local: &NaiveDate) -> MappedLocalTime<FixedOffset> {
+            self.offset_from_local_datetime(&local.and_hms_opt(0, 0, 0).unwrap())
+        }
+
+        fn offset_from_local_datetime(
+            &self,
+            local: &NaiveDateTime,
+        ) -> MappedLocalTime<FixedOffset> {
+            let skipped_from = Self::change() + chrono::Duration::hours(1);
+            if *local < skipped_from {
+                MappedLocalTime::Single(offset(1))
+            } else if *local < skipped_from + chrono::Duration::hours(1) {
+                MappedLocalTime::None
+            } else {
+                MappedLocalTime::Single(offset(2))
+            }
+        }
+
+        fn offset_from_utc_date(&self, utc: &NaiveDate) -> FixedOffset {
+            self.offset_from_utc_datetime(&utc.and_hms_opt(0, 0, 0).unwrap())
+        }
+
+        fn offset_from_utc_datetime(&self, utc: &NaiveDateTime) -> FixedOffset {
+            offset(if *utc < Self::change() { 1 } else { 2 })
+        }
+    }
+
+    #[test]
+    fn test_normalize_stores_times_in_utc() {
+        let warsaw = offset(2);
+        assert_eq!(normalize("2024-05-01", &warsaw).unwrap(), "2024-05-01");
+        assert_eq!(
+            normalize("2024-05-01T10:30", &warsaw).unwrap(),
+            "2024-05-01T08:30:00+00:00"
+        );
+        assert_eq!(
+            normalize("2024-05-01T00:30:15", &warsaw).unwrap(),
+            "2024-04-30T22:30:15+00:00"
+        );
+        assert_eq!(
+            normalize("2024-05-01T10:30:00-05:00", &warsaw).unwrap(),
+            "2024-05-01T15:30:00+00:00"
+        );
+        assert_eq!(
+            normalize("2024-05-01T15:30:00Z", &offset(-8)).unwrap(),
+            "2024-05-01T15:30:00+00:00"
+        );
+        assert_eq!(normalize("tomorrow", &warsaw), None);
+        // The hour the clocks skip doesn't exist.
+        assert_eq!(normalize("2024-03-31T02:30", &SpringForward), None);
+        assert_eq!(
+            normalize("2024-03-31T03:30", &SpringForward).unwrap(),
+            "2024-03-31T01:30:00+00:00"
+        );
+    }
+
+    #[test]
+    fn test_shift_days() {
+        let warsaw = offset(2);
+        assert_eq!(
+            shift_days("2024-05-01", 3, &warsaw).as_deref(),
+            Some("2024-05-04")
+        );
+        assert_eq!(
+            shift_days("2024-12-30", 5, &warsaw).as_deref(),
+            Some("2025-01-04")
+        );
+        assert_eq!(
+            shift_days("2024-03-01", -1, &warsaw).as_deref(),
+            Some("2024-02-29")
+        );
+        assert_eq!(
+            shift_days("2024-05-31T22:30:00+00:00", 1, &warsaw).as_deref(),
+            Some("2024-06-01T22:30:00+00:00")
+        );
+        // 09:00 local stays 09:00 local across the change to summer time.
+        assert_eq!(
+            shift_days("2024-03-30T08:00:00+00:00", 1, &SpringForward).as_deref(),
+            Some("2024-03-31T07:00:00+00:00")
+        );
+        assert_eq!(
+            shift_days("2024-03-30T01:30:00+00:00", 1, &SpringForward),
+            None
+        );
+        assert_eq!(shift_days("someday", 1, &warsaw), None);
+    }
+
+    #[test]
+    fn test_date_only_lasts_until_local_midnight() {
+        // 09:30 UTC is 23:30 on 1 May at +14 and 21:30 on 30 April at -12.
+        let now = utc("2024-05-01T09:30:00Z");
+        let status_at = |hours| status(Some("2024-05-01"), false, now, &offset(hours));
+        assert_eq!(
+            status_at(14),
+            DeadlineStatus {
+                overdue: false,
+                due_today: true,
+                days_until: Some(0),
+            }
+        );
+        assert_eq!(status_at(-12).days_until, Some(1));
+        assert!(!status_at(-12).due_today);
+
+        let kiritimati_midnight = utc("2024-05-01T10:00:00Z");
+        let after = status(Some("2024-05-01"), false, kiritimati_midnight, &offset(14));
+        assert!(after.overdue && !after.due_today);
+        assert_eq!(after.days_until, Some(-1));
+    }
+
+    #[test]
+    fn test_timed_deadline_day_depends_on_zone() {
+        let deadline = "2024-05-01T23:30:00+00:00";
+        let now = utc("2024-05-01T12:00:00Z");
+        assert!(status(Some(deadline), false, now, &offset(0)).due_today);
+        // In Tokyo it is 21:00 on 1 May and the deadline is 08:30 on 2 May.
+        let tokyo = status(Some(deadline), false, now, &offset(9));
+        assert!(!tokyo.due_today);
+        assert_eq!(tokyo.days_until, Some(1));
+        assert_eq!(
+            deadline_cmp(deadline, now, &offset(9)),
+            deadline_cmp(deadline, now, &offset(-7))
+        );
+    }
+
+    #[test]
+    fn test_dst_change_day() {
+        // 31 March has 23 hours; its end is 22:00 UTC, not 23:00.
+        let deadline = Some("2024-03-31");
+        let late = status(deadline, false, utc("2024-03-31T21:30:00Z"), &SpringForward);
+        assert!(!late.overdue && late.due_today);
+        let midnight = status(deadline, false, utc("2024-03-31T22:00:00Z"), &SpringForward);
+        assert!(midnight.overdue && !midnight.due_today);
+        assert_eq!(midnight.days_until, Some(-1));
+
+        // Just after midnight local on the 31st, before the change.
+        let early = "2024-03-30T23:30:00+00:00";
+        assert_eq!(
+            deadline_day(early, &SpringForward),
+            NaiveDate::from_ymd_opt(2024, 3, 31)
+        );
+        // And a local 03:30 straight after it.
+        assert_eq!(
+            deadline_cmp(
+                "2024-03-31T03:30",
+                utc("2024-03-31T01:30:00Z"),
+                &SpringForward
+            ),
+            Some(Ordering::Equal)
+        );
+    }
+
+    #[test]
+    fn test_completed_and_missing_deadlines() {
+        let now = utc("2024-05-01T12:00:00Z");
+        let done = status(Some("2024-04-20"), true, now, &offset(0));
+        assert!(!done.overdue && !done.due_today);
+        assert_eq!(done.days_until, Some(-11));
+        assert_eq!(
+            status(None, false, now, &offset(0)),
+            DeadlineStatus::default()
+        );
+        assert_eq!(
+            status(Some("someday"), false, now, &offset(0)),
+            DeadlineStatus::default()
+        );
+    }
+}
@@ -0,0 +1,285 @@
+use chrono::{DateTime, Datelike, Days, Local, NaiveDate, NaiveTime, Weekday};
+use serde::Serialize;
+use std::fmt;
+
+/// A deadline read from text, as stored and as shown back to the user.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct ParsedDeadline {
+    /// `YYYY-MM-DD`, or `YYYY-MM-DDTHH:MM` when a time was given.
+    pub deadline: String,
+    /// How it was understood, e.g. "Fri, 7 Jun 17:00".
+    pub display: String,
+}
+
+/// Why text couldn't be read as a deadline. Serialized as `{"kind", "message"}`
+/// like the other typed command errors.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+#[serde(tag = "kind", content = "message", rename_all = "snake_case")]
+pub enum DeadlineTextError {
+    /// There was nothing but whitespace.
+    Empty,
+    /// This word isn't understood here, or repeats a date or time already
+    /// given.
+    Unrecognized(String),
+}
+
+impl fmt::Display for DeadlineTextError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeadlineTextError::Empty => write!(f, "no deadline given"),
+            DeadlineTextError::Unrecognized(word) => {
+                write!(f, "didn't understand '{}' in the deadline", word)
+            }
+        }
+    }
+}
+
+/// The first `weekday` from `today` on, counting today unless `skip_today`.
+pub fn coming(today: NaiveDate, weekday: Weekday, skip_today: bool) -> NaiveDate {
+    let days = (7 + weekday.num_days_from_monday() - today.weekday().num_days_from_monday()) % 7;
+    let days = if days == 0 && skip_today { 7 } else { days };
+    today + Days::new(days.into())
+}
+
+/// Reads "5pm", "5:30pm", "12am" or "17:30". A bare hour without am/pm is
+/// too ambiguous to accept.
+fn parse_time(word: &str) -> Option<NaiveTime> {
+    let (clock, offset) = if let Some(clock) = word.strip_suffix("am") {
+        (clock, Some(0))
+    } else if let Some(clock) = word.strip_suffix("pm") {
+        (clock, Some(12))
+    } else {
+        (word, None)
+    };
+    let (hour, minute): (u32, u32) = match clock.split_once(':') {
+        Some((h, m)) if m.len() == 2 => (h.parse().ok()?, m.parse().ok()?),
+        None if offset.is_some() => (clock.parse().ok()?, 0),
+        _ => return None,
+    };
+    let hour = match offset {
+        Some(offset) if (1..=12).contains(&hour) => hour % 12 + offset,
+        Some(_) => return None,
+        None => hour,
+    };
+    NaiveTime::from_hms_opt(hour, minute, 0)
+}
+
+/// Days in one `unit` of "in N days" or "in N weeks".
+fn unit_days(unit: &str) -> Option<u64> {
+    match unit {
+        "day" | "days" => Some(1),
+        "week" | "weeks" => Some(7),
+        _ => None,
+    }
+}
+
+/// Reads a deadline typed as text, relative to `now`:
+///
+/// * a day: "today", "tomorrow", "in 3 days", "in a week", "in 2 weeks",
+///   a weekday such as "friday" or "fri", or a `YYYY-MM-DD` date;
+/// * a time: "5pm", "5 pm", "5:30pm", "17:30", optionally after "at".
+///
+/// Either can be left out: a day alone is a date-only deadline, a time alone
+/// is today. A bare weekday is the next one from today on, so "friday" on a
+/// Friday is today; "next friday" always skips today. Anything else is an
+/// error rather than a guess.
+pub fn parse_deadline(
+    input: &str,
+    now: DateTime<Local>,
+) -> Result<ParsedDeadline, DeadlineTextError> {
+    let lower = input.trim().to_lowercase();
+    let mut words: Vec<String> = Vec::new();
+    for word in lower.split_whitespace() {
+        match (word, words.last_mut()) {
+            ("am" | "pm", Some(last)) if last.ends_with(|c: char| c.is_ascii_digit()) => {
+                last.push_str(word)
+            }
+            _ => words.push(word.to_string()),
+        }
+    }
+    if words.is_empty() {
+        return Err(DeadlineTextError::Empty);
+    }
+
+    let today = now.date_naive();
+    let mut date = None;
+    let mut time = None;
+    let mut i = 0;
+    while i < words.len() {
+        let word = words[i].as_str();
+        let next = words.get(i + 1).map(String::as_str);
+        let unrecognized = || DeadlineTextError::Unrecognized(word.to_string());
+        let (day, used) = match word {
+            "today" => (Some(today), 1),
+            "tomorrow" => (Some(today + Days::new(1)), 1),
+            "next" => {
+                let weekday = next.and_then(|w| w.parse::<Weekday>().ok());
+                (weekday.map(|w| coming(today, w, true)), 2)
+            }
+            "in" => {
+                let count = match next {
+                    Some("a" | "an") => Some(1),
+                    Some(n) => n.parse::<u64>().ok(),
+                    None => None,
+                };
+                let unit = words.get(i + 2).and_then(|u| unit_days(u));
+                let day = count
+                    .zip(unit)
+                    .and_then(|(count, unit)| count.checked_mul(unit))
+                    .and_then(|days| today.checked_add_days(Days::new(days)));
+                (day, 3)
+            }
+            "at" if next.is_some_and(|w| parse_time(w).is_some()) => {
+                i += 1;
+                continue;
+            }
+            _ => {
+                if let Some(at) = parse_time(word) {
+                    if time.replace(at).is_some() {
+                        return Err(unrecognized());
+                    }
+                    i += 1;
+                    continue;
+                }
+                let day = word
+                    .parse::<Weekday>()
+                    .ok()
+                    .map(|w| coming(today, w, false))
+                    .or_else(|| NaiveDate::parse_from_str(word, "%Y-%m-%d").ok());
+                (day, 1)
+            }
+        };
+        match day {
+            Some(day) if date.is_none() => date = Some(day),
+            _ => return Err(unrecognized()),
+        }
+        i += used;
+    }
+
+    let date = date.unwrap_or(today);
+    Ok(match time {
+        Some(time) => {
+            let at = date.and_time(time);
+            ParsedDeadline {
+                deadline: at.format("%Y-%m-%dT%H:%M").to_string(),
+                display: at.format("%a, %-d %b %H:%M").to_string(),
+            }
+        }
+        None => ParsedDeadline {
+            deadline: date.format("%Y-%m-%d").to_string(),
+            display: date.format("%a, %-d %b").to_string(),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(y: i32, m: u32, d: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(y, m, d, 12, 0, 0).unwrap()
+    }
+
+    fn now() -> DateTime<Local> {
+        // A Wednesday.
+        at(2024, 5, 1)
+    }
+
+    fn deadline(input: &str, now: DateTime<Local>) -> String {
+        parse_deadline(input, now).unwrap().deadline
+    }
+
+    #[test]
+    fn test_relative_days() {
+        assert_eq!(deadline("today", now()), "2024-05-01");
+        assert_eq!(deadline("Tomorrow", now()), "2024-05-02");
+        assert_eq!(deadline("in 3 days", now()), "2024-05-04");
+        assert_eq!(deadline("in 1 day", now()), "2024-05-02");
+        assert_eq!(deadline("in a week", now()), "2024-05-08");
+        assert_eq!(deadline("in 2 weeks", now()), "2024-05-15");
+        assert_eq!(deadline("  IN  0 DAYS ", now()), "2024-05-01");
+    }
+
+    #[test]
+    fn test_end_of_month_and_year() {
+        assert_eq!(deadline("in 3 days", at(2024, 1, 30)), "2024-02-02");
+        assert_eq!(deadline("tomorrow", at(2024, 2, 28)), "2024-02-29");
+        assert_eq!(deadline("tomorrow", at(2023, 2, 28)), "2023-03-01");
+        assert_eq!(deadline("in 2 weeks", at(2024, 12, 25)), "2025-01-08");
+        assert_eq!(
+            deadline("tomorrow 9am", at(2024, 4, 30)),
+            "2024-05-01T09:00"
+        );
+    }
+
+    #[test]
+    fn test_weekdays() {
+        assert_eq!(deadline("friday", now()), "2024-05-03");
+        assert_eq!(deadline("fri", now()), "2024-05-03");
+        assert_eq!(deadline("next friday", now()), "2024-05-03");
+        // Earlier in the week than today rolls over to the next week.
+        assert_eq!(deadline("monday", now()), "2024-05-06");
+        assert_eq!(deadline("Sunday", at(2024, 5, 30)), "2024-06-02");
+    }
+
+    #[test]
+    fn test_weekday_named_today() {
+        let friday = at(2024, 6, 7);
+        assert_eq!(deadline("friday", friday), "2024-06-07");
+        assert_eq!(deadline("next friday", friday), "2024-06-14");
+        assert_eq!(deadline("next fri 17:00", friday), "2024-06-14T17:00");
+    }
+
+    #[test]
+    fn test_times() {
+        assert_eq!(deadline("5pm", now()), "2024-05-01T17:00");
+        assert_eq!(deadline("5 PM", now()), "2024-05-01T17:00");
+        assert_eq!(deadline("5:30am", now()), "2024-05-01T05:30");
+        assert_eq!(deadline("12am", now()), "2024-05-01T00:00");
+        assert_eq!(deadline("12pm", now()), "2024-05-01T12:00");
+        assert_eq!(deadline("17:30", now()), "2024-05-01T17:30");
+        assert_eq!(deadline("friday at 9:05", now()), "2024-05-03T09:05");
+        assert_eq!(deadline("5pm tomorrow", now()), "2024-05-02T17:00");
+        assert_eq!(deadline("2024-06-07 8am", now()), "2024-06-07T08:00");
+    }
+
+    #[test]
+    fn test_display_echo() {
+        assert_eq!(
+            parse_deadline("friday 5pm", at(2024, 6, 5)),
+            Ok(ParsedDeadline {
+                deadline: "2024-06-07T17:00".to_string(),
+                display: "Fri, 7 Jun 17:00".to_string(),
+            })
+        );
+        assert_eq!(
+            parse_deadline("tomorrow", now()).unwrap().display,
+            "Thu, 2 May"
+        );
+    }
+
+    #[test]
+    fn test_unrecognized_input_is_an_error() {
+        let unrecognized = |word: &str| Err(DeadlineTextError::Unrecognized(word.to_string()));
+        assert_eq!(parse_deadline("   ", now()), Err(DeadlineTextError::Empty));
+        assert_eq!(parse_deadline("someday", now()), unrecognized("someday"));
+        assert_eq!(parse_deadline("friday soon", now()), unrecognized("soon"));
+        assert_eq!(parse_deadline("in days", now()), unrecognized("in"));
+        assert_eq!(parse_deadline("in 3 months", now()), unrecognized("in"));
+        assert_eq!(parse_deadline("next", now()), unrecognized("next"));
+        assert_eq!(parse_deadline("5", now()), unrecognized("5"));
+        assert_eq!(parse_deadline("13pm", now()), unrecognized("13pm"));
+        assert_eq!(parse_deadline("25:00", now()), unrecognized("25:00"));
+        assert_eq!(parse_deadline("at", now()), unrecognized("at"));
+        assert_eq!(
+            parse_deadline("tomorrow friday", now()),
+            unrecognized("friday")
+        );
+        assert_eq!(parse_deadline("5pm 6pm", now()), unrecognized("6pm"));
+        assert_eq!(
+            parse_deadline("in 99999999999999999 weeks", now()),
+            unrecognized("in")
+        );
+    }
+}
@@ -0,0 +1,211 @@
+use crate::notifications::Notifier;
+use crate::{
+    create_todo, logging, max_title_chars, tray, validate_new_todo, validation, AppState, NewTodo,
+};
+use tauri::{AppHandle, Manager};
+
+/// Custom URL scheme registered for the app, e.g.
+/// `todoapp://add?title=Buy%20milk&deadline=2024-06-01`.
+pub const SCHEME: &str = "todoapp";
+const ADD_ACTION: &str = "add";
+
+/// Decodes one `application/x-www-form-urlencoded` component: `+` is a space
+/// and `%XX` a byte. The decoded bytes must be UTF-8.
+fn decode_component(raw: &str) -> Result<String, String> {
+    let mut bytes = Vec::with_capacity(raw.len());
+    let mut input = raw.bytes();
+    while let Some(b) = input.next() {
+        match b {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = [input.next(), input.next()];
+                let byte = match hex {
+                    [Some(hi), Some(lo)] => std::str::from_utf8(&[hi, lo])
+                        .ok()
+                        .and_then(|h| u8::from_str_radix(h, 16).ok()),
+                    _ => None,
+                };
+                bytes.push(byte.ok_or_else(|| format!("Bad percent-encoding in '{}'", raw))?);
+            }
+            _ => bytes.push(b),
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| format!("'{}' is not valid UTF-8", raw))
+}
+
+/// Turns a `todoapp://add?...` link into a todo, validated the same way as
+/// `add_todo`, with titles of up to `max_title_chars`. `title` is required
+/// and `deadline` optional; other parameters are ignored so links written for
+/// newer versions still work.
+pub fn parse_add_url(url: &str, max_title_chars: usize) -> Result<NewTodo, String> {
+    let rest = url
+        .split_once("://")
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case(SCHEME))
+        .map(|(_, rest)| rest)
+        .ok_or_else(|| format!("Not a {}:// link", SCHEME))?;
+    let rest = rest.split('#').next().unwrap_or_default();
+    let (action, query) = rest.split_once('?').unwrap_or((rest, ""));
+    if action.trim_end_matches('/') != ADD_ACTION {
+        return Err(format!("Unknown action '{}'", action));
+    }
+
+    let mut title = None;
+    let mut deadline = None;
+    for pair in query.split('&').filter(|p| !p.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        match decode_component(key)?.as_str() {
+            "title" => title = Some(decode_component(value)?),
+            "deadline" => deadline = Some(decode_component(value)?),
+            _ => {}
+        }
+    }
+
+    let title = title.ok_or_else(|| "The link has no title".to_string())?;
+    let new = NewTodo {
+        title,
+        deadline,
+        ..NewTodo::default()
+    };
+    validate_new_todo(new, max_title_chars).map_err(|errors| validation::describe(&errors))
+}
+
+/// Creates a todo for each link and shows it in the main window. A link that
+/// can't be used is explained in a notification.
+pub fn handle_urls(app: &AppHandle, urls: impl IntoIterator<Item = String>) {
+    if app.state::<AppState>().lock.is_locked() {
+        tracing::warn!("ignored deep links while the database is locked");
+        if let Err(e) = app.notify(
+            "Couldn't add todo from link",
+            "Unlock the database first, then open the link again.",
+        ) {
+            tracing::warn!("failed to show notification: {}", e);
+        }
+        return;
+    }
+    let max_chars = max_title_chars(&app.state::<AppState>().db.lock().unwrap());
+    for url in urls {
+        let new = match parse_add_url(&url, max_chars) {
+            Ok(new) => new,
+            Err(e) => {
+                tracing::warn!("rejected deep link {}: {}", logging::redact(&url), e);
+                if let Err(e) = app.notify("Couldn't add todo from link", &e) {
+                    tracing::warn!("failed to show notification: {}", e);
+                }
+                continue;
+            }
+        };
+        let todo = create_todo(&app.state::<AppState>(), app, new);
+        if let Err(e) = tray::reveal_todo(app, todo.id) {
+            tracing::warn!("failed to focus todo {}: {}", todo.id, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MAX_TITLE_CHARS;
+
+    #[test]
+    fn test_parses_title_and_deadline() {
+        let new = parse_add_url(
+            "todoapp://add?title=Buy%20milk&deadline=2024-06-01",
+            MAX_TITLE_CHARS,
+        )
+        .unwrap();
+
+        assert_eq!(new.title, "Buy milk");
+        assert_eq!(new.deadline.as_deref(), Some("2024-06-01"));
+        assert!(!new.completed);
+    }
+
+    #[test]
+    fn test_decoding() {
+        let new = parse_add_url(
+            "TodoApp://add/?title=Caf%C3%A9+%26+cake%3F",
+            MAX_TITLE_CHARS,
+        )
+        .unwrap();
+        assert_eq!(new.title, "Café & cake?");
+        assert_eq!(new.deadline, None);
+
+        let new = parse_add_url(
+            "todoapp://add?title=Call&deadline=2024-06-01T09%3A30",
+            MAX_TITLE_CHARS,
+        )
+        .unwrap();
+        assert_eq!(
+            new.deadline,
+            crate::deadline::normalize("2024-06-01T09:30", &chrono::Local)
+        );
+    }
+
+    #[test]
+    fn test_missing_or_blank_title() {
+        for url in [
+            "todoapp://add",
+            "todoapp://add?deadline=2024-06-01",
+            "todoapp://add?title=",
+            "todoapp://add?title=%20%20",
+        ] {
+            assert!(
+                parse_add_url(url, MAX_TITLE_CHARS).is_err(),
+                "{} should be rejected",
+                url
+            );
+        }
+    }
+
+    #[test]
+    fn test_bad_encoding() {
+        for url in [
+            "todoapp://add?title=100%",
+            "todoapp://add?title=%4",
+            "todoapp://add?title=%ZZmilk",
+            "todoapp://add?title=%FF%FE",
+        ] {
+            assert!(
+                parse_add_url(url, MAX_TITLE_CHARS).is_err(),
+                "{} should be rejected",
+                url
+            );
+        }
+    }
+
+    #[test]
+    fn test_unknown_parameters_are_ignored() {
+        let new = parse_add_url(
+            "todoapp://add?source=raycast&title=Milk&flag&priority=3#ignored",
+            MAX_TITLE_CHARS,
+        )
+        .unwrap();
+
+        assert_eq!(new.title, "Milk");
+        assert_eq!(new.priority, 0);
+    }
+
+    #[test]
+    fn test_long_title_rejected() {
+        let ok = format!("todoapp://add?title={}", "x".repeat(MAX_TITLE_CHARS));
+        let too_long = format!("todoapp://add?title={}", "x".repeat(MAX_TITLE_CHARS + 1));
+
+        assert!(parse_add_url(&ok, MAX_TITLE_CHARS).is_ok());
+        assert!(parse_add_url(&too_long, MAX_TITLE_CHARS)
+            .unwrap_err()
+            .contains("limit"));
+    }
+
+    #[test]
+    fn test_invalid_deadline_rejected() {
+        let err =
+            parse_add_url("todoapp://add?title=Milk&deadline=soon", MAX_TITLE_CHARS).unwrap_err();
+        assert!(err.contains("soon"));
+    }
+
+    #[test]
+    fn test_wrong_scheme_or_action() {
+        assert!(parse_add_url("https://add?title=Milk", MAX_TITLE_CHARS).is_err());
+        assert!(parse_add_url("todoapp://delete?title=Milk", MAX_TITLE_CHARS).is_err());
+        assert!(parse_add_url("not a url", MAX_TITLE_CHARS).is_err());
+    }
+}
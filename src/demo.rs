@@ -0,0 +1,129 @@
+use crate::{db_add_tag, db_count_todos, db_insert_todo, NewTodo, MAX_PRIORITY};
+use chrono::{DateTime, Duration as ChronoDuration, Local};
+use rusqlite::Connection;
+
+/// Seeding refuses to add to a database holding more todos than this,
+/// since they are probably real ones, unless forced.
+pub const MAX_EXISTING_TODOS: u64 = 10;
+
+const VERBS: [&str; 8] = [
+    "Write", "Review", "Call", "Buy", "Fix", "Plan", "Email", "Clean",
+];
+const OBJECTS: [&str; 9] = [
+    "quarterly report",
+    "dentist",
+    "groceries",
+    "leaking tap",
+    "team offsite",
+    "landlord",
+    "garage",
+    "release notes",
+    "birthday present",
+];
+/// Days from today for each seeded deadline, cycled through; `None` leaves
+/// the todo unscheduled.
+const DEADLINE_OFFSETS: [Option<i64>; 10] = [
+    Some(-5),
+    Some(0),
+    Some(1),
+    None,
+    Some(-1),
+    Some(7),
+    Some(0),
+    Some(3),
+    None,
+    Some(14),
+];
+const TAGS: [&str; 3] = ["work", "home", "errands"];
+
+/// The `i`th demo todo and the tag it gets, if any. Picked by cycling
+/// through lists of different lengths, so the mix looks varied but is the
+/// same on every run.
+fn demo_todo(i: usize, now: DateTime<Local>) -> (NewTodo, Option<&'static str>) {
+    let title = format!("{} {}", VERBS[i % VERBS.len()], OBJECTS[i % OBJECTS.len()]);
+    let deadline = DEADLINE_OFFSETS[i % DEADLINE_OFFSETS.len()].map(|days| {
+        (now + ChronoDuration::days(days))
+            .format("%Y-%m-%d")
+            .to_string()
+    });
+    let todo = NewTodo {
+        title,
+        completed: i % 4 == 3,
+        deadline,
+        priority: (i % (MAX_PRIORITY as usize + 1)) as u8,
+        ..NewTodo::default()
+    };
+    (todo, (i % 5 != 4).then(|| TAGS[i % TAGS.len()]))
+}
+
+/// Inserts `count` demo todos in one transaction and returns how many were
+/// created. Refuses if the database already holds more than
+/// `MAX_EXISTING_TODOS` todos, unless `force` is set.
+pub fn seed_demo_data(
+    conn: &Connection,
+    count: u32,
+    force: bool,
+    now: DateTime<Local>,
+) -> Result<usize, String> {
+    let existing = db_count_todos(conn);
+    if existing > MAX_EXISTING_TODOS && !force {
+        return Err(format!(
+            "The database already has {} todos; pass force to seed anyway",
+            existing
+        ));
+    }
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    for i in 0..count as usize {
+        let (todo, tag) = demo_todo(i, now);
+        let id = db_insert_todo(&tx, todo).map_err(|e| e.to_string())?.id;
+        if let Some(tag) = tag {
+            db_add_tag(&tx, id, tag);
+        }
+    }
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(count as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db_get_due_today, db_get_overdue_todos, db_get_todos, setup_test_db};
+    use chrono::TimeZone;
+
+    fn now() -> DateTime<Local> {
+        Local.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_seed_covers_every_bucket() {
+        let conn = setup_test_db();
+
+        assert_eq!(seed_demo_data(&conn, 40, false, now()), Ok(40));
+
+        let todos = db_get_todos(&conn);
+        assert_eq!(todos.len(), 40);
+        let completed = todos.iter().filter(|t| t.completed).count();
+        assert!(completed > 0 && completed < 20);
+        assert!(db_get_overdue_todos(&conn, now()).len() >= 4);
+        assert!(db_get_due_today(&conn, now()).len() >= 4);
+        assert!(todos.iter().any(|t| t.deadline.is_none()));
+        assert!(todos.iter().any(|t| t.priority == MAX_PRIORITY));
+        let mut titles: Vec<&str> = todos.iter().map(|t| t.title.as_str()).collect();
+        titles.sort();
+        titles.dedup();
+        assert_eq!(titles.len(), 40);
+    }
+
+    #[test]
+    fn test_seed_refuses_populated_database() {
+        let conn = setup_test_db();
+        seed_demo_data(&conn, MAX_EXISTING_TODOS as u32, false, now()).unwrap();
+        // At the limit it still seeds.
+        seed_demo_data(&conn, 1, false, now()).unwrap();
+
+        assert!(seed_demo_data(&conn, 5, false, now()).is_err());
+        assert_eq!(db_count_todos(&conn), MAX_EXISTING_TODOS + 1);
+        assert_eq!(seed_demo_data(&conn, 5, true, now()), Ok(5));
+        assert_eq!(db_count_todos(&conn), MAX_EXISTING_TODOS + 6);
+    }
+}
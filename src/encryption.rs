@@ -0,0 +1,460 @@
+use rusqlite::{Connection, DatabaseName, ErrorCode};
+use serde::Serialize;
+use std::fmt;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// Whether this build can encrypt; SQLCipher is only compiled in with the
+/// `sqlcipher` feature.
+pub const SUPPORTED: bool = cfg!(feature = "sqlcipher");
+
+/// The commands that still run while the database is locked.
+pub const ALLOWED_WHILE_LOCKED: &[&str] = &[
+    "unlock_database",
+    "get_encryption_status",
+    "list_profiles",
+    "switch_profile",
+];
+
+/// How every plaintext SQLite file starts. An encrypted one starts with its
+/// salt instead, so it looks like noise.
+const PLAINTEXT_HEADER: &[u8; 16] = b"SQLite format 3\0";
+/// SQLCipher's default page size. An encrypted file is a whole number of
+/// pages long.
+const CIPHER_PAGE_SIZE: usize = 4096;
+
+/// Why an encryption command failed. Serialized as `{"kind", "message"}`
+/// like the other typed command errors.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+#[serde(tag = "kind", content = "message", rename_all = "snake_case")]
+pub enum EncryptionError {
+    /// The database is encrypted and `unlock_database` hasn't succeeded yet.
+    Locked,
+    WrongPassphrase,
+    EmptyPassphrase,
+    /// This build doesn't have the `sqlcipher` feature.
+    NotSupported,
+    /// The database only lives in memory, so there is no file to encrypt.
+    InMemory,
+    AlreadyEncrypted,
+    NotEncrypted,
+    /// Anything else, with SQLite's or the file system's message.
+    Failed(String),
+}
+
+impl fmt::Display for EncryptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncryptionError::Locked => write!(f, "the database is locked"),
+            EncryptionError::WrongPassphrase => write!(f, "wrong passphrase"),
+            EncryptionError::EmptyPassphrase => write!(f, "the passphrase is empty"),
+            EncryptionError::NotSupported => {
+                write!(f, "this build was made without database encryption")
+            }
+            EncryptionError::InMemory => write!(f, "an in-memory database can't be encrypted"),
+            EncryptionError::AlreadyEncrypted => write!(f, "the database is already encrypted"),
+            EncryptionError::NotEncrypted => write!(f, "the database isn't encrypted"),
+            EncryptionError::Failed(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for EncryptionError {
+    fn from(e: rusqlite::Error) -> Self {
+        EncryptionError::Failed(e.to_string())
+    }
+}
+
+impl From<std::io::Error> for EncryptionError {
+    fn from(e: std::io::Error) -> Self {
+        EncryptionError::Failed(e.to_string())
+    }
+}
+
+/// Holds commands back until an encrypted database is unlocked, and keeps
+/// the passphrase it was opened with, which its copies are encrypted with.
+#[derive(Default)]
+pub struct Lock {
+    locked: AtomicBool,
+    passphrase: Mutex<Option<String>>,
+}
+
+impl Lock {
+    pub fn new(locked: bool) -> Self {
+        Lock {
+            locked: AtomicBool::new(locked),
+            passphrase: Mutex::default(),
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Acquire)
+    }
+
+    /// Unlocked, with the database now open under `passphrase`.
+    pub fn unlock(&self, passphrase: &str) {
+        self.remember(Some(passphrase));
+        self.locked.store(false, Ordering::Release);
+    }
+
+    /// Locked or not, for a database just switched to. Its passphrase isn't
+    /// known until it is unlocked.
+    pub fn set(&self, locked: bool) {
+        self.remember(None);
+        self.locked.store(locked, Ordering::Release);
+    }
+
+    /// Notes the passphrase the open database is encrypted with, after
+    /// encrypting it or changing it.
+    pub fn remember(&self, passphrase: Option<&str>) {
+        *self.passphrase.lock().unwrap() = passphrase.map(str::to_string);
+    }
+
+    /// The passphrase of the open database; `None` for a plaintext one.
+    pub fn passphrase(&self) -> Option<String> {
+        self.passphrase.lock().unwrap().clone()
+    }
+
+    /// `Locked` if `command` has to wait for the database to be unlocked.
+    pub fn check(&self, command: &str) -> Result<(), EncryptionError> {
+        if self.is_locked() && !ALLOWED_WHILE_LOCKED.contains(&command) {
+            Err(EncryptionError::Locked)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Whether the file at `path` is an encrypted database rather than a
+/// damaged one. Neither starts like a plaintext database, so this takes a
+/// whole first page that looks like noise: no byte value in it comes up
+/// more than four times its fair share, where a plaintext page, damaged or
+/// not, is mostly zeros. Always false without the `sqlcipher` feature,
+/// where such a file is just damaged.
+pub fn is_encrypted(path: &Path) -> bool {
+    if !SUPPORTED {
+        return false;
+    }
+    let Ok(mut file) = fs::File::open(path) else {
+        return false;
+    };
+    let len = file.metadata().map(|m| m.len()).unwrap_or(0);
+    if len == 0 || !len.is_multiple_of(CIPHER_PAGE_SIZE as u64) {
+        return false;
+    }
+    let mut page = vec![0; CIPHER_PAGE_SIZE];
+    if file.read_exact(&mut page).is_err() {
+        return false;
+    }
+    page[..PLAINTEXT_HEADER.len()] != PLAINTEXT_HEADER[..] && looks_random(&page)
+}
+
+fn looks_random(bytes: &[u8]) -> bool {
+    let mut counts = [0usize; 256];
+    for &byte in bytes {
+        counts[usize::from(byte)] += 1;
+    }
+    let fair = bytes.len() / counts.len();
+    counts.iter().all(|&n| n <= fair * 4)
+}
+
+fn check_passphrase(passphrase: &str) -> Result<(), EncryptionError> {
+    if !SUPPORTED {
+        Err(EncryptionError::NotSupported)
+    } else if passphrase.is_empty() {
+        Err(EncryptionError::EmptyPassphrase)
+    } else {
+        Ok(())
+    }
+}
+
+/// Opens the encrypted database at `path`. SQLCipher turns the passphrase
+/// into the key itself, with PBKDF2-HMAC-SHA512 over a random salt kept at
+/// the start of the file, so the same passphrase gives a different key in
+/// every file. The key is only checked on the first read, which happens
+/// here.
+pub fn open(path: &Path, passphrase: &str) -> Result<Connection, EncryptionError> {
+    check_passphrase(passphrase)?;
+    let conn = Connection::open(path)?;
+    conn.pragma_update(None, "key", passphrase)?;
+    match conn.query_row("SELECT count(*) FROM sqlite_master", [], |_| Ok(())) {
+        Ok(()) => Ok(conn),
+        Err(e) if e.sqlite_error_code() == Some(ErrorCode::NotADatabase) => {
+            Err(EncryptionError::WrongPassphrase)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Where `rewrite` builds the new file, next to the old one so the swap is a
+/// rename within one file system.
+fn staging_path(path: &Path) -> PathBuf {
+    let mut staging = path.as_os_str().to_os_string();
+    staging.push(".encrypting");
+    PathBuf::from(staging)
+}
+
+/// Copies everything `conn` holds into a new file encrypted with
+/// `passphrase` and swaps it in for `path`, then points `conn` at it. Until
+/// the rename the old file is untouched, so a failure leaves it as it was.
+///
+/// `conn` is a fresh connection afterwards, and may be one on failure too,
+/// so the caller sets it up again either way.
+fn rewrite(
+    conn: &mut Connection,
+    path: &Path,
+    old_passphrase: Option<&str>,
+    passphrase: &str,
+) -> Result<(), EncryptionError> {
+    let staging = stage(conn, path, passphrase)?;
+    swap_in(conn, path, &staging, old_passphrase, passphrase)
+}
+
+/// Replaces the encrypted database `conn` has open with everything `source`
+/// holds, encrypted with the same `passphrase`. As with `rewrite`, the old
+/// file is untouched until the copy is complete and checked.
+pub fn replace_with(
+    conn: &mut Connection,
+    source: &Connection,
+    passphrase: &str,
+) -> Result<(), EncryptionError> {
+    let path = conn
+        .path()
+        .filter(|p| !p.is_empty())
+        .map(PathBuf::from)
+        .ok_or(EncryptionError::InMemory)?;
+    let staging = stage(source, &path, passphrase)?;
+    swap_in(conn, &path, &staging, Some(passphrase), passphrase)
+}
+
+/// Exports `source` to the staging file next to `path` and checks the copy.
+fn stage(source: &Connection, path: &Path, passphrase: &str) -> Result<PathBuf, EncryptionError> {
+    let staging = staging_path(path);
+    if staging.exists() {
+        fs::remove_file(&staging)?;
+    }
+    let exported = export(source, &staging, passphrase).and_then(|()| {
+        crate::recovery::quick_check(&open(&staging, passphrase)?).map_err(EncryptionError::Failed)
+    });
+    match exported {
+        Ok(()) => Ok(staging),
+        Err(e) => {
+            let _ = fs::remove_file(&staging);
+            Err(e)
+        }
+    }
+}
+
+/// Closes `conn`, renames `staging` over `path` and opens the result. If
+/// the rename fails, `conn` is opened on the old file again.
+fn swap_in(
+    conn: &mut Connection,
+    path: &Path,
+    staging: &Path,
+    old_passphrase: Option<&str>,
+    passphrase: &str,
+) -> Result<(), EncryptionError> {
+    // The old connection has to let go of the file before it can be replaced.
+    let old = std::mem::replace(conn, Connection::open_in_memory()?);
+    if let Err((old, e)) = old.close() {
+        *conn = old;
+        let _ = fs::remove_file(staging);
+        return Err(e.into());
+    }
+    let swapped = fs::rename(staging, path);
+    let reopened = match (&swapped, old_passphrase) {
+        (Ok(()), _) => open(path, passphrase),
+        (Err(_), Some(old)) => open(path, old),
+        (Err(_), None) => Connection::open(path).map_err(EncryptionError::from),
+    };
+    *conn = reopened?;
+    swapped.map_err(|e| {
+        let _ = fs::remove_file(staging);
+        e.into()
+    })
+}
+
+/// Writes a copy of `conn` to `dest`, a new file, encrypted with
+/// `passphrase`. `sqlcipher_export` copies the schema and rows but not
+/// `user_version`, which the migrations go by, so that is carried over by
+/// hand.
+pub fn export(conn: &Connection, dest: &Path, passphrase: &str) -> Result<(), EncryptionError> {
+    let user_version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    conn.execute(
+        "ATTACH DATABASE ?1 AS encrypted KEY ?2",
+        (dest.to_string_lossy(), passphrase),
+    )?;
+    let exported = conn
+        .query_row("SELECT sqlcipher_export('encrypted')", [], |_| Ok(()))
+        .and_then(|()| {
+            conn.pragma_update(
+                Some(DatabaseName::Attached("encrypted")),
+                "user_version",
+                user_version,
+            )
+        });
+    conn.execute("DETACH DATABASE encrypted", [])?;
+    Ok(exported?)
+}
+
+/// Encrypts the plaintext database at `path`, which `conn` has open, with
+/// `passphrase`. Backups made before stay as they were.
+pub fn enable_encryption(
+    conn: &mut Connection,
+    path: Option<&Path>,
+    passphrase: &str,
+) -> Result<(), EncryptionError> {
+    check_passphrase(passphrase)?;
+    let path = path.ok_or(EncryptionError::InMemory)?;
+    if is_encrypted(path) {
+        return Err(EncryptionError::AlreadyEncrypted);
+    }
+    rewrite(conn, path, None, passphrase)
+}
+
+/// Re-encrypts the database at `path` with `new`, once `current` is shown to
+/// be the passphrase it has now.
+pub fn change_passphrase(
+    conn: &mut Connection,
+    path: Option<&Path>,
+    current: &str,
+    new: &str,
+) -> Result<(), EncryptionError> {
+    check_passphrase(new)?;
+    let path = path.ok_or(EncryptionError::InMemory)?;
+    if !is_encrypted(path) {
+        return Err(EncryptionError::NotEncrypted);
+    }
+    drop(open(path, current)?);
+    rewrite(conn, path, Some(current), new)
+}
+
+#[cfg(all(test, feature = "sqlcipher"))]
+mod tests {
+    use super::*;
+
+    fn plaintext_db(path: &Path) -> Connection {
+        let conn = Connection::open(path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE todos (id INTEGER PRIMARY KEY, title TEXT NOT NULL);
+             INSERT INTO todos (title) VALUES ('secret plans');
+             PRAGMA user_version = 7;",
+        )
+        .unwrap();
+        conn
+    }
+
+    fn titles(conn: &Connection) -> Vec<String> {
+        let mut stmt = conn.prepare("SELECT title FROM todos").unwrap();
+        stmt.query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_encrypt_and_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("todos.db");
+        let mut conn = plaintext_db(&path);
+        assert!(!is_encrypted(&path));
+
+        enable_encryption(&mut conn, Some(&path), "correct horse").unwrap();
+        assert_eq!(titles(&conn), ["secret plans"]);
+        drop(conn);
+        assert!(is_encrypted(&path));
+        assert!(!staging_path(&path).exists());
+        let raw = fs::read(&path).unwrap();
+        assert!(!raw.windows(12).any(|w| w == b"secret plans"));
+
+        let reopened = open(&path, "correct horse").unwrap();
+        assert_eq!(titles(&reopened), ["secret plans"]);
+        let version: i64 = reopened
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, 7);
+        assert_eq!(
+            open(&path, "wrong horse").unwrap_err(),
+            EncryptionError::WrongPassphrase
+        );
+        assert!(Connection::open(&path)
+            .unwrap()
+            .query_row("SELECT count(*) FROM sqlite_master", [], |_| Ok(()))
+            .is_err());
+
+        let mut conn = reopened;
+        assert_eq!(
+            enable_encryption(&mut conn, Some(&path), "again").unwrap_err(),
+            EncryptionError::AlreadyEncrypted
+        );
+    }
+
+    #[test]
+    fn test_damaged_file_is_not_taken_for_encrypted() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("todos.db");
+        drop(plaintext_db(&path));
+        let mut damaged = fs::read(&path).unwrap();
+        damaged[..16].copy_from_slice(b"garbage garbage!");
+        fs::write(&path, &damaged).unwrap();
+        assert!(!is_encrypted(&path));
+
+        fs::write(&path, "definitely not a database").unwrap();
+        assert!(!is_encrypted(&path));
+        fs::write(&path, vec![0; CIPHER_PAGE_SIZE * 2]).unwrap();
+        assert!(!is_encrypted(&path));
+
+        let mut conn = plaintext_db(&dir.path().join("other.db"));
+        enable_encryption(&mut conn, Some(&dir.path().join("other.db")), "pass").unwrap();
+        assert!(is_encrypted(&dir.path().join("other.db")));
+    }
+
+    #[test]
+    fn test_change_passphrase() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("todos.db");
+        let mut conn = plaintext_db(&path);
+        assert_eq!(
+            change_passphrase(&mut conn, Some(&path), "old", "new").unwrap_err(),
+            EncryptionError::NotEncrypted
+        );
+        enable_encryption(&mut conn, Some(&path), "old").unwrap();
+
+        assert_eq!(
+            change_passphrase(&mut conn, Some(&path), "guess", "new").unwrap_err(),
+            EncryptionError::WrongPassphrase
+        );
+        assert_eq!(
+            change_passphrase(&mut conn, Some(&path), "old", "").unwrap_err(),
+            EncryptionError::EmptyPassphrase
+        );
+        change_passphrase(&mut conn, Some(&path), "old", "new").unwrap();
+        assert_eq!(titles(&conn), ["secret plans"]);
+        drop(conn);
+        assert_eq!(
+            open(&path, "old").unwrap_err(),
+            EncryptionError::WrongPassphrase
+        );
+        assert_eq!(titles(&open(&path, "new").unwrap()), ["secret plans"]);
+    }
+
+    #[test]
+    fn test_lock_lets_only_unlock_through() {
+        let lock = Lock::new(true);
+        assert_eq!(lock.check("get_todos"), Err(EncryptionError::Locked));
+        assert_eq!(lock.check("unlock_database"), Ok(()));
+        assert_eq!(lock.check("get_encryption_status"), Ok(()));
+        lock.unlock("hunter2");
+        assert_eq!(lock.check("get_todos"), Ok(()));
+        assert_eq!(lock.passphrase().as_deref(), Some("hunter2"));
+        lock.set(false);
+        assert_eq!(lock.passphrase(), None);
+        assert_eq!(
+            serde_json::to_value(EncryptionError::Locked).unwrap(),
+            serde_json::json!({"kind": "locked"})
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, Runtime};
+
+/// Emitted after the todo list changed, so every open window can refresh.
+pub const TODOS_CHANGED_EVENT: &str = "todos-changed";
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Added,
+    Updated,
+    Deleted,
+    /// The list as a whole was replaced (restore, reset, undo); `ids` is empty
+    /// and listeners should reload everything.
+    Reloaded,
+}
+
+/// Payload of `TODOS_CHANGED_EVENT`.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct TodosChanged {
+    pub kind: ChangeKind,
+    pub ids: Vec<u64>,
+}
+
+impl TodosChanged {
+    pub fn added(ids: Vec<u64>) -> Self {
+        TodosChanged {
+            kind: ChangeKind::Added,
+            ids,
+        }
+    }
+
+    pub fn updated(ids: Vec<u64>) -> Self {
+        TodosChanged {
+            kind: ChangeKind::Updated,
+            ids,
+        }
+    }
+
+    pub fn deleted(ids: Vec<u64>) -> Self {
+        TodosChanged {
+            kind: ChangeKind::Deleted,
+            ids,
+        }
+    }
+
+    pub fn reloaded() -> Self {
+        TodosChanged {
+            kind: ChangeKind::Reloaded,
+            ids: Vec::new(),
+        }
+    }
+}
+
+/// Where change notifications go. The app sends them to its windows; tests
+/// record them.
+pub trait ChangeEmitter {
+    fn emit_changed(&self, change: &TodosChanged) -> Result<(), String>;
+}
+
+impl<R: Runtime> ChangeEmitter for AppHandle<R> {
+    fn emit_changed(&self, change: &TodosChanged) -> Result<(), String> {
+        self.emit(TODOS_CHANGED_EVENT, change)
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+pub mod test_emitter {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Keeps every emitted change so tests can assert on them.
+    #[derive(Default)]
+    pub struct RecordingEmitter {
+        changes: Mutex<Vec<TodosChanged>>,
+    }
+
+    impl RecordingEmitter {
+        pub fn take(&self) -> Vec<TodosChanged> {
+            std::mem::take(&mut self.changes.lock().unwrap())
+        }
+    }
+
+    impl ChangeEmitter for RecordingEmitter {
+        fn emit_changed(&self, change: &TodosChanged) -> Result<(), String> {
+            self.changes.lock().unwrap().push(change.clone());
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payload_serializes_kind_and_ids() {
+        let json = serde_json::to_string(&TodosChanged::deleted(vec![3, 4])).unwrap();
+        assert_eq!(json, r#"{"kind":"deleted","ids":[3,4]}"#);
+
+        let json = serde_json::to_string(&TodosChanged::reloaded()).unwrap();
+        assert_eq!(json, r#"{"kind":"reloaded","ids":[]}"#);
+    }
+}
@@ -0,0 +1,679 @@
+use crate::{db_query_todos, parse_deadline, Todo, TodoQuery};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, Utc};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::Path;
+
+const CSV_HEADER: [&str; 8] = [
+    "id",
+    "title",
+    "completed",
+    "created_at",
+    "deadline",
+    "description",
+    "priority",
+    "completed_at",
+];
+
+fn csv_record(todo: &Todo) -> [String; 8] {
+    [
+        todo.id.to_string(),
+        todo.title.clone(),
+        todo.completed.to_string(),
+        todo.created_at.clone(),
+        todo.deadline.clone().unwrap_or_default(),
+        todo.description.clone().unwrap_or_default(),
+        todo.priority.to_string(),
+        todo.completed_at.clone().unwrap_or_default(),
+    ]
+}
+
+/// Writes `todos` as RFC 4180 CSV with a header row. The header is always
+/// written, so an empty list still produces a valid file.
+fn write_csv<W: Write>(todos: &[Todo], out: W) -> csv::Result<()> {
+    let mut writer = csv::Writer::from_writer(out);
+    writer.write_record(CSV_HEADER)?;
+    for todo in todos {
+        writer.write_record(csv_record(todo))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Exports the todos matching `query` to a CSV file at `path`, returning how
+/// many rows were written. An empty selection still writes the header.
+pub fn export_csv(
+    conn: &Connection,
+    path: &Path,
+    query: &TodoQuery,
+    now: DateTime<Local>,
+) -> Result<usize, String> {
+    let todos = db_query_todos(conn, query, now);
+    let file = std::fs::File::create(path).map_err(|e| e.to_string())?;
+    write_csv(&todos, file).map_err(|e| e.to_string())?;
+    Ok(todos.len())
+}
+
+/// Which iCalendar component each todo becomes.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum IcsComponent {
+    #[default]
+    Todo,
+    Event,
+}
+
+const ICS_LINE_LIMIT: usize = 75;
+
+/// Escapes a TEXT value per RFC 5545 section 3.3.11.
+fn ics_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Appends `line` to `out`, folded so that no physical line exceeds 75 octets.
+/// Continuation lines start with a single space and folds never split a
+/// multi-byte character.
+fn push_folded(out: &mut String, line: &str) {
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > ICS_LINE_LIMIT {
+            out.push_str("\r\n ");
+            width = 1;
+        }
+        out.push(c);
+        width += c.len_utf8();
+    }
+    out.push_str("\r\n");
+}
+
+/// Formats a deadline as a property suffix: `;VALUE=DATE:20240501` for
+/// date-only deadlines, `:20240501T083000Z` (UTC) otherwise.
+fn ics_date_value(deadline: &str) -> Option<String> {
+    if let Ok(date) = NaiveDate::parse_from_str(deadline, "%Y-%m-%d") {
+        return Some(format!(";VALUE=DATE:{}", date.format("%Y%m%d")));
+    }
+    let due = parse_deadline(deadline)?.with_timezone(&Utc);
+    Some(format!(":{}", due.format("%Y%m%dT%H%M%SZ")))
+}
+
+/// Renders a VCALENDAR with one component per todo that has a parseable
+/// deadline, leaving out completed todos unless `include_completed` is set.
+/// Included completed todos carry `STATUS:COMPLETED` as VTODOs; RFC 5545
+/// has no such status for VEVENT, so events get none.
+fn render_ics(
+    todos: &[Todo],
+    component: IcsComponent,
+    include_completed: bool,
+    now: DateTime<Utc>,
+) -> String {
+    let (name, date_property) = match component {
+        IcsComponent::Todo => ("VTODO", "DUE"),
+        IcsComponent::Event => ("VEVENT", "DTSTART"),
+    };
+    let stamp = now.format("%Y%m%dT%H%M%SZ");
+
+    let mut out = String::new();
+    push_folded(&mut out, "BEGIN:VCALENDAR");
+    push_folded(&mut out, "VERSION:2.0");
+    push_folded(&mut out, "PRODID:-//tauri_basic_example//Todo List//EN");
+    for todo in todos.iter().filter(|t| include_completed || !t.completed) {
+        let Some(date) = todo.deadline.as_deref().and_then(ics_date_value) else {
+            continue;
+        };
+        push_folded(&mut out, &format!("BEGIN:{}", name));
+        push_folded(
+            &mut out,
+            &format!("UID:todo-{}@tauri_basic_example", todo.id),
+        );
+        push_folded(&mut out, &format!("DTSTAMP:{}", stamp));
+        push_folded(&mut out, &format!("SUMMARY:{}", ics_escape(&todo.title)));
+        if let Some(description) = todo.description.as_deref().filter(|d| !d.is_empty()) {
+            push_folded(
+                &mut out,
+                &format!("DESCRIPTION:{}", ics_escape(description)),
+            );
+        }
+        push_folded(&mut out, &format!("{}{}", date_property, date));
+        if todo.completed && component == IcsComponent::Todo {
+            push_folded(&mut out, "STATUS:COMPLETED");
+            let completed_at = todo
+                .completed_at
+                .as_deref()
+                .and_then(|at| DateTime::parse_from_rfc3339(at).ok());
+            if let Some(at) = completed_at {
+                let at = at.with_timezone(&Utc).format("%Y%m%dT%H%M%SZ");
+                push_folded(&mut out, &format!("COMPLETED:{}", at));
+            }
+        }
+        push_folded(&mut out, &format!("END:{}", name));
+    }
+    push_folded(&mut out, "END:VCALENDAR");
+    out
+}
+
+/// Writes the deadlines of the todos matching `query` to an `.ics` file at
+/// `path`. Completed todos are only written with `include_completed`.
+pub fn export_ics(
+    conn: &Connection,
+    path: &Path,
+    component: IcsComponent,
+    include_completed: bool,
+    query: &TodoQuery,
+) -> Result<(), String> {
+    let todos = db_query_todos(conn, query, Local::now());
+    let ics = render_ics(&todos, component, include_completed, Utc::now());
+    std::fs::write(path, ics).map_err(|e| e.to_string())
+}
+
+/// Backslash-escapes characters that Markdown would otherwise interpret.
+fn markdown_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(
+            c,
+            '\\' | '`' | '*' | '_' | '[' | ']' | '#' | '<' | '>' | '~' | '|'
+        ) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Formats a deadline for display: dates stay `YYYY-MM-DD`, datetimes become
+/// `YYYY-MM-DD HH:MM` in local time. Unrecognized values are shown verbatim.
+fn display_deadline(deadline: &str) -> String {
+    if NaiveDate::parse_from_str(deadline, "%Y-%m-%d").is_ok() {
+        return deadline.to_string();
+    }
+    if let Ok(dt) = NaiveDateTime::parse_from_str(deadline, "%Y-%m-%dT%H:%M") {
+        return dt.format("%Y-%m-%d %H:%M").to_string();
+    }
+    match parse_deadline(deadline) {
+        Some(dt) => dt.format("%Y-%m-%d %H:%M").to_string(),
+        None => deadline.to_string(),
+    }
+}
+
+/// Renders one checklist item. The description, if any, follows as an
+/// indented block so it stays attached to the item.
+fn markdown_item(todo: &Todo) -> String {
+    let check = if todo.completed { "x" } else { " " };
+    let mut item = format!("- [{}] {}", check, markdown_escape(&todo.title));
+    if let Some(deadline) = todo.deadline.as_deref().filter(|d| !d.is_empty()) {
+        item.push_str(&format!(" (due {})", display_deadline(deadline)));
+    }
+    if let Some(description) = todo.description.as_deref().filter(|d| !d.is_empty()) {
+        item.push('\n');
+        for line in description.lines() {
+            item.push('\n');
+            if !line.is_empty() {
+                item.push_str("    ");
+                item.push_str(&markdown_escape(line));
+            }
+        }
+    }
+    item
+}
+
+/// Renders todos as a Markdown checklist with a section for pending and one
+/// for completed items. Empty sections are left out.
+fn render_markdown(todos: &[Todo]) -> String {
+    let mut out = String::new();
+    for (heading, completed) in [("Pending", false), ("Completed", true)] {
+        let items: Vec<String> = todos
+            .iter()
+            .filter(|t| t.completed == completed)
+            .map(markdown_item)
+            .collect();
+        if items.is_empty() {
+            continue;
+        }
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out.push_str(&format!("## {}\n\n", heading));
+        for item in items {
+            out.push_str(&item);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// How `export_markdown` lays out the todos.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MarkdownStyle {
+    /// Pending and Completed sections, with escaped text and descriptions.
+    #[default]
+    Sections,
+    /// One flat checklist for pasting into another document. Titles are
+    /// kept exactly as typed, so Markdown in them renders, and descriptions
+    /// are left out.
+    Checklist,
+}
+
+/// Renders todos as a single checklist in their stored order.
+fn render_checklist(todos: &[Todo]) -> String {
+    let mut out = String::new();
+    for todo in todos {
+        let check = if todo.completed { "x" } else { " " };
+        out.push_str(&format!("- [{}] {}", check, todo.title));
+        if let Some(deadline) = todo.deadline.as_deref().filter(|d| !d.is_empty()) {
+            out.push_str(&format!(" (due {})", display_deadline(deadline)));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Renders the todos matching `query` as Markdown, also writing it to `path`
+/// when one is given.
+pub fn export_markdown(
+    conn: &Connection,
+    path: Option<&Path>,
+    query: &TodoQuery,
+    style: MarkdownStyle,
+) -> Result<String, String> {
+    let todos = db_query_todos(conn, query, Local::now());
+    let markdown = match style {
+        MarkdownStyle::Sections => render_markdown(&todos),
+        MarkdownStyle::Checklist => render_checklist(&todos),
+    };
+    if let Some(path) = path {
+        std::fs::write(path, &markdown).map_err(|e| e.to_string())?;
+    }
+    Ok(markdown)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db_add_todo, db_insert_todo, db_update_todo, setup_test_db, test_todo, Filter, NewTodo,
+    };
+
+    fn read_rows(path: &Path) -> Vec<Vec<String>> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_path(path)
+            .unwrap();
+        reader
+            .records()
+            .map(|r| r.unwrap().iter().map(String::from).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_export_csv_round_trips_special_characters() {
+        let conn = setup_test_db();
+        let title = "Say \"hi\", then leave".to_string();
+        let description = "First line,\n\"second\" line".to_string();
+        db_insert_todo(
+            &conn,
+            NewTodo {
+                title: title.clone(),
+                deadline: Some("2024-05-01".to_string()),
+                description: Some(description.clone()),
+                ..NewTodo::default()
+            },
+        )
+        .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("todos.csv");
+
+        let count = export_csv(&conn, &path, &TodoQuery::default(), Local::now()).unwrap();
+
+        assert_eq!(count, 1);
+        let rows = read_rows(&path);
+        assert_eq!(rows[0], CSV_HEADER);
+        assert_eq!(rows[1][1], title);
+        assert_eq!(rows[1][4], "2024-05-01");
+        assert_eq!(rows[1][5], description);
+    }
+
+    #[test]
+    fn test_export_csv_empty_database_writes_header_only() {
+        let conn = setup_test_db();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("todos.csv");
+
+        let count = export_csv(&conn, &path, &TodoQuery::default(), Local::now()).unwrap();
+
+        assert_eq!(count, 0);
+        assert_eq!(read_rows(&path), vec![CSV_HEADER.to_vec()]);
+    }
+
+    #[test]
+    fn test_export_csv_honors_filter() {
+        let conn = setup_test_db();
+        db_add_todo(&conn, "Late".to_string(), Some("2000-01-01".to_string()));
+        db_add_todo(&conn, "Later".to_string(), Some("2999-01-01".to_string()));
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("todos.csv");
+
+        let count = export_csv(
+            &conn,
+            &path,
+            &TodoQuery {
+                filter: Filter::Overdue,
+                ..TodoQuery::default()
+            },
+            Local::now(),
+        )
+        .unwrap();
+
+        assert_eq!(count, 1);
+        let rows = read_rows(&path);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1][1], "Late");
+    }
+
+    fn todo(id: u64, title: &str, completed: bool, deadline: Option<&str>) -> Todo {
+        Todo {
+            completed,
+            deadline: deadline.map(String::from),
+            ..test_todo(id, title)
+        }
+    }
+
+    fn unfold(ics: &str) -> String {
+        ics.replace("\r\n ", "")
+    }
+
+    #[test]
+    fn test_ics_escapes_text() {
+        assert_eq!(ics_escape("a,b;c\\d\ne"), "a\\,b\\;c\\\\d\\ne");
+    }
+
+    #[test]
+    fn test_ics_folds_long_lines() {
+        let title = "Very long title ".repeat(20);
+        let todos = [todo(1, &title, false, Some("2024-05-01"))];
+
+        let ics = render_ics(&todos, IcsComponent::Todo, false, Utc::now());
+
+        assert!(ics.ends_with("END:VCALENDAR\r\n"));
+        for line in ics.split("\r\n") {
+            assert!(line.len() <= ICS_LINE_LIMIT, "line too long: {:?}", line);
+        }
+        assert!(unfold(&ics).contains(&format!("SUMMARY:{}\r\n", title)));
+    }
+
+    #[test]
+    fn test_ics_folding_keeps_multibyte_characters_intact() {
+        let mut out = String::new();
+        push_folded(&mut out, &"ż".repeat(60));
+
+        for line in out.split("\r\n") {
+            assert!(line.len() <= ICS_LINE_LIMIT);
+        }
+        assert_eq!(out.replace("\r\n ", "").trim_end(), "ż".repeat(60));
+    }
+
+    #[test]
+    fn test_ics_date_only_and_datetime_deadlines() {
+        let todos = [
+            todo(1, "Date", false, Some("2024-05-01")),
+            todo(2, "Time", false, Some("2024-05-01T10:30:00+02:00")),
+        ];
+
+        let ics = render_ics(&todos, IcsComponent::Event, false, Utc::now());
+
+        assert!(ics.contains("DTSTART;VALUE=DATE:20240501\r\n"));
+        assert!(ics.contains("DTSTART:20240501T083000Z\r\n"));
+        assert_eq!(ics.matches("BEGIN:VEVENT").count(), 2);
+        assert!(ics.contains("UID:todo-2@tauri_basic_example"));
+    }
+
+    #[test]
+    fn test_ics_skips_completed_and_undated_todos() {
+        let todos = [
+            todo(1, "Open", false, Some("2024-05-01")),
+            todo(2, "Done", true, Some("2024-05-01")),
+            todo(3, "Undated", false, None),
+        ];
+
+        let ics = render_ics(&todos, IcsComponent::Todo, false, Utc::now());
+
+        assert_eq!(ics.matches("BEGIN:VTODO").count(), 1);
+        assert!(ics.contains("SUMMARY:Open"));
+        assert!(ics.contains("DUE;VALUE=DATE:20240501"));
+    }
+
+    #[test]
+    fn test_ics_event_feed_structure() {
+        let mut done = todo(2, "Done", true, Some("2024-05-02"));
+        done.completed_at = Some("2024-05-01T18:00:00+02:00".to_string());
+        let todos = [
+            todo(1, "Open", false, Some("2024-05-01T09:00:00Z")),
+            done,
+            todo(3, "Undated", false, None),
+        ];
+
+        let ics = render_ics(&todos, IcsComponent::Event, true, Utc::now());
+        let lines: Vec<&str> = ics.split_terminator("\r\n").collect();
+
+        assert!(!ics.replace("\r\n", "").contains('\n'));
+        assert_eq!(lines.first(), Some(&"BEGIN:VCALENDAR"));
+        assert_eq!(lines.last(), Some(&"END:VCALENDAR"));
+        let begins: Vec<usize> = (0..lines.len())
+            .filter(|&i| lines[i] == "BEGIN:VEVENT")
+            .collect();
+        let ends: Vec<usize> = (0..lines.len())
+            .filter(|&i| lines[i] == "END:VEVENT")
+            .collect();
+        assert_eq!(begins.len(), 2);
+        for (begin, end) in begins.iter().zip(&ends) {
+            assert!(begin < end);
+            let event = &lines[*begin..*end];
+            for property in ["UID:", "DTSTAMP:", "SUMMARY:", "DTSTART"] {
+                assert_eq!(
+                    event.iter().filter(|l| l.starts_with(property)).count(),
+                    1,
+                    "{} in {:?}",
+                    property,
+                    event
+                );
+            }
+        }
+        assert!(!ics.contains("Undated"));
+        assert!(!ics.contains("STATUS:"));
+
+        let ics = render_ics(&todos, IcsComponent::Todo, true, Utc::now());
+        assert_eq!(ics.matches("STATUS:COMPLETED").count(), 1);
+        assert!(ics.contains("COMPLETED:20240501T160000Z\r\n"));
+    }
+
+    #[test]
+    fn test_markdown_checkbox_state() {
+        let todos = [todo(1, "Open", false, None), todo(2, "Done", true, None)];
+
+        let markdown = render_markdown(&todos);
+
+        assert!(markdown.contains("- [ ] Open\n"));
+        assert!(markdown.contains("- [x] Done\n"));
+    }
+
+    #[test]
+    fn test_markdown_deadline_suffix() {
+        assert_eq!(
+            markdown_item(&todo(1, "Date", false, Some("2024-05-01"))),
+            "- [ ] Date (due 2024-05-01)"
+        );
+        assert_eq!(
+            markdown_item(&todo(2, "Time", false, Some("2024-05-01T09:30"))),
+            "- [ ] Time (due 2024-05-01 09:30)"
+        );
+        assert_eq!(markdown_item(&todo(3, "None", false, None)), "- [ ] None");
+    }
+
+    #[test]
+    fn test_markdown_description_is_indented() {
+        let mut item = todo(1, "Call", false, None);
+        item.description = Some("Number is on\nthe *fridge*".to_string());
+
+        assert_eq!(
+            markdown_item(&item),
+            "- [ ] Call\n\n    Number is on\n    the \\*fridge\\*"
+        );
+    }
+
+    #[test]
+    fn test_ics_includes_description() {
+        let mut item = todo(1, "Call", false, Some("2024-05-01"));
+        item.description = Some("Ask about a, b; c".to_string());
+
+        let ics = render_ics(&[item], IcsComponent::Todo, false, Utc::now());
+
+        assert!(ics.contains("DESCRIPTION:Ask about a\\, b\\; c\r\n"));
+    }
+
+    #[test]
+    fn test_markdown_escapes_special_characters() {
+        assert_eq!(
+            markdown_item(&todo(1, "Fix *bold* [link] #1 `code`", false, None)),
+            "- [ ] Fix \\*bold\\* \\[link\\] \\#1 \\`code\\`"
+        );
+    }
+
+    #[test]
+    fn test_markdown_groups_by_completion() {
+        let todos = [
+            todo(1, "First", false, None),
+            todo(2, "Second", true, None),
+            todo(3, "Third", false, None),
+        ];
+
+        assert_eq!(
+            render_markdown(&todos),
+            "## Pending\n\n- [ ] First\n- [ ] Third\n\n## Completed\n\n- [x] Second\n"
+        );
+        assert_eq!(render_markdown(&todos[..1]), "## Pending\n\n- [ ] First\n");
+    }
+
+    #[test]
+    fn test_export_markdown_writes_file_and_returns_string() {
+        let conn = setup_test_db();
+        db_add_todo(&conn, "Write docs".to_string(), None);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("todos.md");
+
+        let markdown = export_markdown(
+            &conn,
+            Some(&path),
+            &TodoQuery::default(),
+            MarkdownStyle::default(),
+        )
+        .unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), markdown);
+        assert_eq!(
+            export_markdown(&conn, None, &TodoQuery::default(), MarkdownStyle::default()).unwrap(),
+            markdown
+        );
+    }
+
+    #[test]
+    fn test_export_csv_selects_explicit_ids() {
+        let conn = setup_test_db();
+        let first = db_add_todo(&conn, "First".to_string(), None);
+        db_add_todo(&conn, "Second".to_string(), None);
+        let third = db_add_todo(&conn, "Third".to_string(), None);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("todos.csv");
+        let query = TodoQuery {
+            ids: Some(vec![first.id, third.id]),
+            ..TodoQuery::default()
+        };
+
+        let count = export_csv(&conn, &path, &query, Local::now()).unwrap();
+
+        assert_eq!(count, 2);
+        let titles: Vec<String> = read_rows(&path)[1..].iter().map(|r| r[1].clone()).collect();
+        assert_eq!(titles, vec!["First", "Third"]);
+    }
+
+    #[test]
+    fn test_export_markdown_honors_filter() {
+        let conn = setup_test_db();
+        db_add_todo(&conn, "Open".to_string(), None);
+        let done = db_add_todo(&conn, "Done".to_string(), None);
+        crate::db_update_todo(&conn, done.id, None, Some(true), None, None);
+        let query = TodoQuery {
+            filter: Filter::Completed,
+            ..TodoQuery::default()
+        };
+
+        let markdown = export_markdown(&conn, None, &query, MarkdownStyle::default()).unwrap();
+
+        assert_eq!(markdown, "## Completed\n\n- [x] Done\n");
+    }
+
+    #[test]
+    fn test_export_empty_selection_writes_valid_empty_files() {
+        let conn = setup_test_db();
+        db_add_todo(&conn, "Task".to_string(), Some("2024-05-01".to_string()));
+        let dir = tempfile::tempdir().unwrap();
+        let csv_path = dir.path().join("todos.csv");
+        let ics_path = dir.path().join("todos.ics");
+        let query = TodoQuery {
+            ids: Some(Vec::new()),
+            ..TodoQuery::default()
+        };
+
+        let count = export_csv(&conn, &csv_path, &query, Local::now()).unwrap();
+        export_ics(&conn, &ics_path, IcsComponent::Todo, false, &query).unwrap();
+
+        assert_eq!(count, 0);
+        assert_eq!(read_rows(&csv_path), vec![CSV_HEADER.to_vec()]);
+        let ics = std::fs::read_to_string(&ics_path).unwrap();
+        assert!(ics.starts_with("BEGIN:VCALENDAR\r\n"));
+        assert!(ics.ends_with("END:VCALENDAR\r\n"));
+        assert!(!ics.contains("BEGIN:VTODO"));
+        assert_eq!(
+            export_markdown(&conn, None, &query, MarkdownStyle::default()).unwrap(),
+            ""
+        );
+    }
+
+    #[test]
+    fn test_checklist_keeps_titles_as_typed() {
+        let conn = setup_test_db();
+        db_add_todo(
+            &conn,
+            "Write *intro*".to_string(),
+            Some("2024-01-01".to_string()),
+        );
+        let done = db_add_todo(&conn, "Fix [link] #2".to_string(), None);
+        db_update_todo(&conn, done.id, None, Some(true), None, None);
+        db_add_todo(
+            &conn,
+            "Call <Bob>".to_string(),
+            Some("2024-01-02T15:00".to_string()),
+        );
+
+        let markdown =
+            export_markdown(&conn, None, &TodoQuery::default(), MarkdownStyle::Checklist).unwrap();
+
+        assert_eq!(
+            markdown,
+            "- [ ] Write *intro* (due 2024-01-01)\n\
+             - [x] Fix [link] #2\n\
+             - [ ] Call <Bob> (due 2024-01-02 15:00)\n"
+        );
+    }
+}
@@ -0,0 +1,423 @@
+use crate::{db_get_setting, db_set_setting};
+use rusqlite::Connection;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+pub const ENABLED_SETTING: &str = "http_api_enabled";
+pub const PORT_SETTING: &str = "http_api_port";
+/// The bearer token, made the first time it is asked for and kept after.
+pub const TOKEN_SETTING: &str = "http_api_token";
+pub const DEFAULT_PORT: u16 = 7878;
+/// Ports below this need elevated rights on most systems.
+pub const MIN_PORT: u16 = 1024;
+const TOKEN_BYTES: usize = 32;
+const MAX_HEAD_BYTES: usize = 16 * 1024;
+const MAX_BODY_BYTES: usize = 64 * 1024;
+/// A client that stops sending mid-request is dropped after this, so it
+/// can't hold up the ones behind it.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often the accept loop checks whether it should stop.
+const ACCEPT_POLL: Duration = Duration::from_millis(50);
+
+pub fn enabled(conn: &Connection) -> bool {
+    db_get_setting(conn, ENABLED_SETTING).is_some_and(|v| v == "true")
+}
+
+pub fn set_enabled(conn: &Connection, enabled: bool) {
+    db_set_setting(conn, ENABLED_SETTING, &enabled.to_string());
+}
+
+pub fn port(conn: &Connection) -> u16 {
+    db_get_setting(conn, PORT_SETTING)
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_PORT)
+}
+
+pub fn check_port(port: u16) -> Result<(), String> {
+    if port < MIN_PORT {
+        return Err(format!("The API port must be at least {}", MIN_PORT));
+    }
+    Ok(())
+}
+
+pub fn set_port(conn: &Connection, port: u16) {
+    db_set_setting(conn, PORT_SETTING, &port.to_string());
+}
+
+/// The token requests must carry as `Authorization: Bearer <token>`,
+/// generated and saved on first use.
+pub fn token(conn: &Connection) -> String {
+    if let Some(token) = db_get_setting(conn, TOKEN_SETTING) {
+        return token;
+    }
+    let mut bytes = [0u8; TOKEN_BYTES];
+    getrandom::getrandom(&mut bytes).expect("Failed to read random bytes");
+    let token = to_hex(&bytes);
+    db_set_setting(conn, TOKEN_SETTING, &token);
+    token
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// The only address the server listens on. Other machines can't reach it,
+/// whatever the firewall says.
+pub fn loopback(port: u16) -> SocketAddr {
+    SocketAddr::from((Ipv4Addr::LOCALHOST, port))
+}
+
+/// The parts of an HTTP request the API looks at.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Request {
+    pub method: String,
+    /// Without the query string.
+    pub path: String,
+    /// Decoded query parameters, in order.
+    pub query: Vec<(String, String)>,
+    pub authorization: Option<String>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// Whether the request carries `token` as its bearer token. Compared in
+    /// full whatever differs, so timing doesn't give it away.
+    pub fn is_authorized(&self, token: &str) -> bool {
+        let Some(given) = self
+            .authorization
+            .as_deref()
+            .and_then(|value| value.strip_prefix("Bearer "))
+        else {
+            return false;
+        };
+        given.len() == token.len()
+            && given
+                .bytes()
+                .zip(token.bytes())
+                .fold(0, |acc, (a, b)| acc | (a ^ b))
+                == 0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    /// Sent as JSON; `Null` sends no body.
+    pub body: Value,
+}
+
+impl Response {
+    pub fn json(status: u16, body: impl Serialize) -> Self {
+        Response {
+            status,
+            body: serde_json::to_value(body).unwrap_or(Value::Null),
+        }
+    }
+
+    pub fn error(status: u16, message: impl Into<String>) -> Self {
+        Response {
+            status,
+            body: json!({ "error": message.into() }),
+        }
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        422 => "Unprocessable Entity",
+        423 => "Locked",
+        _ => "Internal Server Error",
+    }
+}
+
+/// `%XX` escapes and `+` for space, as in a query string. Invalid escapes
+/// are kept as they are.
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' => match text
+                .get(i + 1..i + 3)
+                .filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()))
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                Some(byte) => {
+                    out.push(byte);
+                    i += 2;
+                }
+                None => out.push(b'%'),
+            },
+            byte => out.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn parse_query(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(key), percent_decode(value))
+        })
+        .collect()
+}
+
+/// Reads one request, or the response to send instead if it is malformed
+/// or too big.
+fn read_request(stream: &TcpStream) -> Result<Request, Response> {
+    let bad = |message: &str| Response::error(400, message);
+    let mut reader = BufReader::new(stream);
+    let mut head_bytes = 0;
+    let mut read_line = |reader: &mut BufReader<&TcpStream>| -> Result<String, Response> {
+        let mut line = String::new();
+        let read = reader
+            .by_ref()
+            .take((MAX_HEAD_BYTES - head_bytes) as u64)
+            .read_line(&mut line)
+            .map_err(|_| bad("Couldn't read the request"))?;
+        head_bytes += read;
+        if !line.ends_with('\n') {
+            return Err(if head_bytes >= MAX_HEAD_BYTES {
+                Response::error(413, "The request headers are too large")
+            } else {
+                bad("The request ended early")
+            });
+        }
+        Ok(line.trim_end().to_string())
+    };
+
+    let request_line = read_line(&mut reader)?;
+    let mut parts = request_line.split(' ');
+    let (Some(method), Some(target), Some(_version)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(bad("Not an HTTP request"));
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let mut request = Request {
+        method: method.to_string(),
+        path: percent_decode(path),
+        query: parse_query(query),
+        ..Request::default()
+    };
+
+    let mut content_length = 0;
+    loop {
+        let line = read_line(&mut reader)?;
+        if line.is_empty() {
+            break;
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            return Err(bad("Malformed header"));
+        };
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "content-length" => {
+                content_length = value.parse().map_err(|_| bad("Bad Content-Length"))?
+            }
+            "authorization" => request.authorization = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    if content_length > MAX_BODY_BYTES {
+        return Err(Response::error(413, "The request body is too large"));
+    }
+    request.body = vec![0; content_length];
+    reader
+        .read_exact(&mut request.body)
+        .map_err(|_| bad("The request body ended early"))?;
+    Ok(request)
+}
+
+fn write_response(mut stream: &TcpStream, response: &Response) -> io::Result<()> {
+    let body = match &response.body {
+        Value::Null => Vec::new(),
+        body => serde_json::to_vec(body)?,
+    };
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status,
+        reason(response.status),
+        body.len()
+    );
+    if !body.is_empty() {
+        head.push_str("Content-Type: application/json\r\n");
+    }
+    if response.status == 401 {
+        head.push_str("WWW-Authenticate: Bearer\r\n");
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(&body)?;
+    stream.flush()
+}
+
+fn serve(stream: TcpStream, handler: &impl Fn(&Request) -> Response) {
+    let prepared = stream
+        .set_nonblocking(false)
+        .and_then(|()| stream.set_read_timeout(Some(READ_TIMEOUT)));
+    if let Err(e) = prepared {
+        tracing::warn!("http api: failed to set up connection: {}", e);
+        return;
+    }
+    let response = match read_request(&stream) {
+        Ok(request) => {
+            let response = handler(&request);
+            tracing::debug!(
+                "http api {} {} status={}",
+                request.method,
+                request.path,
+                response.status
+            );
+            response
+        }
+        Err(response) => response,
+    };
+    if let Err(e) = write_response(&stream, &response) {
+        tracing::debug!("http api: failed to write response: {}", e);
+    }
+}
+
+/// A running API server. Requests are handled one at a time, which is
+/// plenty for scripts. Dropping it stops the server and frees the port.
+pub struct Server {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Server {
+    /// Listens on `loopback(port)`; port 0 picks a free one.
+    pub fn start(
+        port: u16,
+        handler: impl Fn(&Request) -> Response + Send + 'static,
+    ) -> io::Result<Server> {
+        let listener = TcpListener::bind(loopback(port))?;
+        listener.set_nonblocking(true)?;
+        let addr = listener.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopping = stop.clone();
+        let thread = thread::spawn(move || {
+            while !stopping.load(Ordering::Acquire) {
+                match listener.accept() {
+                    Ok((stream, _)) => serve(stream, &handler),
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(ACCEPT_POLL),
+                    Err(e) => {
+                        tracing::warn!("http api: accept failed: {}", e);
+                        thread::sleep(ACCEPT_POLL);
+                    }
+                }
+            }
+        });
+        Ok(Server {
+            addr,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::setup_test_db;
+
+    #[test]
+    fn test_token_is_made_once() {
+        let conn = setup_test_db();
+        let token = token(&conn);
+        assert_eq!(token.len(), 2 * TOKEN_BYTES);
+        assert_eq!(super::token(&conn), token);
+        assert_ne!(super::token(&setup_test_db()), token);
+
+        let request = |authorization: &str| Request {
+            authorization: Some(authorization.to_string()),
+            ..Request::default()
+        };
+        assert!(request(&format!("Bearer {}", token)).is_authorized(&token));
+        assert!(!request(&format!("Bearer {}x", token)).is_authorized(&token));
+        assert!(!request(&token).is_authorized(&token));
+        assert!(!Request::default().is_authorized(&token));
+    }
+
+    #[test]
+    fn test_settings_defaults() {
+        let conn = setup_test_db();
+        assert!(!enabled(&conn));
+        assert_eq!(port(&conn), DEFAULT_PORT);
+        set_enabled(&conn, true);
+        set_port(&conn, 8080);
+        assert!(enabled(&conn));
+        assert_eq!(port(&conn), 8080);
+        assert!(check_port(80).is_err());
+        assert!(check_port(MIN_PORT).is_ok());
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("a%20b+c"), "a b c");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz%e2%9c%93"), "%zz✓");
+        assert_eq!(
+            parse_query("filter=active&text=milk%26eggs&flag"),
+            [
+                ("filter".to_string(), "active".to_string()),
+                ("text".to_string(), "milk&eggs".to_string()),
+                ("flag".to_string(), String::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_server_binds_to_loopback_only() {
+        let server = Server::start(0, |_| Response::json(200, "pong")).unwrap();
+        assert!(server.addr().ip().is_loopback());
+        assert_eq!(loopback(DEFAULT_PORT).ip(), Ipv4Addr::LOCALHOST);
+
+        let mut stream = TcpStream::connect(server.addr()).unwrap();
+        stream
+            .write_all(b"GET /ping HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.ends_with("\r\n\r\n\"pong\""), "{}", response);
+
+        let addr = server.addr();
+        drop(server);
+        assert!(TcpListener::bind(addr).is_ok());
+    }
+}
@@ -0,0 +1,690 @@
+use crate::validation::{self, Checks, ValidationError};
+use crate::{db_insert_todo, deadline, max_title_chars, validate_new_todo, NewTodo, MAX_PRIORITY};
+use chrono::{Local, NaiveDate, NaiveDateTime};
+use csv::StringRecord;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DateFormat {
+    /// `YYYY-MM-DD`, `YYYY-MM-DDTHH:MM` or RFC 3339.
+    #[default]
+    Iso,
+    /// `DD.MM.YYYY`
+    DayMonthYear,
+    /// `MM/DD/YYYY`
+    MonthDayYear,
+}
+
+/// How to read a CSV file. Columns are matched by header name
+/// (case-insensitive); when `has_header` is false they are given as
+/// zero-based column indexes instead, e.g. `"0"`.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct CsvImportOptions {
+    pub title_column: String,
+    pub deadline_column: Option<String>,
+    pub completed_column: Option<String>,
+    pub has_header: bool,
+    pub date_format: DateFormat,
+    pub dry_run: bool,
+}
+
+impl Default for CsvImportOptions {
+    fn default() -> Self {
+        CsvImportOptions {
+            title_column: "title".to_string(),
+            deadline_column: Some("deadline".to_string()),
+            completed_column: None,
+            has_header: true,
+            date_format: DateFormat::Iso,
+            dry_run: false,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct RowError {
+    /// Row number as a spreadsheet would show it, counting the header row.
+    pub row: u64,
+    pub message: String,
+}
+
+/// Result of an import. `errors` are rows that were skipped; `warnings` are
+/// rows that were imported with some data dropped.
+#[derive(Debug, Serialize, Clone, PartialEq, Default)]
+pub struct ImportSummary {
+    pub dry_run: bool,
+    pub imported: usize,
+    pub skipped: usize,
+    /// Rows of a kind the importer doesn't handle (e.g. Todoist sections).
+    pub unsupported: usize,
+    pub ids: Vec<u64>,
+    pub errors: Vec<RowError>,
+    pub warnings: Vec<RowError>,
+}
+
+struct Columns {
+    title: usize,
+    deadline: Option<usize>,
+    completed: Option<usize>,
+}
+
+fn find_column(headers: Option<&StringRecord>, name: &str) -> Result<usize, String> {
+    let found = match headers {
+        Some(headers) => headers
+            .iter()
+            .position(|h| h.trim().eq_ignore_ascii_case(name.trim())),
+        None => name.trim().parse().ok(),
+    };
+    found.ok_or_else(|| format!("Column '{}' not found", name))
+}
+
+fn resolve_columns(
+    headers: Option<&StringRecord>,
+    options: &CsvImportOptions,
+) -> Result<Columns, String> {
+    let optional = |name: &Option<String>| match name {
+        Some(name) => find_column(headers, name).map(Some),
+        None => Ok(None),
+    };
+    Ok(Columns {
+        title: find_column(headers, &options.title_column)?,
+        deadline: optional(&options.deadline_column)?,
+        completed: optional(&options.completed_column)?,
+    })
+}
+
+/// Normalizes a deadline cell into the format the app stores. ISO values are
+/// always accepted; `format` adds one regional date layout on top.
+fn parse_import_deadline(
+    cell: &str,
+    format: DateFormat,
+) -> Result<Option<String>, ValidationError> {
+    let cell = cell.trim();
+    if cell.is_empty() {
+        return Ok(None);
+    }
+    if let Some(deadline) = deadline::normalize(cell, &Local) {
+        return Ok(Some(deadline));
+    }
+    let pattern = match format {
+        DateFormat::Iso => None,
+        DateFormat::DayMonthYear => Some("%d.%m.%Y"),
+        DateFormat::MonthDayYear => Some("%m/%d/%Y"),
+    };
+    pattern
+        .and_then(|p| NaiveDate::parse_from_str(cell, p).ok())
+        .map(|d| Some(d.format("%Y-%m-%d").to_string()))
+        .ok_or_else(|| {
+            ValidationError::new(
+                "deadline",
+                validation::UNREADABLE,
+                format!("Invalid deadline '{}'", cell),
+            )
+        })
+}
+
+fn parse_completed(cell: &str) -> Result<bool, ValidationError> {
+    match cell.trim().to_ascii_lowercase().as_str() {
+        "" | "0" | "false" | "no" | "n" => Ok(false),
+        "1" | "true" | "yes" | "y" | "x" => Ok(true),
+        other => Err(ValidationError::new(
+            "completed",
+            validation::UNREADABLE,
+            format!("Invalid completed value '{}'", other),
+        )),
+    }
+}
+
+/// Reads a row into a todo, or explains everything wrong with it.
+fn parse_row(
+    record: &StringRecord,
+    columns: &Columns,
+    options: &CsvImportOptions,
+    max_chars: usize,
+) -> Result<NewTodo, String> {
+    let cell = |idx: Option<usize>| idx.and_then(|i| record.get(i)).unwrap_or("");
+
+    let mut checks = Checks::default();
+    let new = NewTodo {
+        title: cell(Some(columns.title)).to_string(),
+        completed: checks.check(parse_completed(cell(columns.completed))),
+        deadline: checks.check(parse_import_deadline(
+            cell(columns.deadline),
+            options.date_format,
+        )),
+        ..NewTodo::default()
+    };
+    let new = checks.check_all(validate_new_todo(new, max_chars));
+    checks
+        .finish(new)
+        .map_err(|errors| validation::describe(&errors))
+}
+
+/// Inserts `rows`, each with the row number it came from, in a single
+/// transaction and records the created ids. A row the database refuses is
+/// reported in `errors` and skipped; the others still go in.
+fn insert_rows(
+    conn: &rusqlite::Connection,
+    rows: Vec<(u64, NewTodo)>,
+    summary: &mut ImportSummary,
+) -> Result<(), String> {
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    for (row, todo) in rows {
+        match db_insert_todo(&tx, todo) {
+            Ok(todo) => summary.ids.push(todo.id),
+            Err(e) => summary.errors.push(RowError {
+                row,
+                message: e.to_string(),
+            }),
+        }
+    }
+    tx.commit().map_err(|e| e.to_string())?;
+    summary.imported = summary.ids.len();
+    summary.skipped = summary.errors.len();
+    Ok(())
+}
+
+fn import_csv_reader<R: Read>(
+    conn: &rusqlite::Connection,
+    input: R,
+    options: &CsvImportOptions,
+) -> Result<ImportSummary, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(options.has_header)
+        .flexible(true)
+        .from_reader(input);
+
+    let headers = if options.has_header {
+        Some(reader.headers().map_err(|e| e.to_string())?.clone())
+    } else {
+        None
+    };
+    let columns = resolve_columns(headers.as_ref(), options)?;
+
+    let mut rows = Vec::new();
+    let mut summary = ImportSummary {
+        dry_run: options.dry_run,
+        ..ImportSummary::default()
+    };
+
+    let max_chars = max_title_chars(conn);
+    let first_row = if options.has_header { 2 } else { 1 };
+    for (row, record) in (first_row..).zip(reader.records()) {
+        let record = record.map_err(|e| e.to_string())?;
+        match parse_row(&record, &columns, options, max_chars) {
+            Ok(todo) => rows.push((row, todo)),
+            Err(message) => summary.errors.push(RowError { row, message }),
+        }
+    }
+    summary.skipped = summary.errors.len();
+
+    if options.dry_run {
+        summary.imported = rows.len();
+        return Ok(summary);
+    }
+
+    insert_rows(conn, rows, &mut summary)?;
+    Ok(summary)
+}
+
+/// Imports todos from a CSV file. Rows that fail validation are skipped and
+/// reported with their row number; everything else is inserted in a single
+/// transaction. With `dry_run` set nothing is written.
+pub fn import_csv(
+    conn: &rusqlite::Connection,
+    path: &Path,
+    options: &CsvImportOptions,
+) -> Result<ImportSummary, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    import_csv_reader(conn, file, options)
+}
+
+/// Maps a Todoist priority (1 = p1, most urgent, to 4 = p4, none) onto ours.
+fn todoist_priority(cell: &str) -> u8 {
+    match cell.trim().parse::<u8>() {
+        Ok(p @ 1..=4) => MAX_PRIORITY + 1 - p,
+        _ => 0,
+    }
+}
+
+/// Parses the `DATE` column of a Todoist export. Todoist writes whatever the
+/// user typed, so only absolute dates are understood; recurring or relative
+/// strings ("every monday") yield an error.
+fn todoist_date(cell: &str) -> Result<Option<String>, String> {
+    let cell = cell.trim();
+    if cell.is_empty() {
+        return Ok(None);
+    }
+    if let Some(deadline) = deadline::normalize(cell, &Local) {
+        return Ok(Some(deadline));
+    }
+    for format in ["%Y-%m-%d %H:%M", "%b %d %Y %H:%M", "%d %b %Y %H:%M"] {
+        let local = NaiveDateTime::parse_from_str(cell, format)
+            .ok()
+            .and_then(|dt| dt.and_local_timezone(Local).earliest());
+        if let Some(dt) = local {
+            return Ok(Some(deadline::to_stored(dt)));
+        }
+    }
+    for format in ["%b %d %Y", "%d %b %Y"] {
+        if let Ok(date) = NaiveDate::parse_from_str(cell, format) {
+            return Ok(Some(date.format("%Y-%m-%d").to_string()));
+        }
+    }
+    Err(format!("Unrecognized date '{}'", cell))
+}
+
+fn import_todoist_reader<R: Read>(
+    conn: &rusqlite::Connection,
+    input: R,
+) -> Result<ImportSummary, String> {
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(input);
+    let headers = reader.headers().map_err(|e| e.to_string())?.clone();
+    let column = |name: &str| find_column(Some(&headers), name);
+    let type_col = column("TYPE")?;
+    let content_col = column("CONTENT")?;
+    let description_col = column("DESCRIPTION").ok();
+    let priority_col = column("PRIORITY").ok();
+    let date_col = column("DATE").ok();
+
+    let mut summary = ImportSummary::default();
+    let mut rows: Vec<(u64, NewTodo)> = Vec::new();
+    let max_chars = max_title_chars(conn);
+
+    for (row, record) in (2..).zip(reader.records()) {
+        let record = record.map_err(|e| e.to_string())?;
+        let cell = |idx: Option<usize>| idx.and_then(|i| record.get(i)).unwrap_or("").trim();
+        let content = cell(Some(content_col));
+
+        match cell(Some(type_col)) {
+            "" => {}
+            "task" if content.is_empty() => summary.errors.push(RowError {
+                row,
+                message: "Task has no content".to_string(),
+            }),
+            "task" => {
+                let deadline = todoist_date(cell(date_col)).unwrap_or_else(|message| {
+                    summary.warnings.push(RowError { row, message });
+                    None
+                });
+                let description = Some(cell(description_col))
+                    .filter(|d| !d.is_empty())
+                    .map(String::from);
+                let new = NewTodo {
+                    title: content.to_string(),
+                    deadline,
+                    description,
+                    priority: todoist_priority(cell(priority_col)),
+                    ..NewTodo::default()
+                };
+                match validate_new_todo(new, max_chars) {
+                    Ok(new) => rows.push((row, new)),
+                    Err(errors) => summary.errors.push(RowError {
+                        row,
+                        message: validation::describe(&errors),
+                    }),
+                }
+            }
+            "note" => match rows.last_mut() {
+                Some((_, task)) if !content.is_empty() => {
+                    let description = task.description.get_or_insert_with(String::new);
+                    if !description.is_empty() {
+                        description.push_str("\n\n");
+                    }
+                    description.push_str(content);
+                }
+                Some(_) => {}
+                None => summary.errors.push(RowError {
+                    row,
+                    message: "Note has no preceding task".to_string(),
+                }),
+            },
+            _ => summary.unsupported += 1,
+        }
+    }
+    summary.skipped = summary.errors.len();
+
+    insert_rows(conn, rows, &mut summary)?;
+    Ok(summary)
+}
+
+/// Imports a CSV file exported from Todoist. Tasks become todos and the notes
+/// that follow a task are appended to its description. Other row types
+/// (sections, metadata) are counted in `unsupported` and otherwise ignored.
+pub fn import_todoist_csv(
+    conn: &rusqlite::Connection,
+    path: &Path,
+) -> Result<ImportSummary, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    import_todoist_reader(conn, file)
+}
+
+/// Reads a Markdown task list item such as `- [ ] Title` or `* [x] Title`
+/// into its title and completion state. Backslash escapes, as written by the
+/// Markdown export, are undone.
+fn parse_checklist_item(line: &str) -> Option<(String, bool)> {
+    let rest = line
+        .trim_start()
+        .strip_prefix(['-', '*'])?
+        .strip_prefix(' ')?;
+    let (completed, title) = if let Some(title) = rest.strip_prefix("[ ]") {
+        (false, title)
+    } else if let Some(title) = rest
+        .strip_prefix("[x]")
+        .or_else(|| rest.strip_prefix("[X]"))
+    {
+        (true, title)
+    } else {
+        return None;
+    };
+    if !title.is_empty() && !title.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let mut unescaped = String::with_capacity(title.len());
+    let mut chars = title.trim().chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next()),
+            c => unescaped.push(c),
+        }
+    }
+    Some(unescaped)
+        .filter(|t| !t.is_empty())
+        .map(|t| (t, completed))
+}
+
+/// Creates a todo for every checklist item in `text`, in one transaction.
+/// Headings, prose and other lines are ignored, and so are items whose title
+/// `validate_new_todo` rejects. Returns how many todos were created.
+pub fn import_markdown(conn: &rusqlite::Connection, text: &str) -> Result<ImportSummary, String> {
+    let max_chars = max_title_chars(conn);
+    let items: Vec<(u64, NewTodo)> = (1..)
+        .zip(text.lines())
+        .filter_map(|(line, text)| {
+            let (title, completed) = parse_checklist_item(text)?;
+            let new = NewTodo {
+                title,
+                completed,
+                ..NewTodo::default()
+            };
+            Some((line, validate_new_todo(new, max_chars).ok()?))
+        })
+        .collect();
+    let mut summary = ImportSummary::default();
+    insert_rows(conn, items, &mut summary)?;
+    Ok(summary)
+}
+
+/// Imports the checklist items of a Markdown or plain-text file.
+pub fn import_markdown_file(
+    conn: &rusqlite::Connection,
+    path: &Path,
+) -> Result<ImportSummary, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    import_markdown(conn, &text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db_get_todos, setup_test_db};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_import_maps_columns_by_header() {
+        let conn = setup_test_db();
+        let csv = "Done,Task,Due\nyes,Write report,2024-05-01\n,Call Bob,\n";
+        let options = CsvImportOptions {
+            title_column: "task".to_string(),
+            deadline_column: Some("due".to_string()),
+            completed_column: Some("done".to_string()),
+            ..CsvImportOptions::default()
+        };
+
+        let summary = import_csv_reader(&conn, csv.as_bytes(), &options).unwrap();
+
+        assert_eq!(summary.imported, 2);
+        let todos = db_get_todos(&conn);
+        assert_eq!(todos[0].title, "Write report");
+        assert!(todos[0].completed);
+        assert_eq!(todos[0].deadline, Some("2024-05-01".to_string()));
+        assert_eq!(todos[1].title, "Call Bob");
+        assert!(!todos[1].completed);
+        assert_eq!(todos[1].deadline, None);
+        assert_eq!(summary.ids, vec![todos[0].id, todos[1].id]);
+    }
+
+    #[test]
+    fn test_import_without_header_uses_indexes() {
+        let conn = setup_test_db();
+        let csv = "01.02.2024,Pay rent\n";
+        let options = CsvImportOptions {
+            title_column: "1".to_string(),
+            deadline_column: Some("0".to_string()),
+            completed_column: None,
+            has_header: false,
+            date_format: DateFormat::DayMonthYear,
+            ..CsvImportOptions::default()
+        };
+
+        import_csv_reader(&conn, csv.as_bytes(), &options).unwrap();
+
+        let todos = db_get_todos(&conn);
+        assert_eq!(todos[0].title, "Pay rent");
+        assert_eq!(todos[0].deadline, Some("2024-02-01".to_string()));
+    }
+
+    #[test]
+    fn test_import_dry_run_reports_without_inserting() {
+        let conn = setup_test_db();
+        let csv = "title,deadline\nGood,2024-05-01\n,2024-05-01\n";
+        let options = CsvImportOptions {
+            dry_run: true,
+            ..CsvImportOptions::default()
+        };
+
+        let summary = import_csv_reader(&conn, csv.as_bytes(), &options).unwrap();
+
+        assert!(summary.dry_run);
+        assert_eq!(summary.imported, 1);
+        assert_eq!(summary.skipped, 1);
+        assert!(summary.ids.is_empty());
+        assert!(db_get_todos(&conn).is_empty());
+    }
+
+    #[test]
+    fn test_import_reports_bad_deadlines_with_row_numbers() {
+        let conn = setup_test_db();
+        let csv = "title,deadline\nOk,05/01/2024\nBad,31.12.2024\nWorse,tomorrow\n";
+        let options = CsvImportOptions {
+            date_format: DateFormat::MonthDayYear,
+            ..CsvImportOptions::default()
+        };
+
+        let summary = import_csv_reader(&conn, csv.as_bytes(), &options).unwrap();
+
+        assert_eq!(summary.imported, 1);
+        let rows: Vec<u64> = summary.errors.iter().map(|e| e.row).collect();
+        assert_eq!(rows, vec![3, 4]);
+        assert!(summary.errors[0].message.contains("31.12.2024"));
+        assert_eq!(
+            db_get_todos(&conn)[0].deadline,
+            Some("2024-05-01".to_string())
+        );
+    }
+
+    #[test]
+    fn test_import_reports_every_problem_in_a_row() {
+        let conn = setup_test_db();
+        let csv = "title,deadline,done\n  ,someday,maybe\nFine,,no\n";
+        let options = CsvImportOptions {
+            completed_column: Some("done".to_string()),
+            ..CsvImportOptions::default()
+        };
+
+        let summary = import_csv_reader(&conn, csv.as_bytes(), &options).unwrap();
+
+        assert_eq!(summary.imported, 1);
+        assert_eq!(
+            summary.errors,
+            vec![RowError {
+                row: 2,
+                message: "Invalid completed value 'maybe'; Invalid deadline 'someday'; \
+                          Title is empty"
+                    .to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_import_reports_rows_the_database_refuses() {
+        let conn = setup_test_db();
+        conn.execute_batch(
+            "CREATE TEMP TRIGGER refuse BEFORE INSERT ON todos WHEN NEW.title = 'Refused'
+             BEGIN SELECT RAISE(ABORT, 'refused'); END;",
+        )
+        .unwrap();
+        let csv = "title,deadline\nFirst,\nRefused,\nLast,\n";
+
+        let summary =
+            import_csv_reader(&conn, csv.as_bytes(), &CsvImportOptions::default()).unwrap();
+
+        assert_eq!(summary.imported, 2);
+        assert_eq!(summary.skipped, 1);
+        assert_eq!(summary.errors[0].row, 3);
+        assert!(summary.errors[0].message.contains("refused"));
+        let titles: Vec<String> = db_get_todos(&conn).into_iter().map(|t| t.title).collect();
+        assert_eq!(titles, ["First", "Last"]);
+    }
+
+    #[test]
+    fn test_import_unknown_column_is_an_error() {
+        let conn = setup_test_db();
+        let options = CsvImportOptions {
+            title_column: "name".to_string(),
+            ..CsvImportOptions::default()
+        };
+
+        let result = import_csv_reader(&conn, "title\nA\n".as_bytes(), &options);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_import_large_file_uses_single_transaction() {
+        let conn = setup_test_db();
+        let commits = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&commits);
+        conn.commit_hook(Some(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            false
+        }));
+        let mut csv = String::from("title,deadline\n");
+        for i in 0..5000 {
+            csv.push_str(&format!("Task {},2024-05-01\n", i));
+        }
+
+        let summary =
+            import_csv_reader(&conn, csv.as_bytes(), &CsvImportOptions::default()).unwrap();
+
+        assert_eq!(summary.imported, 5000);
+        assert_eq!(commits.load(Ordering::SeqCst), 1);
+        assert_eq!(db_get_todos(&conn).len(), 5000);
+    }
+
+    #[test]
+    fn test_todoist_priority_mapping() {
+        assert_eq!(todoist_priority("1"), 3);
+        assert_eq!(todoist_priority("2"), 2);
+        assert_eq!(todoist_priority("3"), 1);
+        assert_eq!(todoist_priority("4"), 0);
+        assert_eq!(todoist_priority(""), 0);
+    }
+
+    #[test]
+    fn test_import_todoist_fixture() {
+        let conn = setup_test_db();
+        let fixture = include_str!("../tests/fixtures/todoist_export.csv");
+
+        let summary = import_todoist_reader(&conn, fixture.as_bytes()).unwrap();
+
+        assert_eq!(summary.imported, 4);
+        assert_eq!(summary.unsupported, 2);
+        assert_eq!(summary.skipped, 0);
+        assert_eq!(summary.warnings.len(), 1);
+        assert_eq!(summary.warnings[0].row, 8);
+
+        let todos = db_get_todos(&conn);
+        assert_eq!(todos[0].title, "Pay rent");
+        assert_eq!(todos[0].priority, 3);
+        assert_eq!(todos[0].deadline, Some("2024-06-01".to_string()));
+        assert_eq!(
+            todos[0].description,
+            Some("Transfer before noon\n\nLandlord changed IBAN".to_string())
+        );
+
+        assert_eq!(todos[1].title, "Dentist, 2nd floor");
+        assert_eq!(todos[1].priority, 1);
+        assert_eq!(
+            todos[1].deadline,
+            deadline::normalize("2024-06-12T14:30", &Local)
+        );
+        assert_eq!(todos[1].description, None);
+
+        assert_eq!(todos[2].title, "Water plants");
+        assert_eq!(todos[2].priority, 0);
+        assert_eq!(todos[2].deadline, None);
+
+        assert_eq!(todos[3].title, "Read book");
+        assert_eq!(todos[3].deadline, Some("2024-07-04".to_string()));
+        assert_eq!(todos[3].description, Some("Chapter 3".to_string()));
+    }
+
+    #[test]
+    fn test_import_markdown_checklist() {
+        let conn = setup_test_db();
+        let text = "# Groceries\n\
+                    \n\
+                    Some notes about the week.\n\
+                    - [ ] Buy milk\n\
+                    - [x] Pay rent\n\
+                    * [X] Call \\*Bob\\*\n\
+                    \x20   - [ ] Nested item\n\
+                    - plain bullet\n\
+                    - [ ]\n\
+                    - [maybe] Not a checkbox\n\
+                    -[ ] Missing space\n";
+
+        assert_eq!(import_markdown(&conn, text).map(|s| s.imported), Ok(4));
+
+        let todos: Vec<(String, bool)> = db_get_todos(&conn)
+            .into_iter()
+            .map(|t| (t.title, t.completed))
+            .collect();
+        assert_eq!(
+            todos,
+            vec![
+                ("Buy milk".to_string(), false),
+                ("Pay rent".to_string(), true),
+                ("Call *Bob*".to_string(), true),
+                ("Nested item".to_string(), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_import_markdown_without_items() {
+        let conn = setup_test_db();
+        assert_eq!(
+            import_markdown(&conn, "Just prose.\n\n## Heading\n").map(|s| s.imported),
+            Ok(0)
+        );
+        assert!(db_get_todos(&conn).is_empty());
+    }
+}
@@ -0,0 +1,495 @@
+use crate::app_lock::PASSIVE_COMMANDS;
+use crate::{db_get_setting, db_set_setting};
+use chrono::NaiveDate;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{OnceLock, RwLock};
+use std::time::Instant;
+use tauri::ipc::Invoke;
+use tauri::Runtime;
+use tracing::level_filters::LevelFilter;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{reload, Registry};
+
+/// Folder inside the app data directory holding the log files.
+pub const LOG_DIR: &str = "logs";
+/// Log files are named `todos.<UTC date>.log` by the appender.
+const LOG_FILE_PREFIX: &str = "todos";
+const LOG_FILE_SUFFIX: &str = "log";
+/// Days of log files kept; older ones are deleted when a new day's file is
+/// started, and at startup.
+pub const LOG_FILES_TO_KEEP: usize = 7;
+/// The most lines `recent_lines` returns.
+pub const MAX_RECENT_LINES: usize = 10_000;
+/// How much of user text `redact` keeps.
+const REDACTED_PREFIX_CHARS: usize = 3;
+
+/// Settings key holding the log level.
+pub const LOG_LEVEL_SETTING: &str = "log_level";
+
+/// How much of our own logging goes to the file. Dependencies only get
+/// through at `Warn` and above, so their debug chatter stays out of it.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Default for LogLevel {
+    fn default() -> Self {
+        if cfg!(debug_assertions) {
+            LogLevel::Debug
+        } else {
+            LogLevel::Info
+        }
+    }
+}
+
+impl LogLevel {
+    const ALL: [LogLevel; 5] = [
+        LogLevel::Error,
+        LogLevel::Warn,
+        LogLevel::Info,
+        LogLevel::Debug,
+        LogLevel::Trace,
+    ];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+            LogLevel::Trace => "trace",
+        }
+    }
+
+    fn filter(self) -> LevelFilter {
+        match self {
+            LogLevel::Error => LevelFilter::ERROR,
+            LogLevel::Warn => LevelFilter::WARN,
+            LogLevel::Info => LevelFilter::INFO,
+            LogLevel::Debug => LevelFilter::DEBUG,
+            LogLevel::Trace => LevelFilter::TRACE,
+        }
+    }
+}
+
+pub fn log_level(conn: &Connection) -> LogLevel {
+    db_get_setting(conn, LOG_LEVEL_SETTING)
+        .and_then(|v| LogLevel::ALL.into_iter().find(|level| level.as_str() == v))
+        .unwrap_or_default()
+}
+
+/// Stores `level`; `apply_level` puts it into effect.
+pub fn set_log_level(conn: &Connection, level: LogLevel) {
+    db_set_setting(conn, LOG_LEVEL_SETTING, level.as_str());
+}
+
+/// The level set with `apply_level`, if any yet.
+static APP_LEVEL: RwLock<Option<LogLevel>> = RwLock::new(None);
+/// Swaps the filter of the subscriber `init` installed.
+static FILTER: OnceLock<reload::Handle<Targets, Registry>> = OnceLock::new();
+const DEPENDENCY_LEVEL: LevelFilter = LevelFilter::WARN;
+
+/// Our own events at the chosen level, everyone else's at `DEPENDENCY_LEVEL`.
+fn targets() -> Targets {
+    let level = APP_LEVEL.read().unwrap().unwrap_or_default();
+    Targets::new()
+        .with_default(DEPENDENCY_LEVEL)
+        .with_target(env!("CARGO_CRATE_NAME"), level.filter())
+}
+
+/// Changes how much of our own logging is kept, from the next event on.
+pub fn apply_level(level: LogLevel) {
+    *APP_LEVEL.write().unwrap() = Some(level);
+    if let Some(filter) = FILTER.get() {
+        if let Err(e) = filter.reload(targets()) {
+            tracing::warn!("failed to change the log level: {}", e);
+        }
+    }
+}
+
+/// User text such as a title as it may appear in the log: its first few
+/// characters, its length and a hash, so the same todo can be followed
+/// across lines without the log holding what it says.
+pub fn redact(text: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    let chars = text.chars().count();
+    let prefix: String = text.chars().take(REDACTED_PREFIX_CHARS).collect();
+    let ellipsis = if chars > REDACTED_PREFIX_CHARS {
+        "…"
+    } else {
+        ""
+    };
+    format!(
+        "{:?} ({} chars, #{:08x})",
+        prefix + ellipsis,
+        chars,
+        hasher.finish() as u32
+    )
+}
+
+/// The log files in `dir`, one per day, oldest first. Other files are left
+/// out.
+fn log_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix(LOG_FILE_PREFIX)?.strip_prefix('.'))
+                .and_then(|name| name.strip_suffix(LOG_FILE_SUFFIX)?.strip_suffix('.'))
+                .is_some_and(|day| NaiveDate::parse_from_str(day, "%Y-%m-%d").is_ok())
+        })
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// A file per day in `dir`, keeping the newest `LOG_FILES_TO_KEEP`.
+fn file_appender(dir: &Path) -> Result<RollingFileAppender, String> {
+    RollingFileAppender::builder()
+        .rotation(Rotation::DAILY)
+        .filename_prefix(LOG_FILE_PREFIX)
+        .filename_suffix(LOG_FILE_SUFFIX)
+        .max_log_files(LOG_FILES_TO_KEEP)
+        .build(dir)
+        .map_err(|e| e.to_string())
+}
+
+/// Installs the global subscriber, writing to stderr and a file per day
+/// inside `log_dir`; records from crates still on `log` are forwarded to
+/// it. Also installs a panic hook that logs panics before the default hook
+/// runs. Failed database calls mostly unwrap, so this is where they are
+/// recorded.
+pub fn init(log_dir: &Path) -> Result<(), String> {
+    let file = file_appender(log_dir)?;
+    let (filter, handle) = reload::Layer::new(targets());
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer().with_writer(io::stderr))
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(file),
+        )
+        .try_init()
+        .map_err(|e| e.to_string())?;
+    let _ = FILTER.set(handle);
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        tracing::error!("{}", info);
+        default_hook(info);
+    }));
+    Ok(())
+}
+
+/// The last `lines` lines logged, oldest first, reading back through
+/// earlier days' files when today's is short. At most `MAX_RECENT_LINES`.
+pub fn recent_lines(log_dir: &Path, lines: usize) -> Result<Vec<String>, String> {
+    let wanted = lines.min(MAX_RECENT_LINES);
+    let mut recent = VecDeque::with_capacity(wanted);
+    let files = match log_files(log_dir) {
+        Ok(files) => files,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.to_string()),
+    };
+    for path in files.iter().rev() {
+        if recent.len() >= wanted {
+            break;
+        }
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        for line in text.lines().rev() {
+            if recent.len() >= wanted {
+                break;
+            }
+            recent.push_front(line.to_string());
+        }
+    }
+    Ok(recent.into())
+}
+
+thread_local! {
+    /// Whether the command running on this thread has failed, for `timed`.
+    static FAILED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Marks the command running on this thread as failed. `logged` does this
+/// for every error it passes through.
+pub fn note_failure() {
+    FAILED.set(true);
+}
+
+/// Wraps the command handler so every invocation is logged with its outcome
+/// and how long it took. Our commands are synchronous, so that includes their
+/// work; they report failures through `logged` or `note_failure`.
+pub fn timed<R: Runtime>(
+    handler: impl Fn(Invoke<R>) -> bool + Send + Sync + 'static,
+) -> impl Fn(Invoke<R>) -> bool + Send + Sync + 'static {
+    move |invoke| {
+        let command = invoke.message.command().to_string();
+        timed_call(&command, || handler(invoke))
+    }
+}
+
+/// Commands logged at `debug` however often they run, so they don't bury
+/// everything else: the ones the frontend polls, and the deadline preview,
+/// which runs on every keystroke.
+fn is_quiet(command: &str) -> bool {
+    PASSIVE_COMMANDS.contains(&command) || command == "parse_deadline_text"
+}
+
+/// Runs `command` and logs it: at `info` if it succeeded, at `warn` if it
+/// failed or doesn't exist. Quiet commands are logged at `debug`.
+fn timed_call(command: &str, run: impl FnOnce() -> bool) -> bool {
+    FAILED.set(false);
+    let started = Instant::now();
+    let handled = run();
+    let took = started.elapsed();
+    if !handled {
+        tracing::warn!("command {} is unknown", command);
+    } else if FAILED.take() {
+        tracing::warn!("command {} failed in {:?}", command, took);
+    } else if is_quiet(command) {
+        tracing::debug!("command {} ok in {:?}", command, took);
+    } else {
+        tracing::info!("command {} ok in {:?}", command, took);
+    }
+    handled
+}
+
+/// Logs the outcome of a fallible command under `operation`, marks the
+/// command failed on an error, and passes the result through unchanged.
+pub fn logged<T, E: fmt::Display>(operation: &str, result: Result<T, E>) -> Result<T, E> {
+    match &result {
+        Ok(_) => tracing::debug!("{} succeeded", operation),
+        Err(e) => {
+            note_failure();
+            tracing::error!("{} failed: {}", operation, e);
+        }
+    }
+    result
+}
+
+/// Like `logged`, for a command turning down what it was given (a bad
+/// value, a wrong passphrase): the failure is logged as a warning.
+pub fn rejected<T, E: fmt::Display>(operation: &str, result: Result<T, E>) -> Result<T, E> {
+    if let Err(e) = &result {
+        note_failure();
+        tracing::warn!("{} rejected: {}", operation, e);
+    }
+    result
+}
+
+#[cfg(test)]
+pub mod test_logger {
+    use std::fmt;
+    use std::sync::{Mutex, Once};
+    use tracing::field::{Field, Visit};
+    use tracing::{Event, Level, Subscriber};
+    use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+
+    static RECORDS: Mutex<Vec<(Level, String)>> = Mutex::new(Vec::new());
+    static INIT: Once = Once::new();
+
+    struct Message(String);
+
+    impl Visit for Message {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            if field.name() == "message" {
+                self.0 = format!("{:?}", value);
+            }
+        }
+    }
+
+    struct CaptureLayer;
+
+    impl<S: Subscriber> Layer<S> for CaptureLayer {
+        fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+            let mut message = Message(String::new());
+            event.record(&mut message);
+            RECORDS
+                .lock()
+                .unwrap()
+                .push((*event.metadata().level(), message.0));
+        }
+    }
+
+    /// Installs a subscriber that keeps events in memory. Tests run in
+    /// parallel and share it, so look for messages unique to the test.
+    pub fn install() {
+        INIT.call_once(|| {
+            let subscriber = tracing_subscriber::registry().with(CaptureLayer);
+            tracing::subscriber::set_global_default(subscriber).unwrap();
+        });
+    }
+
+    pub fn records_containing(needle: &str) -> Vec<(Level, String)> {
+        RECORDS
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, message)| message.contains(needle))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{export, setup_test_db, TodoQuery};
+    use chrono::{Local, Utc};
+    use std::io::Write;
+    use std::thread;
+    use std::time::Duration;
+    use tracing::Level;
+    use tracing_subscriber::fmt::MakeWriter;
+
+    #[test]
+    fn test_failed_operation_logs_an_error() {
+        test_logger::install();
+        let conn = setup_test_db();
+        let path = Path::new("/nonexistent-dir/todos.csv");
+
+        let result = logged(
+            "test_export_to_missing_dir",
+            export::export_csv(&conn, path, &TodoQuery::default(), Local::now()),
+        );
+
+        assert!(result.is_err());
+        let records = test_logger::records_containing("test_export_to_missing_dir");
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].0, Level::ERROR);
+        assert!(records[0].1.contains("failed"));
+    }
+
+    fn log_file_name(day: NaiveDate) -> String {
+        format!(
+            "{}.{}.{}",
+            LOG_FILE_PREFIX,
+            day.format("%Y-%m-%d"),
+            LOG_FILE_SUFFIX
+        )
+    }
+
+    fn day(d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 5, d).unwrap()
+    }
+
+    fn names(dir: &Path) -> Vec<String> {
+        log_files(dir)
+            .unwrap()
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_redact_hides_user_text() {
+        let title = "Call the bank about the mortgage";
+        let redacted = redact(title);
+        assert!(redacted.starts_with("\"Cal…\" (32 chars, #"));
+        assert!(!redacted.contains("bank"));
+        assert_eq!(redact(title), redacted);
+        assert_ne!(redact("Call the bank about the overdraft"), redacted);
+        assert!(redact("ab").starts_with("\"ab\" (2 chars"));
+        // Counted in characters, so a multi-byte one isn't cut in half.
+        assert!(redact("żółw").starts_with("\"żół…\" (4 chars"));
+    }
+
+    #[test]
+    fn test_keeps_a_week_of_daily_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("notes.txt"), "not a log").unwrap();
+        for d in 1..=9 {
+            fs::write(dir.path().join(log_file_name(day(d))), "old\n").unwrap();
+            // The appender prunes by creation time, which is coarse.
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        let appender = file_appender(dir.path()).unwrap();
+        writeln!(appender.make_writer(), "today").unwrap();
+
+        let today = log_file_name(Utc::now().date_naive());
+        let mut expected: Vec<String> = (4..=9).map(|d| log_file_name(day(d))).collect();
+        expected.push(today.clone());
+        assert_eq!(names(dir.path()), expected);
+        assert_eq!(
+            fs::read_to_string(dir.path().join(today)).unwrap(),
+            "today\n"
+        );
+        assert!(dir.path().join("notes.txt").exists());
+    }
+
+    #[test]
+    fn test_recent_lines_reads_back_across_days() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(log_file_name(day(1))), "a\nb\nc\n").unwrap();
+        fs::write(dir.path().join(log_file_name(day(2))), "d\ne\n").unwrap();
+
+        assert_eq!(recent_lines(dir.path(), 1).unwrap(), ["e"]);
+        assert_eq!(recent_lines(dir.path(), 3).unwrap(), ["c", "d", "e"]);
+        assert_eq!(recent_lines(dir.path(), 10).unwrap().len(), 5);
+        assert!(recent_lines(&dir.path().join("missing"), 10)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_commands_are_logged_with_their_outcome() {
+        test_logger::install();
+
+        assert!(timed_call("test_command_ok", || true));
+        assert!(timed_call("test_command_err", || {
+            logged("test_inner_step", Err::<(), _>("disk full")).is_err()
+        }));
+        assert!(!timed_call("test_command_unknown", || false));
+
+        let levels = |needle: &str| -> Vec<Level> {
+            test_logger::records_containing(needle)
+                .into_iter()
+                .map(|(level, _)| level)
+                .collect()
+        };
+        assert_eq!(levels("command test_command_ok ok in"), [Level::INFO]);
+        assert_eq!(levels("command test_command_err failed in"), [Level::WARN]);
+        assert_eq!(
+            levels("command test_command_unknown is unknown"),
+            [Level::WARN]
+        );
+        timed_call("parse_deadline_text", || true);
+        assert_eq!(levels("command parse_deadline_text ok"), [Level::DEBUG]);
+        // A failure doesn't stick to the next command on the thread.
+        timed_call("test_command_after", || true);
+        assert_eq!(levels("command test_command_after ok"), [Level::INFO]);
+    }
+
+    #[test]
+    fn test_successful_operation_is_not_an_error() {
+        test_logger::install();
+
+        let result = logged("test_noop_operation", Ok::<_, String>(()));
+
+        assert!(result.is_ok());
+        let records = test_logger::records_containing("test_noop_operation");
+        assert!(records.iter().all(|(level, _)| *level == Level::DEBUG));
+    }
+}
@@ -1,229 +1,8704 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use chrono::Local;
-use rusqlite::Connection;
+mod agenda;
+mod app_lock;
+mod autostart;
+mod backup;
+mod badge;
+mod bundle;
+mod cli;
+mod db_location;
+mod deadline;
+mod deadline_text;
+mod deep_link;
+#[cfg(debug_assertions)]
+mod demo;
+mod encryption;
+mod events;
+mod export;
+mod http_api;
+mod import;
+mod logging;
+mod maintenance;
+mod merge;
+mod notifications;
+mod profiles;
+mod quick_add;
+mod recovery;
+mod settings;
+mod shortcut;
+mod snapshot;
+mod snooze;
+mod stats;
+mod tray;
+mod undo;
+mod validation;
+mod window_state;
+
+use chrono::{DateTime, Duration as ChronoDuration, Local, NaiveDate, Utc};
+use events::{ChangeEmitter, TodosChanged};
+use logging::{logged, rejected};
+use rusqlite::{params_from_iter, Connection, ErrorCode, OptionalExtension};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Mutex;
-use tauri::State;
+use std::thread;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Listener, Manager, State, WindowEvent};
+use tauri_plugin_deep_link::DeepLinkExt;
+use tauri_plugin_global_shortcut::ShortcutState;
+use tracing::{debug, info};
+use validation::ValidationError;
+
+const MAX_PRIORITY: u8 = 3;
+/// Color labels a todo can carry. "none" (or no color) clears it.
+const TODO_COLORS: [&str; 6] = ["red", "orange", "yellow", "green", "blue", "purple"];
+/// Longest title accepted, in characters, unless the `max_title_chars`
+/// setting says otherwise.
+const MAX_TITLE_CHARS: usize = 500;
+/// The most `max_title_chars` may be set to.
+const TITLE_LIMIT_CEILING: usize = 10_000;
+/// Settings key holding the title length limit.
+const MAX_TITLE_CHARS_SETTING: &str = "max_title_chars";
+const WRITE_ATTEMPTS: u32 = 5;
+/// Above this many rows the frontend should switch to paginated loading.
+const LARGE_LIST_THRESHOLD: u64 = 1000;
+const RETRY_DELAY: Duration = Duration::from_millis(20);
+/// Compiled statements kept around by `prepare_cached`. Comfortably above the
+/// number of distinct queries the `db_*` functions issue, counting each
+/// padded length of the bulk `IN (...)` statements.
+const STATEMENT_CACHE_CAPACITY: usize = 64;
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
 struct Todo {
     id: u64,
     title: String,
     completed: bool,
     created_at: String,
     deadline: Option<String>,
+    description: Option<String>,
+    /// 0 (none) to `MAX_PRIORITY` (high).
+    priority: u8,
+    /// RFC3339 timestamp of when the todo was last marked complete.
+    completed_at: Option<String>,
+    /// Pinned todos are listed first, whatever their completion or priority.
+    pinned: bool,
+    /// One of `TODO_COLORS`, if a color label was set.
+    color: Option<String>,
+    /// How long the todo is expected to take.
+    estimate_minutes: Option<u32>,
+    /// Minutes before the deadline to remind about it.
+    reminder_minutes: Option<u32>,
+    /// Bumped by every write to the row. Sent back as `expected_version` so
+    /// an edit made from a stale copy is refused instead of overwriting.
+    version: u64,
+    /// RFC3339 timestamp of the last write, for incremental sync.
+    updated_at: String,
+    /// Derived by `enrich` for the frontend; never stored or read back.
+    #[serde(default, skip_deserializing)]
+    is_overdue: bool,
+    #[serde(default, skip_deserializing)]
+    is_due_today: bool,
+    /// Calendar days from today to the deadline's day; negative once past.
+    #[serde(default, skip_deserializing)]
+    days_until_deadline: Option<i64>,
 }
 
-struct AppState {
-    db: Mutex<Connection>,
+/// Fields for a todo that hasn't been inserted yet.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct NewTodo {
+    title: String,
+    completed: bool,
+    deadline: Option<String>,
+    description: Option<String>,
+    priority: u8,
+    estimate_minutes: Option<u32>,
+    reminder_minutes: Option<u32>,
 }
 
-fn init_db(conn: &Connection) {
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS todos (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            title TEXT NOT NULL,
-            completed BOOLEAN NOT NULL DEFAULT 0,
-            created_at TEXT NOT NULL,
-            deadline TEXT
-        )",
-        [],
+/// What the list's sort buttons sort on.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum SortBy {
+    Deadline,
+    Created,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum SortDir {
+    Asc,
+    Desc,
+}
+
+impl SortDir {
+    fn apply(self, ordering: std::cmp::Ordering) -> std::cmp::Ordering {
+        match self {
+            SortDir::Asc => ordering,
+            SortDir::Desc => ordering.reverse(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+enum Filter {
+    #[default]
+    All,
+    Active,
+    Completed,
+    Overdue,
+}
+
+impl Filter {
+    fn matches(self, todo: &Todo, now: DateTime<Local>) -> bool {
+        match self {
+            Filter::All => true,
+            Filter::Active => !todo.completed,
+            Filter::Completed => todo.completed,
+            Filter::Overdue => deadline_status(todo, now).overdue,
+        }
+    }
+}
+
+/// Where `todo` stands against its deadline in the local time zone. The
+/// overdue filter, due-today list, badge and derived fields all use this.
+fn deadline_status(todo: &Todo, now: DateTime<Local>) -> deadline::DeadlineStatus {
+    deadline::status(
+        todo.deadline.as_deref(),
+        todo.completed,
+        now.with_timezone(&Utc),
+        &Local,
     )
-    .expect("Failed to create table");
 }
 
-// --- Database Logic Functions (Testable) ---
+/// Fills in a todo's derived deadline fields as of `now`, from
+/// `deadline_status`.
+fn enrich(mut todo: Todo, now: DateTime<Local>) -> Todo {
+    let status = deadline_status(&todo, now);
+    todo.is_overdue = status.overdue;
+    todo.is_due_today = status.due_today;
+    todo.days_until_deadline = status.days_until;
+    todo
+}
 
-fn db_get_todos(conn: &Connection) -> Vec<Todo> {
-    let mut stmt = conn
-        .prepare("SELECT id, title, completed, created_at, deadline FROM todos")
-        .unwrap();
+fn enrich_all(todos: Vec<Todo>, now: DateTime<Local>) -> Vec<Todo> {
+    todos.into_iter().map(|todo| enrich(todo, now)).collect()
+}
 
-    let todo_iter = stmt
-        .query_map([], |row| {
-            Ok(Todo {
-                id: row.get(0)?,
-                title: row.get(1)?,
-                completed: row.get(2)?,
-                created_at: row.get(3)?,
-                deadline: row.get(4)?,
-            })
-        })
-        .unwrap();
+/// What a command hands back with todos in it, so `mutate_todos` can `enrich`
+/// them whatever shape the result has.
+trait Enriched {
+    fn enriched(self, now: DateTime<Local>) -> Self;
+}
 
-    let mut todos = Vec::new();
-    for todo in todo_iter {
-        todos.push(todo.unwrap());
+impl Enriched for Todo {
+    fn enriched(self, now: DateTime<Local>) -> Self {
+        enrich(self, now)
     }
-    todos
 }
 
-fn db_add_todo(conn: &Connection, title: String, deadline: Option<String>) -> Todo {
-    let created_at = Local::now().to_rfc3339();
+impl<T: Enriched> Enriched for Option<T> {
+    fn enriched(self, now: DateTime<Local>) -> Self {
+        self.map(|value| value.enriched(now))
+    }
+}
 
-    conn.execute(
-        "INSERT INTO todos (title, completed, created_at, deadline) VALUES (?1, ?2, ?3, ?4)",
-        (&title, false, &created_at, &deadline),
-    )
-    .expect("Failed to insert todo");
+impl<T: Enriched, E> Enriched for Result<T, E> {
+    fn enriched(self, now: DateTime<Local>) -> Self {
+        self.map(|value| value.enriched(now))
+    }
+}
 
-    let id = conn.last_insert_rowid() as u64;
+/// Which todos a list or export command should cover. The fields combine with
+/// AND; `ids: Some(vec![])` is an empty selection and matches nothing, while
+/// `ids: None` places no restriction on ids.
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+#[serde(default)]
+struct TodoQuery {
+    ids: Option<Vec<u64>>,
+    #[serde(alias = "status")]
+    filter: Filter,
+    tag: Option<String>,
+    /// Case-insensitive substring of the title or description.
+    text: Option<String>,
+    /// Deadline at or before this one; a date-only bound covers that day.
+    due_before: Option<String>,
+}
 
-    Todo {
-        id,
-        title,
-        completed: false,
-        created_at,
-        deadline,
+impl TodoQuery {
+    /// The query for a command that used to take only a `filter`: callers
+    /// still sending `{ filter }` get what they asked for, and a `filter`
+    /// given next to a `query` overrides the query's.
+    fn or_filter(query: Option<TodoQuery>, filter: Option<Filter>) -> TodoQuery {
+        let mut query = query.unwrap_or_default();
+        if let Some(filter) = filter {
+            query.filter = filter;
+        }
+        query
     }
 }
 
-fn db_update_todo(
-    conn: &Connection,
+/// What the list needs per row. Descriptions and tags are left out to keep
+/// the payload small; `get_todo_detail` has them.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+struct TodoSummary {
     id: u64,
-    title: Option<String>,
-    completed: Option<bool>,
+    title: String,
+    completed: bool,
+    created_at: String,
     deadline: Option<String>,
-) -> bool {
-    let mut success = false;
+    priority: u8,
+    pinned: bool,
+    color: Option<String>,
+    estimate_minutes: Option<u32>,
+    version: u64,
+    tag_count: u64,
+    is_overdue: bool,
+    is_due_today: bool,
+    days_until_deadline: Option<i64>,
+}
 
-    if let Some(t) = title {
-        conn.execute("UPDATE todos SET title = ?1 WHERE id = ?2", (&t, id))
-            .unwrap();
-        success = true;
+/// A todo with everything attached to it.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+struct TodoDetail {
+    #[serde(flatten)]
+    todo: Todo,
+    tags: Vec<String>,
+}
+
+/// A completed todo moved out of `todos` by `db_archive_completed`.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+struct ArchivedTodo {
+    #[serde(flatten)]
+    todo: Todo,
+    tags: Vec<String>,
+    archived_at: String,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+struct TodosWithMeta {
+    todos: Vec<Todo>,
+    total: u64,
+    large: bool,
+}
+
+/// A todo deleted after a sync cursor.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+struct DeletedTodo {
+    id: u64,
+    deleted_at: String,
+}
+
+/// Everything written after a sync cursor: changed or new todos, oldest
+/// write first, and deleted ones.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+struct ModifiedSince {
+    todos: Vec<Todo>,
+    deleted: Vec<DeletedTodo>,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+struct DayGroup {
+    date: String,
+    todos: Vec<Todo>,
+}
+
+/// Todos whose titles match once trimmed and lowercased. `title` is the
+/// oldest one's; `completed_ids` lists the members already done, so the UI
+/// can tell them apart from active copies.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+struct DuplicateGroup {
+    title: String,
+    ids: Vec<u64>,
+    completed_ids: Vec<u64>,
+}
+
+struct AppState {
+    db: Mutex<Connection>,
+    busy: BusyFlag,
+    /// Locked after `db` whenever both are needed.
+    history: Mutex<undo::UndoHistory>,
+    window_saves: window_state::PendingSaves,
+    /// Which database is open and why.
+    database: Mutex<db_location::DbChoice>,
+    /// Set while an encrypted `db` waits for its passphrase; `db` is an empty
+    /// in-memory stand-in until then.
+    lock: encryption::Lock,
+    /// The privacy lock set with `set_app_lock`, checked after `lock`.
+    app_lock: app_lock::AppLock,
+    /// The local HTTP API, while the `http_api_enabled` setting is on.
+    http_api: Mutex<Option<http_api::Server>>,
+}
+
+/// Wraps the command handler so every command goes through the locks first:
+/// while the database or the app is locked, anything not on their allowed
+/// lists fails with `{"kind": "locked"}` before it runs.
+fn guarded<R: tauri::Runtime>(
+    handler: impl Fn(tauri::ipc::Invoke<R>) -> bool + Send + Sync + 'static,
+) -> impl Fn(tauri::ipc::Invoke<R>) -> bool + Send + Sync + 'static {
+    move |invoke| {
+        let command = invoke.message.command().to_string();
+        let refused =
+            invoke
+                .message
+                .webview()
+                .try_state::<AppState>()
+                .and_then(|state| match state.lock.check(&command) {
+                    Err(e) => Some(serde_json::to_value(e)),
+                    Ok(()) => state
+                        .app_lock
+                        .check(&command, Instant::now())
+                        .err()
+                        .map(serde_json::to_value),
+                });
+        match refused {
+            Some(e) => {
+                logging::note_failure();
+                tracing::warn!("refused {} while locked", command);
+                invoke.resolver.reject(e.unwrap_or_default());
+                true
+            }
+            None => handler(invoke),
+        }
     }
-    if let Some(c) = completed {
-        conn.execute("UPDATE todos SET completed = ?1 WHERE id = ?2", (c, id))
-            .unwrap();
-        success = true;
+}
+
+/// Marks that a long-running job (import, backup, maintenance) is working on
+/// the database, so another one doesn't start on top of it.
+#[derive(Default)]
+struct BusyFlag(AtomicBool);
+
+/// Clears the `BusyFlag` it was taken from when dropped.
+struct BusyGuard<'a>(&'a AtomicBool);
+
+impl BusyFlag {
+    fn try_acquire(&self, operation: &str) -> Result<BusyGuard<'_>, String> {
+        let acquired = self
+            .0
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .map(|_| BusyGuard(&self.0))
+            .map_err(|_| {
+                format!(
+                    "Cannot {} while another operation is in progress",
+                    operation
+                )
+            });
+        rejected(operation, acquired)
     }
-    if let Some(d) = deadline {
-        let val = if d.is_empty() { None } else { Some(d) };
-        conn.execute("UPDATE todos SET deadline = ?1 WHERE id = ?2", (val, id))
-            .unwrap();
-        success = true;
+}
+
+impl Drop for BusyGuard<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
     }
+}
 
-    success
+/// Schema migrations, applied in order. `PRAGMA user_version` records how many
+/// have run, so existing entries must never change; append new ones instead.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS todos (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        title TEXT NOT NULL,
+        completed BOOLEAN NOT NULL DEFAULT 0,
+        created_at TEXT NOT NULL,
+        deadline TEXT
+    )",
+    "ALTER TABLE todos ADD COLUMN description TEXT;
+     ALTER TABLE todos ADD COLUMN priority INTEGER NOT NULL DEFAULT 0;",
+    "CREATE TABLE settings (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    )",
+    "CREATE TABLE tags (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        name TEXT NOT NULL UNIQUE
+    );
+    CREATE TABLE todo_tags (
+        todo_id INTEGER NOT NULL,
+        tag_id INTEGER NOT NULL,
+        PRIMARY KEY (todo_id, tag_id)
+    );",
+    "ALTER TABLE todos ADD COLUMN completed_at TEXT",
+    // History is owned by its todo: the trigger drops it when the todo is
+    // deleted, so there's no orphaned audit trail to clean up later.
+    "CREATE TABLE todo_history (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        todo_id INTEGER NOT NULL,
+        changed_at TEXT NOT NULL,
+        field TEXT NOT NULL,
+        old_value TEXT,
+        new_value TEXT
+    );
+    CREATE INDEX todo_history_by_todo ON todo_history (todo_id, id);
+    CREATE TRIGGER todo_history_cascade AFTER DELETE ON todos BEGIN
+        DELETE FROM todo_history WHERE todo_id = OLD.id;
+    END;",
+    "CREATE TABLE notified_deadlines (
+        todo_id INTEGER NOT NULL,
+        deadline TEXT NOT NULL,
+        notified_at TEXT NOT NULL,
+        PRIMARY KEY (todo_id, deadline)
+    );
+    CREATE TRIGGER notified_deadlines_cascade AFTER DELETE ON todos BEGIN
+        DELETE FROM notified_deadlines WHERE todo_id = OLD.id;
+    END;",
+    "ALTER TABLE todos ADD COLUMN pinned INTEGER NOT NULL DEFAULT 0",
+    "ALTER TABLE todos ADD COLUMN color TEXT",
+    "CREATE INDEX idx_todos_completed ON todos (completed);
+    CREATE INDEX idx_todos_deadline ON todos (deadline);
+    CREATE INDEX idx_todos_completed_deadline ON todos (completed, deadline);",
+    "ALTER TABLE todos ADD COLUMN estimate_minutes INTEGER",
+    "ALTER TABLE todos ADD COLUMN version INTEGER NOT NULL DEFAULT 0",
+    // Same columns as `todos`, so rows copy over with `TODO_COLUMNS`. Tags
+    // are kept as a JSON array of names.
+    "CREATE TABLE todos_archive (
+        id INTEGER PRIMARY KEY,
+        title TEXT NOT NULL,
+        completed BOOLEAN NOT NULL,
+        created_at TEXT NOT NULL,
+        deadline TEXT,
+        description TEXT,
+        priority INTEGER NOT NULL DEFAULT 0,
+        completed_at TEXT,
+        pinned INTEGER NOT NULL DEFAULT 0,
+        color TEXT,
+        estimate_minutes INTEGER,
+        version INTEGER NOT NULL DEFAULT 0,
+        tags TEXT NOT NULL DEFAULT '[]',
+        archived_at TEXT NOT NULL
+    )",
+    "ALTER TABLE todos ADD COLUMN reminder_minutes INTEGER;
+     ALTER TABLE todos_archive ADD COLUMN reminder_minutes INTEGER;",
+    // Every write bumps `version`, so touching `updated_at` on that catches
+    // them all. Deletions leave a tombstone for sync clients; a row inserted
+    // again under the same id (undo, unarchive) clears it.
+    "ALTER TABLE todos ADD COLUMN updated_at TEXT;
+     UPDATE todos SET updated_at = created_at;
+     ALTER TABLE todos_archive ADD COLUMN updated_at TEXT;
+     UPDATE todos_archive SET updated_at = archived_at;
+     CREATE INDEX idx_todos_updated_at ON todos (updated_at);
+     CREATE TABLE deleted_todos (
+         id INTEGER PRIMARY KEY,
+         deleted_at TEXT NOT NULL
+     );
+     CREATE TRIGGER todos_touch_insert AFTER INSERT ON todos BEGIN
+         UPDATE todos SET updated_at = strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')
+         WHERE id = NEW.id AND updated_at IS NULL;
+         DELETE FROM deleted_todos WHERE id = NEW.id;
+     END;
+     CREATE TRIGGER todos_touch_update AFTER UPDATE OF version ON todos BEGIN
+         UPDATE todos SET updated_at = strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')
+         WHERE id = NEW.id;
+     END;
+     CREATE TRIGGER todos_tombstone AFTER DELETE ON todos BEGIN
+         INSERT OR REPLACE INTO deleted_todos (id, deleted_at)
+         VALUES (OLD.id, strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now'));
+     END;",
+    // Deadlines with a time are stored in UTC from here on. Older ones were
+    // local times without an offset, or RFC 3339 in any offset; SQLite's
+    // 'utc' modifier reads the former in the system time zone. Dates stay
+    // as they are.
+    "UPDATE todos SET deadline = COALESCE(
+         CASE WHEN deadline GLOB '*[Zz]' OR deadline GLOB '*[+-][0-9][0-9]:[0-9][0-9]'
+              THEN strftime('%Y-%m-%dT%H:%M:%S+00:00', deadline)
+              ELSE strftime('%Y-%m-%dT%H:%M:%S+00:00', deadline, 'utc') END,
+         deadline)
+     WHERE length(deadline) > 10;
+     UPDATE todos_archive SET deadline = COALESCE(
+         CASE WHEN deadline GLOB '*[Zz]' OR deadline GLOB '*[+-][0-9][0-9]:[0-9][0-9]'
+              THEN strftime('%Y-%m-%dT%H:%M:%S+00:00', deadline)
+              ELSE strftime('%Y-%m-%dT%H:%M:%S+00:00', deadline, 'utc') END,
+         deadline)
+     WHERE length(deadline) > 10;
+     UPDATE notified_deadlines SET deadline = COALESCE(
+         CASE WHEN deadline GLOB '*[Zz]' OR deadline GLOB '*[+-][0-9][0-9]:[0-9][0-9]'
+              THEN strftime('%Y-%m-%dT%H:%M:%S+00:00', deadline)
+              ELSE strftime('%Y-%m-%dT%H:%M:%S+00:00', deadline, 'utc') END,
+         deadline)
+     WHERE length(deadline) > 10;",
+    // Tag links go with their todo. SQLite can't add a foreign key to an
+    // existing table, so it is rebuilt, dropping links already orphaned.
+    "CREATE TABLE todo_tags_new (
+        todo_id INTEGER NOT NULL REFERENCES todos (id) ON DELETE CASCADE,
+        tag_id INTEGER NOT NULL,
+        PRIMARY KEY (todo_id, tag_id)
+    );
+    INSERT INTO todo_tags_new (todo_id, tag_id)
+        SELECT todo_id, tag_id FROM todo_tags WHERE todo_id IN (SELECT id FROM todos);
+    DROP TABLE todo_tags;
+    ALTER TABLE todo_tags_new RENAME TO todo_tags;",
+    // A random (version 4) UUID per todo, the same on every machine, so
+    // `merge_export` can match rows whose ids differ. It follows the todo
+    // into the archive and back.
+    "ALTER TABLE todos ADD COLUMN uuid TEXT;
+     ALTER TABLE todos_archive ADD COLUMN uuid TEXT;
+     UPDATE todos SET uuid = lower(hex(randomblob(4)) || '-' || hex(randomblob(2)) || '-4' ||
+               substr(hex(randomblob(2)), 2) || '-' || substr('89AB', 1 + (random() & 3), 1) ||
+               substr(hex(randomblob(2)), 2) || '-' || hex(randomblob(6)));
+     CREATE UNIQUE INDEX idx_todos_uuid ON todos (uuid);
+     CREATE TRIGGER todos_uuid_insert AFTER INSERT ON todos WHEN NEW.uuid IS NULL BEGIN
+         UPDATE todos SET uuid = lower(hex(randomblob(4)) || '-' || hex(randomblob(2)) || '-4' ||
+                   substr(hex(randomblob(2)), 2) || '-' || substr('89AB', 1 + (random() & 3), 1) ||
+                   substr(hex(randomblob(2)), 2) || '-' || hex(randomblob(6)))
+         WHERE id = NEW.id;
+     END;",
+    // Tombstones keep the uuid, so `merge_export` doesn't bring a todo
+    // deleted here back from another machine's export.
+    "ALTER TABLE deleted_todos ADD COLUMN uuid TEXT;
+     CREATE INDEX idx_deleted_todos_uuid ON deleted_todos (uuid);
+     DROP TRIGGER todos_tombstone;
+     CREATE TRIGGER todos_tombstone AFTER DELETE ON todos BEGIN
+         INSERT OR REPLACE INTO deleted_todos (id, deleted_at, uuid)
+         VALUES (OLD.id, strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now'), OLD.uuid);
+     END;",
+];
+
+fn run_migrations(conn: &Connection) -> rusqlite::Result<()> {
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    for (i, sql) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.unchecked_transaction()?;
+        tx.execute_batch(sql)?;
+        tx.pragma_update(None, "user_version", i + 1)?;
+        tx.commit()?;
+    }
+    Ok(())
 }
 
-fn db_delete_todo(conn: &Connection, id: u64) -> bool {
-    let count = conn
-        .execute("DELETE FROM todos WHERE id = ?1", (id,))
-        .unwrap();
-    count > 0
+/// Prepares a freshly opened connection and brings its schema up to date.
+/// Foreign keys are a per-connection setting in SQLite, so every connection
+/// must come through here.
+fn init_db(conn: &Connection) {
+    conn.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+    conn.pragma_update(None, "foreign_keys", true)
+        .expect("Failed to enable foreign keys");
+    run_migrations(conn).expect("Failed to migrate database");
 }
 
-// --- Tauri Commands ---
+/// Phrase `reset_database` must be given before it wipes anything.
+const RESET_CONFIRMATION: &str = "DELETE ALL";
 
-#[tauri::command]
-fn get_todos(state: State<AppState>) -> Vec<Todo> {
-    let conn = state.db.lock().unwrap();
-    db_get_todos(&conn)
+/// Drops every table and rebuilds the schema from scratch through the
+/// migrations, leaving an empty database.
+fn db_reset(conn: &Connection) -> rusqlite::Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    let tables: Vec<String> = tx
+        .prepare(
+            "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%'",
+        )?
+        .query_map([], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    for table in tables {
+        tx.execute_batch(&format!("DROP TABLE \"{}\"", table))?;
+    }
+    tx.pragma_update(None, "user_version", 0)?;
+    tx.commit()?;
+    run_migrations(conn)
 }
 
-#[tauri::command]
-fn add_todo(title: String, deadline: Option<String>, state: State<AppState>) -> Todo {
-    let conn = state.db.lock().unwrap();
-    db_add_todo(&conn, title, deadline)
+fn is_busy_error(err: &rusqlite::Error) -> bool {
+    matches!(
+        err,
+        rusqlite::Error::SqliteFailure(e, _)
+            if matches!(e.code, ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked)
+    )
 }
 
-#[tauri::command]
-fn update_todo(
-    id: u64,
-    title: Option<String>,
-    completed: Option<bool>,
-    deadline: Option<String>,
-    state: State<AppState>,
-) -> bool {
-    let conn = state.db.lock().unwrap();
-    db_update_todo(&conn, id, title, completed, deadline)
+/// Runs `f` up to `attempts` times, sleeping briefly between tries while SQLite
+/// reports the database as busy or locked. Any other error is returned immediately.
+fn with_retry<T>(mut f: impl FnMut() -> rusqlite::Result<T>, attempts: u32) -> rusqlite::Result<T> {
+    let mut attempt = 1;
+    loop {
+        match f() {
+            Err(e) if is_busy_error(&e) && attempt < attempts => {
+                debug!("database busy, retrying (attempt {}): {}", attempt, e);
+                thread::sleep(RETRY_DELAY);
+                attempt += 1;
+            }
+            Err(e) => {
+                tracing::error!("database call failed after {} attempt(s): {}", attempt, e);
+                return Err(e);
+            }
+            result => return result,
+        }
+    }
 }
 
-#[tauri::command]
-fn delete_todo(id: u64, state: State<AppState>) -> bool {
-    let conn = state.db.lock().unwrap();
-    db_delete_todo(&conn, id)
+/// Runs `f` inside a savepoint. Unlike `unchecked_transaction` this nests, so
+/// it also works when the caller already opened a transaction (as
+/// `undo::record` does). Changes made by `f` are rolled back if it fails.
+fn with_savepoint<T>(
+    conn: &Connection,
+    f: impl FnOnce() -> rusqlite::Result<T>,
+) -> rusqlite::Result<T> {
+    conn.execute_batch("SAVEPOINT nested")?;
+    match f() {
+        Ok(value) => {
+            conn.execute_batch("RELEASE nested")?;
+            Ok(value)
+        }
+        Err(e) => {
+            conn.execute_batch("ROLLBACK TO nested; RELEASE nested")?;
+            Err(e)
+        }
+    }
 }
 
-fn main() {
-    let db_connection = Connection::open("todos.db").expect("Failed to open database");
-    init_db(&db_connection);
+/// Parses a stored deadline in the local time zone; see
+/// `deadline::deadline_in` for the formats.
+fn parse_deadline(value: &str) -> Option<DateTime<Local>> {
+    deadline::deadline_in(value, &Local)
+}
 
-    tauri::Builder::default()
-        .manage(AppState {
-            db: Mutex::new(db_connection),
+/// Characters that take no space and would make equal-looking titles
+/// differ. Joiners are kept, since emoji sequences and some scripts need
+/// them.
+const INVISIBLE_CHARS: [char; 3] = ['\u{200B}', '\u{2060}', '\u{FEFF}'];
+
+/// A title as it is stored: in Unicode NFC, without control or invisible
+/// characters, trimmed and with runs of whitespace collapsed to one space.
+fn clean_title(title: &str) -> String {
+    let composed = icu_normalizer::ComposingNormalizerBorrowed::new_nfc().normalize(title);
+    composed
+        .split_whitespace()
+        .map(|word| {
+            word.chars()
+                .filter(|c| !c.is_control() && !INVISIBLE_CHARS.contains(c))
+                .collect::<String>()
         })
-        .invoke_handler(tauri::generate_handler![
-            get_todos,
-            add_todo,
-            update_todo,
-            delete_todo
-        ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// The title length limit in characters; see `MAX_TITLE_CHARS`.
+fn max_title_chars(conn: &Connection) -> usize {
+    db_get_setting(conn, MAX_TITLE_CHARS_SETTING)
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(MAX_TITLE_CHARS)
+}
 
-    fn setup_test_db() -> Connection {
-        let conn = Connection::open_in_memory().unwrap();
-        init_db(&conn);
-        conn
+fn check_max_title_chars(max: usize) -> Result<(), String> {
+    if !(1..=TITLE_LIMIT_CEILING).contains(&max) {
+        return Err(format!(
+            "The title length limit must be between 1 and {}",
+            TITLE_LIMIT_CEILING
+        ));
     }
+    Ok(())
+}
 
-    #[test]
-    fn test_add_and_get_todo() {
-        let conn = setup_test_db();
+fn set_max_title_chars(conn: &Connection, max: usize) -> Result<(), String> {
+    check_max_title_chars(max)?;
+    db_set_setting(conn, MAX_TITLE_CHARS_SETTING, &max.to_string());
+    Ok(())
+}
 
-        let todo = db_add_todo(
-            &conn,
-            "Test Todo".to_string(),
-            Some("2023-12-31".to_string()),
-        );
+/// Cleans a title with `clean_title` and rejects it if that leaves nothing
+/// or more than `max_chars` characters.
+fn validate_title(title: &str, max_chars: usize) -> Result<String, ValidationError> {
+    let title = clean_title(title);
+    if title.is_empty() {
+        return Err(ValidationError::new(
+            "title",
+            validation::EMPTY,
+            "Title is empty",
+        ));
+    }
+    let chars = title.chars().count();
+    if chars > max_chars {
+        return Err(ValidationError::new(
+            "title",
+            validation::TOO_LONG,
+            format!(
+                "Title is {} characters long; the limit is {}",
+                chars, max_chars
+            ),
+        ));
+    }
+    Ok(title)
+}
 
-        assert_eq!(todo.title, "Test Todo");
-        assert_eq!(todo.completed, false);
-        assert_eq!(todo.deadline, Some("2023-12-31".to_string()));
+/// Treats a blank deadline as none, rejects anything `parse_deadline` can't
+/// read and normalizes the rest for storage with `deadline::normalize`.
+fn validate_deadline(deadline: Option<String>) -> Result<Option<String>, ValidationError> {
+    match deadline.as_deref().map(str::trim) {
+        None | Some("") => Ok(None),
+        Some(d) => deadline::normalize(d, &Local)
+            .ok_or_else(|| {
+                ValidationError::new(
+                    "deadline",
+                    validation::UNREADABLE,
+                    format!("Invalid deadline '{}'", d),
+                )
+            })
+            .map(Some),
+    }
+}
 
-        let todos = db_get_todos(&conn);
-        assert_eq!(todos.len(), 1);
-        assert_eq!(todos[0].title, "Test Todo");
+/// `validate_deadline` for a deadline change, where an empty string means
+/// clearing the deadline.
+fn validate_deadline_change(deadline: Option<String>) -> Result<Option<String>, ValidationError> {
+    deadline
+        .map(|d| validate_deadline(Some(d)).map(Option::unwrap_or_default))
+        .transpose()
+}
+
+fn validate_priority(priority: u8) -> Result<u8, ValidationError> {
+    if priority > MAX_PRIORITY {
+        return Err(ValidationError::new(
+            "priority",
+            validation::OUT_OF_RANGE,
+            format!("Priority must be between 0 and {}", MAX_PRIORITY),
+        ));
     }
+    Ok(priority)
+}
 
-    #[test]
-    fn test_update_todo() {
-        let conn = setup_test_db();
-        let todo = db_add_todo(&conn, "Update Me".to_string(), None);
+/// A color label as stored: one of `TODO_COLORS` in lowercase, or `None`
+/// for `None`, empty or "none".
+fn validate_color(color: Option<&str>) -> Result<Option<String>, ValidationError> {
+    match color.map(|c| c.trim().to_lowercase()) {
+        None => Ok(None),
+        Some(c) if c.is_empty() || c == "none" => Ok(None),
+        Some(c) if TODO_COLORS.contains(&c.as_str()) => Ok(Some(c)),
+        Some(c) => Err(ValidationError::new(
+            "color",
+            validation::UNKNOWN,
+            format!(
+                "Unknown color {:?}; expected one of {} or none",
+                c,
+                TODO_COLORS.join(", ")
+            ),
+        )),
+    }
+}
 
-        // Update completion
-        let updated = db_update_todo(&conn, todo.id, None, Some(true), None);
-        assert!(updated);
+/// Checks every field of a todo about to be created, reporting all the
+/// failures together, and returns it cleaned up for storage. Used by
+/// `add_todo` and the importers.
+fn validate_new_todo(new: NewTodo, max_chars: usize) -> Result<NewTodo, Vec<ValidationError>> {
+    let mut checks = validation::Checks::default();
+    let todo = NewTodo {
+        title: checks.check(validate_title(&new.title, max_chars)),
+        deadline: checks.check(validate_deadline(new.deadline)),
+        priority: checks.check(validate_priority(new.priority)),
+        estimate_minutes: new.estimate_minutes.filter(|&m| m > 0),
+        reminder_minutes: new.reminder_minutes.filter(|&m| m > 0),
+        ..new
+    };
+    checks.finish(todo)
+}
 
-        let todos = db_get_todos(&conn);
-        assert!(todos[0].completed);
+/// `validate_new_todo` for the fields an edit changes. Used by `update_todo`
+/// and `bulk_update_todos`.
+fn validate_update(
+    changes: TodoChanges,
+    max_chars: usize,
+) -> Result<TodoChanges, Vec<ValidationError>> {
+    let mut checks = validation::Checks::default();
+    let changes = TodoChanges {
+        title: checks.check(
+            changes
+                .title
+                .as_deref()
+                .map(|title| validate_title(title, max_chars))
+                .transpose(),
+        ),
+        deadline: checks.check(validate_deadline_change(changes.deadline)),
+        ..changes
+    };
+    checks.finish(changes)
+}
 
-        // Update title
-        db_update_todo(&conn, todo.id, Some("Updated".to_string()), None, None);
-        let todos = db_get_todos(&conn);
-        assert_eq!(todos[0].title, "Updated");
+// --- Database Logic Functions (Testable) ---
+
+fn db_get_setting(conn: &Connection, key: &str) -> Option<String> {
+    conn.prepare_cached("SELECT value FROM settings WHERE key = ?1")
+        .unwrap()
+        .query_row((key,), |row| row.get(0))
+        .optional()
+        .unwrap()
+}
+
+fn db_set_setting(conn: &Connection, key: &str, value: &str) {
+    with_retry(
+        || {
+            conn.prepare_cached(
+                "INSERT INTO settings (key, value) VALUES (?1, ?2)
+                 ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+            )?
+            .execute((key, value))
+        },
+        WRITE_ATTEMPTS,
+    )
+    .expect("Failed to save setting");
+}
+
+const TODO_COLUMNS: &str =
+    "id, title, completed, created_at, deadline, description, priority, completed_at, pinned, color, estimate_minutes, version, reminder_minutes, updated_at";
+
+/// Timestamps the app generates (`created_at`, `completed_at`, history) are
+/// stored in UTC so a database moved between machines stays consistent, and
+/// converted to the local zone whenever they are read.
+fn now_utc() -> String {
+    Utc::now().to_rfc3339()
+}
+
+/// Presents a stored RFC 3339 timestamp in the local zone.
+fn to_local(rfc3339: &str) -> Option<String> {
+    DateTime::parse_from_rfc3339(rfc3339)
+        .ok()
+        .map(|dt| dt.with_timezone(&Local).to_rfc3339())
+}
+
+/// The inverse of `to_local`, for writing a timestamp read back out of a
+/// `Todo` (undo, merge).
+fn to_utc(rfc3339: &str) -> Option<String> {
+    DateTime::parse_from_rfc3339(rfc3339)
+        .ok()
+        .map(|dt| dt.with_timezone(&Utc).to_rfc3339())
+}
+
+/// Values that aren't RFC 3339 (hand-edited rows) are passed through as is.
+fn local_timestamp(stored: String) -> String {
+    to_local(&stored).unwrap_or(stored)
+}
+
+/// Stands in for a missing `created_at`, which rows written by old versions
+/// or other tools may have.
+const MISSING_TIMESTAMP: &str = "1970-01-01T00:00:00+00:00";
+
+fn todo_from_row(row: &rusqlite::Row) -> rusqlite::Result<Todo> {
+    let id = row.get(0)?;
+    let created_at = row.get::<_, Option<String>>(3)?.unwrap_or_else(|| {
+        tracing::warn!("todo {} has no created_at, using {}", id, MISSING_TIMESTAMP);
+        MISSING_TIMESTAMP.to_string()
+    });
+    // Filled from `created_at` when the column was added, so that is the
+    // fallback here too.
+    let updated_at = row
+        .get::<_, Option<String>>(13)?
+        .unwrap_or_else(|| created_at.clone());
+    Ok(Todo {
+        id,
+        title: row.get(1)?,
+        completed: row.get(2)?,
+        created_at: local_timestamp(created_at),
+        deadline: row.get(4)?,
+        description: row.get(5)?,
+        priority: row.get(6)?,
+        completed_at: row.get::<_, Option<String>>(7)?.map(local_timestamp),
+        pinned: row.get(8)?,
+        color: row.get(9)?,
+        estimate_minutes: row.get(10)?,
+        version: row.get(11)?,
+        reminder_minutes: row.get(12)?,
+        updated_at: local_timestamp(updated_at),
+        is_overdue: false,
+        is_due_today: false,
+        days_until_deadline: None,
+    })
+}
+
+/// All todos, pinned ones first and otherwise in creation order. Ids grow
+/// with every insert, so ordering by id is creation order without depending
+/// on how `created_at` was written.
+fn db_get_todos(conn: &Connection) -> Vec<Todo> {
+    let mut stmt = conn
+        .prepare_cached(&format!(
+            "SELECT {} FROM todos ORDER BY pinned DESC, id",
+            TODO_COLUMNS
+        ))
+        .unwrap();
+
+    let todo_iter = stmt.query_map([], todo_from_row).unwrap();
+
+    let mut todos = Vec::new();
+    for todo in todo_iter {
+        todos.push(todo.unwrap());
     }
+    todos
+}
 
-    #[test]
-    fn test_delete_todo() {
-        let conn = setup_test_db();
-        let todo = db_add_todo(&conn, "Delete Me".to_string(), None);
+fn db_get_todo(conn: &Connection, id: u64) -> Option<Todo> {
+    conn.prepare_cached(&format!("SELECT {} FROM todos WHERE id = ?1", TODO_COLUMNS))
+        .unwrap()
+        .query_row((id,), todo_from_row)
+        .optional()
+        .unwrap()
+}
 
-        let todos_before = db_get_todos(&conn);
-        assert_eq!(todos_before.len(), 1);
+/// Incomplete todos with a deadline that sorts as text before the parameter.
+const OVERDUE_CANDIDATES: &str = "completed = 0 AND deadline IS NOT NULL AND deadline < ?";
+const BY_COMPLETION: &str = "completed = ?";
+const WITH_TAG: &str = "id IN (SELECT todo_tags.todo_id FROM todo_tags
+     JOIN tags ON tags.id = todo_tags.tag_id WHERE tags.name = ?)";
+const DUE_CANDIDATES: &str = "deadline IS NOT NULL AND deadline < ?";
+const REMINDER_CANDIDATES: &str =
+    "completed = 0 AND deadline IS NOT NULL AND reminder_minutes IS NOT NULL";
 
-        let deleted = db_delete_todo(&conn, todo.id);
-        assert!(deleted);
+/// `SELECT` of the todos matching `condition`, in `db_get_todos` order.
+fn todos_where_sql(condition: &str) -> String {
+    format!(
+        "SELECT {} FROM todos WHERE {} ORDER BY pinned DESC, id",
+        TODO_COLUMNS, condition
+    )
+}
 
-        let todos_after = db_get_todos(&conn);
-        assert_eq!(todos_after.len(), 0);
+fn db_get_todos_where(
+    conn: &Connection,
+    condition: &str,
+    params: impl rusqlite::Params,
+) -> Vec<Todo> {
+    let mut stmt = conn.prepare_cached(&todos_where_sql(condition)).unwrap();
+    let todos = stmt.query_map(params, todo_from_row).unwrap();
+    todos.map(|todo| todo.unwrap()).collect()
+}
+
+/// A date that every stored deadline at or before `due` sorts below as text.
+/// Deadlines are stored in several formats, so SQL can only narrow rows down
+/// by a plain text comparison on the indexed column: every format starts
+/// with the date, and anything dated two days later is after `due` even at
+/// a +14:00 offset. The caller then checks the candidates exactly.
+fn deadline_text_bound(due: DateTime<Local>) -> String {
+    (due.date_naive() + ChronoDuration::days(2))
+        .format("%Y-%m-%d")
+        .to_string()
+}
+
+/// Incomplete todos whose deadline has passed.
+fn db_get_overdue_todos(conn: &Connection, now: DateTime<Local>) -> Vec<Todo> {
+    db_get_todos_where(conn, OVERDUE_CANDIDATES, (deadline_text_bound(now),))
+        .into_iter()
+        .filter(|todo| Filter::Overdue.matches(todo, now))
+        .collect()
+}
+
+/// How much each priority step adds to the urgency score.
+const URGENCY_PER_PRIORITY: f64 = 2.0;
+/// Score of a todo that is due right now; it falls off as the deadline gets
+/// further away.
+const URGENCY_DUE_NOW: f64 = 8.0;
+/// Overdue todos score above anything not yet due, rising by a point a day
+/// for up to `URGENCY_MAX_OVERDUE_DAYS`.
+const URGENCY_OVERDUE: f64 = 10.0;
+const URGENCY_MAX_OVERDUE_DAYS: f64 = 7.0;
+
+/// Score for sorting by urgency; higher is more urgent. Combines the
+/// priority with how close the deadline is, or how long ago it passed.
+/// Todos without a (readable) deadline are ranked on priority alone.
+fn compute_urgency(todo: &Todo, now: DateTime<Local>) -> f64 {
+    let priority = f64::from(todo.priority.min(MAX_PRIORITY)) * URGENCY_PER_PRIORITY;
+    let Some(deadline) = todo.deadline.as_deref().and_then(parse_deadline) else {
+        return priority;
+    };
+    let days = (deadline - now).num_minutes() as f64 / (24.0 * 60.0);
+    let deadline_score = if days < 0.0 {
+        URGENCY_OVERDUE + (-days).min(URGENCY_MAX_OVERDUE_DAYS)
+    } else {
+        URGENCY_DUE_NOW / (1.0 + days)
+    };
+    priority + deadline_score
+}
+
+/// Incomplete todos, most urgent first by `compute_urgency`. Ties keep the
+/// usual order.
+fn db_get_todos_by_urgency(conn: &Connection, now: DateTime<Local>) -> Vec<Todo> {
+    let mut todos = db_get_todos_where(conn, BY_COMPLETION, (false,));
+    todos.sort_by(|a, b| compute_urgency(b, now).total_cmp(&compute_urgency(a, now)));
+    todos
+}
+
+/// The `n` incomplete todos to focus on: pinned ones first, then the rest,
+/// each most urgent first.
+fn db_get_focus(conn: &Connection, now: DateTime<Local>, n: u32) -> Vec<Todo> {
+    let mut todos = db_get_todos_by_urgency(conn, now);
+    todos.sort_by_key(|todo| !todo.pinned);
+    todos.truncate(n as usize);
+    todos
+}
+
+/// Sorts `todos` the way the list shows them: pinned first, then incomplete
+/// before completed, then by `sort` in `dir`. Todos without a readable
+/// deadline come last in either direction, and ties stay in id order.
+fn sort_todos(todos: &mut [Todo], sort: SortBy, dir: SortDir) {
+    todos.sort_by(|a, b| {
+        let by_field = match sort {
+            SortBy::Deadline => {
+                let due = |todo: &Todo| todo.deadline.as_deref().and_then(parse_deadline);
+                match (due(a), due(b)) {
+                    (Some(a), Some(b)) => dir.apply(a.cmp(&b)),
+                    (a, b) => a.is_none().cmp(&b.is_none()),
+                }
+            }
+            SortBy::Created => {
+                let created = |todo: &Todo| DateTime::parse_from_rfc3339(&todo.created_at).ok();
+                dir.apply(created(a).cmp(&created(b)))
+            }
+        };
+        b.pinned
+            .cmp(&a.pinned)
+            .then(a.completed.cmp(&b.completed))
+            .then(by_field)
+            .then(a.id.cmp(&b.id))
+    });
+}
+
+/// Where `id` falls in the list sorted by `sort_todos`, counting from 0, or
+/// `None` if there is no such todo.
+fn db_index_of(conn: &Connection, id: u64, sort: SortBy, dir: SortDir) -> Option<usize> {
+    let mut todos = db_get_todos(conn);
+    sort_todos(&mut todos, sort, dir);
+    todos.iter().position(|todo| todo.id == id)
+}
+
+/// Incomplete todos due on `now`'s calendar day, earliest deadline first.
+/// Date-only deadlines last until the end of the day, so they come after
+/// any with a time. Todos overdue from earlier days are left out.
+fn db_get_due_today(conn: &Connection, now: DateTime<Local>) -> Vec<Todo> {
+    let mut due: Vec<(DateTime<Local>, Todo)> =
+        db_get_todos_where(conn, OVERDUE_CANDIDATES, (deadline_text_bound(now),))
+            .into_iter()
+            .filter(|todo| deadline_status(todo, now).due_today)
+            .filter_map(|todo| Some((parse_deadline(todo.deadline.as_deref()?)?, todo)))
+            .collect();
+    due.sort_by_key(|(deadline, _)| *deadline);
+    due.into_iter().map(|(_, todo)| todo).collect()
+}
+
+/// Incomplete todos whose reminder has gone off but whose deadline hasn't
+/// passed yet, i.e. `deadline - reminder_minutes <= now < deadline`,
+/// earliest deadline first.
+fn db_get_pending_reminders(conn: &Connection, now: DateTime<Local>) -> Vec<Todo> {
+    let mut pending: Vec<(DateTime<Local>, Todo)> =
+        db_get_todos_where(conn, REMINDER_CANDIDATES, ())
+            .into_iter()
+            .filter_map(|todo| {
+                let deadline = todo.deadline.as_deref().and_then(parse_deadline)?;
+                let remind_at = deadline - ChronoDuration::minutes(todo.reminder_minutes?.into());
+                (remind_at <= now && now < deadline).then_some((deadline, todo))
+            })
+            .collect();
+    pending.sort_by_key(|(deadline, _)| *deadline);
+    pending.into_iter().map(|(_, todo)| todo).collect()
+}
+
+fn db_get_todos_filtered(conn: &Connection, filter: Filter, now: DateTime<Local>) -> Vec<Todo> {
+    match filter {
+        Filter::All => db_get_todos(conn),
+        Filter::Active => db_get_todos_where(conn, BY_COMPLETION, (false,)),
+        Filter::Completed => db_get_todos_where(conn, BY_COMPLETION, (true,)),
+        Filter::Overdue => db_get_overdue_todos(conn, now),
+    }
+}
+
+/// A title or search text cleaned by `clean_title` and lowercased.
+/// Duplicate detection and text search both compare through this, so they
+/// agree on what matches.
+fn normalize_title(text: &str) -> String {
+    clean_title(text).to_lowercase()
+}
+
+/// The todos matching every field of `query` that is set. The WHERE clause
+/// is assembled from fixed conditions with the values bound as parameters;
+/// deadline conditions are narrowed in SQL and decided exactly here, and
+/// `ids` and `text` are decided here alone, the text through
+/// `normalize_title`. A `due_before` that isn't a valid deadline matches
+/// nothing.
+fn db_query_todos(conn: &Connection, query: &TodoQuery, now: DateTime<Local>) -> Vec<Todo> {
+    use rusqlite::types::Value;
+
+    let mut conditions: Vec<&str> = Vec::new();
+    let mut params: Vec<Value> = Vec::new();
+    match query.filter {
+        Filter::All => {}
+        Filter::Active | Filter::Completed => {
+            conditions.push(BY_COMPLETION);
+            params.push(Value::from(query.filter == Filter::Completed));
+        }
+        Filter::Overdue => {
+            conditions.push(OVERDUE_CANDIDATES);
+            params.push(Value::from(deadline_text_bound(now)));
+        }
+    }
+    if let Some(tag) = &query.tag {
+        conditions.push(WITH_TAG);
+        params.push(Value::from(tag.trim().to_string()));
+    }
+    let text = query
+        .text
+        .as_deref()
+        .map(normalize_title)
+        .filter(|t| !t.is_empty());
+    let due_before = match query.due_before.as_deref() {
+        Some(bound) => match parse_deadline(bound) {
+            Some(due) => Some(due),
+            None => return Vec::new(),
+        },
+        None => None,
+    };
+    if let Some(due) = due_before {
+        conditions.push(DUE_CANDIDATES);
+        params.push(Value::from(deadline_text_bound(due)));
+    }
+
+    let todos = if conditions.is_empty() {
+        db_get_todos(conn)
+    } else {
+        db_get_todos_where(conn, &conditions.join(" AND "), params_from_iter(params))
+    };
+    todos
+        .into_iter()
+        .filter(|todo| query.filter.matches(todo, now))
+        .filter(|todo| {
+            due_before.is_none_or(|bound| {
+                todo.deadline
+                    .as_deref()
+                    .and_then(parse_deadline)
+                    .is_some_and(|due| due <= bound)
+            })
+        })
+        .filter(|todo| query.ids.as_ref().is_none_or(|ids| ids.contains(&todo.id)))
+        .filter(|todo| {
+            text.as_ref().is_none_or(|text| {
+                normalize_title(&todo.title).contains(text)
+                    || todo
+                        .description
+                        .as_deref()
+                        .is_some_and(|d| normalize_title(d).contains(text))
+            })
+        })
+        .collect()
+}
+
+/// Incomplete todos that were never given a deadline.
+fn db_get_todos_without_deadline(conn: &Connection) -> Vec<Todo> {
+    let mut stmt = conn
+        .prepare_cached(&format!(
+            "SELECT {} FROM todos
+             WHERE completed = 0 AND (deadline IS NULL OR deadline = '')",
+            TODO_COLUMNS
+        ))
+        .unwrap();
+    let todos = stmt.query_map([], todo_from_row).unwrap();
+    todos.map(|todo| todo.unwrap()).collect()
+}
+
+/// The `limit` most recently created todos, newest first. `created_at` is
+/// RFC 3339, which sorts chronologically as text only while every timestamp
+/// carries the same UTC offset. New rows are stored in UTC; rows written
+/// before that carry the local offset of the day and can come back slightly
+/// out of order around a DST change.
+fn db_get_recent(conn: &Connection, limit: u32) -> Vec<Todo> {
+    let mut stmt = conn
+        .prepare_cached(&format!(
+            "SELECT {} FROM todos ORDER BY created_at DESC, id DESC LIMIT ?1",
+            TODO_COLUMNS
+        ))
+        .unwrap();
+    let todos = stmt.query_map((limit,), todo_from_row).unwrap();
+    todos.map(|todo| todo.unwrap()).collect()
+}
+
+/// Todos written after `since`, an RFC3339 timestamp in any offset, in the
+/// order they were written. `since` is compared as UTC text, the way
+/// `updated_at` is stored.
+fn db_get_modified_since(conn: &Connection, since: &str) -> Vec<Todo> {
+    let Some(since) = to_utc(since) else {
+        return Vec::new();
+    };
+    let mut stmt = conn
+        .prepare_cached(&format!(
+            "SELECT {} FROM todos WHERE updated_at > ?1 ORDER BY updated_at, id",
+            TODO_COLUMNS
+        ))
+        .unwrap();
+    let todos = stmt.query_map((since,), todo_from_row).unwrap();
+    todos.map(|todo| todo.unwrap()).collect()
+}
+
+/// Todos deleted after `since`, oldest deletion first.
+fn db_get_deleted_since(conn: &Connection, since: &str) -> Vec<DeletedTodo> {
+    let Some(since) = to_utc(since) else {
+        return Vec::new();
+    };
+    let mut stmt = conn
+        .prepare_cached(
+            "SELECT id, deleted_at FROM deleted_todos WHERE deleted_at > ?1
+             ORDER BY deleted_at, id",
+        )
+        .unwrap();
+    let deleted = stmt
+        .query_map((since,), |row| {
+            Ok(DeletedTodo {
+                id: row.get(0)?,
+                deleted_at: local_timestamp(row.get(1)?),
+            })
+        })
+        .unwrap();
+    deleted.map(|row| row.unwrap()).collect()
+}
+
+/// Groups of two or more todos with the same title once normalized by
+/// `normalize_title`. Completed and active todos are grouped together.
+/// Groups and their ids are in creation order.
+fn db_find_duplicates(conn: &Connection) -> Vec<DuplicateGroup> {
+    let mut groups: Vec<DuplicateGroup> = Vec::new();
+    let mut by_key: HashMap<String, usize> = HashMap::new();
+    let mut todos = db_get_todos(conn);
+    todos.sort_by_key(|todo| todo.id);
+    for todo in todos {
+        let key = normalize_title(&todo.title);
+        let index = *by_key.entry(key).or_insert_with(|| {
+            groups.push(DuplicateGroup {
+                title: todo.title.trim().to_string(),
+                ids: Vec::new(),
+                completed_ids: Vec::new(),
+            });
+            groups.len() - 1
+        });
+        groups[index].ids.push(todo.id);
+        if todo.completed {
+            groups[index].completed_ids.push(todo.id);
+        }
+    }
+    groups.retain(|group| group.ids.len() > 1);
+    groups
+}
+
+/// The oldest incomplete todo whose title matches `title` once both are
+/// normalized by `normalize_title`.
+fn db_find_active_duplicate(conn: &Connection, title: &str) -> Option<Todo> {
+    let key = normalize_title(title);
+    db_get_todos_where(conn, BY_COMPLETION, (false,))
+        .into_iter()
+        .filter(|todo| normalize_title(&todo.title) == key)
+        .min_by_key(|todo| todo.id)
+}
+
+/// Total estimated minutes of the todos matching `filter`. Todos without an
+/// estimate count as zero.
+fn db_sum_estimates(conn: &Connection, filter: Filter, now: DateTime<Local>) -> u64 {
+    db_get_todos_filtered(conn, filter, now)
+        .iter()
+        .filter_map(|todo| todo.estimate_minutes)
+        .map(u64::from)
+        .sum()
+}
+
+fn db_count_todos(conn: &Connection) -> u64 {
+    conn.prepare_cached("SELECT COUNT(*) FROM todos")
+        .unwrap()
+        .query_row([], |row| row.get(0))
+        .unwrap()
+}
+
+/// How many incomplete todos there are at each priority, indexed by
+/// priority. Anything stored above `MAX_PRIORITY` counts as high.
+fn db_count_by_priority(conn: &Connection) -> [u64; MAX_PRIORITY as usize + 1] {
+    let mut counts = [0; MAX_PRIORITY as usize + 1];
+    let mut stmt = conn
+        .prepare_cached(
+            "SELECT MIN(priority, ?1) AS level, COUNT(*) FROM todos
+             WHERE completed = 0 GROUP BY level",
+        )
+        .unwrap();
+    let rows = stmt
+        .query_map([MAX_PRIORITY], |row| {
+            Ok((row.get::<_, usize>(0)?, row.get::<_, u64>(1)?))
+        })
+        .unwrap();
+    for row in rows {
+        let (level, count) = row.unwrap();
+        counts[level] = count;
+    }
+    counts
+}
+
+/// Like `db_get_todos_filtered`, but also reports the table size and whether it
+/// has grown past `LARGE_LIST_THRESHOLD`. This is a hint, not a cap: all
+/// matching todos are still returned.
+fn db_get_todos_with_meta(
+    conn: &Connection,
+    filter: Filter,
+    now: DateTime<Local>,
+) -> TodosWithMeta {
+    let total = db_count_todos(conn);
+    TodosWithMeta {
+        todos: db_get_todos_filtered(conn, filter, now),
+        total,
+        large: total > LARGE_LIST_THRESHOLD,
+    }
+}
+
+/// Returns the incomplete todo whose deadline is the soonest one strictly after
+/// `now`. Deadlines that don't parse are ignored.
+fn db_get_next_deadline(conn: &Connection, now: DateTime<Local>) -> Option<Todo> {
+    db_get_todos(conn)
+        .into_iter()
+        .filter(|todo| !todo.completed)
+        .filter_map(|todo| {
+            let due = parse_deadline(todo.deadline.as_deref()?)?;
+            (due > now).then_some((due, todo))
+        })
+        .min_by_key(|(due, _)| *due)
+        .map(|(_, todo)| todo)
+}
+
+/// Buckets todos by the local calendar day (`YYYY-MM-DD`) of their deadline.
+/// Todos without a parseable deadline are left out.
+fn db_get_todos_grouped_by_day(conn: &Connection) -> BTreeMap<String, Vec<Todo>> {
+    let mut days: BTreeMap<String, Vec<Todo>> = BTreeMap::new();
+    for todo in db_get_todos(conn) {
+        let Some(due) = todo.deadline.as_deref().and_then(parse_deadline) else {
+            continue;
+        };
+        let day = due.date_naive().format("%Y-%m-%d").to_string();
+        days.entry(day).or_default().push(todo);
+    }
+    days
+}
+
+/// Incomplete todos as an agenda, grouped by `agenda::group` in local time.
+fn db_get_agenda(
+    conn: &Connection,
+    now: DateTime<Local>,
+    days_ahead: u32,
+) -> Vec<agenda::AgendaGroup> {
+    let todos = db_get_todos_where(conn, BY_COMPLETION, (false,));
+    agenda::group(todos, now.with_timezone(&Utc), &Local, days_ahead)
+}
+
+/// Counts completed todos per local day of `completed_at`, for `start..=end`
+/// (both `YYYY-MM-DD`). Days without completions are omitted. Grouping
+/// happens here rather than in SQL because the stored timestamps are UTC.
+fn db_completion_history(conn: &Connection, start: &str, end: &str) -> Vec<(String, u64)> {
+    let mut stmt = conn
+        .prepare_cached("SELECT completed_at FROM todos WHERE completed = 1")
+        .unwrap();
+    let mut days: BTreeMap<String, u64> = BTreeMap::new();
+    let stamps = stmt
+        .query_map([], |row| row.get::<_, Option<String>>(0))
+        .unwrap();
+    for stamp in stamps.filter_map(|s| s.unwrap()) {
+        let day = DateTime::parse_from_rfc3339(&stamp)
+            .map(|dt| dt.with_timezone(&Local).format("%Y-%m-%d").to_string())
+            .unwrap_or_else(|_| stamp.chars().take(10).collect());
+        if (start..=end).contains(&day.as_str()) {
+            *days.entry(day).or_default() += 1;
+        }
+    }
+    days.into_iter().collect()
+}
+
+/// A fresh in-memory database with the full schema, for tests.
+#[cfg(test)]
+fn setup_test_db() -> Connection {
+    let conn = Connection::open_in_memory().unwrap();
+    init_db(&conn);
+    conn
+}
+
+/// An unsaved todo for tests that only need the struct; override fields with
+/// `..test_todo(id, title)`.
+#[cfg(test)]
+fn test_todo(id: u64, title: &str) -> Todo {
+    Todo {
+        id,
+        title: title.to_string(),
+        created_at: "2024-01-01T00:00:00+00:00".to_string(),
+        updated_at: "2024-01-01T00:00:00+00:00".to_string(),
+        ..Todo::default()
+    }
+}
+
+/// Shorthand for a plain todo; commands validate and go through `create_todo`.
+#[cfg(test)]
+fn db_add_todo(conn: &Connection, title: String, deadline: Option<String>) -> Todo {
+    db_insert_todo(
+        conn,
+        NewTodo {
+            title,
+            deadline,
+            ..NewTodo::default()
+        },
+    )
+    .unwrap()
+}
+
+/// Inserts a todo that has already been validated and returns it as stored.
+fn db_insert_todo(conn: &Connection, new: NewTodo) -> rusqlite::Result<Todo> {
+    let title = clean_title(&new.title);
+    let created_at = now_utc();
+    let completed_at = new.completed.then(|| created_at.clone());
+
+    with_retry(
+        || {
+            conn.prepare_cached(
+                "INSERT INTO todos
+                     (title, completed, created_at, deadline, description, priority, completed_at,
+                      estimate_minutes, reminder_minutes, updated_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?3)",
+            )?
+            .execute((
+                &title,
+                new.completed,
+                &created_at,
+                &new.deadline,
+                &new.description,
+                new.priority,
+                &completed_at,
+                new.estimate_minutes,
+                new.reminder_minutes,
+            ))
+        },
+        WRITE_ATTEMPTS,
+    )?;
+
+    let id = conn.last_insert_rowid() as u64;
+
+    Ok(Todo {
+        id,
+        title,
+        completed: new.completed,
+        created_at: local_timestamp(created_at.clone()),
+        updated_at: local_timestamp(created_at),
+        deadline: new.deadline,
+        description: new.description,
+        priority: new.priority,
+        completed_at: completed_at.map(local_timestamp),
+        pinned: false,
+        color: None,
+        estimate_minutes: new.estimate_minutes,
+        version: 0,
+        reminder_minutes: new.reminder_minutes,
+        is_overdue: false,
+        is_due_today: false,
+        days_until_deadline: None,
+    })
+}
+
+fn db_set_pinned(conn: &Connection, id: u64, pinned: bool) -> bool {
+    let rows = with_retry(
+        || {
+            conn.prepare_cached(
+                "UPDATE todos SET pinned = ?1, version = version + 1 WHERE id = ?2",
+            )?
+            .execute((pinned, id))
+        },
+        WRITE_ATTEMPTS,
+    )
+    .unwrap();
+    rows > 0
+}
+
+/// Sets or clears (`None`, empty or "none") the color label of `id`.
+/// Returns whether the todo exists; colors outside `TODO_COLORS` are
+/// rejected.
+fn db_set_color(conn: &Connection, id: u64, color: Option<&str>) -> Result<bool, String> {
+    let color = validate_color(color).map_err(|e| e.to_string())?;
+    let rows = with_retry(
+        || {
+            conn.prepare_cached("UPDATE todos SET color = ?1, version = version + 1 WHERE id = ?2")?
+                .execute((&color, id))
+        },
+        WRITE_ATTEMPTS,
+    )
+    .unwrap();
+    Ok(rows > 0)
+}
+
+/// What `db_update_todo` did, with the todo as it is afterwards.
+#[derive(Debug, Clone, PartialEq)]
+enum UpdateOutcome {
+    NotFound,
+    /// The todo was at a different version than expected, so nothing was
+    /// written. Carries it as it is now.
+    Conflict(Todo),
+    /// No field was given, or every given field already had that value.
+    Unchanged(Todo),
+    Updated(Todo),
+}
+
+impl UpdateOutcome {
+    /// Whether the todo exists.
+    fn found(&self) -> bool {
+        !matches!(self, UpdateOutcome::NotFound)
+    }
+
+    fn into_result(self, id: u64) -> Result<Todo, TodoError> {
+        match self {
+            UpdateOutcome::NotFound => Err(TodoError::NotFound(id)),
+            UpdateOutcome::Conflict(current) => Err(TodoError::Conflict(Box::new(current))),
+            UpdateOutcome::Unchanged(todo) | UpdateOutcome::Updated(todo) => Ok(todo),
+        }
+    }
+}
+
+/// Why a command couldn't act on a todo. Serialized as `{"kind", "message"}`
+/// like the other typed command errors.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+#[serde(tag = "kind", content = "message", rename_all = "snake_case")]
+enum TodoError {
+    /// No todo has this id.
+    NotFound(u64),
+    /// Someone else changed the todo first; this is how it is now.
+    Conflict(Box<Todo>),
+    /// Some of the values given were rejected; every failure is listed, in
+    /// the shape documented on `ValidationError`, e.g.
+    /// `{"kind": "validation", "message": [{"field": "title", "code": "empty",
+    /// "message": "Title is empty"}]}`.
+    Validation(Vec<ValidationError>),
+    /// An incomplete todo already has this title; this is the one.
+    Duplicate(Box<Todo>),
+}
+
+impl fmt::Display for TodoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TodoError::NotFound(id) => write!(f, "todo {} not found", id),
+            TodoError::Conflict(current) => write!(
+                f,
+                "todo {} was changed elsewhere (now version {})",
+                current.id, current.version
+            ),
+            TodoError::Validation(errors) => write!(f, "{}", validation::describe(errors)),
+            TodoError::Duplicate(existing) => {
+                write!(f, "todo {} already has this title", existing.id)
+            }
+        }
+    }
+}
+
+/// What `add_todo` does when an incomplete todo already has the title.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum OnDuplicate {
+    /// Add it anyway.
+    #[default]
+    Allow,
+    /// Fail with `TodoError::Duplicate`.
+    Reject,
+    /// Add nothing and return the existing todo.
+    ReturnExisting,
+}
+
+/// Fields to change on one or more todos; `None` leaves a field as it is.
+/// An empty `deadline` or 0 minutes for an estimate or reminder clears it.
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+struct TodoChanges {
+    title: Option<String>,
+    completed: Option<bool>,
+    deadline: Option<String>,
+    estimate_minutes: Option<u32>,
+    reminder_minutes: Option<u32>,
+}
+
+impl TodoChanges {
+    fn is_empty(&self) -> bool {
+        *self == TodoChanges::default()
+    }
+}
+
+/// `SET` fragments for the fields `apply_changes` writes, in the order they
+/// appear. Any update is some subset of these over an `IN` list padded by
+/// `padded_ids`, so the statement cache only has to hold a few shapes.
+/// Completion keeps `completed_at` on rows already in the new state.
+const UPDATE_ASSIGNMENTS: [&str; 5] = [
+    "title = ?",
+    "completed_at = CASE WHEN completed = ? THEN completed_at ELSE ? END, completed = ?",
+    "deadline = ?",
+    "estimate_minutes = ?",
+    "reminder_minutes = ?",
+];
+
+/// Writes `changes` to every row in `rows` that differs from them, with one
+/// `UPDATE`, and appends a `todo_history` row for each field that actually
+/// changed on each row. `rows` must be the todos as they are now. Returns
+/// the ids that were updated.
+fn apply_changes(
+    conn: &Connection,
+    rows: &[Todo],
+    changes: &TodoChanges,
+) -> rusqlite::Result<Vec<u64>> {
+    use rusqlite::types::Value;
+
+    let title = changes.title.as_deref().map(clean_title);
+    let completed = changes.completed;
+    let deadline = changes
+        .deadline
+        .clone()
+        .map(|d| Some(d).filter(|d| !d.is_empty()));
+    let estimate_minutes = changes.estimate_minutes.map(|m| Some(m).filter(|&m| m > 0));
+    let reminder_minutes = changes.reminder_minutes.map(|m| Some(m).filter(|&m| m > 0));
+    let title_changes = |t: &Todo| title.as_ref().is_some_and(|v| *v != t.title);
+    let completed_changes = |t: &Todo| completed.is_some_and(|v| v != t.completed);
+    let deadline_changes = |t: &Todo| deadline.as_ref().is_some_and(|v| *v != t.deadline);
+    let estimate_changes = |t: &Todo| estimate_minutes.is_some_and(|v| v != t.estimate_minutes);
+    let reminder_changes = |t: &Todo| reminder_minutes.is_some_and(|v| v != t.reminder_minutes);
+    let changing: Vec<&Todo> = rows
+        .iter()
+        .filter(|t| {
+            title_changes(t)
+                || completed_changes(t)
+                || deadline_changes(t)
+                || estimate_changes(t)
+                || reminder_changes(t)
+        })
+        .collect();
+    if changing.is_empty() {
+        return Ok(Vec::new());
+    }
+    let now = now_utc();
+
+    let mut assignments = Vec::new();
+    let mut params: Vec<Value> = Vec::new();
+    if changing.iter().any(|t| title_changes(t)) {
+        assignments.push(UPDATE_ASSIGNMENTS[0]);
+        params.push(Value::from(title.clone()));
+    }
+    if let Some(c) = completed.filter(|_| changing.iter().any(|t| completed_changes(t))) {
+        assignments.push(UPDATE_ASSIGNMENTS[1]);
+        params.push(Value::from(c));
+        params.push(Value::from(c.then(|| now.clone())));
+        params.push(Value::from(c));
+    }
+    if let Some(d) = deadline
+        .as_ref()
+        .filter(|_| changing.iter().any(|t| deadline_changes(t)))
+    {
+        assignments.push(UPDATE_ASSIGNMENTS[2]);
+        params.push(Value::from(d.clone()));
+    }
+    if let Some(m) = estimate_minutes.filter(|_| changing.iter().any(|t| estimate_changes(t))) {
+        assignments.push(UPDATE_ASSIGNMENTS[3]);
+        params.push(Value::from(m));
+    }
+    if let Some(m) = reminder_minutes.filter(|_| changing.iter().any(|t| reminder_changes(t))) {
+        assignments.push(UPDATE_ASSIGNMENTS[4]);
+        params.push(Value::from(m));
+    }
+    let ids: Vec<u64> = changing.iter().map(|t| t.id).collect();
+    let id_params = padded_ids(&ids);
+    let sql = format!(
+        "UPDATE todos SET {}, version = version + 1 WHERE id IN ({})",
+        assignments.join(", "),
+        vec!["?"; id_params.len()].join(", ")
+    );
+    params.extend(id_params);
+    with_retry(
+        || {
+            conn.prepare_cached(&sql)?
+                .execute(params_from_iter(&params))
+        },
+        WRITE_ATTEMPTS,
+    )?;
+
+    for old in changing {
+        let id = old.id;
+        if let Some(t) = title.as_deref().filter(|_| title_changes(old)) {
+            db_record_history(conn, id, "title", Some(&old.title), Some(t), &now)?;
+        }
+        if let Some(c) = completed.filter(|_| completed_changes(old)) {
+            let (from, to) = (old.completed.to_string(), c.to_string());
+            db_record_history(conn, id, "completed", Some(&from), Some(&to), &now)?;
+        }
+        if let Some(d) = deadline.as_ref().filter(|_| deadline_changes(old)) {
+            db_record_history(
+                conn,
+                id,
+                "deadline",
+                old.deadline.as_deref(),
+                d.as_deref(),
+                &now,
+            )?;
+        }
+        if let Some(m) = estimate_minutes.filter(|_| estimate_changes(old)) {
+            let (from, to) = (
+                old.estimate_minutes.map(|m| m.to_string()),
+                m.map(|m| m.to_string()),
+            );
+            db_record_history(
+                conn,
+                id,
+                "estimate_minutes",
+                from.as_deref(),
+                to.as_deref(),
+                &now,
+            )?;
+        }
+        if let Some(m) = reminder_minutes.filter(|_| reminder_changes(old)) {
+            let (from, to) = (
+                old.reminder_minutes.map(|m| m.to_string()),
+                m.map(|m| m.to_string()),
+            );
+            db_record_history(
+                conn,
+                id,
+                "reminder_minutes",
+                from.as_deref(),
+                to.as_deref(),
+                &now,
+            )?;
+        }
+    }
+    Ok(ids)
+}
+
+fn try_update_todo(
+    conn: &Connection,
+    id: u64,
+    expected_version: Option<u64>,
+    changes: &TodoChanges,
+) -> rusqlite::Result<UpdateOutcome> {
+    with_savepoint(conn, || {
+        let Some(old) = db_get_todo(conn, id) else {
+            return Ok(UpdateOutcome::NotFound);
+        };
+        if expected_version.is_some_and(|v| v != old.version) {
+            return Ok(UpdateOutcome::Conflict(old));
+        }
+        if apply_changes(conn, std::slice::from_ref(&old), changes)?.is_empty() {
+            return Ok(UpdateOutcome::Unchanged(old));
+        }
+        let todo = conn
+            .prepare_cached(&format!("SELECT {} FROM todos WHERE id = ?1", TODO_COLUMNS))?
+            .query_row([id], todo_from_row)?;
+        Ok(UpdateOutcome::Updated(todo))
+    })
+}
+
+/// Applies the given field changes with a single `UPDATE` and appends a
+/// `todo_history` row for each field whose value actually changed, all
+/// inside one savepoint, so other connections never see half an edit. An
+/// empty `deadline` or an estimate of 0 minutes clears it. The outcome
+/// carries the todo as read back inside that savepoint, including fields
+/// the database fills in such as `completed_at` and `version`.
+fn db_update_todo(
+    conn: &Connection,
+    id: u64,
+    title: Option<String>,
+    completed: Option<bool>,
+    deadline: Option<String>,
+    estimate_minutes: Option<u32>,
+) -> UpdateOutcome {
+    let changes = TodoChanges {
+        title,
+        completed,
+        deadline,
+        estimate_minutes,
+        ..TodoChanges::default()
+    };
+    try_update_todo(conn, id, None, &changes).unwrap()
+}
+
+/// `db_update_todo`, but only if the todo is still at `expected_version`;
+/// `None` skips the check.
+fn db_update_todo_at_version(
+    conn: &Connection,
+    id: u64,
+    expected_version: Option<u64>,
+    changes: &TodoChanges,
+) -> UpdateOutcome {
+    try_update_todo(conn, id, expected_version, changes).unwrap()
+}
+
+/// What `db_bulk_update_todos` did to one id.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum BulkOutcome {
+    Updated,
+    /// Every field already had the requested value.
+    Unchanged,
+    NotFound,
+    /// Left alone by `postpone_todos`: there is no deadline it can move.
+    NoDeadline,
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+struct BulkUpdateResult {
+    id: u64,
+    outcome: BulkOutcome,
+}
+
+/// Applies `changes` to all of `ids` in one transaction, one `UPDATE` per
+/// `BULK_CHUNK` ids, with history recorded for every row as for a single
+/// update. Returns a result per id, in the order given.
+fn db_bulk_update_todos(
+    conn: &Connection,
+    ids: &[u64],
+    changes: &TodoChanges,
+) -> Result<Vec<BulkUpdateResult>, String> {
+    if changes.is_empty() {
+        return Err("No fields to change".to_string());
+    }
+    let (found, updated) = with_savepoint(conn, || {
+        let mut found = HashSet::new();
+        let mut updated = HashSet::new();
+        for chunk in ids.chunks(BULK_CHUNK) {
+            let chunk = padded_ids(chunk);
+            let rows: Vec<Todo> = conn
+                .prepare_cached(&format!(
+                    "SELECT {} FROM todos WHERE id IN ({})",
+                    TODO_COLUMNS,
+                    id_placeholders(1, chunk.len())
+                ))?
+                .query_map(params_from_iter(chunk), todo_from_row)?
+                .collect::<rusqlite::Result<_>>()?;
+            found.extend(rows.iter().map(|t| t.id));
+            updated.extend(apply_changes(conn, &rows, changes)?);
+        }
+        Ok((found, updated))
+    })
+    .map_err(|e: rusqlite::Error| e.to_string())?;
+    Ok(ids
+        .iter()
+        .map(|&id| BulkUpdateResult {
+            id,
+            outcome: if updated.contains(&id) {
+                BulkOutcome::Updated
+            } else if found.contains(&id) {
+                BulkOutcome::Unchanged
+            } else {
+                BulkOutcome::NotFound
+            },
+        })
+        .collect())
+}
+
+/// Clears the deadline of each of `ids` in one transaction, with history
+/// recorded. Returns how many todos had a deadline to clear.
+fn db_clear_deadlines(conn: &Connection, ids: &[u64]) -> usize {
+    if ids.is_empty() {
+        return 0;
+    }
+    let changes = TodoChanges {
+        deadline: Some(String::new()),
+        ..TodoChanges::default()
+    };
+    db_bulk_update_todos(conn, ids, &changes)
+        .unwrap()
+        .iter()
+        .filter(|r| r.outcome == BulkOutcome::Updated)
+        .count()
+}
+
+/// Moves every overdue todo to today: its deadline becomes today's date,
+/// which lasts until the end of the day. Done in one transaction, with
+/// history recorded. Returns how many todos were moved.
+fn db_reschedule_overdue(conn: &Connection, now: DateTime<Local>) -> usize {
+    let ids: Vec<u64> = db_get_overdue_todos(conn, now)
+        .iter()
+        .map(|t| t.id)
+        .collect();
+    if ids.is_empty() {
+        return 0;
+    }
+    let changes = TodoChanges {
+        deadline: Some(now.format("%Y-%m-%d").to_string()),
+        ..TodoChanges::default()
+    };
+    db_bulk_update_todos(conn, &ids, &changes)
+        .unwrap()
+        .iter()
+        .filter(|r| r.outcome == BulkOutcome::Updated)
+        .count()
+}
+
+/// Moves the deadline of each of `ids` by `days` (back if negative) as
+/// `deadline::shift_days` does, in one transaction, with history recorded as
+/// for a single update. Returns a result per id, in the order given.
+fn db_postpone_todos(
+    conn: &Connection,
+    ids: &[u64],
+    days: i32,
+) -> Result<Vec<BulkUpdateResult>, String> {
+    with_savepoint(conn, || {
+        let mut results = Vec::with_capacity(ids.len());
+        for &id in ids {
+            let Some(old) = db_get_todo(conn, id) else {
+                results.push(BulkUpdateResult {
+                    id,
+                    outcome: BulkOutcome::NotFound,
+                });
+                continue;
+            };
+            let Some(deadline) = old
+                .deadline
+                .as_deref()
+                .and_then(|d| deadline::shift_days(d, days.into(), &Local))
+            else {
+                results.push(BulkUpdateResult {
+                    id,
+                    outcome: BulkOutcome::NoDeadline,
+                });
+                continue;
+            };
+            let changes = TodoChanges {
+                deadline: Some(deadline),
+                ..TodoChanges::default()
+            };
+            let updated = !apply_changes(conn, std::slice::from_ref(&old), &changes)?.is_empty();
+            results.push(BulkUpdateResult {
+                id,
+                outcome: if updated {
+                    BulkOutcome::Updated
+                } else {
+                    BulkOutcome::Unchanged
+                },
+            });
+        }
+        Ok(results)
+    })
+    .map_err(|e: rusqlite::Error| e.to_string())
+}
+
+/// Sets or clears a todo's deadline. Both `None` and `Some("")` clear it.
+/// Returns `false` if the todo doesn't exist.
+fn db_set_deadline(conn: &Connection, id: u64, deadline: Option<String>) -> bool {
+    db_update_todo(
+        conn,
+        id,
+        None,
+        None,
+        Some(deadline.unwrap_or_default()),
+        None,
+    )
+    .found()
+}
+
+/// Moves a todo's deadline as `preset` says, counting from `now`, and clears
+/// its delivered notifications so the new deadline is announced. Returns the
+/// updated todo, or `None` if it doesn't exist.
+fn db_snooze_todo(
+    conn: &Connection,
+    id: u64,
+    preset: snooze::SnoozePreset,
+    now: DateTime<Local>,
+) -> Result<Option<Todo>, String> {
+    let Some(todo) = db_get_todo(conn, id) else {
+        return Ok(None);
+    };
+    let until = snooze::snoozed_until(preset, todo.deadline.as_deref(), now)
+        .ok_or("The snoozed deadline doesn't exist")?;
+    let snoozed = with_savepoint(conn, || {
+        if !db_set_deadline(conn, id, Some(deadline::to_stored(until))) {
+            return Ok(false);
+        }
+        with_retry(|| notifications::clear_notified(conn, id), WRITE_ATTEMPTS)?;
+        Ok(true)
+    })
+    .map_err(|e| e.to_string())?;
+    Ok(if snoozed { db_get_todo(conn, id) } else { None })
+}
+
+fn db_delete_todo(conn: &Connection, id: u64) -> bool {
+    let count = with_retry(
+        || {
+            conn.prepare_cached("DELETE FROM todos WHERE id = ?1")?
+                .execute((id,))
+        },
+        WRITE_ATTEMPTS,
+    )
+    .unwrap();
+    count > 0
+}
+
+fn db_completed_ids(conn: &Connection) -> Vec<u64> {
+    let mut stmt = conn
+        .prepare_cached("SELECT id FROM todos WHERE completed = 1")
+        .unwrap();
+    let ids = stmt.query_map([], |row| row.get(0)).unwrap();
+    ids.map(|id| id.unwrap()).collect()
+}
+
+/// Deletes every completed todo, returning how many were removed.
+fn db_clear_completed(conn: &Connection) -> usize {
+    with_retry(
+        || {
+            conn.prepare_cached("DELETE FROM todos WHERE completed = 1")?
+                .execute([])
+        },
+        WRITE_ATTEMPTS,
+    )
+    .unwrap()
+}
+
+/// Moves every completed todo into `todos_archive`, tags included, and
+/// deletes it from `todos`, all in one transaction. Returns how many todos
+/// were archived.
+fn db_archive_completed(conn: &Connection) -> usize {
+    with_savepoint(conn, || {
+        conn.prepare_cached(&format!(
+            "INSERT INTO todos_archive ({columns}, uuid, tags, archived_at)
+             SELECT {columns}, uuid,
+                    (SELECT json_group_array(tags.name) FROM todo_tags
+                     JOIN tags ON tags.id = todo_tags.tag_id
+                     WHERE todo_tags.todo_id = todos.id),
+                    ?1
+             FROM todos WHERE completed = 1",
+            columns = TODO_COLUMNS
+        ))?
+        .execute((now_utc(),))?;
+        conn.prepare_cached(
+            "DELETE FROM todo_tags WHERE todo_id IN (SELECT id FROM todos WHERE completed = 1)",
+        )?
+        .execute([])?;
+        conn.prepare_cached("DELETE FROM todos WHERE completed = 1")?
+            .execute([])
+    })
+    .unwrap()
+}
+
+/// Archived todos, most recently archived first.
+fn db_get_archive(conn: &Connection) -> Vec<ArchivedTodo> {
+    let mut stmt = conn
+        .prepare_cached(&format!(
+            "SELECT {}, tags, archived_at FROM todos_archive ORDER BY archived_at DESC, id",
+            TODO_COLUMNS
+        ))
+        .unwrap();
+    let rows = stmt
+        .query_map([], |row| {
+            let tags: String = row.get(14)?;
+            Ok(ArchivedTodo {
+                todo: todo_from_row(row)?,
+                tags: serde_json::from_str(&tags).unwrap_or_default(),
+                archived_at: local_timestamp(row.get(15)?),
+            })
+        })
+        .unwrap();
+    rows.map(|row| row.unwrap()).collect()
+}
+
+/// Moves an archived todo back into `todos` as an active (incomplete) todo,
+/// tags included, in one transaction. It keeps its id and uuid unless
+/// another todo has taken them meanwhile, e.g. from an imported bundle.
+/// Returns `None` if nothing is archived under `id`.
+fn db_unarchive_todo(conn: &Connection, id: u64) -> Option<Todo> {
+    with_savepoint(conn, || {
+        let archived: Option<(bool, String)> = conn
+            .prepare_cached(
+                "SELECT EXISTS (SELECT 1 FROM todos WHERE id = ?1), tags
+                 FROM todos_archive WHERE id = ?1",
+            )?
+            .query_row((id,), |row| Ok((row.get(0)?, row.get(1)?)))
+            .optional()?;
+        let Some((id_taken, tags)) = archived else {
+            return Ok(None);
+        };
+        conn.prepare_cached(
+            "INSERT INTO todos
+                 (id, title, completed, created_at, deadline, description, priority,
+                  completed_at, pinned, color, estimate_minutes, version, reminder_minutes,
+                  uuid)
+             SELECT CASE WHEN ?2 THEN NULL ELSE id END, title, 0, created_at, deadline,
+                    description, priority, NULL, pinned, color, estimate_minutes, version + 1,
+                    reminder_minutes,
+                    CASE WHEN EXISTS (SELECT 1 FROM todos WHERE uuid = a.uuid) THEN NULL
+                         ELSE uuid END
+             FROM todos_archive a WHERE id = ?1",
+        )?
+        .execute((id, id_taken))?;
+        let new_id = conn.last_insert_rowid() as u64;
+        conn.prepare_cached("DELETE FROM todos_archive WHERE id = ?1")?
+            .execute((id,))?;
+        for tag in serde_json::from_str::<Vec<String>>(&tags).unwrap_or_default() {
+            db_add_tag(conn, new_id, &tag);
+        }
+        Ok(db_get_todo(conn, new_id))
+    })
+    .unwrap()
+}
+
+// --- Tags ---
+
+fn db_get_or_create_tag(conn: &Connection, name: &str) -> u64 {
+    with_retry(
+        || {
+            conn.prepare_cached("INSERT OR IGNORE INTO tags (name) VALUES (?1)")?
+                .execute((name,))
+        },
+        WRITE_ATTEMPTS,
+    )
+    .expect("Failed to create tag");
+    conn.prepare_cached("SELECT id FROM tags WHERE name = ?1")
+        .unwrap()
+        .query_row((name,), |row| row.get(0))
+        .unwrap()
+}
+
+/// Attaches a tag to a todo, creating the tag if needed. Returns `false` if the
+/// todo already had it or the name is blank.
+fn db_add_tag(conn: &Connection, todo_id: u64, name: &str) -> bool {
+    let name = name.trim();
+    if name.is_empty() {
+        return false;
+    }
+    let tag_id = db_get_or_create_tag(conn, name);
+    let count = with_retry(
+        || {
+            conn.prepare_cached(
+                "INSERT OR IGNORE INTO todo_tags (todo_id, tag_id) VALUES (?1, ?2)",
+            )?
+            .execute((todo_id, tag_id))
+        },
+        WRITE_ATTEMPTS,
+    )
+    .unwrap();
+    count > 0
+}
+
+/// Most ids bound into one `IN (...)` list, well under SQLite's variable limit.
+const BULK_CHUNK: usize = 512;
+
+/// `ids` padded to the next power of two by repeating the last one, which
+/// doesn't change what an `IN (...)` list matches. Statements then come in
+/// a handful of lengths and stay in the statement cache, instead of one
+/// shape per selection size.
+fn padded_ids(ids: &[u64]) -> Vec<rusqlite::types::Value> {
+    let last = ids.last().copied().unwrap_or_default();
+    ids.iter()
+        .copied()
+        .chain(std::iter::repeat(last))
+        .take(ids.len().next_power_of_two())
+        .map(|id| rusqlite::types::Value::from(id as i64))
+        .collect()
+}
+
+/// `?first, ?first+1, ...`: one numbered placeholder per id, following the
+/// statement's other parameters.
+fn id_placeholders(first: usize, count: usize) -> String {
+    (first..first + count)
+        .map(|i| format!("?{}", i))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Marks each of `ids` complete or incomplete in one transaction, returning
+/// how many rows changed. Todos already in that state and ids that don't
+/// exist are left alone; each change is recorded in the history like a
+/// single update.
+fn db_set_completed_bulk(conn: &Connection, ids: &[u64], completed: bool) -> usize {
+    use rusqlite::types::Value;
+
+    if ids.is_empty() {
+        return 0;
+    }
+    let now = now_utc();
+    let (from, to) = ((!completed).to_string(), completed.to_string());
+    with_savepoint(conn, || {
+        let mut changed = 0;
+        for chunk in ids.chunks(BULK_CHUNK) {
+            let chunk = padded_ids(chunk);
+            let to_change: Vec<u64> = conn
+                .prepare_cached(&format!(
+                    "SELECT id FROM todos WHERE completed != ?1 AND id IN ({})",
+                    id_placeholders(2, chunk.len())
+                ))?
+                .query_map(
+                    params_from_iter(std::iter::once(Value::from(completed)).chain(chunk)),
+                    |row| row.get(0),
+                )?
+                .collect::<rusqlite::Result<_>>()?;
+            if to_change.is_empty() {
+                continue;
+            }
+            let changing = padded_ids(&to_change);
+            let placeholders = id_placeholders(3, changing.len());
+            let params: Vec<Value> = [
+                Value::from(completed),
+                Value::from(completed.then(|| now.clone())),
+            ]
+            .into_iter()
+            .chain(changing)
+            .collect();
+            changed += with_retry(
+                || {
+                    conn.prepare_cached(&format!(
+                        "UPDATE todos SET completed = ?1, completed_at = ?2, version = version + 1
+                         WHERE id IN ({})",
+                        placeholders
+                    ))?
+                    .execute(params_from_iter(&params))
+                },
+                WRITE_ATTEMPTS,
+            )?;
+            for &id in &to_change {
+                db_record_history(conn, id, "completed", Some(&from), Some(&to), &now)?;
+            }
+        }
+        Ok(changed)
+    })
+    .unwrap()
+}
+
+/// Attaches a tag to each of `ids` in one transaction, returning how many
+/// todos newly got it. Ids that already have the tag or don't exist are
+/// skipped.
+fn db_add_tag_to_todos(conn: &Connection, ids: &[u64], name: &str) -> usize {
+    let name = name.trim();
+    if name.is_empty() || ids.is_empty() {
+        return 0;
+    }
+    with_savepoint(conn, || {
+        let tag_id = db_get_or_create_tag(conn, name);
+        let mut added = 0;
+        for &id in ids {
+            added += with_retry(
+                || {
+                    conn.prepare_cached(
+                        "INSERT OR IGNORE INTO todo_tags (todo_id, tag_id)
+                         SELECT id, ?2 FROM todos WHERE id = ?1",
+                    )?
+                    .execute((id, tag_id))
+                },
+                WRITE_ATTEMPTS,
+            )?;
+        }
+        Ok(added)
+    })
+    .unwrap()
+}
+
+fn db_remove_tag(conn: &Connection, todo_id: u64, name: &str) -> bool {
+    let count = with_retry(
+        || {
+            conn.prepare_cached(
+                "DELETE FROM todo_tags
+                 WHERE todo_id = ?1 AND tag_id = (SELECT id FROM tags WHERE name = ?2)",
+            )?
+            .execute((todo_id, name.trim()))
+        },
+        WRITE_ATTEMPTS,
+    )
+    .unwrap();
+    count > 0
+}
+
+/// Removes a todo's extra associations with tags whose names differ only by
+/// case, such as "Work" and "work", keeping the oldest tag of each. Returns
+/// how many were removed.
+fn db_dedup_tags(conn: &Connection, todo_id: u64) -> usize {
+    with_retry(
+        || {
+            conn.prepare_cached(
+                "DELETE FROM todo_tags
+                 WHERE todo_id = ?1 AND tag_id NOT IN (
+                     SELECT MIN(tags.id) FROM todo_tags
+                     JOIN tags ON tags.id = todo_tags.tag_id
+                     WHERE todo_tags.todo_id = ?1
+                     GROUP BY LOWER(tags.name)
+                 )",
+            )?
+            .execute((todo_id,))
+        },
+        WRITE_ATTEMPTS,
+    )
+    .unwrap()
+}
+
+fn db_todo_ids_with_tag(conn: &Connection, name: &str) -> Vec<u64> {
+    let mut stmt = conn
+        .prepare_cached(
+            "SELECT todo_tags.todo_id FROM todo_tags
+             JOIN tags ON tags.id = todo_tags.tag_id
+             WHERE tags.name = ?1",
+        )
+        .unwrap();
+    let ids = stmt.query_map((name.trim(),), |row| row.get(0)).unwrap();
+    ids.map(|id| id.unwrap()).collect()
+}
+
+/// Renames a tag on every todo in one transaction, returning how many
+/// associations moved. If `new` already exists the two tags are merged, and
+/// todos that had both keep a single association.
+fn db_rename_tag(conn: &Connection, old: &str, new: &str) -> usize {
+    let (old, new) = (old.trim(), new.trim());
+    if new.is_empty() || old == new {
+        return 0;
+    }
+    let tx = conn.unchecked_transaction().unwrap();
+    let Some(old_id) = tx
+        .prepare_cached("SELECT id FROM tags WHERE name = ?1")
+        .unwrap()
+        .query_row((old,), |row| row.get::<_, u64>(0))
+        .optional()
+        .unwrap()
+    else {
+        return 0;
+    };
+    let moved = db_todo_ids_with_tag(&tx, old).len();
+    let existing: Option<u64> = tx
+        .prepare_cached("SELECT id FROM tags WHERE name = ?1")
+        .unwrap()
+        .query_row((new,), |row| row.get(0))
+        .optional()
+        .unwrap();
+    match existing {
+        Some(new_id) => {
+            with_retry(
+                || {
+                    tx.prepare_cached(
+                        "INSERT OR IGNORE INTO todo_tags (todo_id, tag_id)
+                         SELECT todo_id, ?1 FROM todo_tags WHERE tag_id = ?2",
+                    )?
+                    .execute((new_id, old_id))?;
+                    tx.prepare_cached("DELETE FROM todo_tags WHERE tag_id = ?1")?
+                        .execute((old_id,))?;
+                    tx.prepare_cached("DELETE FROM tags WHERE id = ?1")?
+                        .execute((old_id,))
+                },
+                WRITE_ATTEMPTS,
+            )
+            .unwrap();
+        }
+        None => {
+            with_retry(
+                || {
+                    tx.prepare_cached("UPDATE tags SET name = ?1 WHERE id = ?2")?
+                        .execute((new, old_id))
+                },
+                WRITE_ATTEMPTS,
+            )
+            .unwrap();
+        }
+    }
+    tx.commit().unwrap();
+    moved
+}
+
+fn db_incomplete_ids_with_tag(conn: &Connection, tag_id: u64) -> Vec<u64> {
+    let mut stmt = conn
+        .prepare_cached(
+            "SELECT todos.id FROM todo_tags
+             JOIN todos ON todos.id = todo_tags.todo_id
+             WHERE todo_tags.tag_id = ?1 AND todos.completed = 0
+             ORDER BY todos.id",
+        )
+        .unwrap();
+    let ids = stmt.query_map((tag_id,), |row| row.get(0)).unwrap();
+    ids.map(|id| id.unwrap()).collect()
+}
+
+/// Moves every incomplete todo tagged `from_tag` to `to_tag` in one
+/// transaction, returning how many moved. Completed todos keep `from_tag`,
+/// and a todo that already has `to_tag` just loses `from_tag`. Both tags
+/// must exist.
+fn db_move_incomplete(conn: &Connection, from_tag: u64, to_tag: u64) -> Result<usize, String> {
+    for tag_id in [from_tag, to_tag] {
+        let exists: bool = conn
+            .prepare_cached("SELECT EXISTS (SELECT 1 FROM tags WHERE id = ?1)")
+            .and_then(|mut stmt| stmt.query_row((tag_id,), |row| row.get(0)))
+            .map_err(|e| e.to_string())?;
+        if !exists {
+            return Err(format!("There is no tag {}", tag_id));
+        }
+    }
+    if from_tag == to_tag {
+        return Ok(0);
+    }
+    let ids = db_incomplete_ids_with_tag(conn, from_tag);
+    with_savepoint(conn, || {
+        for &id in &ids {
+            with_retry(
+                || {
+                    conn.prepare_cached(
+                        "INSERT OR IGNORE INTO todo_tags (todo_id, tag_id) VALUES (?1, ?2)",
+                    )?
+                    .execute((id, to_tag))?;
+                    conn.prepare_cached("DELETE FROM todo_tags WHERE todo_id = ?1 AND tag_id = ?2")?
+                        .execute((id, from_tag))
+                },
+                WRITE_ATTEMPTS,
+            )?;
+        }
+        Ok(ids.len())
+    })
+    .map_err(|e| e.to_string())
+}
+
+fn db_get_tags(conn: &Connection, todo_id: u64) -> Vec<String> {
+    let mut stmt = conn
+        .prepare_cached(
+            "SELECT tags.name FROM todo_tags
+             JOIN tags ON tags.id = todo_tags.tag_id
+             WHERE todo_tags.todo_id = ?1
+             ORDER BY tags.name",
+        )
+        .unwrap();
+    let names = stmt.query_map((todo_id,), |row| row.get(0)).unwrap();
+    names.map(|name| name.unwrap()).collect()
+}
+
+/// Every tag as `(tag_id, name, active_count)`, by name, for sidebar badges.
+/// Only incomplete todos count; archived ones live in another table. Tags
+/// left on no open todo are listed with zero.
+fn db_count_per_tag(conn: &Connection) -> Vec<(u64, String, u64)> {
+    let mut stmt = conn
+        .prepare_cached(
+            "SELECT tags.id, tags.name, COUNT(todos.id) FROM tags
+             LEFT JOIN todo_tags ON todo_tags.tag_id = tags.id
+             LEFT JOIN todos ON todos.id = todo_tags.todo_id AND todos.completed = 0
+             GROUP BY tags.id
+             ORDER BY tags.name",
+        )
+        .unwrap();
+    let counts = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+        .unwrap();
+    counts.map(|count| count.unwrap()).collect()
+}
+
+/// Summaries of the todos matching `query`. Tag counts come from one
+/// grouped query, not one per row.
+fn db_query_summaries(
+    conn: &Connection,
+    query: &TodoQuery,
+    now: DateTime<Local>,
+) -> Vec<TodoSummary> {
+    let todos = db_query_todos(conn, query, now);
+    let tag_counts: HashMap<u64, u64> = conn
+        .prepare_cached("SELECT todo_id, COUNT(*) FROM todo_tags GROUP BY todo_id")
+        .unwrap()
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+        .unwrap()
+        .map(|row| row.unwrap())
+        .collect();
+    enrich_all(todos, now)
+        .into_iter()
+        .map(|todo| TodoSummary {
+            tag_count: tag_counts.get(&todo.id).copied().unwrap_or(0),
+            id: todo.id,
+            title: todo.title,
+            completed: todo.completed,
+            created_at: todo.created_at,
+            deadline: todo.deadline,
+            priority: todo.priority,
+            pinned: todo.pinned,
+            color: todo.color,
+            estimate_minutes: todo.estimate_minutes,
+            version: todo.version,
+            is_overdue: todo.is_overdue,
+            is_due_today: todo.is_due_today,
+            days_until_deadline: todo.days_until_deadline,
+        })
+        .collect()
+}
+
+/// The todo `id` with its tags, in two queries however many tags it has.
+fn db_get_todo_detail(conn: &Connection, id: u64) -> Option<TodoDetail> {
+    let todo = db_get_todo(conn, id)?;
+    Some(TodoDetail {
+        tags: db_get_tags(conn, id),
+        todo,
+    })
+}
+
+/// Folds `merge_id` into `keep_id`: the tags of both are combined, the earlier
+/// `created_at` and the earlier deadline win, and `merge_id` is deleted. The
+/// writes run in one savepoint, so `undo::record` can wrap them. Returns
+/// `None` if either todo is missing or both ids are the same.
+fn db_merge_todos(conn: &Connection, keep_id: u64, merge_id: u64) -> Option<Todo> {
+    if keep_id == merge_id {
+        return None;
+    }
+    let keep = db_get_todo(conn, keep_id)?;
+    let merge = db_get_todo(conn, merge_id)?;
+
+    let created_at = match (
+        DateTime::parse_from_rfc3339(&keep.created_at),
+        DateTime::parse_from_rfc3339(&merge.created_at),
+    ) {
+        (Ok(k), Ok(m)) if m < k => merge.created_at,
+        _ => keep.created_at,
+    };
+    let created_at = to_utc(&created_at).unwrap_or(created_at);
+    let deadline = match (&keep.deadline, &merge.deadline) {
+        (None, Some(_)) => merge.deadline,
+        (Some(k), Some(m)) => match (parse_deadline(k), parse_deadline(m)) {
+            (Some(k), Some(m)) if m < k => merge.deadline,
+            _ => keep.deadline,
+        },
+        _ => keep.deadline,
+    };
+
+    with_savepoint(conn, || {
+        conn.prepare_cached(
+            "INSERT OR IGNORE INTO todo_tags (todo_id, tag_id)
+             SELECT ?1, tag_id FROM todo_tags WHERE todo_id = ?2",
+        )?
+        .execute((keep_id, merge_id))?;
+        conn.prepare_cached("DELETE FROM todo_tags WHERE todo_id = ?1")?
+            .execute((merge_id,))?;
+        conn.prepare_cached(
+            "UPDATE todos SET created_at = ?1, deadline = ?2, version = version + 1 WHERE id = ?3",
+        )?
+        .execute((&created_at, &deadline, keep_id))?;
+        conn.prepare_cached("DELETE FROM todos WHERE id = ?1")?
+            .execute((merge_id,))?;
+        Ok(db_get_todo(conn, keep_id))
+    })
+    .expect("Failed to merge todos")
+}
+
+// --- History ---
+
+/// Default page size for `get_todo_history`.
+const HISTORY_PAGE_SIZE: u32 = 50;
+
+/// One field change recorded by `db_update_todo`. Values are stored as text;
+/// `None` means the field was empty.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+struct HistoryEntry {
+    id: u64,
+    todo_id: u64,
+    changed_at: String,
+    field: String,
+    old_value: Option<String>,
+    new_value: Option<String>,
+}
+
+fn db_record_history(
+    conn: &Connection,
+    todo_id: u64,
+    field: &str,
+    old_value: Option<&str>,
+    new_value: Option<&str>,
+    changed_at: &str,
+) -> rusqlite::Result<()> {
+    with_retry(
+        || {
+            conn.prepare_cached(
+                "INSERT INTO todo_history (todo_id, changed_at, field, old_value, new_value)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?
+            .execute((todo_id, changed_at, field, old_value, new_value))
+        },
+        WRITE_ATTEMPTS,
+    )?;
+    Ok(())
+}
+
+fn history_entry_from_row(row: &rusqlite::Row) -> rusqlite::Result<HistoryEntry> {
+    Ok(HistoryEntry {
+        id: row.get(0)?,
+        todo_id: row.get(1)?,
+        changed_at: local_timestamp(row.get(2)?),
+        field: row.get(3)?,
+        old_value: row.get(4)?,
+        new_value: row.get(5)?,
+    })
+}
+
+/// Returns a page of a todo's history, newest first.
+fn db_get_todo_history(
+    conn: &Connection,
+    todo_id: u64,
+    limit: u32,
+    offset: u32,
+) -> Vec<HistoryEntry> {
+    let mut stmt = conn
+        .prepare_cached(
+            "SELECT id, todo_id, changed_at, field, old_value, new_value FROM todo_history
+             WHERE todo_id = ?1
+             ORDER BY id DESC
+             LIMIT ?2 OFFSET ?3",
+        )
+        .unwrap();
+    let entries = stmt
+        .query_map((todo_id, limit, offset), history_entry_from_row)
+        .unwrap();
+    entries.map(|entry| entry.unwrap()).collect()
+}
+
+/// A todo's whole history in the order the changes happened, for reading it
+/// as an audit log rather than paging through recent edits.
+fn db_get_history(conn: &Connection, todo_id: u64) -> Vec<HistoryEntry> {
+    let mut stmt = conn
+        .prepare_cached(
+            "SELECT id, todo_id, changed_at, field, old_value, new_value FROM todo_history
+             WHERE todo_id = ?1
+             ORDER BY id",
+        )
+        .unwrap();
+    let entries = stmt.query_map((todo_id,), history_entry_from_row).unwrap();
+    entries.map(|entry| entry.unwrap()).collect()
+}
+
+/// Sets the field changed by history entry `entry_id` back to its old value,
+/// whatever it has been changed to since. The revert goes through
+/// `db_update_todo`, so it is itself recorded in the history.
+fn db_revert_todo(conn: &Connection, id: u64, entry_id: u64) -> Result<Todo, String> {
+    let entry = conn
+        .prepare_cached(
+            "SELECT id, todo_id, changed_at, field, old_value, new_value FROM todo_history
+             WHERE id = ?1",
+        )
+        .and_then(|mut stmt| {
+            stmt.query_row((entry_id,), history_entry_from_row)
+                .optional()
+        })
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("History entry {} not found", entry_id))?;
+    if entry.todo_id != id {
+        return Err(format!(
+            "History entry {} does not belong to todo {}",
+            entry_id, id
+        ));
+    }
+
+    let old = entry.old_value;
+    match entry.field.as_str() {
+        "title" => {
+            let title = old.ok_or("History entry has no previous title")?;
+            db_update_todo(conn, id, Some(title), None, None, None);
+        }
+        "completed" => {
+            let completed = old.as_deref() == Some("true");
+            db_update_todo(conn, id, None, Some(completed), None, None);
+        }
+        "deadline" => {
+            db_update_todo(conn, id, None, None, Some(old.unwrap_or_default()), None);
+        }
+        "estimate_minutes" => {
+            let minutes = old.and_then(|m| m.parse().ok()).unwrap_or(0);
+            db_update_todo(conn, id, None, None, None, Some(minutes));
+        }
+        "reminder_minutes" => {
+            let changes = TodoChanges {
+                reminder_minutes: Some(old.and_then(|m| m.parse().ok()).unwrap_or(0)),
+                ..TodoChanges::default()
+            };
+            db_update_todo_at_version(conn, id, None, &changes);
+        }
+        field => return Err(format!("Cannot revert field '{}'", field)),
+    }
+    db_get_todo(conn, id).ok_or_else(|| "Todo not found".to_string())
+}
+
+// --- Tauri Commands ---
+
+/// Runs the body of a mutating command with the database (and undo history)
+/// locked, then emits the changes it reports. Events go out only once the lock
+/// is released, by which point every transaction the body opened has
+/// committed. Every mutating command goes through here so none can forget to
+/// notify the other windows.
+fn mutate<T>(
+    state: &AppState,
+    events: &impl ChangeEmitter,
+    body: impl FnOnce(&mut Connection, &mut undo::UndoHistory) -> (T, Vec<TodosChanged>),
+) -> T {
+    let (out, changes) = {
+        let mut conn = state.db.lock().unwrap();
+        let mut history = state.history.lock().unwrap();
+        body(&mut conn, &mut history)
+    };
+    for change in &changes {
+        if let Err(e) = events.emit_changed(change) {
+            tracing::warn!("failed to emit {}: {}", events::TODOS_CHANGED_EVENT, e);
+        }
+    }
+    out
+}
+
+/// `mutate` for commands that return the todos they touched: those leave
+/// with their deadline fields filled in.
+fn mutate_todos<T: Enriched>(
+    state: &AppState,
+    events: &impl ChangeEmitter,
+    body: impl FnOnce(&mut Connection, &mut undo::UndoHistory) -> (T, Vec<TodosChanged>),
+) -> T {
+    mutate(state, events, body).enriched(Local::now())
+}
+
+/// After a restore or reset the old row ids mean nothing, so the undo history
+/// is dropped and listeners are told to reload everything.
+fn replaced_database(
+    result: Result<(), String>,
+    history: &mut undo::UndoHistory,
+) -> (Result<(), String>, Vec<TodosChanged>) {
+    if result.is_err() {
+        return (result, Vec::new());
+    }
+    history.clear();
+    info!("database replaced");
+    (result, vec![TodosChanged::reloaded()])
+}
+
+/// `replaced_database` for a restore or reset, which replaces the settings
+/// too: the app lock is set up again from the new ones.
+fn replaced_settings(
+    state: &AppState,
+    conn: &Connection,
+    result: Result<(), String>,
+    history: &mut undo::UndoHistory,
+) -> (Result<(), String>, Vec<TodosChanged>) {
+    if result.is_ok() {
+        load_app_lock(state, conn, false);
+    }
+    replaced_database(result, history)
+}
+
+/// Records the todos an import added, so one undo takes them all out again,
+/// and reports them to listeners.
+fn imported_changes(
+    summary: &Result<import::ImportSummary, String>,
+    history: &mut undo::UndoHistory,
+) -> Vec<TodosChanged> {
+    match summary {
+        Ok(summary) if !summary.dry_run && !summary.ids.is_empty() => {
+            history.push(undo::Change::created_all(&summary.ids));
+            vec![TodosChanged::added(summary.ids.clone())]
+        }
+        _ => Vec::new(),
+    }
+}
+
+#[tauri::command]
+fn get_todos(
+    query: Option<TodoQuery>,
+    filter: Option<Filter>,
+    state: State<AppState>,
+) -> Vec<TodoSummary> {
+    let conn = state.db.lock().unwrap();
+    let query = TodoQuery::or_filter(query, filter);
+    let todos = db_query_summaries(&conn, &query, Local::now());
+    debug!("get_todos filter={:?} rows={}", query.filter, todos.len());
+    todos
+}
+
+/// Searches with every filter set in `query` at once.
+#[tauri::command]
+fn query_todos(query: TodoQuery, state: State<AppState>) -> Result<Vec<TodoSummary>, String> {
+    rejected(
+        "query_todos",
+        validate_deadline(query.due_before.clone()).map_err(|e| e.to_string()),
+    )?;
+    let conn = state.db.lock().unwrap();
+    let todos = db_query_summaries(&conn, &query, Local::now());
+    let redacted =
+        |text: &Option<String>| text.as_deref().map_or("none".to_string(), logging::redact);
+    debug!(
+        "query_todos filter={:?} tag={} text={} due_before={:?} rows={}",
+        query.filter,
+        redacted(&query.tag),
+        redacted(&query.text),
+        query.due_before,
+        todos.len()
+    );
+    Ok(todos)
+}
+
+/// `get_todos` as it was before summaries: full rows, descriptions included.
+/// Kept while callers move to `get_todos` plus `get_todo_detail`.
+#[tauri::command]
+fn get_todos_full(query: Option<TodoQuery>, state: State<AppState>) -> Vec<Todo> {
+    let conn = state.db.lock().unwrap();
+    let query = query.unwrap_or_default();
+    let todos = db_query_todos(&conn, &query, Local::now());
+    debug!(
+        "get_todos_full filter={:?} rows={}",
+        query.filter,
+        todos.len()
+    );
+    enrich_all(todos, Local::now())
+}
+
+/// What changed after `since`, for a sync client pulling deltas. Pass the
+/// latest `updated_at` or `deleted_at` seen as the next `since`.
+#[tauri::command]
+fn get_modified_since(since: String, state: State<AppState>) -> Result<ModifiedSince, String> {
+    if to_utc(&since).is_none() {
+        return rejected(
+            "get_modified_since",
+            Err(format!("Invalid timestamp '{}'", since)),
+        );
+    }
+    let conn = state.db.lock().unwrap();
+    let modified = ModifiedSince {
+        todos: enrich_all(db_get_modified_since(&conn, &since), Local::now()),
+        deleted: db_get_deleted_since(&conn, &since),
+    };
+    debug!(
+        "get_modified_since rows={} deleted={}",
+        modified.todos.len(),
+        modified.deleted.len()
+    );
+    Ok(modified)
+}
+
+#[tauri::command]
+fn get_todo_detail(id: u64, state: State<AppState>) -> Option<TodoDetail> {
+    let conn = state.db.lock().unwrap();
+    let detail = db_get_todo_detail(&conn, id);
+    debug!("get_todo_detail id={} found={}", id, detail.is_some());
+    detail.map(|detail| TodoDetail {
+        todo: enrich(detail.todo, Local::now()),
+        ..detail
+    })
+}
+
+#[tauri::command]
+fn get_todos_with_meta(filter: Option<Filter>, state: State<AppState>) -> TodosWithMeta {
+    let conn = state.db.lock().unwrap();
+    let now = Local::now();
+    let mut meta = db_get_todos_with_meta(&conn, filter.unwrap_or_default(), now);
+    meta.todos = enrich_all(meta.todos, now);
+    debug!(
+        "get_todos_with_meta filter={:?} rows={} total={} large={}",
+        filter,
+        meta.todos.len(),
+        meta.total,
+        meta.large
+    );
+    meta
+}
+
+#[tauri::command]
+fn get_next_deadline(state: State<AppState>) -> Option<Todo> {
+    let conn = state.db.lock().unwrap();
+    let next = db_get_next_deadline(&conn, Local::now());
+    debug!("get_next_deadline id={:?}", next.as_ref().map(|t| t.id));
+    next.map(|todo| enrich(todo, Local::now()))
+}
+
+#[tauri::command]
+fn get_todos_by_day(state: State<AppState>) -> Vec<DayGroup> {
+    let conn = state.db.lock().unwrap();
+    let now = Local::now();
+    let days: Vec<DayGroup> = db_get_todos_grouped_by_day(&conn)
+        .into_iter()
+        .map(|(date, todos)| DayGroup {
+            date,
+            todos: enrich_all(todos, now),
+        })
+        .collect();
+    debug!("get_todos_by_day days={}", days.len());
+    days
+}
+
+/// Overdue, each day up to `days_ahead` days from today, later and no
+/// deadline, in that order; see `agenda::group`.
+#[tauri::command]
+fn get_agenda(days_ahead: u32, state: State<AppState>) -> Vec<agenda::AgendaGroup> {
+    let conn = state.db.lock().unwrap();
+    let now = Local::now();
+    let groups: Vec<agenda::AgendaGroup> = db_get_agenda(&conn, now, days_ahead)
+        .into_iter()
+        .map(|group| agenda::AgendaGroup {
+            todos: enrich_all(group.todos, now),
+            ..group
+        })
+        .collect();
+    debug!(
+        "get_agenda days_ahead={} groups={}",
+        days_ahead,
+        groups.len()
+    );
+    groups
+}
+
+#[tauri::command]
+fn get_unscheduled(state: State<AppState>) -> Vec<Todo> {
+    let conn = state.db.lock().unwrap();
+    let todos = db_get_todos_without_deadline(&conn);
+    debug!("get_unscheduled rows={}", todos.len());
+    enrich_all(todos, Local::now())
+}
+
+#[tauri::command]
+fn get_recent(limit: u32, state: State<AppState>) -> Vec<Todo> {
+    let conn = state.db.lock().unwrap();
+    let todos = db_get_recent(&conn, limit);
+    debug!("get_recent limit={} rows={}", limit, todos.len());
+    enrich_all(todos, Local::now())
+}
+
+/// Fills the database with demo todos for frontend work. Only in debug
+/// builds.
+#[cfg(debug_assertions)]
+#[tauri::command]
+fn seed_demo_data(
+    count: u32,
+    force: Option<bool>,
+    app: AppHandle,
+    state: State<AppState>,
+) -> Result<usize, String> {
+    mutate(&state, &app, |conn, history| {
+        let created = rejected(
+            "seed_demo_data",
+            demo::seed_demo_data(conn, count, force.unwrap_or(false), Local::now()),
+        );
+        info!("seed_demo_data count={} created={:?}", count, created);
+        if !matches!(created, Ok(n) if n > 0) {
+            return (created, Vec::new());
+        }
+        history.clear();
+        (created, vec![TodosChanged::reloaded()])
+    })
+}
+
+#[tauri::command]
+fn get_todos_by_urgency(state: State<AppState>) -> Vec<Todo> {
+    let conn = state.db.lock().unwrap();
+    let todos = db_get_todos_by_urgency(&conn, Local::now());
+    debug!("get_todos_by_urgency rows={}", todos.len());
+    enrich_all(todos, Local::now())
+}
+
+#[tauri::command]
+fn get_focus(n: u32, state: State<AppState>) -> Vec<Todo> {
+    let conn = state.db.lock().unwrap();
+    let now = Local::now();
+    let todos = db_get_focus(&conn, now, n);
+    debug!("get_focus n={} rows={}", n, todos.len());
+    enrich_all(todos, now)
+}
+
+/// The position of `id` in the list as sorted on screen, for keyboard
+/// navigation without fetching the whole list.
+#[tauri::command]
+fn get_todo_index(id: u64, sort: SortBy, dir: SortDir, state: State<AppState>) -> Option<usize> {
+    let conn = state.db.lock().unwrap();
+    let index = db_index_of(&conn, id, sort, dir);
+    debug!(
+        "get_todo_index id={} sort={:?} dir={:?} index={:?}",
+        id, sort, dir, index
+    );
+    index
+}
+
+/// Created and completed counts per day or week for a chart; the range is
+/// given as local `YYYY-MM-DD` dates, both included.
+#[tauri::command]
+fn get_completion_stats(
+    range_start: String,
+    range_end: String,
+    bucket: stats::Bucket,
+    state: State<AppState>,
+) -> Result<Vec<stats::CompletionStat>, String> {
+    let parse = |value: &str| {
+        NaiveDate::parse_from_str(value.trim(), "%Y-%m-%d")
+            .map_err(|_| format!("Invalid date '{}'", value))
+    };
+    let (start, end) = rejected(
+        "get_completion_stats",
+        parse(&range_start).and_then(|start| Ok((start, parse(&range_end)?))),
+    )?;
+    let conn = state.db.lock().unwrap();
+    let series = logged(
+        "get_completion_stats",
+        stats::completion_stats(&conn, start, end, bucket),
+    )?;
+    debug!(
+        "get_completion_stats bucket={:?} buckets={}",
+        bucket,
+        series.len()
+    );
+    Ok(series)
+}
+
+#[tauri::command]
+fn get_streak(state: State<AppState>) -> Result<stats::Streak, String> {
+    let conn = state.db.lock().unwrap();
+    let streak = logged(
+        "get_streak",
+        stats::streak(&conn, Local::now().date_naive()),
+    )?;
+    debug!("get_streak {:?}", streak);
+    Ok(streak)
+}
+
+/// Incomplete todos per priority, for a chart: `[none, low, medium, high]`.
+#[tauri::command]
+fn get_priority_counts(state: State<AppState>) -> [u64; MAX_PRIORITY as usize + 1] {
+    let conn = state.db.lock().unwrap();
+    let counts = db_count_by_priority(&conn);
+    debug!("get_priority_counts {:?}", counts);
+    counts
+}
+
+/// The folder the log files are written to, for opening it from a support
+/// dialog.
+#[tauri::command]
+fn get_log_dir(app: AppHandle) -> Result<PathBuf, String> {
+    let dir = app_data_dir(&app)?;
+    Ok(dir.join(logging::LOG_DIR))
+}
+
+/// The last `lines` lines of the log, oldest first.
+#[tauri::command]
+fn get_recent_logs(lines: u32, app: AppHandle) -> Result<Vec<String>, String> {
+    let recent = logged(
+        "get_recent_logs",
+        logging::recent_lines(&get_log_dir(app)?, lines as usize),
+    )?;
+    debug!("get_recent_logs lines={} rows={}", lines, recent.len());
+    Ok(recent)
+}
+
+#[tauri::command]
+fn get_due_today(state: State<AppState>) -> Vec<Todo> {
+    let conn = state.db.lock().unwrap();
+    let todos = db_get_due_today(&conn, Local::now());
+    debug!("get_due_today rows={}", todos.len());
+    enrich_all(todos, Local::now())
+}
+
+/// Todos whose reminder is due now; the frontend polls this.
+#[tauri::command]
+fn get_pending_reminders(state: State<AppState>) -> Vec<Todo> {
+    let conn = state.db.lock().unwrap();
+    let todos = db_get_pending_reminders(&conn, Local::now());
+    debug!("get_pending_reminders rows={}", todos.len());
+    enrich_all(todos, Local::now())
+}
+
+/// Minutes estimated for the todos matching `filter`, active ones by default.
+#[tauri::command]
+fn get_total_estimate(filter: Option<Filter>, state: State<AppState>) -> u64 {
+    let conn = state.db.lock().unwrap();
+    let filter = filter.unwrap_or(Filter::Active);
+    let total = db_sum_estimates(&conn, filter, Local::now());
+    debug!("get_total_estimate filter={:?} minutes={}", filter, total);
+    total
+}
+
+/// The current list, to hand back to `diff_todo_snapshots` later.
+#[tauri::command]
+fn take_snapshot(state: State<AppState>) -> Vec<Todo> {
+    let conn = state.db.lock().unwrap();
+    let todos = snapshot::snapshot(&conn);
+    debug!("take_snapshot count={}", todos.len());
+    todos
+}
+
+#[tauri::command]
+fn diff_todo_snapshots(before: Vec<Todo>, after: Vec<Todo>) -> snapshot::Diff {
+    let diff = snapshot::diff_snapshots(&before, &after);
+    debug!(
+        "diff_todo_snapshots added={} removed={} changed={}",
+        diff.added.len(),
+        diff.removed.len(),
+        diff.changed.len()
+    );
+    diff
+}
+
+#[tauri::command]
+fn find_duplicates(state: State<AppState>) -> Vec<DuplicateGroup> {
+    let conn = state.db.lock().unwrap();
+    let groups = db_find_duplicates(&conn);
+    debug!("find_duplicates groups={}", groups.len());
+    groups
+}
+
+#[tauri::command]
+fn get_completion_history(
+    start: String,
+    end: String,
+    state: State<AppState>,
+) -> Vec<(String, u64)> {
+    let conn = state.db.lock().unwrap();
+    let history = db_completion_history(&conn, &start, &end);
+    debug!("get_completion_history days={}", history.len());
+    history
+}
+
+/// Inserts an already validated todo the way `add_todo` does: undoable and
+/// announced to every window. Also used for todos coming from deep links.
+fn create_todo(state: &AppState, events: &impl ChangeEmitter, new: NewTodo) -> Todo {
+    mutate_todos(state, events, |conn, history| {
+        let todo = db_insert_todo(conn, new).expect("Failed to insert todo");
+        history.push(undo::Change::created(todo.id));
+        info!("add_todo id={}", todo.id);
+        let changes = vec![TodosChanged::added(vec![todo.id])];
+        (todo, changes)
+    })
+}
+
+/// `create_todo`, unless an incomplete todo already has the title (see
+/// `db_find_active_duplicate`) and `on_duplicate` says not to add another.
+/// The check and the insert happen under one lock.
+fn create_todo_checked(
+    state: &AppState,
+    events: &impl ChangeEmitter,
+    new: NewTodo,
+    on_duplicate: OnDuplicate,
+) -> Result<Todo, TodoError> {
+    mutate_todos(state, events, |conn, history| {
+        if on_duplicate != OnDuplicate::Allow {
+            if let Some(existing) = db_find_active_duplicate(conn, &new.title) {
+                info!(
+                    "add_todo duplicate_of={} on_duplicate={:?}",
+                    existing.id, on_duplicate
+                );
+                return match on_duplicate {
+                    OnDuplicate::ReturnExisting => (Ok(existing), Vec::new()),
+                    _ => (Err(TodoError::Duplicate(Box::new(existing))), Vec::new()),
+                };
+            }
+        }
+        let todo = db_insert_todo(conn, new).expect("Failed to insert todo");
+        history.push(undo::Change::created(todo.id));
+        info!("add_todo id={}", todo.id);
+        let changes = vec![TodosChanged::added(vec![todo.id])];
+        (Ok(todo), changes)
+    })
+}
+
+/// Adds a todo. With `on_duplicate` set to `reject` or `return_existing` it
+/// first looks for an incomplete todo with the same title.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+fn add_todo(
+    title: String,
+    deadline: Option<String>,
+    deadline_text: Option<String>,
+    estimate_minutes: Option<u32>,
+    reminder_minutes: Option<u32>,
+    on_duplicate: Option<OnDuplicate>,
+    app: AppHandle,
+    state: State<AppState>,
+) -> Result<Todo, TodoError> {
+    let args = AddTodoArgs {
+        title,
+        deadline,
+        deadline_text,
+        estimate_minutes,
+        reminder_minutes,
+        on_duplicate,
+    };
+    rejected("add_todo", add_todo_with(&state, &app, args))
+}
+
+/// The arguments of `add_todo`, also the body of `POST /todos`.
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+struct AddTodoArgs {
+    title: String,
+    deadline: Option<String>,
+    deadline_text: Option<String>,
+    estimate_minutes: Option<u32>,
+    reminder_minutes: Option<u32>,
+    on_duplicate: Option<OnDuplicate>,
+}
+
+fn add_todo_with(
+    state: &AppState,
+    events: &impl ChangeEmitter,
+    args: AddTodoArgs,
+) -> Result<Todo, TodoError> {
+    let AddTodoArgs {
+        title,
+        deadline,
+        deadline_text,
+        estimate_minutes,
+        reminder_minutes,
+        on_duplicate,
+    } = args;
+    let mut checks = validation::Checks::default();
+    let deadline = match deadline_text.as_deref().map(str::trim) {
+        None | Some("") => deadline,
+        Some(_) if deadline.is_some() => checks.check(Err(ValidationError::new(
+            "deadline_text",
+            validation::CONFLICT,
+            "Pass either deadline or deadline_text, not both",
+        ))),
+        Some(text) => checks.check(
+            deadline_text::parse_deadline(text, Local::now())
+                .map(|parsed| Some(parsed.deadline))
+                .map_err(|e| {
+                    ValidationError::new("deadline_text", validation::UNREADABLE, e.to_string())
+                }),
+        ),
+    };
+    let max_chars = max_title_chars(&state.db.lock().unwrap());
+    let new = checks.check_all(validate_new_todo(
+        NewTodo {
+            title,
+            deadline,
+            estimate_minutes,
+            reminder_minutes,
+            ..NewTodo::default()
+        },
+        max_chars,
+    ));
+    let new = checks.finish(new).map_err(TodoError::Validation)?;
+    create_todo_checked(state, events, new, on_duplicate.unwrap_or_default())
+}
+
+/// How `add_todo` would read `input` as `deadline_text`, for previewing it
+/// while it is typed.
+#[tauri::command]
+fn parse_deadline_text(
+    input: String,
+) -> Result<deadline_text::ParsedDeadline, deadline_text::DeadlineTextError> {
+    deadline_text::parse_deadline(&input, Local::now())
+}
+
+/// Adds a todo from free text such as "Buy milk tomorrow"; see
+/// `quick_add::parse_quick_add` for the phrases it understands.
+#[tauri::command]
+fn quick_add(input: String, app: AppHandle, state: State<AppState>) -> Result<Todo, String> {
+    let (title, deadline) = quick_add::parse_quick_add(&input, Local::now());
+    let max_chars = max_title_chars(&state.db.lock().unwrap());
+    let new = NewTodo {
+        title: rejected(
+            "quick_add",
+            validate_title(&title, max_chars).map_err(|e| e.to_string()),
+        )?,
+        deadline,
+        ..NewTodo::default()
+    };
+    Ok(create_todo(&state, &app, new))
+}
+
+#[tauri::command]
+fn set_pinned(id: u64, pinned: bool, app: AppHandle, state: State<AppState>) -> bool {
+    mutate(&state, &app, |conn, history| {
+        let (updated, change) = undo::record(conn, &[id], |conn| db_set_pinned(conn, id, pinned));
+        info!("set_pinned id={} pinned={} updated={}", id, pinned, updated);
+        if !updated {
+            return (false, Vec::new());
+        }
+        history.push(change);
+        (true, vec![TodosChanged::updated(vec![id])])
+    })
+}
+
+#[tauri::command]
+fn set_color(
+    id: u64,
+    color: Option<String>,
+    app: AppHandle,
+    state: State<AppState>,
+) -> Result<bool, String> {
+    mutate(&state, &app, |conn, history| {
+        let (updated, change) =
+            undo::record(conn, &[id], |conn| db_set_color(conn, id, color.as_deref()));
+        let updated = rejected("set_color", updated);
+        info!(
+            "set_color id={} color={:?} updated={:?}",
+            id, color, updated
+        );
+        if updated != Ok(true) {
+            return (updated, Vec::new());
+        }
+        history.push(change);
+        (updated, vec![TodosChanged::updated(vec![id])])
+    })
+}
+
+/// Applies the same changes to all of `ids`; see `db_bulk_update_todos`.
+#[tauri::command]
+fn bulk_update_todos(
+    ids: Vec<u64>,
+    changes: TodoChanges,
+    app: AppHandle,
+    state: State<AppState>,
+) -> Result<Vec<BulkUpdateResult>, String> {
+    let max_chars = max_title_chars(&state.db.lock().unwrap());
+    let changes = rejected(
+        "bulk_update_todos",
+        validate_update(changes, max_chars).map_err(|errors| validation::describe(&errors)),
+    )?;
+    mutate(&state, &app, |conn, history| {
+        let (results, change) = undo::record(conn, &ids, |conn| {
+            db_bulk_update_todos(conn, &ids, &changes)
+        });
+        let results = logged("bulk_update_todos", results);
+        let updated: Vec<u64> = results
+            .iter()
+            .flatten()
+            .filter(|r| r.outcome == BulkOutcome::Updated)
+            .map(|r| r.id)
+            .collect();
+        info!(
+            "bulk_update_todos ids={} updated={} ok={}",
+            ids.len(),
+            updated.len(),
+            results.is_ok()
+        );
+        if updated.is_empty() {
+            return (results, Vec::new());
+        }
+        history.push(change);
+        (results, vec![TodosChanged::updated(updated)])
+    })
+}
+
+#[tauri::command]
+fn clear_deadlines(ids: Vec<u64>, app: AppHandle, state: State<AppState>) -> usize {
+    mutate(&state, &app, |conn, history| {
+        let (cleared, change) = undo::record(conn, &ids, |conn| db_clear_deadlines(conn, &ids));
+        info!("clear_deadlines ids={} cleared={}", ids.len(), cleared);
+        if cleared == 0 {
+            return (0, Vec::new());
+        }
+        history.push(change);
+        (cleared, vec![TodosChanged::updated(ids)])
+    })
+}
+
+/// Moves every overdue todo's deadline to today, for catching up after a
+/// break. Returns how many were moved.
+#[tauri::command]
+fn reschedule_overdue(app: AppHandle, state: State<AppState>) -> usize {
+    mutate(&state, &app, |conn, history| {
+        let now = Local::now();
+        let ids: Vec<u64> = db_get_overdue_todos(conn, now)
+            .iter()
+            .map(|t| t.id)
+            .collect();
+        let (moved, change) = undo::record(conn, &ids, |conn| db_reschedule_overdue(conn, now));
+        info!("reschedule_overdue moved={}", moved);
+        if moved == 0 {
+            return (0, Vec::new());
+        }
+        history.push(change);
+        (moved, vec![TodosChanged::updated(ids)])
+    })
+}
+
+/// Which todos a bulk command applies to: `{"ids": [...]}` or `"overdue"`.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+enum TodoSelector {
+    Ids(Vec<u64>),
+    Overdue,
+}
+
+/// Shifts deadlines by `days`, keeping dates as dates and times at the same
+/// time of day. Todos without a deadline are reported as `no_deadline`.
+#[tauri::command]
+fn postpone_todos(
+    selector: TodoSelector,
+    days: i32,
+    app: AppHandle,
+    state: State<AppState>,
+) -> Result<Vec<BulkUpdateResult>, String> {
+    mutate(&state, &app, |conn, history| {
+        let ids = match selector {
+            TodoSelector::Ids(ids) => ids,
+            TodoSelector::Overdue => db_get_overdue_todos(conn, Local::now())
+                .iter()
+                .map(|t| t.id)
+                .collect(),
+        };
+        let (results, change) =
+            undo::record(conn, &ids, |conn| db_postpone_todos(conn, &ids, days));
+        let results = logged("postpone_todos", results);
+        let updated: Vec<u64> = results
+            .iter()
+            .flatten()
+            .filter(|r| r.outcome == BulkOutcome::Updated)
+            .map(|r| r.id)
+            .collect();
+        info!(
+            "postpone_todos ids={} days={} updated={} ok={}",
+            ids.len(),
+            days,
+            updated.len(),
+            results.is_ok()
+        );
+        if updated.is_empty() {
+            return (results, Vec::new());
+        }
+        history.push(change);
+        (results, vec![TodosChanged::updated(updated)])
+    })
+}
+
+/// Returns the todo as saved, so the caller doesn't need to refetch it.
+/// With `expected_version` the edit is refused with `Conflict` if the todo
+/// has changed since that version was read.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+fn update_todo(
+    id: u64,
+    expected_version: Option<u64>,
+    title: Option<String>,
+    completed: Option<bool>,
+    deadline: Option<String>,
+    estimate_minutes: Option<u32>,
+    reminder_minutes: Option<u32>,
+    app: AppHandle,
+    state: State<AppState>,
+) -> Result<Todo, TodoError> {
+    let changes = TodoChanges {
+        title,
+        completed,
+        deadline,
+        estimate_minutes,
+        reminder_minutes,
+    };
+    rejected(
+        "update_todo",
+        update_todo_with(&state, &app, id, expected_version, changes),
+    )
+}
+
+fn update_todo_with(
+    state: &AppState,
+    events: &impl ChangeEmitter,
+    id: u64,
+    expected_version: Option<u64>,
+    changes: TodoChanges,
+) -> Result<Todo, TodoError> {
+    let max_chars = max_title_chars(&state.db.lock().unwrap());
+    let changes = validate_update(changes, max_chars).map_err(TodoError::Validation)?;
+    mutate_todos(state, events, |conn, history| {
+        let (outcome, change) = undo::record(conn, &[id], |conn| {
+            db_update_todo_at_version(conn, id, expected_version, &changes)
+        });
+        let updated = matches!(outcome, UpdateOutcome::Updated(_));
+        info!(
+            "update_todo id={} found={} updated={}",
+            id,
+            outcome.found(),
+            updated
+        );
+        if !updated {
+            return (outcome.into_result(id), Vec::new());
+        }
+        history.push(change);
+        (
+            outcome.into_result(id),
+            vec![TodosChanged::updated(vec![id])],
+        )
+    })
+}
+
+/// Sets or clears a deadline, normalized like `add_todo`'s. Returns `false`
+/// without changing anything if the deadline can't be read.
+#[tauri::command]
+fn set_deadline(id: u64, deadline: Option<String>, app: AppHandle, state: State<AppState>) -> bool {
+    let Ok(deadline) = validate_deadline(deadline) else {
+        return false;
+    };
+    mutate(&state, &app, |conn, history| {
+        let (updated, change) =
+            undo::record(conn, &[id], |conn| db_set_deadline(conn, id, deadline));
+        info!("set_deadline id={} updated={}", id, updated);
+        if !updated {
+            return (false, Vec::new());
+        }
+        history.push(change);
+        (true, vec![TodosChanged::updated(vec![id])])
+    })
+}
+
+#[tauri::command]
+fn snooze_todo(
+    id: u64,
+    preset: snooze::SnoozePreset,
+    app: AppHandle,
+    state: State<AppState>,
+) -> Result<Option<Todo>, String> {
+    rejected("snooze_todo", preset.check())?;
+    mutate_todos(&state, &app, |conn, history| {
+        let now = Local::now();
+        let (todo, change) =
+            undo::record(conn, &[id], |conn| db_snooze_todo(conn, id, preset, now));
+        let todo = logged("snooze_todo", todo);
+        info!(
+            "snooze_todo id={} preset={:?} deadline={:?}",
+            id,
+            preset,
+            todo.as_ref().ok().flatten().map(|t| &t.deadline)
+        );
+        let todo = match todo {
+            Ok(Some(todo)) => todo,
+            other => return (other, Vec::new()),
+        };
+        history.push(change);
+        (Ok(Some(todo)), vec![TodosChanged::updated(vec![id])])
+    })
+}
+
+#[tauri::command]
+fn delete_todo(id: u64, app: AppHandle, state: State<AppState>) -> bool {
+    delete_todo_with(&state, &app, id)
+}
+
+fn delete_todo_with(state: &AppState, events: &impl ChangeEmitter, id: u64) -> bool {
+    mutate(state, events, |conn, history| {
+        let (deleted, change) = undo::record(conn, &[id], |conn| db_delete_todo(conn, id));
+        info!("delete_todo id={} deleted={}", id, deleted);
+        if !deleted {
+            return (false, Vec::new());
+        }
+        history.push(change);
+        (true, vec![TodosChanged::deleted(vec![id])])
+    })
+}
+
+/// Deletes the completed todos and returns their ids. With `dry_run` it only
+/// returns the ids that would go, leaving the table and undo history alone.
+fn clear_completed_todos(state: &AppState, events: &impl ChangeEmitter, dry_run: bool) -> Vec<u64> {
+    if dry_run {
+        let ids = db_completed_ids(&state.db.lock().unwrap());
+        info!("clear_completed dry_run would_remove={}", ids.len());
+        return ids;
+    }
+    mutate(state, events, |conn, history| {
+        let ids = db_completed_ids(conn);
+        let (removed, change) = undo::record(conn, &ids, db_clear_completed);
+        info!("clear_completed removed={}", removed);
+        if removed == 0 {
+            return (Vec::new(), Vec::new());
+        }
+        history.push(change);
+        (ids.clone(), vec![TodosChanged::deleted(ids)])
+    })
+}
+
+#[tauri::command]
+fn clear_completed(dry_run: bool, app: AppHandle, state: State<AppState>) -> Vec<u64> {
+    clear_completed_todos(&state, &app, dry_run)
+}
+
+/// Moves completed todos to the archive table and returns their ids, or with
+/// `dry_run` just the ids that would move. The undo history is cleared,
+/// since undoing an earlier change could otherwise bring back a todo that
+/// now lives in the archive.
+fn archive_completed_todos(
+    state: &AppState,
+    events: &impl ChangeEmitter,
+    dry_run: bool,
+) -> Vec<u64> {
+    if dry_run {
+        let ids = db_completed_ids(&state.db.lock().unwrap());
+        info!("archive_completed dry_run would_archive={}", ids.len());
+        return ids;
+    }
+    mutate(state, events, |conn, history| {
+        let ids = db_completed_ids(conn);
+        let archived = db_archive_completed(conn);
+        info!("archive_completed archived={}", archived);
+        if archived == 0 {
+            return (Vec::new(), Vec::new());
+        }
+        history.clear();
+        (ids.clone(), vec![TodosChanged::deleted(ids)])
+    })
+}
+
+#[tauri::command]
+fn archive_completed(dry_run: bool, app: AppHandle, state: State<AppState>) -> Vec<u64> {
+    archive_completed_todos(&state, &app, dry_run)
+}
+
+/// Not recorded for undo: undoing would delete the todo rather than put it
+/// back in the archive.
+#[tauri::command]
+fn unarchive_todo(id: u64, app: AppHandle, state: State<AppState>) -> Option<Todo> {
+    mutate_todos(&state, &app, |conn, _| {
+        let todo = db_unarchive_todo(conn, id);
+        info!(
+            "unarchive_todo id={} restored={:?}",
+            id,
+            todo.as_ref().map(|t| t.id)
+        );
+        let changes = match &todo {
+            Some(todo) => vec![TodosChanged::added(vec![todo.id])],
+            None => Vec::new(),
+        };
+        (todo, changes)
+    })
+}
+
+#[tauri::command]
+fn get_archive(state: State<AppState>) -> Vec<ArchivedTodo> {
+    let conn = state.db.lock().unwrap();
+    let archive = db_get_archive(&conn);
+    debug!("get_archive rows={}", archive.len());
+    archive
+}
+
+#[tauri::command]
+fn undo(app: AppHandle, state: State<AppState>) -> Result<bool, String> {
+    mutate(&state, &app, |conn, history| {
+        let undone = logged("undo", history.undo(conn));
+        info!("undo undone={:?}", undone);
+        let changes = match undone {
+            Ok(true) => vec![TodosChanged::reloaded()],
+            _ => Vec::new(),
+        };
+        (undone, changes)
+    })
+}
+
+#[tauri::command]
+fn redo(app: AppHandle, state: State<AppState>) -> Result<bool, String> {
+    mutate(&state, &app, |conn, history| {
+        let redone = logged("redo", history.redo(conn));
+        info!("redo redone={:?}", redone);
+        let changes = match redone {
+            Ok(true) => vec![TodosChanged::reloaded()],
+            _ => Vec::new(),
+        };
+        (redone, changes)
+    })
+}
+
+#[tauri::command]
+fn can_undo(state: State<AppState>) -> bool {
+    state.history.lock().unwrap().can_undo()
+}
+
+#[tauri::command]
+fn can_redo(state: State<AppState>) -> bool {
+    state.history.lock().unwrap().can_redo()
+}
+
+#[tauri::command]
+fn add_tag(todo_id: u64, name: String, app: AppHandle, state: State<AppState>) -> bool {
+    mutate(&state, &app, |conn, history| {
+        let (added, change) =
+            undo::record(conn, &[todo_id], |conn| db_add_tag(conn, todo_id, &name));
+        info!("add_tag todo_id={} added={}", todo_id, added);
+        if !added {
+            return (false, Vec::new());
+        }
+        history.push(change);
+        (true, vec![TodosChanged::updated(vec![todo_id])])
+    })
+}
+
+#[tauri::command]
+fn add_tag_to_todos(ids: Vec<u64>, name: String, app: AppHandle, state: State<AppState>) -> usize {
+    mutate(&state, &app, |conn, history| {
+        let (added, change) =
+            undo::record(conn, &ids, |conn| db_add_tag_to_todos(conn, &ids, &name));
+        info!("add_tag_to_todos ids={} added={}", ids.len(), added);
+        if added == 0 {
+            return (0, Vec::new());
+        }
+        history.push(change);
+        (added, vec![TodosChanged::updated(ids)])
+    })
+}
+
+#[tauri::command]
+fn set_completed_bulk(
+    ids: Vec<u64>,
+    completed: bool,
+    app: AppHandle,
+    state: State<AppState>,
+) -> usize {
+    mutate(&state, &app, |conn, history| {
+        let (changed, change) = undo::record(conn, &ids, |conn| {
+            db_set_completed_bulk(conn, &ids, completed)
+        });
+        info!(
+            "set_completed_bulk ids={} completed={} changed={}",
+            ids.len(),
+            completed,
+            changed
+        );
+        if changed == 0 {
+            return (0, Vec::new());
+        }
+        history.push(change);
+        (changed, vec![TodosChanged::updated(ids)])
+    })
+}
+
+#[tauri::command]
+fn remove_tag(todo_id: u64, name: String, app: AppHandle, state: State<AppState>) -> bool {
+    mutate(&state, &app, |conn, history| {
+        let (removed, change) =
+            undo::record(conn, &[todo_id], |conn| db_remove_tag(conn, todo_id, &name));
+        info!("remove_tag todo_id={} removed={}", todo_id, removed);
+        if !removed {
+            return (false, Vec::new());
+        }
+        history.push(change);
+        (true, vec![TodosChanged::updated(vec![todo_id])])
+    })
+}
+
+#[tauri::command]
+fn dedup_tags(todo_id: u64, app: AppHandle, state: State<AppState>) -> usize {
+    mutate(&state, &app, |conn, history| {
+        let (removed, change) = undo::record(conn, &[todo_id], |conn| db_dedup_tags(conn, todo_id));
+        info!("dedup_tags todo_id={} removed={}", todo_id, removed);
+        if removed == 0 {
+            return (0, Vec::new());
+        }
+        history.push(change);
+        (removed, vec![TodosChanged::updated(vec![todo_id])])
+    })
+}
+
+#[tauri::command]
+fn rename_tag(old: String, new: String, app: AppHandle, state: State<AppState>) -> usize {
+    mutate(&state, &app, |conn, _| {
+        let ids = db_todo_ids_with_tag(conn, &old);
+        let moved = db_rename_tag(conn, &old, &new);
+        info!("rename_tag moved={}", moved);
+        let changes = if moved > 0 {
+            vec![TodosChanged::updated(ids)]
+        } else {
+            Vec::new()
+        };
+        (moved, changes)
+    })
+}
+
+/// Moves the incomplete todos of one tag to another, e.g. when retiring a
+/// project.
+#[tauri::command]
+fn move_incomplete(
+    from_tag: u64,
+    to_tag: u64,
+    app: AppHandle,
+    state: State<AppState>,
+) -> Result<usize, String> {
+    mutate(&state, &app, |conn, history| {
+        let ids = db_incomplete_ids_with_tag(conn, from_tag);
+        let (moved, change) = undo::record(conn, &ids, |conn| {
+            db_move_incomplete(conn, from_tag, to_tag)
+        });
+        let moved = logged("move_incomplete", moved);
+        let changes = match moved {
+            Ok(moved) if moved > 0 => {
+                info!("move_incomplete moved={}", moved);
+                history.push(change);
+                vec![TodosChanged::updated(ids)]
+            }
+            _ => Vec::new(),
+        };
+        (moved, changes)
+    })
+}
+
+#[tauri::command]
+fn get_tags(todo_id: u64, state: State<AppState>) -> Vec<String> {
+    let conn = state.db.lock().unwrap();
+    db_get_tags(&conn, todo_id)
+}
+
+#[tauri::command]
+fn get_tag_counts(state: State<AppState>) -> Vec<(u64, String, u64)> {
+    let conn = state.db.lock().unwrap();
+    db_count_per_tag(&conn)
+}
+
+#[tauri::command]
+fn merge_todos(
+    keep_id: u64,
+    merge_id: u64,
+    app: AppHandle,
+    state: State<AppState>,
+) -> Result<Todo, String> {
+    if keep_id == merge_id {
+        return logged(
+            "merge_todos",
+            Err("Cannot merge a todo with itself".to_string()),
+        );
+    }
+    mutate_todos(&state, &app, |conn, history| {
+        let (merged, change) = undo::record(conn, &[keep_id, merge_id], |conn| {
+            db_merge_todos(conn, keep_id, merge_id)
+        });
+        match logged(
+            "merge_todos",
+            merged.ok_or_else(|| "Todo not found".to_string()),
+        ) {
+            Ok(merged) => {
+                history.push(change);
+                info!("merge_todos keep_id={} merge_id={}", keep_id, merge_id);
+                let changes = vec![
+                    TodosChanged::updated(vec![keep_id]),
+                    TodosChanged::deleted(vec![merge_id]),
+                ];
+                (Ok(merged), changes)
+            }
+            Err(e) => (Err(e), Vec::new()),
+        }
+    })
+}
+
+#[tauri::command]
+fn export_csv(
+    path: String,
+    query: Option<TodoQuery>,
+    filter: Option<Filter>,
+    state: State<AppState>,
+) -> Result<usize, String> {
+    let conn = state.db.lock().unwrap();
+    let query = TodoQuery::or_filter(query, filter);
+    let rows = logged(
+        "export_csv",
+        export::export_csv(&conn, Path::new(&path), &query, Local::now()),
+    )?;
+    info!("export_csv filter={:?} rows={}", query.filter, rows);
+    Ok(rows)
+}
+
+#[tauri::command]
+fn export_ics(
+    path: String,
+    component: Option<export::IcsComponent>,
+    include_completed: Option<bool>,
+    query: Option<TodoQuery>,
+    state: State<AppState>,
+) -> Result<(), String> {
+    let conn = state.db.lock().unwrap();
+    logged(
+        "export_ics",
+        export::export_ics(
+            &conn,
+            Path::new(&path),
+            component.unwrap_or_default(),
+            include_completed.unwrap_or(false),
+            &query.unwrap_or_default(),
+        ),
+    )
+}
+
+#[tauri::command]
+fn export_markdown(
+    path: Option<String>,
+    query: Option<TodoQuery>,
+    style: Option<export::MarkdownStyle>,
+    state: State<AppState>,
+) -> Result<String, String> {
+    let conn = state.db.lock().unwrap();
+    logged(
+        "export_markdown",
+        export::export_markdown(
+            &conn,
+            path.as_deref().map(Path::new),
+            &query.unwrap_or_default(),
+            style.unwrap_or_default(),
+        ),
+    )
+}
+
+#[tauri::command]
+fn import_csv(
+    path: String,
+    options: import::CsvImportOptions,
+    app: AppHandle,
+    state: State<AppState>,
+) -> Result<import::ImportSummary, String> {
+    let _busy = state.busy.try_acquire("import")?;
+    mutate(&state, &app, |conn, history| {
+        let summary = logged(
+            "import_csv",
+            import::import_csv(conn, Path::new(&path), &options),
+        );
+        if let Ok(summary) = &summary {
+            info!(
+                "import_csv dry_run={} imported={} skipped={}",
+                summary.dry_run, summary.imported, summary.skipped
+            );
+        }
+        let changes = imported_changes(&summary, history);
+        (summary, changes)
+    })
+}
+
+#[tauri::command]
+fn import_todoist_csv(
+    path: String,
+    app: AppHandle,
+    state: State<AppState>,
+) -> Result<import::ImportSummary, String> {
+    let _busy = state.busy.try_acquire("import")?;
+    mutate(&state, &app, |conn, history| {
+        let summary = logged(
+            "import_todoist_csv",
+            import::import_todoist_csv(conn, Path::new(&path)),
+        );
+        if let Ok(summary) = &summary {
+            info!(
+                "import_todoist_csv imported={} skipped={} unsupported={}",
+                summary.imported, summary.skipped, summary.unsupported
+            );
+        }
+        let changes = imported_changes(&summary, history);
+        (summary, changes)
+    })
+}
+
+/// Imports the `- [ ]`/`- [x]` items of a Markdown or plain-text checklist.
+#[tauri::command]
+fn import_markdown(path: String, app: AppHandle, state: State<AppState>) -> Result<usize, String> {
+    let _busy = state.busy.try_acquire("import")?;
+    mutate(&state, &app, |conn, history| {
+        let summary = logged(
+            "import_markdown",
+            import::import_markdown_file(conn, Path::new(&path)),
+        );
+        if let Ok(summary) = &summary {
+            info!("import_markdown imported={}", summary.imported);
+        }
+        let changes = imported_changes(&summary, history);
+        (summary.map(|summary| summary.imported), changes)
+    })
+}
+
+/// Writes the todos, tags and their links to `path` as a JSON bundle, for
+/// moving them to another device with `import_bundle`.
+#[tauri::command]
+fn export_bundle(path: String, state: State<AppState>) -> Result<(), String> {
+    let conn = state.db.lock().unwrap();
+    let json = logged("export_bundle", bundle::export_bundle(&conn))?;
+    logged(
+        "export_bundle",
+        std::fs::write(&path, json).map_err(|e| e.to_string()),
+    )
+}
+
+/// Replaces the todos, tags and their links with the bundle at `path`.
+#[tauri::command]
+fn import_bundle(path: String, app: AppHandle, state: State<AppState>) -> Result<(), String> {
+    let _busy = state.busy.try_acquire("import")?;
+    let json = logged(
+        "import_bundle",
+        std::fs::read_to_string(&path).map_err(|e| e.to_string()),
+    )?;
+    mutate(&state, &app, |conn, history| {
+        let imported = logged("import_bundle", bundle::import_bundle(conn, &json));
+        replaced_database(imported, history)
+    })
+}
+
+/// Merges a bundle exported on another machine into the list, matching
+/// todos by uuid, so both machines' changes survive a manual sync.
+#[tauri::command]
+fn merge_export(
+    path: String,
+    app: AppHandle,
+    state: State<AppState>,
+) -> Result<merge::MergeReport, String> {
+    let _busy = state.busy.try_acquire("import")?;
+    let json = logged(
+        "merge_export",
+        std::fs::read_to_string(&path).map_err(|e| e.to_string()),
+    )?;
+    mutate(&state, &app, |conn, history| {
+        let report = logged("merge_export", merge::merge_export(conn, &json));
+        let changes = match &report {
+            Ok(report) if report.inserted + report.updated > 0 => {
+                info!(
+                    "merge_export inserted={} updated={} conflicts={}",
+                    report.inserted,
+                    report.updated,
+                    report.conflicts.len()
+                );
+                // Undo snapshots from before would overwrite merged rows.
+                history.clear();
+                vec![TodosChanged::reloaded()]
+            }
+            _ => Vec::new(),
+        };
+        (report, changes)
+    })
+}
+
+#[tauri::command]
+fn backup_database(dest_path: String, state: State<AppState>) -> Result<(), String> {
+    let _busy = state.busy.try_acquire("back up")?;
+    let conn = state.db.lock().unwrap();
+    logged(
+        "backup_database",
+        backup::backup_database(
+            &conn,
+            Path::new(&dest_path),
+            state.lock.passphrase().as_deref(),
+        ),
+    )
+}
+
+#[tauri::command]
+fn restore_database(
+    src_path: String,
+    app: AppHandle,
+    state: State<AppState>,
+) -> Result<(), String> {
+    let _busy = state.busy.try_acquire("restore")?;
+    let passphrase = state.lock.passphrase();
+    mutate(&state, &app, |conn, history| {
+        let restored = logged(
+            "restore_database",
+            backup::restore_database(conn, Path::new(&src_path), passphrase.as_deref()),
+        );
+        replaced_settings(&state, conn, restored, history)
+    })
+    .inspect(|()| apply_saved_settings(&app))
+}
+
+/// Moves the database file into `new_dir` and keeps using it from there.
+/// The old file is deleted unless `keep_old` is set.
+#[tauri::command]
+fn set_database_location(
+    new_dir: String,
+    keep_old: Option<bool>,
+    app: AppHandle,
+    state: State<AppState>,
+) -> Result<String, String> {
+    let _busy = state.busy.try_acquire("move the database")?;
+    let mut database = state.database.lock().unwrap();
+    if !database.is_persistent_default() {
+        return rejected(
+            "set_database_location",
+            Err("The database was chosen for this run only and can't be moved".to_string()),
+        );
+    }
+    if database.source == db_location::DbSource::Profile {
+        return rejected(
+            "set_database_location",
+            Err("Only the default profile's database can be moved".to_string()),
+        );
+    }
+    let app_dir = app_data_dir(&app)?;
+    let mut conn = state.db.lock().unwrap();
+    let new_path = logged(
+        "set_database_location",
+        db_location::move_database(
+            &mut conn,
+            &app_dir,
+            Path::new(&new_dir),
+            keep_old.unwrap_or(false),
+            state.lock.passphrase().as_deref(),
+            |_| Ok(()),
+        ),
+    )?;
+    info!("database moved to {}", new_path.display());
+    *database = db_location::DbChoice {
+        source: db_location::DbSource::Pointer,
+        ephemeral: false,
+        path: Some(new_path.clone()),
+    };
+    Ok(new_path.display().to_string())
+}
+
+/// How this run was started, so an end-to-end test can check it isn't
+/// touching real data.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+struct RuntimeInfo {
+    database: db_location::DbChoice,
+    debug_build: bool,
+}
+
+#[tauri::command]
+fn get_runtime_info(state: State<AppState>) -> RuntimeInfo {
+    RuntimeInfo {
+        database: state.database.lock().unwrap().clone(),
+        debug_build: cfg!(debug_assertions),
+    }
+}
+
+#[tauri::command]
+fn list_profiles(app: AppHandle) -> Result<profiles::ProfileIndex, String> {
+    let app_dir = app_data_dir(&app)?;
+    Ok(profiles::load(&app_dir))
+}
+
+/// Adds a profile with an empty todo list of its own. It isn't switched to.
+#[tauri::command]
+fn create_profile(name: String, app: AppHandle) -> Result<profiles::Profile, String> {
+    let app_dir = app_data_dir(&app)?;
+    let profile = logged("create_profile", profiles::create(&app_dir, &name))?;
+    info!("profile {} created", profile.name);
+    Ok(profile)
+}
+
+/// Closes the current database and opens that of profile `name`, which is
+/// also used from the next start on. Its own settings and locks apply.
+#[tauri::command]
+fn switch_profile(
+    name: String,
+    app: AppHandle,
+    state: State<AppState>,
+) -> Result<profiles::Profile, String> {
+    let app_dir = app_data_dir(&app)?;
+    let profile = logged(
+        "switch_profile",
+        db_switch_profile(&state, &app, &app_dir, &name),
+    )?;
+    if state.lock.is_locked() {
+        // The old profile's API would otherwise keep its port.
+        sync_http_api(&app);
+    } else {
+        apply_saved_settings(&app);
+    }
+    if state.app_lock.is_locked(Instant::now()) {
+        emit_app_locked(&app);
+    }
+    Ok(profile)
+}
+
+fn db_switch_profile(
+    state: &AppState,
+    events: &impl ChangeEmitter,
+    app_dir: &Path,
+    name: &str,
+) -> Result<profiles::Profile, String> {
+    let _busy = state.busy.try_acquire("switch profiles")?;
+    let mut database = state.database.lock().unwrap();
+    if !database.is_persistent_default() {
+        return Err("The database was chosen for this run only, so profiles are off".to_string());
+    }
+    let index = profiles::load(app_dir);
+    let profile = index
+        .find(name)
+        .cloned()
+        .ok_or_else(|| format!("There is no profile called {}", name))?;
+    let chosen = profiles::choice_for(app_dir, &profile, &db_location::main_database(app_dir));
+    let path = chosen
+        .path
+        .clone()
+        .ok_or("A profile has no database file")?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    // As at startup: an encrypted database waits for its passphrase behind
+    // an empty stand-in, any other is checked for damage first.
+    let locked = encryption::is_encrypted(&path);
+    let opened = if locked {
+        Connection::open_in_memory()
+    } else {
+        if let Some(notice) = recovery_notice(recovery::recover_at_startup(&path)) {
+            tracing::warn!("{}", notice);
+        }
+        chosen.open()
+    }
+    .map_err(|e| e.to_string())?;
+    init_db(&opened);
+    profiles::set_active(app_dir, &profile.name)?;
+
+    // Everything reaching the database goes through `state.db`, and the
+    // background jobs either take `busy` or look it up on every run, so
+    // none of them keeps using the old connection.
+    load_app_lock(state, &opened, true);
+    mutate(state, events, |conn, history| {
+        *conn = opened;
+        history.clear();
+        state.lock.set(locked);
+        ((), vec![TodosChanged::reloaded()])
+    });
+    *database = chosen;
+    info!("switched to profile {}", profile.name);
+    Ok(profile)
+}
+
+/// Removes profile `name`, which can't be the active one or the default.
+/// With `delete_data` its database and backups are deleted too.
+#[tauri::command]
+fn delete_profile(name: String, delete_data: bool, app: AppHandle) -> Result<(), String> {
+    let app_dir = app_data_dir(&app)?;
+    logged(
+        "delete_profile",
+        profiles::delete(&app_dir, &name, delete_data),
+    )?;
+    info!("profile {} deleted delete_data={}", name, delete_data);
+    Ok(())
+}
+
+/// The app's data folder, which holds the profiles and the logs.
+fn app_data_dir(app: &AppHandle) -> Result<PathBuf, String> {
+    logged(
+        "app_data_dir",
+        app.path().app_data_dir().map_err(|e| e.to_string()),
+    )
+}
+
+/// The backup folder of the profile in use.
+fn backups_dir(app: &AppHandle) -> Result<PathBuf, String> {
+    let app_dir = app_data_dir(app)?;
+    let database = app.state::<AppState>().database.lock().unwrap().clone();
+    Ok(profiles::backups_dir(&app_dir, &database))
+}
+
+/// Takes a rolling backup if the database changed since the last one.
+fn run_rolling_backup(app: &AppHandle) {
+    let state = app.state::<AppState>();
+    // Held throughout, so a profile switch can't swap the database between
+    // choosing the folder and copying into it.
+    let _busy = match state.busy.try_acquire("back up") {
+        Ok(busy) => busy,
+        Err(e) => {
+            tracing::error!("rolling backup failed: {}", e);
+            return;
+        }
+    };
+    if state.lock.is_locked() {
+        debug!("rolling backup skipped, database locked");
+        return;
+    }
+    let result = backups_dir(app).and_then(|dir| {
+        let conn = state.db.lock().unwrap();
+        let keep = backup::backups_to_keep(&conn);
+        backup::rolling_backup(
+            &conn,
+            &dir,
+            keep,
+            Local::now(),
+            state.lock.passphrase().as_deref(),
+        )
+    });
+    match result {
+        Ok(Some(name)) => info!("rolling backup created {}", name),
+        Ok(None) => debug!("rolling backup skipped, database unchanged"),
+        Err(e) => tracing::error!("rolling backup failed: {}", e),
+    }
+}
+
+fn save_window_geometry(app: &AppHandle, label: &str) {
+    let Some(window) = app.get_webview_window(label) else {
+        return;
+    };
+    let state = app.state::<AppState>();
+    let conn = state.db.lock().unwrap();
+    if let Err(e) = window_state::save(&conn, &window) {
+        tracing::warn!("failed to save geometry of {}: {}", label, e);
+    }
+}
+
+/// Sends a notification for each todo that is about to fall due.
+/// Titles stay out of the notifications while the app is locked, as they
+/// do out of the tray.
+fn check_deadlines(
+    state: &AppState,
+    notifier: &impl notifications::Notifier,
+    now: DateTime<Local>,
+) -> Result<usize, String> {
+    let hide_titles = state.app_lock.is_locked(Instant::now());
+    let conn = state.db.lock().unwrap();
+    notifications::check_deadlines(&conn, notifier, now, hide_titles)
+}
+
+fn run_deadline_check(app: &AppHandle) {
+    match check_deadlines(&app.state::<AppState>(), app, Local::now()) {
+        Ok(0) => {}
+        Ok(shown) => info!("deadline notifications shown={}", shown),
+        Err(e) => tracing::error!("deadline check failed: {}", e),
+    }
+}
+
+/// Arguments forwarded from a second launch. The running instance adds the
+/// todo the normal way; without `--no-gui` it also comes to the front.
+fn handle_second_launch(app: &AppHandle, argv: Vec<String>) {
+    let args = match cli::parse_args(argv.into_iter().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            tracing::warn!("rejected arguments from second launch: {}", e);
+            if let Err(e) = notifications::Notifier::notify(app, "Couldn't add todo", &e) {
+                tracing::warn!("failed to show notification: {}", e);
+            }
+            return;
+        }
+    };
+    let added = match args.add {
+        Some(new) => match add_from_second_launch(&app.state::<AppState>(), app, app, new) {
+            Some(todo) => Some(todo),
+            None => return,
+        },
+        None => None,
+    };
+    if args.no_gui || args.autostart {
+        return;
+    }
+    let shown = match added {
+        Some(todo) => tray::reveal_todo(app, todo.id),
+        None => {
+            tray::show_main_window(app);
+            Ok(())
+        }
+    };
+    if let Err(e) = shown {
+        tracing::warn!("failed to show main window: {}", e);
+    }
+}
+
+/// Adds the todo a second launch passed with `--add`. Refused while the
+/// database is locked, as it would go into the stand-in database and be
+/// dropped on unlock.
+fn add_from_second_launch(
+    state: &AppState,
+    events: &impl ChangeEmitter,
+    notifier: &impl notifications::Notifier,
+    new: NewTodo,
+) -> Option<Todo> {
+    if state.lock.is_locked() {
+        tracing::warn!("ignored --add from second launch while the database is locked");
+        if let Err(e) = notifier.notify(
+            "Couldn't add todo",
+            "Unlock the database first, then add it again.",
+        ) {
+            tracing::warn!("failed to show notification: {}", e);
+        }
+        return None;
+    }
+    Some(create_todo(state, events, new))
+}
+
+fn refresh_badge(app: &AppHandle) {
+    let state = app.state::<AppState>();
+    let conn = state.db.lock().unwrap();
+    if let Err(e) = badge::refresh(&conn, app, Local::now()) {
+        tracing::warn!("failed to update badge: {}", e);
+    }
+}
+
+#[tauri::command]
+fn set_badge_mode(mode: badge::BadgeMode, app: AppHandle, state: State<AppState>) {
+    badge::set_badge_mode(&state.db.lock().unwrap(), mode);
+    info!("set_badge_mode mode={:?}", mode);
+    refresh_badge(&app);
+}
+
+#[tauri::command]
+fn set_autostart(
+    enabled: bool,
+    app: AppHandle,
+    state: State<AppState>,
+) -> Result<autostart::AutostartStatus, autostart::AutostartError> {
+    let conn = state.db.lock().unwrap();
+    let result = autostart::set_autostart(&conn, &app, enabled);
+    match &result {
+        Ok(_) => info!("set_autostart enabled={}", enabled),
+        Err(e) => tracing::error!("set_autostart enabled={} failed: {}", enabled, e),
+    }
+    result
+}
+
+#[tauri::command]
+fn get_autostart(
+    app: AppHandle,
+    state: State<AppState>,
+) -> Result<autostart::AutostartStatus, autostart::AutostartError> {
+    let conn = state.db.lock().unwrap();
+    autostart::status(&conn, &app)
+}
+
+#[tauri::command]
+fn set_deadline_notice_minutes(minutes: u32, state: State<AppState>) {
+    let conn = state.db.lock().unwrap();
+    notifications::set_notice_minutes(&conn, minutes);
+    info!("set_deadline_notice_minutes minutes={}", minutes);
+}
+
+#[tauri::command]
+fn set_close_to_tray(enabled: bool, state: State<AppState>) {
+    let conn = state.db.lock().unwrap();
+    tray::set_close_to_tray(&conn, enabled);
+    info!("set_close_to_tray enabled={}", enabled);
+}
+
+#[tauri::command]
+fn set_quick_add_shortcut(
+    accel: String,
+    app: AppHandle,
+    state: State<AppState>,
+) -> Result<(), shortcut::ShortcutError> {
+    let conn = state.db.lock().unwrap();
+    let result = shortcut::set_quick_add_shortcut(&conn, &app, &accel);
+    match &result {
+        Ok(()) => info!("set_quick_add_shortcut accel={}", accel),
+        Err(e) => tracing::error!("set_quick_add_shortcut accel={} failed: {}", accel, e),
+    }
+    result
+}
+
+#[tauri::command]
+fn hide_quick_add(app: AppHandle) -> Result<(), String> {
+    match app.get_webview_window(tray::QUICK_ADD_WINDOW) {
+        Some(window) => window.hide().map_err(|e| e.to_string()),
+        None => Ok(()),
+    }
+}
+
+#[tauri::command]
+fn list_backups(app: AppHandle) -> Result<Vec<backup::BackupInfo>, String> {
+    logged(
+        "list_backups",
+        backups_dir(&app).and_then(|dir| backup::list_backups(&dir)),
+    )
+}
+
+#[tauri::command]
+fn get_settings(state: State<AppState>) -> settings::Settings {
+    let conn = state.db.lock().unwrap();
+    settings::load(&conn)
+}
+
+/// A frontend preference stored with `set_setting`, such as the default
+/// sort; `null` if none was set.
+#[tauri::command]
+fn get_setting(key: String, state: State<AppState>) -> Result<Option<String>, String> {
+    let conn = state.db.lock().unwrap();
+    logged("get_setting", settings::get_ui(&conn, &key))
+}
+
+/// Stores a frontend preference under `key`. Values are kept as the strings
+/// given; the frontend serializes and reads them.
+#[tauri::command]
+fn set_setting(key: String, value: String, state: State<AppState>) -> Result<(), String> {
+    let conn = state.db.lock().unwrap();
+    logged("set_setting", settings::set_ui(&conn, &key, &value))?;
+    debug!("set_setting key={}", key);
+    Ok(())
+}
+
+#[tauri::command]
+fn update_settings(
+    partial: settings::SettingsUpdate,
+    app: AppHandle,
+    state: State<AppState>,
+) -> Result<settings::Settings, String> {
+    let updated = {
+        let conn = state.db.lock().unwrap();
+        logged("update_settings", settings::update(&conn, partial.clone()))?
+    };
+    info!("update_settings {:?}", partial);
+    if partial.badge_mode.is_some() {
+        refresh_badge(&app);
+    }
+    if let Some(minutes) = partial.auto_lock_minutes {
+        state.app_lock.set_auto_lock(minutes);
+    }
+    if partial.http_api_enabled.is_some() || partial.http_api_port.is_some() {
+        sync_http_api(&app);
+    }
+    Ok(updated)
+}
+
+#[tauri::command]
+fn set_backups_to_keep(keep: usize, state: State<AppState>) -> Result<(), String> {
+    let conn = state.db.lock().unwrap();
+    logged(
+        "set_backups_to_keep",
+        backup::set_backups_to_keep(&conn, keep),
+    )
+}
+
+#[tauri::command]
+fn restore_backup(name: String, app: AppHandle, state: State<AppState>) -> Result<(), String> {
+    let _busy = state.busy.try_acquire("restore")?;
+    let dir = backups_dir(&app)?;
+    let passphrase = state.lock.passphrase();
+    mutate(&state, &app, |conn, history| {
+        let restored = logged(
+            "restore_backup",
+            backup::restore_backup(conn, &dir, &name, passphrase.as_deref()),
+        );
+        replaced_settings(&state, conn, restored, history)
+    })
+    .inspect(|()| apply_saved_settings(&app))
+}
+
+#[tauri::command]
+fn get_todo_history(
+    id: u64,
+    limit: Option<u32>,
+    offset: Option<u32>,
+    state: State<AppState>,
+) -> Vec<HistoryEntry> {
+    let conn = state.db.lock().unwrap();
+    let entries = db_get_todo_history(
+        &conn,
+        id,
+        limit.unwrap_or(HISTORY_PAGE_SIZE),
+        offset.unwrap_or(0),
+    );
+    debug!("get_todo_history id={} rows={}", id, entries.len());
+    entries
+}
+
+#[tauri::command]
+fn get_history(id: u64, state: State<AppState>) -> Vec<HistoryEntry> {
+    let conn = state.db.lock().unwrap();
+    let entries = db_get_history(&conn, id);
+    debug!("get_history id={} rows={}", id, entries.len());
+    entries
+}
+
+#[tauri::command]
+fn revert_todo(
+    id: u64,
+    history_entry_id: u64,
+    app: AppHandle,
+    state: State<AppState>,
+) -> Result<Todo, String> {
+    mutate_todos(&state, &app, |conn, history| {
+        let (reverted, change) = undo::record(conn, &[id], |conn| {
+            db_revert_todo(conn, id, history_entry_id)
+        });
+        match logged("revert_todo", reverted) {
+            Ok(todo) => {
+                history.push(change);
+                info!("revert_todo id={} entry={}", id, history_entry_id);
+                (Ok(todo), vec![TodosChanged::updated(vec![id])])
+            }
+            Err(e) => (Err(e), Vec::new()),
+        }
+    })
+}
+
+#[tauri::command]
+fn reset_database(confirm: String, app: AppHandle, state: State<AppState>) -> Result<(), String> {
+    if confirm != RESET_CONFIRMATION {
+        return logged(
+            "reset_database",
+            Err(format!(
+                "Type \"{}\" to confirm the reset",
+                RESET_CONFIRMATION
+            )),
+        );
+    }
+    let _busy = state.busy.try_acquire("reset the database")?;
+    mutate(&state, &app, |conn, history| {
+        let reset = logged("reset_database", db_reset(conn).map_err(|e| e.to_string()));
+        replaced_settings(&state, conn, reset, history)
+    })
+    .inspect(|()| apply_saved_settings(&app))
+}
+
+/// Whether this build can encrypt, whether the database is encrypted, and
+/// whether it still waits for `unlock_database`.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+struct EncryptionStatus {
+    supported: bool,
+    encrypted: bool,
+    locked: bool,
+}
+
+#[tauri::command]
+fn get_encryption_status(state: State<AppState>) -> EncryptionStatus {
+    let database = state.database.lock().unwrap();
+    EncryptionStatus {
+        supported: encryption::SUPPORTED,
+        encrypted: database
+            .path
+            .as_deref()
+            .is_some_and(encryption::is_encrypted),
+        locked: state.lock.is_locked(),
+    }
+}
+
+/// Sets up `state.app_lock` from the settings in `conn`, locked at once if
+/// `locked` and a passphrase is set.
+fn load_app_lock(state: &AppState, conn: &Connection, locked: bool) {
+    state.app_lock.configure(
+        app_lock::stored_hash(conn).is_some(),
+        app_lock::auto_lock_minutes(conn),
+        locked,
+        Instant::now(),
+    );
+}
+
+/// Whether a privacy lock is set, whether the app is locked now, and after
+/// how many idle minutes it locks itself.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+struct AppLockStatus {
+    enabled: bool,
+    locked: bool,
+    auto_lock_minutes: u32,
+}
+
+#[tauri::command]
+fn get_app_lock_status(state: State<AppState>) -> AppLockStatus {
+    let conn = state.db.lock().unwrap();
+    AppLockStatus {
+        enabled: state.app_lock.is_enabled(),
+        locked: state.app_lock.is_locked(Instant::now()),
+        auto_lock_minutes: app_lock::auto_lock_minutes(&conn),
+    }
+}
+
+/// Sets the app lock passphrase, replacing any earlier one; `null` removes
+/// the lock. Once one is set, `current` has to be it. Takes effect the next
+/// time the app locks.
+#[tauri::command]
+fn set_app_lock(
+    current: Option<String>,
+    passphrase: Option<String>,
+    state: State<AppState>,
+) -> Result<(), app_lock::AppLockError> {
+    let conn = state.db.lock().unwrap();
+    app_lock::set_passphrase(&conn, current.as_deref(), passphrase.as_deref())
+        .inspect_err(|e| tracing::warn!("set_app_lock failed: {}", e))?;
+    load_app_lock(&state, &conn, false);
+    info!(
+        "app lock {}",
+        if passphrase.is_some() {
+            "set"
+        } else {
+            "removed"
+        }
+    );
+    Ok(())
+}
+
+#[tauri::command]
+fn unlock(
+    passphrase: String,
+    app: AppHandle,
+    state: State<AppState>,
+) -> Result<(), app_lock::AppLockError> {
+    let stored = app_lock::stored_hash(&state.db.lock().unwrap());
+    state
+        .app_lock
+        .unlock(stored.as_deref(), &passphrase, Instant::now())
+        .inspect_err(|e| tracing::warn!("unlock failed: {}", e))?;
+    info!("app unlocked");
+    if let Err(e) = tray::refresh(&app) {
+        tracing::warn!("failed to refresh tray: {}", e);
+    }
+    Ok(())
+}
+
+/// Locks the app now, and tells every window to hide the list.
+#[tauri::command]
+fn lock(app: AppHandle, state: State<AppState>) -> Result<(), app_lock::AppLockError> {
+    if !state.app_lock.lock() {
+        return Err(app_lock::AppLockError::NotSet);
+    }
+    info!("app locked");
+    emit_app_locked(&app);
+    Ok(())
+}
+
+fn emit_app_locked(app: &AppHandle) {
+    if let Err(e) = app.emit(app_lock::APP_LOCKED_EVENT, ()) {
+        tracing::warn!("failed to emit {}: {}", app_lock::APP_LOCKED_EVENT, e);
+    }
+    if let Err(e) = tray::refresh(app) {
+        tracing::warn!("failed to refresh tray: {}", e);
+    }
+}
+
+/// Opens the encrypted database with `passphrase`, after which the other
+/// commands work. Does nothing if it is already unlocked.
+#[tauri::command]
+fn unlock_database(
+    passphrase: String,
+    app: AppHandle,
+    state: State<AppState>,
+) -> Result<(), encryption::EncryptionError> {
+    if !state.lock.is_locked() {
+        return Ok(());
+    }
+    db_unlock(&state, &app, &passphrase)
+        .inspect_err(|e| tracing::warn!("unlock_database failed: {}", e))?;
+    apply_saved_settings(&app);
+    Ok(())
+}
+
+fn db_unlock(
+    state: &AppState,
+    events: &impl ChangeEmitter,
+    passphrase: &str,
+) -> Result<(), encryption::EncryptionError> {
+    let path = state.database.lock().unwrap().path.clone();
+    let unlocked = encryption::open(
+        path.as_deref()
+            .ok_or(encryption::EncryptionError::InMemory)?,
+        passphrase,
+    )?;
+    init_db(&unlocked);
+    // The database passphrase was just given, so the app lock starts open.
+    load_app_lock(state, &unlocked, false);
+    mutate(state, events, |conn, history| {
+        *conn = unlocked;
+        history.clear();
+        state.lock.unlock(passphrase);
+        info!("database unlocked");
+        ((), vec![TodosChanged::reloaded()])
+    });
+    Ok(())
+}
+
+/// Encrypts the database file with `passphrase`, which is then needed at
+/// every start. Backups made before are left as they are.
+#[tauri::command]
+fn enable_encryption(
+    passphrase: String,
+    state: State<AppState>,
+) -> Result<(), encryption::EncryptionError> {
+    let _busy = state
+        .busy
+        .try_acquire("encrypt the database")
+        .map_err(encryption::EncryptionError::Failed)?;
+    let database = state.database.lock().unwrap();
+    let mut conn = state.db.lock().unwrap();
+    let result = encryption::enable_encryption(&mut conn, database.path.as_deref(), &passphrase);
+    init_db(&conn);
+    match &result {
+        Ok(()) => {
+            state.lock.remember(Some(&passphrase));
+            info!("database encrypted");
+        }
+        Err(e) => tracing::error!("enable_encryption failed: {}", e),
+    }
+    result
+}
+
+#[tauri::command]
+fn change_passphrase(
+    current: String,
+    new: String,
+    state: State<AppState>,
+) -> Result<(), encryption::EncryptionError> {
+    let _busy = state
+        .busy
+        .try_acquire("change the passphrase")
+        .map_err(encryption::EncryptionError::Failed)?;
+    let database = state.database.lock().unwrap();
+    let mut conn = state.db.lock().unwrap();
+    let result = encryption::change_passphrase(&mut conn, database.path.as_deref(), &current, &new);
+    init_db(&conn);
+    match &result {
+        Ok(()) => {
+            state.lock.remember(Some(&new));
+            info!("database passphrase changed");
+        }
+        Err(e) => tracing::warn!("change_passphrase failed: {}", e),
+    }
+    result
+}
+
+#[tauri::command]
+fn get_db_info(state: State<AppState>) -> Result<maintenance::DbInfo, String> {
+    let conn = state.db.lock().unwrap();
+    logged("get_db_info", maintenance::get_db_info(&conn))
+}
+
+#[tauri::command]
+fn compact_database(state: State<AppState>) -> Result<maintenance::CompactReport, String> {
+    let _busy = state.busy.try_acquire("compact the database")?;
+    let conn = state.db.lock().unwrap();
+    let report = logged("compact_database", maintenance::compact_database(&conn))?;
+    info!(
+        "compact_database pages_before={} pages_after={}",
+        report.pages_before, report.pages_after
+    );
+    Ok(report)
+}
+
+#[tauri::command]
+fn maintain_database(state: State<AppState>) -> Result<maintenance::MaintenanceReport, String> {
+    let _busy = logged(
+        "maintain_database",
+        state.busy.try_acquire("run maintenance"),
+    )?;
+    let conn = state.db.lock().unwrap();
+    let report = logged("maintain_database", maintenance::maintain_database(&conn))?;
+    info!(
+        "maintain_database reclaimed_bytes={}",
+        report.reclaimed_bytes
+    );
+    Ok(report)
+}
+
+/// `"ok"`, or the problems SQLite's integrity and foreign key checks found.
+#[tauri::command]
+fn check_integrity(state: State<AppState>) -> Result<String, Vec<String>> {
+    let conn = state.db.lock().unwrap();
+    let result = maintenance::check_integrity(&conn);
+    match &result {
+        Ok(_) => info!("check_integrity ok"),
+        Err(problems) => tracing::error!("check_integrity problems={:?}", problems),
+    }
+    result
+}
+
+/// Applies the settings kept in the database to the app: log level,
+/// autostart, window geometry, the quick add shortcut and the HTTP API. Runs
+/// at startup, once an encrypted database is unlocked, and after a restore
+/// or reset.
+fn apply_saved_settings(app: &AppHandle) {
+    apply_settings_to_os(app);
+    sync_http_api(app);
+}
+
+fn apply_settings_to_os(app: &AppHandle) {
+    let state = app.state::<AppState>();
+    let conn = state.db.lock().unwrap();
+    logging::apply_level(logging::log_level(&conn));
+    match autostart::reconcile(&conn, app) {
+        Ok(status) => debug!("autostart {:?}", status),
+        Err(e) => tracing::warn!("failed to reconcile autostart: {}", e),
+    }
+    if let Some(window) = app.get_webview_window(tray::MAIN_WINDOW) {
+        if let Err(e) = window_state::restore(&conn, &window) {
+            tracing::warn!("failed to restore window geometry: {}", e);
+        }
+    }
+    if let Err(e) = shortcut::register_saved(&conn, app) {
+        tracing::warn!("failed to register quick add shortcut: {}", e);
+    }
+}
+
+/// The bearer token for the local HTTP API, made on first use.
+#[tauri::command]
+fn get_api_token(state: State<AppState>) -> String {
+    http_api::token(&state.db.lock().unwrap())
+}
+
+/// Starts, moves or stops the HTTP API to match the settings of the
+/// database in use. The database lock is not held while an old server
+/// stops, as it may be finishing a request that needs it.
+fn sync_http_api(app: &AppHandle) {
+    let state = app.state::<AppState>();
+    let wanted = {
+        let conn = state.db.lock().unwrap();
+        http_api::enabled(&conn).then(|| http_api::port(&conn))
+    };
+    let mut server = state.http_api.lock().unwrap();
+    if server.as_ref().map(|s| s.addr().port()) == wanted {
+        return;
+    }
+    if server.take().is_some() {
+        info!("http api stopped");
+    }
+    let Some(port) = wanted else {
+        return;
+    };
+    let handle = app.clone();
+    let started = http_api::Server::start(port, move |request| {
+        api_request(&handle.state::<AppState>(), &handle, request)
+    });
+    match started {
+        Ok(started) => {
+            info!("http api listening on {}", started.addr());
+            *server = Some(started);
+        }
+        Err(e) => tracing::error!("failed to start http api on port {}: {}", port, e),
+    }
+}
+
+fn todo_error_response(error: TodoError) -> http_api::Response {
+    let status = match error {
+        TodoError::NotFound(_) => 404,
+        TodoError::Conflict(_) | TodoError::Duplicate(_) => 409,
+        TodoError::Validation(_) => 422,
+    };
+    http_api::Response::json(status, error)
+}
+
+/// `GET /todos` parameters as a `TodoQuery`; `ids` is comma-separated.
+fn api_query(params: &[(String, String)]) -> Result<TodoQuery, String> {
+    let mut fields = serde_json::Map::new();
+    for (key, value) in params {
+        let value = match key.as_str() {
+            "ids" => serde_json::Value::from(
+                value
+                    .split(',')
+                    .map(|id| id.trim().parse::<u64>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|_| format!("Bad ids '{}'", value))?,
+            ),
+            _ => serde_json::Value::from(value.as_str()),
+        };
+        fields.insert(key.clone(), value);
+    }
+    serde_json::from_value(serde_json::Value::Object(fields)).map_err(|e| e.to_string())
+}
+
+/// Answers a request to the local HTTP API with the functions and checks
+/// behind the matching commands: `GET /todos` takes `get_todos`' query as
+/// parameters, `POST /todos` `add_todo`'s arguments, `PATCH /todos/:id`
+/// `update_todo`'s, and `DELETE /todos/:id` deletes.
+fn api_request(
+    state: &AppState,
+    events: &impl ChangeEmitter,
+    request: &http_api::Request,
+) -> http_api::Response {
+    use http_api::Response;
+    // The token is kept in the database, which can't be read until then.
+    if state.lock.is_locked() {
+        return Response::error(423, "The database is locked");
+    }
+    let token = http_api::token(&state.db.lock().unwrap());
+    if !request.is_authorized(&token) {
+        return Response::error(401, "Missing or wrong bearer token");
+    }
+    if state.app_lock.is_locked(Instant::now()) {
+        return Response::error(423, "The app is locked");
+    }
+    let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+    let id = match segments.as_slice() {
+        ["todos"] => None,
+        ["todos", id] => match id.parse::<u64>() {
+            Ok(id) => Some(id),
+            Err(_) => return todo_error_response(TodoError::NotFound(0)),
+        },
+        _ => return Response::error(404, format!("Nothing at {}", request.path)),
+    };
+    match (request.method.as_str(), id) {
+        ("GET", None) => {
+            let query = match api_query(&request.query) {
+                Ok(query) => query,
+                Err(e) => return Response::error(400, e),
+            };
+            if let Err(e) = validate_deadline(query.due_before.clone()) {
+                return todo_error_response(TodoError::Validation(vec![e]));
+            }
+            let conn = state.db.lock().unwrap();
+            Response::json(200, db_query_summaries(&conn, &query, Local::now()))
+        }
+        ("POST", None) => match serde_json::from_slice::<AddTodoArgs>(&request.body) {
+            Ok(args) => match add_todo_with(state, events, args) {
+                Ok(todo) => Response::json(201, todo),
+                Err(e) => todo_error_response(e),
+            },
+            Err(e) => Response::error(400, e.to_string()),
+        },
+        ("PATCH", Some(id)) => {
+            let parsed =
+                serde_json::from_slice::<serde_json::Map<String, serde_json::Value>>(&request.body)
+                    .and_then(|mut fields| {
+                        let expected_version = fields
+                            .remove("expected_version")
+                            .map(serde_json::from_value::<Option<u64>>)
+                            .transpose()?
+                            .flatten();
+                        let changes = serde_json::from_value::<TodoChanges>(fields.into())?;
+                        Ok((expected_version, changes))
+                    });
+            match parsed {
+                Ok((expected_version, changes)) => {
+                    match update_todo_with(state, events, id, expected_version, changes) {
+                        Ok(todo) => Response::json(200, todo),
+                        Err(e) => todo_error_response(e),
+                    }
+                }
+                Err(e) => Response::error(400, e.to_string()),
+            }
+        }
+        ("DELETE", Some(id)) => {
+            if delete_todo_with(state, events, id) {
+                Response::json(204, serde_json::Value::Null)
+            } else {
+                todo_error_response(TodoError::NotFound(id))
+            }
+        }
+        _ => Response::error(405, format!("{} isn't allowed here", request.method)),
+    }
+}
+
+/// What to tell the user after `recovery::recover_at_startup`, if anything.
+fn recovery_notice(outcome: Result<Option<recovery::RecoveryReport>, String>) -> Option<String> {
+    match outcome {
+        Ok(None) => None,
+        Ok(Some(report)) => {
+            info!("database recovered {:?}", report);
+            let mut notice = format!(
+                "The database was damaged; {} todos were recovered.",
+                report.todos_recovered()
+            );
+            if !report.damaged_tables.is_empty() {
+                notice.push_str(" Some could not be read.");
+            }
+            if let Some(damaged) = &report.damaged_copy {
+                notice.push_str(&format!(
+                    " The damaged file was kept at {}.",
+                    damaged.display()
+                ));
+            }
+            Some(notice)
+        }
+        Err(e) => {
+            tracing::error!("database recovery failed: {}", e);
+            Some(format!(
+                "The database was damaged and could not be recovered, so the list starts empty: {}",
+                e
+            ))
+        }
+    }
+}
+
+fn main() {
+    let cli = match cli::parse_args(std::env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(e) => {
+            eprintln!("{}\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+    tauri::Builder::default()
+        // Must come first: a second launch forwards its arguments (and, via
+        // the deep-link feature, its URLs) here and exits before anything
+        // else starts.
+        .plugin(tauri_plugin_single_instance::init(|app, argv, _cwd| {
+            handle_second_launch(app, argv)
+        }))
+        .plugin(tauri_plugin_notification::init())
+        .plugin(
+            tauri_plugin_autostart::Builder::new()
+                .arg(autostart::AUTOSTART_ARG)
+                .build(),
+        )
+        .plugin(tauri_plugin_deep_link::init())
+        .plugin(
+            tauri_plugin_global_shortcut::Builder::new()
+                .with_handler(|app, _shortcut, event| {
+                    if event.state == ShortcutState::Pressed {
+                        if let Err(e) = tray::show_quick_add(app) {
+                            tracing::warn!("failed to open quick add: {}", e);
+                        }
+                    }
+                })
+                .build(),
+        )
+        .setup(move |app| {
+            let app_dir = app.path().app_data_dir()?;
+            if let Err(e) = logging::init(&app_dir.join(logging::LOG_DIR)) {
+                eprintln!("Failed to initialize logging: {}", e);
+            }
+
+            // The database may have been moved; the pointer file says where,
+            // unless this run was pointed somewhere else. Another profile
+            // may be active, with a database of its own.
+            let database = profiles::resolve(
+                &app_dir,
+                db_location::choose_at_startup(&cli.database, &app_dir),
+            );
+            if let Some(dir) = database.path.as_deref().and_then(Path::parent) {
+                let _ = std::fs::create_dir_all(dir);
+            }
+            info!(
+                "opening database {:?} from {:?}",
+                database.path, database.source
+            );
+            // An encrypted file can't be read until the user gives the
+            // passphrase, so it starts locked with an empty stand-in.
+            let locked = database
+                .path
+                .as_deref()
+                .is_some_and(encryption::is_encrypted);
+            // A file damaged by a crash would make every query fail, so it
+            // is checked, and salvaged if need be, before it is opened.
+            let recovery_notice = database
+                .path
+                .as_deref()
+                .filter(|_| !locked)
+                .and_then(|path| recovery_notice(recovery::recover_at_startup(path)));
+            let db_connection = if locked {
+                info!("database is encrypted, waiting for unlock_database");
+                Connection::open_in_memory()
+            } else {
+                database.open()
+            }
+            .expect("Failed to open database");
+            init_db(&db_connection);
+            let persistent = database.is_persistent_default();
+            app.manage(AppState {
+                db: Mutex::new(db_connection),
+                busy: BusyFlag::default(),
+                history: Mutex::default(),
+                window_saves: window_state::PendingSaves::default(),
+                database: Mutex::new(database),
+                lock: encryption::Lock::new(locked),
+                app_lock: app_lock::AppLock::default(),
+                http_api: Mutex::default(),
+            });
+
+            // The main window starts hidden so `--no-gui` never flashes it.
+            if let Some(new) = cli.add.clone() {
+                if locked {
+                    eprintln!("The database is encrypted; unlock it in the app to add todos");
+                    std::process::exit(1);
+                }
+                let todo = create_todo(&app.state::<AppState>(), app.handle(), new);
+                println!("Added todo {}", todo.id);
+                if cli.no_gui {
+                    std::process::exit(0);
+                }
+            }
+            if !locked {
+                let state = app.state::<AppState>();
+                load_app_lock(&state, &state.db.lock().unwrap(), true);
+                apply_saved_settings(app.handle());
+            }
+            if !cli.autostart {
+                tray::show_main_window(app.handle());
+            }
+            let handle = app.handle().clone();
+            thread::spawn(move || loop {
+                thread::sleep(window_state::SAVE_DEBOUNCE / 2);
+                let state = handle.state::<AppState>();
+                for label in state.window_saves.due(Instant::now()) {
+                    save_window_geometry(&handle, &label);
+                }
+            });
+
+            let handle = app.handle().clone();
+            thread::spawn(move || loop {
+                thread::sleep(app_lock::TICK_INTERVAL);
+                if handle.state::<AppState>().app_lock.tick(Instant::now()) {
+                    info!("app locked after inactivity");
+                    emit_app_locked(&handle);
+                }
+            });
+
+            // Rolling backups share one folder, so a test database would
+            // rotate the real backups out.
+            if persistent {
+                let handle = app.handle().clone();
+                thread::spawn(move || loop {
+                    run_rolling_backup(&handle);
+                    thread::sleep(backup::ROLLING_BACKUP_INTERVAL);
+                });
+            }
+
+            tray::create(app.handle())?;
+            if let Some(notice) = recovery_notice {
+                if let Err(e) =
+                    notifications::Notifier::notify(app.handle(), "Database repaired", &notice)
+                {
+                    tracing::warn!("failed to show notification: {}", e);
+                }
+            }
+
+            // Installers register the scheme; this covers dev runs and
+            // unpacked builds. macOS only registers through the bundle.
+            #[cfg(any(windows, target_os = "linux"))]
+            if let Err(e) = app.deep_link().register_all() {
+                tracing::warn!("failed to register {}:// links: {}", deep_link::SCHEME, e);
+            }
+            if let Some(urls) = app.deep_link().get_current()? {
+                deep_link::handle_urls(app.handle(), urls.iter().map(|u| u.to_string()));
+            }
+            let handle = app.handle().clone();
+            app.deep_link().on_open_url(move |event| {
+                deep_link::handle_urls(&handle, event.urls().iter().map(|u| u.to_string()));
+            });
+            let handle = app.handle().clone();
+            app.listen(events::TODOS_CHANGED_EVENT, move |_| {
+                if let Err(e) = tray::refresh(&handle) {
+                    tracing::warn!("failed to refresh tray: {}", e);
+                }
+                refresh_badge(&handle);
+            });
+            refresh_badge(app.handle());
+
+            let handle = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                let mut interval = tokio::time::interval(notifications::DEADLINE_CHECK_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    run_deadline_check(&handle);
+                    // "Due today" and "overdue" change with the clock too.
+                    refresh_badge(&handle);
+                }
+            });
+            Ok(())
+        })
+        .on_window_event(|window, event| match event {
+            WindowEvent::Moved(_) | WindowEvent::Resized(_) => {
+                let state = window.state::<AppState>();
+                state.window_saves.touch(window.label(), Instant::now());
+            }
+            WindowEvent::CloseRequested { api, .. } => {
+                let state = window.state::<AppState>();
+                state.window_saves.cancel(window.label());
+                save_window_geometry(window.app_handle(), window.label());
+                if window.label() != tray::MAIN_WINDOW {
+                    return;
+                }
+                if tray::close_to_tray(&state.db.lock().unwrap()) {
+                    api.prevent_close();
+                    let _ = window.hide();
+                }
+            }
+            _ => {}
+        })
+        .invoke_handler(logging::timed(guarded(tauri::generate_handler![
+            get_todos,
+            get_todos_full,
+            query_todos,
+            get_todo_detail,
+            get_modified_since,
+            get_todos_with_meta,
+            get_next_deadline,
+            get_todos_by_day,
+            get_agenda,
+            get_unscheduled,
+            get_recent,
+            find_duplicates,
+            get_total_estimate,
+            get_due_today,
+            get_pending_reminders,
+            get_todos_by_urgency,
+            get_focus,
+            get_completion_stats,
+            get_streak,
+            #[cfg(debug_assertions)]
+            seed_demo_data,
+            take_snapshot,
+            diff_todo_snapshots,
+            get_completion_history,
+            add_todo,
+            quick_add,
+            parse_deadline_text,
+            update_todo,
+            set_pinned,
+            set_color,
+            set_deadline,
+            snooze_todo,
+            delete_todo,
+            clear_completed,
+            archive_completed,
+            get_archive,
+            unarchive_todo,
+            undo,
+            redo,
+            can_undo,
+            can_redo,
+            export_csv,
+            export_ics,
+            export_markdown,
+            import_csv,
+            import_todoist_csv,
+            import_markdown,
+            backup_database,
+            restore_database,
+            set_database_location,
+            get_runtime_info,
+            list_backups,
+            set_backups_to_keep,
+            get_settings,
+            update_settings,
+            set_deadline_notice_minutes,
+            set_close_to_tray,
+            set_quick_add_shortcut,
+            set_badge_mode,
+            set_autostart,
+            get_autostart,
+            hide_quick_add,
+            restore_backup,
+            reset_database,
+            add_tag,
+            add_tag_to_todos,
+            set_completed_bulk,
+            bulk_update_todos,
+            postpone_todos,
+            clear_deadlines,
+            remove_tag,
+            dedup_tags,
+            rename_tag,
+            get_tags,
+            merge_todos,
+            get_todo_history,
+            get_history,
+            revert_todo,
+            get_db_info,
+            compact_database,
+            maintain_database,
+            check_integrity,
+            get_priority_counts,
+            reschedule_overdue,
+            get_recent_logs,
+            get_log_dir,
+            get_todo_index,
+            get_encryption_status,
+            unlock_database,
+            enable_encryption,
+            change_passphrase,
+            export_bundle,
+            import_bundle,
+            get_app_lock_status,
+            set_app_lock,
+            unlock,
+            lock,
+            get_setting,
+            set_setting,
+            list_profiles,
+            create_profile,
+            switch_profile,
+            delete_profile,
+            get_tag_counts,
+            merge_export,
+            move_incomplete,
+            get_api_token
+        ])))
+        .run(tauri::generate_context!())
+        .expect("error while running tauri application");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_migrations_upgrade_legacy_schema() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(MIGRATIONS[0]).unwrap();
+        conn.execute(
+            "INSERT INTO todos (title, completed, created_at) VALUES ('Old', 0, '2024-01-01')",
+            [],
+        )
+        .unwrap();
+
+        init_db(&conn);
+        init_db(&conn);
+
+        let version: usize = conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+        let todos = db_get_todos(&conn);
+        assert_eq!(todos[0].title, "Old");
+        assert_eq!(todos[0].description, None);
+        assert_eq!(todos[0].priority, 0);
+    }
+
+    #[test]
+    fn test_settings_round_trip() {
+        let conn = setup_test_db();
+        assert_eq!(db_get_setting(&conn, "theme"), None);
+
+        db_set_setting(&conn, "theme", "dark");
+        db_set_setting(&conn, "theme", "light");
+
+        assert_eq!(db_get_setting(&conn, "theme"), Some("light".to_string()));
+    }
+
+    #[test]
+    fn test_add_and_get_todo() {
+        let conn = setup_test_db();
+
+        let todo = db_add_todo(
+            &conn,
+            "Test Todo".to_string(),
+            Some("2023-12-31".to_string()),
+        );
+
+        assert_eq!(todo.title, "Test Todo");
+        assert!(!todo.completed);
+        assert_eq!(todo.deadline, Some("2023-12-31".to_string()));
+
+        let todos = db_get_todos(&conn);
+        assert_eq!(todos.len(), 1);
+        assert_eq!(todos[0].title, "Test Todo");
+    }
+
+    #[test]
+    fn test_update_todo() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, "Update Me".to_string(), None);
+
+        // Update completion
+        let updated = db_update_todo(&conn, todo.id, None, Some(true), None, None);
+        assert!(matches!(updated, UpdateOutcome::Updated(_)));
+
+        let todos = db_get_todos(&conn);
+        assert!(todos[0].completed);
+
+        // Update title
+        db_update_todo(
+            &conn,
+            todo.id,
+            Some("Updated".to_string()),
+            None,
+            None,
+            None,
+        );
+        let todos = db_get_todos(&conn);
+        assert_eq!(todos[0].title, "Updated");
+    }
+
+    #[test]
+    fn test_agenda_from_database() {
+        let conn = setup_test_db();
+        let now = Local.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+        let today = db_add_todo(&conn, "Today".to_string(), Some("2024-05-01".to_string()));
+        let undated = db_add_todo(&conn, "Undated".to_string(), None);
+        let done = db_add_todo(&conn, "Done".to_string(), Some("2024-05-01".to_string()));
+        db_update_todo(&conn, done.id, None, Some(true), None, None);
+
+        let groups = db_get_agenda(&conn, now, 1);
+
+        let ids: Vec<Vec<u64>> = groups
+            .iter()
+            .map(|g| g.todos.iter().map(|t| t.id).collect())
+            .collect();
+        assert_eq!(
+            ids,
+            vec![vec![], vec![today.id], vec![], vec![], vec![undated.id]]
+        );
+    }
+
+    #[test]
+    fn test_get_todos_grouped_by_day() {
+        let conn = setup_test_db();
+        let morning = db_add_todo(
+            &conn,
+            "Morning".to_string(),
+            Some("2024-05-01T08:00".to_string()),
+        );
+        let evening = db_add_todo(
+            &conn,
+            "Evening".to_string(),
+            Some("2024-05-01T21:30".to_string()),
+        );
+        let all_day = db_add_todo(&conn, "All day".to_string(), Some("2024-05-01".to_string()));
+        let next = db_add_todo(
+            &conn,
+            "Next day".to_string(),
+            Some("2024-05-02T09:00".to_string()),
+        );
+        db_add_todo(&conn, "Unscheduled".to_string(), None);
+
+        let days = db_get_todos_grouped_by_day(&conn);
+
+        let ids = |day: &str| -> Vec<u64> { days[day].iter().map(|t| t.id).collect() };
+        assert_eq!(
+            days.keys().collect::<Vec<_>>(),
+            vec!["2024-05-01", "2024-05-02"]
+        );
+        assert_eq!(ids("2024-05-01"), vec![morning.id, evening.id, all_day.id]);
+        assert_eq!(ids("2024-05-02"), vec![next.id]);
+    }
+
+    #[test]
+    fn test_get_todos_without_deadline() {
+        let conn = setup_test_db();
+        let unscheduled = db_add_todo(&conn, "Someday".to_string(), None);
+        db_add_todo(
+            &conn,
+            "Scheduled".to_string(),
+            Some("2024-05-01".to_string()),
+        );
+        let done = db_add_todo(&conn, "Done".to_string(), None);
+        db_update_todo(&conn, done.id, None, Some(true), None, None);
+        let blank = db_add_todo(&conn, "Blank".to_string(), None);
+        conn.execute("UPDATE todos SET deadline = '' WHERE id = ?1", (blank.id,))
+            .unwrap();
+
+        let ids: Vec<u64> = db_get_todos_without_deadline(&conn)
+            .iter()
+            .map(|t| t.id)
+            .collect();
+
+        assert_eq!(ids, vec![unscheduled.id, blank.id]);
+    }
+
+    #[test]
+    fn test_get_recent_returns_newest_first() {
+        let conn = setup_test_db();
+        for (title, created_at) in [
+            ("Second", "2024-05-02T09:00:00+02:00"),
+            ("Fourth", "2024-05-04T09:00:00+02:00"),
+            ("First", "2024-05-01T09:00:00+02:00"),
+            ("Third", "2024-05-03T09:00:00+02:00"),
+        ] {
+            let todo = db_add_todo(&conn, title.to_string(), None);
+            conn.execute(
+                "UPDATE todos SET created_at = ?1 WHERE id = ?2",
+                (created_at, todo.id),
+            )
+            .unwrap();
+        }
+
+        let titles: Vec<String> = db_get_recent(&conn, 3)
+            .into_iter()
+            .map(|t| t.title)
+            .collect();
+
+        assert_eq!(titles, vec!["Fourth", "Third", "Second"]);
+        assert_eq!(db_get_recent(&conn, 10).len(), 4);
+        assert!(db_get_recent(&conn, 0).is_empty());
+    }
+
+    #[test]
+    fn test_update_sets_and_clears_completed_at() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, "Task".to_string(), None);
+        assert_eq!(todo.completed_at, None);
+
+        db_update_todo(&conn, todo.id, None, Some(true), None, None);
+        let done = db_get_todo(&conn, todo.id).unwrap();
+        assert!(done.completed_at.is_some());
+
+        db_update_todo(&conn, todo.id, None, Some(true), None, None);
+        let again = db_get_todo(&conn, todo.id).unwrap();
+        assert_eq!(again.completed_at, done.completed_at);
+
+        db_update_todo(&conn, todo.id, None, Some(false), None, None);
+        assert_eq!(db_get_todo(&conn, todo.id).unwrap().completed_at, None);
+    }
+
+    #[test]
+    fn test_completion_history_counts_per_day() {
+        let conn = setup_test_db();
+        let complete_on = |title: &str, completed_at: &str| {
+            let todo = db_add_todo(&conn, title.to_string(), None);
+            db_update_todo(&conn, todo.id, None, Some(true), None, None);
+            conn.execute(
+                "UPDATE todos SET completed_at = ?1 WHERE id = ?2",
+                (completed_at, todo.id),
+            )
+            .unwrap();
+        };
+        let utc = |day: u32, hour: u32| {
+            Local
+                .with_ymd_and_hms(2024, 5, day, hour, 0, 0)
+                .unwrap()
+                .with_timezone(&Utc)
+                .to_rfc3339()
+        };
+        complete_on("A", &utc(1, 0));
+        complete_on("B", &utc(1, 23));
+        complete_on("C", &utc(3, 8));
+        complete_on("Too late", &utc(10, 8));
+        db_add_todo(&conn, "Pending".to_string(), None);
+
+        let history = db_completion_history(&conn, "2024-05-01", "2024-05-07");
+
+        assert_eq!(
+            history,
+            vec![("2024-05-01".to_string(), 2), ("2024-05-03".to_string(), 1)]
+        );
+    }
+
+    #[test]
+    fn test_set_deadline() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, "Schedule Me".to_string(), None);
+
+        assert!(db_set_deadline(
+            &conn,
+            todo.id,
+            Some("2024-05-01".to_string())
+        ));
+        assert_eq!(
+            db_get_todos(&conn)[0].deadline,
+            Some("2024-05-01".to_string())
+        );
+
+        assert!(db_set_deadline(
+            &conn,
+            todo.id,
+            Some("2024-06-01T10:00".to_string())
+        ));
+        assert_eq!(
+            db_get_todos(&conn)[0].deadline,
+            Some("2024-06-01T10:00".to_string())
+        );
+
+        assert!(db_set_deadline(&conn, todo.id, Some(String::new())));
+        assert_eq!(db_get_todos(&conn)[0].deadline, None);
+
+        db_set_deadline(&conn, todo.id, Some("2024-05-01".to_string()));
+        assert!(db_set_deadline(&conn, todo.id, None));
+        assert_eq!(db_get_todos(&conn)[0].deadline, None);
+
+        assert!(!db_set_deadline(&conn, todo.id + 1, None));
+    }
+
+    #[test]
+    fn test_snooze_future_deadline() {
+        let conn = setup_test_db();
+        let now = parse_deadline("2024-05-01T12:00").unwrap();
+        let todo = db_add_todo(
+            &conn,
+            "Later".to_string(),
+            Some("2024-05-03T09:00".to_string()),
+        );
+
+        let deadline = db_snooze_todo(&conn, todo.id, snooze::SnoozePreset::Hours(24), now)
+            .unwrap()
+            .and_then(|t| t.deadline);
+
+        assert_eq!(deadline, deadline::normalize("2024-05-04T09:00", &Local));
+        assert_eq!(db_get_todo(&conn, todo.id).unwrap().deadline, deadline);
+    }
+
+    #[test]
+    fn test_snooze_overdue_deadline_anchors_to_now() {
+        let conn = setup_test_db();
+        let now = parse_deadline("2024-05-01T12:00").unwrap();
+        let todo = db_add_todo(
+            &conn,
+            "Late".to_string(),
+            Some("2024-04-20T09:00".to_string()),
+        );
+
+        let deadline = db_snooze_todo(&conn, todo.id, snooze::SnoozePreset::Hours(2), now)
+            .unwrap()
+            .and_then(|t| t.deadline);
+
+        assert_eq!(deadline, deadline::normalize("2024-05-01T14:00", &Local));
+    }
+
+    #[test]
+    fn test_snooze_without_deadline_anchors_to_now() {
+        let conn = setup_test_db();
+        let now = parse_deadline("2024-05-01T12:00").unwrap();
+        let todo = db_add_todo(&conn, "Someday".to_string(), None);
+
+        assert_eq!(
+            db_snooze_todo(&conn, todo.id, snooze::SnoozePreset::Hours(3), now)
+                .unwrap()
+                .and_then(|t| t.deadline),
+            deadline::normalize("2024-05-01T15:00", &Local)
+        );
+        assert_eq!(
+            db_snooze_todo(&conn, todo.id + 1, snooze::SnoozePreset::Hours(3), now),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn test_snooze_past_the_calendar_is_an_error() {
+        let conn = setup_test_db();
+        let now = parse_deadline("2024-05-01T12:00").unwrap();
+        let todo = db_add_todo(&conn, "Forever".to_string(), None);
+
+        assert!(
+            db_snooze_todo(&conn, todo.id, snooze::SnoozePreset::Hours(i64::MAX), now).is_err()
+        );
+        assert_eq!(db_get_todo(&conn, todo.id).unwrap().deadline, None);
+    }
+
+    #[test]
+    fn test_snooze_preset_renotifies() {
+        let conn = setup_test_db();
+        // A Friday evening, with the deadline ten minutes off.
+        let now = parse_deadline("2024-06-07T19:30").unwrap();
+        let todo = db_add_todo(
+            &conn,
+            "Call back".to_string(),
+            Some("2024-06-07T19:40".to_string()),
+        );
+        assert_eq!(
+            notifications::check_deadlines(
+                &conn,
+                &notifications::test_notifier::RecordingNotifier::default(),
+                now,
+                false
+            ),
+            Ok(1)
+        );
+
+        let snoozed = db_snooze_todo(&conn, todo.id, snooze::SnoozePreset::TomorrowMorning, now)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            snoozed.deadline,
+            deadline::normalize("2024-06-08T09:00", &Local)
+        );
+        let notified: u32 = conn
+            .query_row("SELECT COUNT(*) FROM notified_deadlines", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(notified, 0);
+        let morning = parse_deadline("2024-06-08T08:50").unwrap();
+        assert_eq!(
+            notifications::check_deadlines(&conn, &Silent, morning, false),
+            Ok(1)
+        );
+    }
+
+    #[test]
+    fn test_delete_todo() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, "Delete Me".to_string(), None);
+
+        let todos_before = db_get_todos(&conn);
+        assert_eq!(todos_before.len(), 1);
+
+        let deleted = db_delete_todo(&conn, todo.id);
+        assert!(deleted);
+
+        let todos_after = db_get_todos(&conn);
+        assert_eq!(todos_after.len(), 0);
+    }
+
+    #[test]
+    fn test_get_todos_filtered() {
+        let conn = setup_test_db();
+        let now = Local::now();
+        let overdue = db_add_todo(
+            &conn,
+            "Overdue".to_string(),
+            Some("2000-01-01T09:00".to_string()),
+        );
+        let future = db_add_todo(&conn, "Future".to_string(), Some("2999-01-01".to_string()));
+        let done = db_add_todo(&conn, "Done".to_string(), Some("2000-01-01".to_string()));
+        db_update_todo(&conn, done.id, None, Some(true), None, None);
+
+        let ids = |filter| -> Vec<u64> {
+            db_get_todos_filtered(&conn, filter, now)
+                .iter()
+                .map(|t| t.id)
+                .collect()
+        };
+
+        assert_eq!(ids(Filter::All), vec![overdue.id, future.id, done.id]);
+        assert_eq!(ids(Filter::Active), vec![overdue.id, future.id]);
+        assert_eq!(ids(Filter::Completed), vec![done.id]);
+        assert_eq!(ids(Filter::Overdue), vec![overdue.id]);
+    }
+
+    #[test]
+    fn test_parse_deadline_formats() {
+        assert!(parse_deadline("2024-05-01T10:30").is_some());
+        assert!(parse_deadline("2024-05-01T10:30:15").is_some());
+        assert!(parse_deadline("2024-05-01T10:30:00+02:00").is_some());
+        assert!(parse_deadline("not a date").is_none());
+
+        let date_only = parse_deadline("2024-05-01").unwrap();
+        assert_eq!(date_only.naive_local().to_string(), "2024-05-01 23:59:59");
+    }
+
+    #[test]
+    fn test_get_next_deadline_picks_earliest_future() {
+        let conn = setup_test_db();
+        let now = parse_deadline("2024-05-01T12:00").unwrap();
+        db_add_todo(
+            &conn,
+            "Past".to_string(),
+            Some("2024-04-30T12:00".to_string()),
+        );
+        db_add_todo(
+            &conn,
+            "Later".to_string(),
+            Some("2024-05-10T09:00".to_string()),
+        );
+        let soon = db_add_todo(
+            &conn,
+            "Soon".to_string(),
+            Some("2024-05-02T09:00".to_string()),
+        );
+        db_add_todo(&conn, "Broken".to_string(), Some("someday".to_string()));
+        let done = db_add_todo(
+            &conn,
+            "Done".to_string(),
+            Some("2024-05-01T13:00".to_string()),
+        );
+        db_update_todo(&conn, done.id, None, Some(true), None, None);
+
+        let next = db_get_next_deadline(&conn, now).unwrap();
+        assert_eq!(next.id, soon.id);
+    }
+
+    #[test]
+    fn test_get_next_deadline_none_when_only_past() {
+        let conn = setup_test_db();
+        let now = parse_deadline("2024-05-01T12:00").unwrap();
+        db_add_todo(
+            &conn,
+            "Past".to_string(),
+            Some("2024-04-30T12:00".to_string()),
+        );
+
+        assert_eq!(db_get_next_deadline(&conn, now), None);
+    }
+
+    #[test]
+    fn test_get_next_deadline_none_without_deadlines() {
+        let conn = setup_test_db();
+        db_add_todo(&conn, "Whenever".to_string(), None);
+
+        assert_eq!(db_get_next_deadline(&conn, Local::now()), None);
+    }
+
+    #[test]
+    fn test_get_todos_with_meta_flags_large_lists() {
+        let conn = setup_test_db();
+        db_add_todo(&conn, "First".to_string(), None);
+
+        let meta = db_get_todos_with_meta(&conn, Filter::All, Local::now());
+        assert_eq!(meta.total, 1);
+        assert!(!meta.large);
+
+        for i in 0..LARGE_LIST_THRESHOLD {
+            db_add_todo(&conn, format!("Todo {}", i), None);
+        }
+
+        let meta = db_get_todos_with_meta(&conn, Filter::All, Local::now());
+        assert_eq!(db_count_todos(&conn), LARGE_LIST_THRESHOLD + 1);
+        assert_eq!(meta.total, LARGE_LIST_THRESHOLD + 1);
+        assert_eq!(meta.todos.len() as u64, LARGE_LIST_THRESHOLD + 1);
+        assert!(meta.large);
+    }
+
+    fn busy_error() -> rusqlite::Error {
+        rusqlite::Error::SqliteFailure(rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_BUSY), None)
+    }
+
+    #[test]
+    fn test_with_retry_recovers_from_busy() {
+        let mut calls = 0;
+
+        let result = with_retry(
+            || {
+                calls += 1;
+                if calls == 1 {
+                    Err(busy_error())
+                } else {
+                    Ok(42)
+                }
+            },
+            3,
+        );
+
+        assert_eq!(result.unwrap(), 42);
+        assert_eq!(calls, 2);
+    }
+
+    #[test]
+    fn test_with_retry_gives_up_after_attempts() {
+        let mut calls = 0;
+
+        let result: rusqlite::Result<()> = with_retry(
+            || {
+                calls += 1;
+                Err(busy_error())
+            },
+            3,
+        );
+
+        assert!(result.is_err_and(|e| is_busy_error(&e)));
+        assert_eq!(calls, 3);
+    }
+
+    #[test]
+    fn test_tags_add_remove_and_list() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, "Tag Me".to_string(), None);
+
+        assert!(db_add_tag(&conn, todo.id, "work"));
+        assert!(!db_add_tag(&conn, todo.id, " work "));
+        assert!(!db_add_tag(&conn, todo.id, "  "));
+        assert!(db_add_tag(&conn, todo.id, "home"));
+        assert_eq!(db_get_tags(&conn, todo.id), vec!["home", "work"]);
+
+        assert!(db_remove_tag(&conn, todo.id, "home"));
+        assert!(!db_remove_tag(&conn, todo.id, "home"));
+        assert_eq!(db_get_tags(&conn, todo.id), vec!["work"]);
+    }
+
+    #[test]
+    fn test_count_per_tag_includes_empty_tags() {
+        let conn = setup_test_db();
+        let groceries = db_add_todo(&conn, "Buy milk".to_string(), None);
+        let eggs = db_add_todo(&conn, "Buy eggs".to_string(), None);
+        let bread = db_add_todo(&conn, "Buy bread".to_string(), None);
+        let taxes = db_add_todo(&conn, "File taxes".to_string(), None);
+        for todo in [&groceries, &eggs, &bread] {
+            db_add_tag(&conn, todo.id, "groceries");
+        }
+        db_add_tag(&conn, taxes.id, "admin");
+        db_remove_tag(&conn, taxes.id, "admin");
+        db_set_completed_bulk(&conn, &[bread.id], true);
+
+        let counts = db_count_per_tag(&conn);
+        let admin = db_get_or_create_tag(&conn, "admin");
+        let groceries_id = db_get_or_create_tag(&conn, "groceries");
+        assert_eq!(
+            counts,
+            [
+                (admin, "admin".to_string(), 0),
+                (groceries_id, "groceries".to_string(), 2)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_move_incomplete_leaves_completed_todos() {
+        let conn = setup_test_db();
+        let todos: Vec<Todo> = ["Draft spec", "Review spec", "Ship v1", "Write retro"]
+            .iter()
+            .map(|title| db_add_todo(&conn, title.to_string(), None))
+            .collect();
+        for todo in &todos[..3] {
+            db_add_tag(&conn, todo.id, "project");
+        }
+        db_add_tag(&conn, todos[1].id, "backlog");
+        db_add_tag(&conn, todos[3].id, "backlog");
+        db_set_completed_bulk(&conn, &[todos[2].id], true);
+        let project = db_get_or_create_tag(&conn, "project");
+        let backlog = db_get_or_create_tag(&conn, "backlog");
+
+        assert_eq!(db_move_incomplete(&conn, project, backlog), Ok(2));
+        let counts: Vec<(String, u64)> = db_count_per_tag(&conn)
+            .into_iter()
+            .map(|(_, name, count)| (name, count))
+            .collect();
+        assert_eq!(
+            counts,
+            [("backlog".to_string(), 3), ("project".to_string(), 0)]
+        );
+        assert_eq!(db_todo_ids_with_tag(&conn, "project"), [todos[2].id]);
+        assert_eq!(db_get_tags(&conn, todos[1].id), ["backlog"]);
+
+        assert_eq!(db_move_incomplete(&conn, project, backlog), Ok(0));
+        assert!(db_move_incomplete(&conn, project, 999).is_err());
+        assert!(db_move_incomplete(&conn, 999, backlog).is_err());
+    }
+
+    #[test]
+    fn test_undo_move_incomplete_restores_tags() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, "Draft spec".to_string(), None);
+        db_add_tag(&conn, todo.id, "project");
+        let project = db_get_or_create_tag(&conn, "project");
+        let backlog = db_get_or_create_tag(&conn, "backlog");
+        let mut history = undo::UndoHistory::default();
+
+        let ids = db_incomplete_ids_with_tag(&conn, project);
+        let (moved, change) = undo::record(&conn, &ids, |conn| {
+            db_move_incomplete(conn, project, backlog)
+        });
+        history.push(change);
+        assert_eq!(moved, Ok(1));
+        assert_eq!(db_get_tags(&conn, todo.id), ["backlog"]);
+
+        assert!(history.undo(&conn).unwrap());
+
+        assert_eq!(db_get_tags(&conn, todo.id), ["project"]);
+    }
+
+    #[test]
+    fn test_add_tag_to_todos_skips_already_tagged() {
+        let conn = setup_test_db();
+        let a = db_add_todo(&conn, "A".to_string(), None);
+        let b = db_add_todo(&conn, "B".to_string(), None);
+        let c = db_add_todo(&conn, "C".to_string(), None);
+        db_add_tag(&conn, b.id, "urgent");
+
+        let added = db_add_tag_to_todos(&conn, &[a.id, b.id, c.id], "urgent");
+
+        assert_eq!(added, 2);
+        for todo in [&a, &b, &c] {
+            assert_eq!(db_get_tags(&conn, todo.id), vec!["urgent"]);
+        }
+        assert_eq!(db_add_tag_to_todos(&conn, &[a.id, 999], "urgent"), 0);
+    }
+
+    #[test]
+    fn test_rename_tag() {
+        let conn = setup_test_db();
+        let a = db_add_todo(&conn, "A".to_string(), None);
+        let b = db_add_todo(&conn, "B".to_string(), None);
+        db_add_tag(&conn, a.id, "wrok");
+        db_add_tag(&conn, b.id, "wrok");
+
+        assert_eq!(db_rename_tag(&conn, "wrok", "work"), 2);
+
+        assert_eq!(db_get_tags(&conn, a.id), vec!["work"]);
+        assert_eq!(db_get_tags(&conn, b.id), vec!["work"]);
+        assert_eq!(db_rename_tag(&conn, "missing", "work"), 0);
+    }
+
+    #[test]
+    fn test_rename_tag_merges_into_existing() {
+        let conn = setup_test_db();
+        let both = db_add_todo(&conn, "Both".to_string(), None);
+        let old_only = db_add_todo(&conn, "Old only".to_string(), None);
+        db_add_tag(&conn, both.id, "wrok");
+        db_add_tag(&conn, both.id, "work");
+        db_add_tag(&conn, old_only.id, "wrok");
+
+        assert_eq!(db_rename_tag(&conn, "wrok", "work"), 2);
+
+        assert_eq!(db_get_tags(&conn, both.id), vec!["work"]);
+        assert_eq!(db_get_tags(&conn, old_only.id), vec!["work"]);
+        let tags: u64 = conn
+            .query_row("SELECT COUNT(*) FROM tags", [], |row| row.get(0))
+            .unwrap();
+        let links: u64 = conn
+            .query_row("SELECT COUNT(*) FROM todo_tags", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!((tags, links), (1, 2));
+    }
+
+    #[test]
+    fn test_merge_todos_unions_tags_and_deletes_source() {
+        let conn = setup_test_db();
+        let keep = db_add_todo(
+            &conn,
+            "Buy milk".to_string(),
+            Some("2024-05-10".to_string()),
+        );
+        let merge = db_add_todo(
+            &conn,
+            "buy milk".to_string(),
+            Some("2024-05-03T09:00".to_string()),
+        );
+        conn.execute(
+            "UPDATE todos SET created_at = '2024-01-01T00:00:00+00:00' WHERE id = ?1",
+            (merge.id,),
+        )
+        .unwrap();
+        db_add_tag(&conn, keep.id, "shopping");
+        db_add_tag(&conn, merge.id, "shopping");
+        db_add_tag(&conn, merge.id, "errands");
+
+        let merged = db_merge_todos(&conn, keep.id, merge.id).unwrap();
+
+        assert_eq!(merged.id, keep.id);
+        assert_eq!(merged.title, "Buy milk");
+        assert_eq!(
+            Some(merged.created_at),
+            to_local("2024-01-01T00:00:00+00:00")
+        );
+        assert_eq!(merged.deadline, Some("2024-05-03T09:00".to_string()));
+        assert_eq!(db_get_tags(&conn, keep.id), vec!["errands", "shopping"]);
+        assert_eq!(db_get_todo(&conn, merge.id), None);
+        assert!(db_get_tags(&conn, merge.id).is_empty());
+    }
+
+    #[test]
+    fn test_undo_merge_todos_restores_both_rows() {
+        let conn = setup_test_db();
+        let keep = db_add_todo(&conn, "Buy milk".to_string(), None);
+        let merge = db_add_todo(
+            &conn,
+            "buy milk".to_string(),
+            Some("2024-05-03".to_string()),
+        );
+        db_add_tag(&conn, keep.id, "shopping");
+        db_add_tag(&conn, merge.id, "errands");
+        let mut history = undo::UndoHistory::default();
+
+        let (merged, change) = undo::record(&conn, &[keep.id, merge.id], |conn| {
+            db_merge_todos(conn, keep.id, merge.id)
+        });
+        history.push(change);
+        assert!(merged.is_some());
+        assert_eq!(db_get_todo(&conn, merge.id), None);
+
+        assert!(history.undo(&conn).unwrap());
+
+        let restored = db_get_todo(&conn, keep.id).unwrap();
+        assert_eq!(restored.deadline, None);
+        assert_eq!(restored.created_at, keep.created_at);
+        assert_eq!(db_get_tags(&conn, keep.id), vec!["shopping"]);
+        let restored = db_get_todo(&conn, merge.id).unwrap();
+        assert_eq!(restored.title, "buy milk");
+        assert_eq!(restored.deadline, merge.deadline);
+        assert_eq!(db_get_tags(&conn, merge.id), vec!["errands"]);
+    }
+
+    #[test]
+    fn test_merge_todos_rejects_self_and_missing() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, "Only".to_string(), None);
+
+        assert_eq!(db_merge_todos(&conn, todo.id, todo.id), None);
+        assert_eq!(db_merge_todos(&conn, todo.id, todo.id + 1), None);
+        assert!(db_get_todo(&conn, todo.id).is_some());
+    }
+
+    #[test]
+    fn test_busy_flag_is_exclusive_until_released() {
+        let busy = BusyFlag::default();
+
+        let guard = busy.try_acquire("import").unwrap();
+        let err = busy.try_acquire("run maintenance").err().unwrap();
+        assert!(err.contains("run maintenance"));
+
+        drop(guard);
+        assert!(busy.try_acquire("run maintenance").is_ok());
+    }
+
+    #[test]
+    fn test_query_todos_combines_ids_filter_and_tag() {
+        let conn = setup_test_db();
+        let work = db_add_todo(&conn, "Work".to_string(), None);
+        let done = db_add_todo(&conn, "Done work".to_string(), None);
+        let home = db_add_todo(&conn, "Home".to_string(), None);
+        db_add_tag(&conn, work.id, "work");
+        db_add_tag(&conn, done.id, "work");
+        db_update_todo(&conn, done.id, None, Some(true), None, None);
+        let ids = |query: TodoQuery| -> Vec<u64> {
+            db_query_todos(&conn, &query, Local::now())
+                .iter()
+                .map(|t| t.id)
+                .collect()
+        };
+
+        assert_eq!(ids(TodoQuery::default()), vec![work.id, done.id, home.id]);
+        assert_eq!(
+            ids(TodoQuery {
+                tag: Some("work".to_string()),
+                filter: Filter::Active,
+                ..TodoQuery::default()
+            }),
+            vec![work.id]
+        );
+        assert_eq!(
+            ids(TodoQuery {
+                ids: Some(vec![done.id, home.id]),
+                tag: Some("work".to_string()),
+                ..TodoQuery::default()
+            }),
+            vec![done.id]
+        );
+        assert!(ids(TodoQuery {
+            ids: Some(Vec::new()),
+            ..TodoQuery::default()
+        })
+        .is_empty());
+    }
+
+    /// Number of statements currently prepared on `conn`, cached or not.
+    fn prepared_statement_count(conn: &Connection) -> usize {
+        let mut count = 0;
+        // SAFETY: only walks the statement list of a connection we borrow and
+        // never dereferences the statements.
+        unsafe {
+            let db = conn.handle();
+            let mut stmt = rusqlite::ffi::sqlite3_next_stmt(db, std::ptr::null_mut());
+            while !stmt.is_null() {
+                count += 1;
+                stmt = rusqlite::ffi::sqlite3_next_stmt(db, stmt);
+            }
+        }
+        count
+    }
+
+    #[test]
+    fn test_reads_reuse_cached_statements() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, "Task".to_string(), None);
+        conn.flush_prepared_statement_cache();
+        assert_eq!(prepared_statement_count(&conn), 0);
+
+        db_get_todos(&conn);
+        db_get_todo(&conn, todo.id);
+        let cached = prepared_statement_count(&conn);
+        assert_eq!(cached, 2);
+
+        for _ in 0..100 {
+            db_get_todos(&conn);
+            db_get_todo(&conn, todo.id);
+        }
+        assert_eq!(prepared_statement_count(&conn), cached);
+    }
+
+    fn history_fields(conn: &Connection, id: u64) -> Vec<(String, Option<String>, Option<String>)> {
+        db_get_todo_history(conn, id, HISTORY_PAGE_SIZE, 0)
+            .into_iter()
+            .map(|e| (e.field, e.old_value, e.new_value))
+            .collect()
+    }
+
+    #[test]
+    fn test_update_records_one_history_row_per_changed_field() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, "Old".to_string(), None);
+
+        db_update_todo(
+            &conn,
+            todo.id,
+            Some("New".to_string()),
+            Some(true),
+            Some("2024-05-01".to_string()),
+            None,
+        );
+
+        let some = |s: &str| Some(s.to_string());
+        assert_eq!(
+            history_fields(&conn, todo.id),
+            vec![
+                ("deadline".to_string(), None, some("2024-05-01")),
+                ("completed".to_string(), some("false"), some("true")),
+                ("title".to_string(), some("Old"), some("New")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_noop_update_records_no_history() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, "Same".to_string(), Some("2024-05-01".to_string()));
+
+        db_update_todo(
+            &conn,
+            todo.id,
+            Some("Same".to_string()),
+            Some(false),
+            Some("2024-05-01".to_string()),
+            None,
+        );
+        db_set_deadline(&conn, todo.id, Some("2024-05-01".to_string()));
+
+        assert!(history_fields(&conn, todo.id).is_empty());
+    }
+
+    #[test]
+    fn test_history_is_newest_first_and_paginated() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, "0".to_string(), None);
+        for i in 1..=5 {
+            db_update_todo(&conn, todo.id, Some(i.to_string()), None, None, None);
+        }
+        let new_values = |limit, offset| -> Vec<String> {
+            db_get_todo_history(&conn, todo.id, limit, offset)
+                .into_iter()
+                .map(|e| e.new_value.unwrap())
+                .collect()
+        };
+
+        assert_eq!(new_values(2, 0), vec!["5", "4"]);
+        assert_eq!(new_values(2, 2), vec!["3", "2"]);
+        assert_eq!(new_values(2, 4), vec!["1"]);
+    }
+
+    #[test]
+    fn test_get_history_lists_title_changes_in_order() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, "Draft".to_string(), None);
+        db_update_todo(&conn, todo.id, Some("Second".to_string()), None, None, None);
+        db_update_todo(&conn, todo.id, Some("Final".to_string()), None, None, None);
+
+        let changes: Vec<(String, Option<String>, Option<String>)> = db_get_history(&conn, todo.id)
+            .into_iter()
+            .map(|e| (e.field, e.old_value, e.new_value))
+            .collect();
+
+        assert_eq!(
+            changes,
+            vec![
+                (
+                    "title".to_string(),
+                    Some("Draft".to_string()),
+                    Some("Second".to_string())
+                ),
+                (
+                    "title".to_string(),
+                    Some("Second".to_string()),
+                    Some("Final".to_string())
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_deleting_todo_drops_its_history() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, "Gone".to_string(), None);
+        let other = db_add_todo(&conn, "Kept".to_string(), None);
+        db_set_deadline(&conn, todo.id, Some("2024-05-01".to_string()));
+        db_set_deadline(&conn, other.id, Some("2024-05-01".to_string()));
+
+        db_delete_todo(&conn, todo.id);
+
+        assert!(history_fields(&conn, todo.id).is_empty());
+        assert_eq!(history_fields(&conn, other.id).len(), 1);
+    }
+
+    #[test]
+    fn test_revert_title_after_later_edits() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, "First".to_string(), None);
+        db_update_todo(&conn, todo.id, Some("Second".to_string()), None, None, None);
+        db_update_todo(&conn, todo.id, Some("Third".to_string()), None, None, None);
+        db_update_todo(&conn, todo.id, Some("Fourth".to_string()), None, None, None);
+        let history = db_get_todo_history(&conn, todo.id, HISTORY_PAGE_SIZE, 0);
+        let first_edit = history.last().unwrap();
+
+        let reverted = db_revert_todo(&conn, todo.id, first_edit.id).unwrap();
+
+        assert_eq!(reverted.title, "First");
+        let latest = &db_get_todo_history(&conn, todo.id, 1, 0)[0];
+        assert_eq!(latest.old_value.as_deref(), Some("Fourth"));
+        assert_eq!(latest.new_value.as_deref(), Some("First"));
+    }
+
+    #[test]
+    fn test_revert_deadline_to_none() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, "Task".to_string(), None);
+        db_set_deadline(&conn, todo.id, Some("2024-05-01".to_string()));
+        let entry = db_get_todo_history(&conn, todo.id, 1, 0).remove(0);
+
+        let reverted = db_revert_todo(&conn, todo.id, entry.id).unwrap();
+
+        assert_eq!(reverted.deadline, None);
+    }
+
+    #[test]
+    fn test_revert_rejects_mismatched_ids() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, "Task".to_string(), None);
+        let other = db_add_todo(&conn, "Other".to_string(), None);
+        db_update_todo(
+            &conn,
+            todo.id,
+            Some("Renamed".to_string()),
+            None,
+            None,
+            None,
+        );
+        let entry = db_get_todo_history(&conn, todo.id, 1, 0).remove(0);
+
+        assert!(db_revert_todo(&conn, other.id, entry.id).is_err());
+        assert!(db_revert_todo(&conn, todo.id, entry.id + 100).is_err());
+        assert_eq!(db_get_todo(&conn, other.id).unwrap().title, "Other");
+    }
+
+    #[test]
+    fn test_reset_empties_and_keeps_database_usable() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, "Task".to_string(), None);
+        db_add_tag(&conn, todo.id, "work");
+        db_update_todo(
+            &conn,
+            todo.id,
+            Some("Renamed".to_string()),
+            None,
+            None,
+            None,
+        );
+        db_set_setting(&conn, "theme", "dark");
+
+        db_reset(&conn).unwrap();
+
+        assert!(db_get_todos(&conn).is_empty());
+        assert_eq!(db_get_setting(&conn, "theme"), None);
+        let version: usize = conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+
+        let fresh = db_add_todo(&conn, "Fresh".to_string(), None);
+        assert_eq!(fresh.id, 1);
+        assert!(db_get_tags(&conn, fresh.id).is_empty());
+        assert_eq!(db_get_todos(&conn), vec![fresh]);
+    }
+
+    #[test]
+    fn test_reset_removes_the_app_lock() {
+        let state = test_state();
+        let events = events::test_emitter::RecordingEmitter::default();
+        {
+            let conn = state.db.lock().unwrap();
+            app_lock::set_passphrase(&conn, None, Some("hunter2")).unwrap();
+            load_app_lock(&state, &conn, false);
+        }
+        assert!(state.app_lock.is_enabled());
+
+        let reset = mutate(&state, &events, |conn, history| {
+            let reset = db_reset(conn).map_err(|e| e.to_string());
+            replaced_settings(&state, conn, reset, history)
+        });
+
+        assert_eq!(reset, Ok(()));
+        assert!(!state.app_lock.is_enabled());
+        // Nothing to lock with, so it can't get stuck locked.
+        assert!(!state.app_lock.lock());
+        assert!(!state.app_lock.is_locked(Instant::now()));
+    }
+
+    fn test_state() -> AppState {
+        AppState {
+            db: Mutex::new(setup_test_db()),
+            busy: BusyFlag::default(),
+            history: Mutex::default(),
+            window_saves: window_state::PendingSaves::default(),
+            database: Mutex::new(db_location::DbChoice {
+                source: db_location::DbSource::Flag,
+                ephemeral: true,
+                path: None,
+            }),
+            lock: encryption::Lock::default(),
+            app_lock: app_lock::AppLock::default(),
+            http_api: Mutex::default(),
+        }
+    }
+
+    /// Checks at emit time that the database is unlocked and the change is
+    /// visible to a fresh reader.
+    struct CommittedCheck<'a>(&'a AppState, std::cell::RefCell<Vec<(TodosChanged, usize)>>);
+
+    impl ChangeEmitter for CommittedCheck<'_> {
+        fn emit_changed(&self, change: &TodosChanged) -> Result<(), String> {
+            let conn = self.0.db.try_lock().expect("database still locked");
+            let rows = db_get_todos(&conn).len();
+            self.1.borrow_mut().push((change.clone(), rows));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_mutate_emits_after_commit() {
+        let state = test_state();
+        let events = CommittedCheck(&state, Default::default());
+
+        let todo = mutate(&state, &events, |conn, history| {
+            let (todo, change) = undo::record(conn, &[], |conn| {
+                db_add_todo(conn, "Task".to_string(), None)
+            });
+            history.push(change);
+            let id = todo.id;
+            (todo, vec![TodosChanged::added(vec![id])])
+        });
+
+        assert_eq!(
+            events.1.into_inner(),
+            vec![(TodosChanged::added(vec![todo.id]), 1)]
+        );
+    }
+
+    #[test]
+    fn test_second_launch_add_is_refused_while_locked() {
+        let state = test_state();
+        state.lock.set(true);
+        let events = events::test_emitter::RecordingEmitter::default();
+        let notifier = notifications::test_notifier::RecordingNotifier::default();
+        let new = NewTodo {
+            title: "Buy milk".to_string(),
+            ..NewTodo::default()
+        };
+
+        assert_eq!(
+            add_from_second_launch(&state, &events, &notifier, new),
+            None
+        );
+
+        assert!(db_get_todos(&state.db.lock().unwrap()).is_empty());
+        assert!(events.take().is_empty());
+        assert_eq!(
+            notifier.take(),
+            [(
+                "Couldn't add todo".to_string(),
+                "Unlock the database first, then add it again.".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_normalize_title() {
+        assert_eq!(normalize_title("  Pay   Rent\t"), "pay rent");
+        assert_eq!(normalize_title("ŁÓDŹ trip"), "łódź trip");
+        assert_eq!(normalize_title(" \n "), "");
+    }
+
+    #[test]
+    fn test_find_active_duplicate() {
+        let conn = setup_test_db();
+        let rent = db_add_todo(&conn, "Pay rent".to_string(), None);
+        let done = db_add_todo(&conn, "Water plants".to_string(), None);
+        db_update_todo(&conn, done.id, None, Some(true), None, None);
+
+        let found = |title: &str| db_find_active_duplicate(&conn, title).map(|t| t.id);
+        assert_eq!(found("pay rent"), Some(rent.id));
+        assert_eq!(found("  PAY\t rent "), Some(rent.id));
+        assert_eq!(found("Pay rent now"), None);
+        assert_eq!(found("water plants"), None);
+    }
+
+    #[test]
+    fn test_add_todo_on_duplicate() {
+        let state = test_state();
+        let events = events::test_emitter::RecordingEmitter::default();
+        let new = |title: &str| NewTodo {
+            title: title.to_string(),
+            ..NewTodo::default()
+        };
+        let rent = create_todo(&state, &events, new("Pay rent"));
+        events.take();
+
+        assert_eq!(
+            create_todo_checked(&state, &events, new("pay  RENT"), OnDuplicate::Reject),
+            Err(TodoError::Duplicate(Box::new(rent.clone())))
+        );
+        assert_eq!(
+            create_todo_checked(
+                &state,
+                &events,
+                new("Pay rent "),
+                OnDuplicate::ReturnExisting
+            ),
+            Ok(rent.clone())
+        );
+        assert!(events.take().is_empty());
+        assert_eq!(db_count_todos(&state.db.lock().unwrap()), 1);
+
+        let allowed =
+            create_todo_checked(&state, &events, new("Pay rent"), OnDuplicate::Allow).unwrap();
+        assert_ne!(allowed.id, rent.id);
+        let other =
+            create_todo_checked(&state, &events, new("Pay bills"), OnDuplicate::Reject).unwrap();
+        assert_eq!(other.title, "Pay bills");
+        assert_eq!(db_count_todos(&state.db.lock().unwrap()), 3);
+    }
+
+    #[test]
+    fn test_added_todos_come_back_enriched() {
+        let state = test_state();
+        let events = events::test_emitter::RecordingEmitter::default();
+        let late = NewTodo {
+            title: "Renew passport".to_string(),
+            deadline: Some("2020-01-01".to_string()),
+            ..NewTodo::default()
+        };
+
+        let added = create_todo(&state, &events, late.clone());
+        assert!(added.is_overdue);
+        assert!(added.days_until_deadline.unwrap() < 0);
+        let again = create_todo_checked(&state, &events, late, OnDuplicate::ReturnExisting);
+        assert!(again.unwrap().is_overdue);
+    }
+
+    #[test]
+    fn test_text_search_matches_like_duplicates() {
+        let conn = setup_test_db();
+        let rent = db_add_todo(&conn, "Pay   Rent".to_string(), None);
+        let query = |text: &str| -> Vec<u64> {
+            let query = TodoQuery {
+                text: Some(text.to_string()),
+                ..TodoQuery::default()
+            };
+            db_query_todos(&conn, &query, Local::now())
+                .iter()
+                .map(|t| t.id)
+                .collect()
+        };
+        assert_eq!(query("pay rent"), vec![rent.id]);
+        assert_eq!(query(" PAY  rent"), vec![rent.id]);
+        assert_eq!(
+            db_find_active_duplicate(&conn, " PAY  rent").map(|t| t.id),
+            Some(rent.id)
+        );
+    }
+
+    #[test]
+    fn test_mutate_emits_reported_ids_for_bulk_changes() {
+        let state = test_state();
+        let events = events::test_emitter::RecordingEmitter::default();
+        let ids: Vec<u64> = (0..3)
+            .map(|i| {
+                let conn = state.db.lock().unwrap();
+                db_add_todo(&conn, i.to_string(), None).id
+            })
+            .collect();
+
+        let added = mutate(&state, &events, |conn, _| {
+            let added = db_add_tag_to_todos(conn, &ids, "work");
+            (added, vec![TodosChanged::updated(ids.clone())])
+        });
+        let unchanged: bool = mutate(&state, &events, |_, _| (false, Vec::new()));
+
+        assert_eq!(added, 3);
+        assert!(!unchanged);
+        assert_eq!(events.take(), vec![TodosChanged::updated(ids)]);
+    }
+
+    #[test]
+    fn test_import_changes_skip_dry_runs() {
+        let summary = |dry_run| {
+            Ok(import::ImportSummary {
+                dry_run,
+                ids: vec![7, 8],
+                ..Default::default()
+            })
+        };
+
+        let mut history = undo::UndoHistory::default();
+
+        assert!(imported_changes(&summary(true), &mut history).is_empty());
+        assert!(imported_changes(&Err("boom".to_string()), &mut history).is_empty());
+        assert!(!history.can_undo());
+        assert_eq!(
+            imported_changes(&summary(false), &mut history),
+            vec![TodosChanged::added(vec![7, 8])]
+        );
+        assert!(history.can_undo());
+    }
+
+    #[test]
+    fn test_undo_import_removes_every_imported_todo() {
+        let conn = setup_test_db();
+        let kept = db_add_todo(&conn, "Already here".to_string(), None);
+        let mut history = undo::UndoHistory::default();
+
+        let summary = import::import_markdown(&conn, "- [ ] Buy milk\n- [x] Pay rent\n");
+        imported_changes(&summary, &mut history);
+        assert_eq!(db_count_todos(&conn), 3);
+
+        assert!(history.undo(&conn).unwrap());
+
+        let left: Vec<u64> = db_get_todos(&conn).iter().map(|t| t.id).collect();
+        assert_eq!(left, [kept.id]);
+    }
+
+    #[test]
+    fn test_undo_tagging() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, "Pay rent".to_string(), None);
+        db_add_tag(&conn, todo.id, "home");
+        let mut history = undo::UndoHistory::default();
+
+        let (added, change) = undo::record(&conn, &[todo.id], |conn| {
+            db_add_tag_to_todos(conn, &[todo.id], "bills")
+        });
+        history.push(change);
+        assert_eq!(added, 1);
+        let (removed, change) = undo::record(&conn, &[todo.id], |conn| {
+            db_remove_tag(conn, todo.id, "home")
+        });
+        history.push(change);
+        assert!(removed);
+        assert_eq!(db_get_tags(&conn, todo.id), ["bills"]);
+
+        assert!(history.undo(&conn).unwrap());
+        assert_eq!(db_get_tags(&conn, todo.id), ["bills", "home"]);
+        assert!(history.undo(&conn).unwrap());
+        assert_eq!(db_get_tags(&conn, todo.id), ["home"]);
+    }
+
+    #[test]
+    fn test_validate_title() {
+        assert_eq!(
+            validate_title("  Buy milk ", MAX_TITLE_CHARS).unwrap(),
+            "Buy milk"
+        );
+        assert!(validate_title("   ", MAX_TITLE_CHARS).is_err());
+        assert!(validate_title("\u{200B}\u{7}", MAX_TITLE_CHARS).is_err());
+        assert!(validate_title(&"x".repeat(MAX_TITLE_CHARS), MAX_TITLE_CHARS).is_ok());
+        assert!(validate_title(&"é".repeat(MAX_TITLE_CHARS + 1), MAX_TITLE_CHARS).is_err());
+        // Characters are counted, not bytes: 500 two-byte characters fit.
+        let boundary = "ż".repeat(MAX_TITLE_CHARS);
+        assert_eq!(boundary.len(), 2 * MAX_TITLE_CHARS);
+        assert_eq!(
+            validate_title(&boundary, MAX_TITLE_CHARS).unwrap(),
+            boundary
+        );
+        // Counted after NFC: 500 decomposed "é"s are 1000 chars going in.
+        let decomposed = "e\u{301}".repeat(MAX_TITLE_CHARS);
+        assert_eq!(
+            validate_title(&decomposed, MAX_TITLE_CHARS)
+                .unwrap()
+                .chars()
+                .count(),
+            MAX_TITLE_CHARS
+        );
+        assert_eq!(
+            validate_title("Pay rent", 4),
+            Err(ValidationError::new(
+                "title",
+                validation::TOO_LONG,
+                "Title is 8 characters long; the limit is 4"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_clean_title() {
+        assert_eq!(clean_title("  Pay \t\n  rent  "), "Pay rent");
+        assert_eq!(clean_title("Pay\u{7}\u{1b} rent\u{0}"), "Pay rent");
+        assert_eq!(clean_title("Pay\u{200B} re\u{FEFF}nt\u{2060}"), "Pay rent");
+        assert_eq!(clean_title("Cafe\u{301}"), "Caf\u{e9}");
+        // Joiners hold emoji sequences together and are kept.
+        assert_eq!(
+            clean_title("\u{1F469}\u{200D}\u{1F4BB}"),
+            "\u{1F469}\u{200D}\u{1F4BB}"
+        );
+        assert_eq!(clean_title(" \u{200B} "), "");
+    }
+
+    #[test]
+    fn test_titles_are_stored_clean() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, " Pay\u{200B}  rent ".to_string(), None);
+        assert_eq!(todo.title, "Pay rent");
+        assert_eq!(db_get_todo(&conn, todo.id).unwrap().title, "Pay rent");
+        assert_eq!(
+            db_find_active_duplicate(&conn, "pay rent").map(|t| t.id),
+            Some(todo.id)
+        );
+
+        let changes = TodoChanges {
+            title: Some("Cafe\u{301}\t bill".to_string()),
+            ..TodoChanges::default()
+        };
+        let updated = db_update_todo_at_version(&conn, todo.id, None, &changes);
+        assert!(matches!(updated, UpdateOutcome::Updated(ref t) if t.title == "Caf\u{e9} bill"));
+        // Cleaning to what is already stored is not a change.
+        let same = TodoChanges {
+            title: Some("  Caf\u{e9}   bill".to_string()),
+            ..TodoChanges::default()
+        };
+        assert!(matches!(
+            db_update_todo_at_version(&conn, todo.id, None, &same),
+            UpdateOutcome::Unchanged(_)
+        ));
+    }
+
+    #[test]
+    fn test_title_limit_setting() {
+        let conn = setup_test_db();
+        assert_eq!(max_title_chars(&conn), MAX_TITLE_CHARS);
+        set_max_title_chars(&conn, 20).unwrap();
+        assert_eq!(max_title_chars(&conn), 20);
+        assert!(set_max_title_chars(&conn, 0).is_err());
+        assert!(set_max_title_chars(&conn, TITLE_LIMIT_CEILING + 1).is_err());
+        assert_eq!(max_title_chars(&conn), 20);
+    }
+
+    #[test]
+    fn test_validate_deadline() {
+        assert_eq!(validate_deadline(None).unwrap(), None);
+        assert_eq!(validate_deadline(Some(" ".to_string())).unwrap(), None);
+        assert_eq!(
+            validate_deadline(Some("2024-06-01T09:30".to_string())).unwrap(),
+            Some(deadline::to_stored(
+                parse_deadline("2024-06-01T09:30").unwrap()
+            ))
+        );
+        assert_eq!(
+            validate_deadline(Some("2024-06-01T09:30:00+02:00".to_string())).unwrap(),
+            Some("2024-06-01T07:30:00+00:00".to_string())
+        );
+        assert_eq!(
+            validate_deadline(Some(" 2024-06-01 ".to_string())).unwrap(),
+            Some("2024-06-01".to_string())
+        );
+        assert!(validate_deadline(Some("next tuesday".to_string())).is_err());
+    }
+
+    #[test]
+    fn test_timestamps_round_trip_between_utc_and_local() {
+        let stored = "2024-05-01T10:30:00+00:00";
+        let local = to_local(stored).unwrap();
+
+        assert_eq!(
+            DateTime::parse_from_rfc3339(&local).unwrap(),
+            DateTime::parse_from_rfc3339(stored).unwrap()
+        );
+        assert_eq!(to_utc(&local).unwrap(), stored);
+        assert_eq!(to_local("yesterday"), None);
+        assert_eq!(to_utc("yesterday"), None);
+    }
+
+    #[test]
+    fn test_created_at_is_stored_in_utc_and_read_as_local() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, "Stamped".to_string(), None);
+        let stored: String = conn
+            .query_row(
+                "SELECT created_at FROM todos WHERE id = ?1",
+                (todo.id,),
+                |row| row.get(0),
+            )
+            .unwrap();
+
+        assert!(stored.ends_with("+00:00"), "{}", stored);
+        assert_eq!(todo.created_at, to_local(&stored).unwrap());
+        assert_eq!(
+            db_get_todo(&conn, todo.id).unwrap().created_at,
+            todo.created_at
+        );
+    }
+
+    #[test]
+    fn test_pinned_todo_sorts_first() {
+        let conn = setup_test_db();
+        let first = db_add_todo(&conn, "First".to_string(), None);
+        let second = db_add_todo(&conn, "Second".to_string(), None);
+        let last = db_add_todo(&conn, "Last".to_string(), None);
+        db_update_todo(&conn, last.id, None, Some(true), None, None);
+
+        assert!(db_set_pinned(&conn, last.id, true));
+
+        let todos = db_get_todos(&conn);
+        let ids: Vec<u64> = todos.iter().map(|t| t.id).collect();
+        assert_eq!(ids, vec![last.id, first.id, second.id]);
+        assert!(todos[0].pinned && todos[0].completed);
+
+        assert!(db_set_pinned(&conn, last.id, false));
+        assert_eq!(db_get_todos(&conn)[2].id, last.id);
+        assert!(!db_set_pinned(&conn, 999, true));
+    }
+
+    #[test]
+    fn test_set_completed_bulk_changes_only_given_ids() {
+        let conn = setup_test_db();
+        let todos: Vec<Todo> = (0..5)
+            .map(|i| db_add_todo(&conn, format!("Todo {}", i), None))
+            .collect();
+        let picked = [todos[0].id, todos[2].id, todos[4].id];
+
+        assert_eq!(db_set_completed_bulk(&conn, &picked, true), 3);
+
+        for todo in db_get_todos(&conn) {
+            let expected = picked.contains(&todo.id);
+            assert_eq!(todo.completed, expected, "todo {}", todo.id);
+            assert_eq!(todo.completed_at.is_some(), expected);
+        }
+        assert_eq!(db_get_history(&conn, todos[2].id).len(), 1);
+        assert_eq!(
+            db_set_completed_bulk(&conn, &[todos[0].id, todos[1].id, 999], true),
+            1
+        );
+        assert_eq!(db_set_completed_bulk(&conn, &[], true), 0);
+        assert_eq!(db_set_completed_bulk(&conn, &picked, false), 3);
+        assert!(db_get_todos(&conn)[0].completed_at.is_none());
+    }
+
+    #[test]
+    fn test_padded_ids_use_few_statement_shapes() {
+        use rusqlite::types::Value;
+
+        assert_eq!(padded_ids(&[7]), vec![Value::Integer(7)]);
+        assert_eq!(
+            padded_ids(&[1, 2, 3]),
+            [1, 2, 3, 3].map(Value::Integer).to_vec()
+        );
+        let lengths: std::collections::BTreeSet<usize> = (1..=BULK_CHUNK)
+            .map(|n| padded_ids(&vec![1; n]).len())
+            .collect();
+        assert_eq!(lengths.len(), 10);
+        assert!(lengths.iter().all(|&len| len <= BULK_CHUNK));
+    }
+
+    /// Guards against statements being re-prepared on every call: 10,000
+    /// cached reads take a few milliseconds, so the bound only trips when
+    /// something is badly off.
+    #[test]
+    fn test_repeated_queries_stay_fast() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, "Cached".to_string(), None);
+        db_set_setting(&conn, "theme", "dark");
+
+        let start = Instant::now();
+        for _ in 0..10_000 {
+            assert!(db_get_todo(&conn, todo.id).is_some());
+            assert!(db_get_setting(&conn, "theme").is_some());
+        }
+
+        let elapsed = start.elapsed();
+        assert!(elapsed < Duration::from_secs(5), "took {:?}", elapsed);
+    }
+
+    #[test]
+    fn test_find_duplicates() {
+        let conn = setup_test_db();
+        let milk = db_add_todo(&conn, "Buy milk".to_string(), None);
+        let call = db_add_todo(&conn, "Call Ana".to_string(), None);
+        let milk_again = db_add_todo(&conn, "  buy MILK ".to_string(), None);
+        db_add_todo(&conn, "Water plants".to_string(), None);
+        let call_done = db_add_todo(&conn, "call ana".to_string(), None);
+        db_update_todo(&conn, call_done.id, None, Some(true), None, None);
+
+        assert_eq!(
+            db_find_duplicates(&conn),
+            vec![
+                DuplicateGroup {
+                    title: "Buy milk".to_string(),
+                    ids: vec![milk.id, milk_again.id],
+                    completed_ids: vec![],
+                },
+                DuplicateGroup {
+                    title: "Call Ana".to_string(),
+                    ids: vec![call.id, call_done.id],
+                    completed_ids: vec![call_done.id],
+                },
+            ]
+        );
+    }
+
+    thread_local! {
+        static STATEMENTS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+    }
+
+    /// Runs `f` and returns how many statements it executed on `conn`.
+    fn count_statements<T>(conn: &mut Connection, f: impl FnOnce(&Connection) -> T) -> (T, usize) {
+        fn trace(_sql: &str) {
+            STATEMENTS.with(|n| n.set(n.get() + 1));
+        }
+        STATEMENTS.with(|n| n.set(0));
+        conn.trace(Some(trace));
+        let result = f(conn);
+        conn.trace(None);
+        (result, STATEMENTS.with(|n| n.get()))
+    }
+
+    #[test]
+    fn test_summary_leaves_out_heavy_fields() {
+        let conn = setup_test_db();
+        let todo = db_insert_todo(
+            &conn,
+            NewTodo {
+                title: "Summary".to_string(),
+                deadline: Some("2024-05-01".to_string()),
+                description: Some("A long description".to_string()),
+                ..NewTodo::default()
+            },
+        )
+        .unwrap();
+        db_add_tag(&conn, todo.id, "work");
+        db_add_tag(&conn, todo.id, "home");
+
+        let summaries = db_query_summaries(&conn, &TodoQuery::default(), Local::now());
+
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].tag_count, 2);
+        assert_eq!(summaries[0].deadline.as_deref(), Some("2024-05-01"));
+        let json = serde_json::to_value(&summaries[0]).unwrap();
+        assert!(json.get("description").is_none());
+        assert!(json.get("tags").is_none());
+    }
+
+    #[test]
+    fn test_detail_includes_everything_in_constant_queries() {
+        let mut conn = setup_test_db();
+        let few = db_add_todo(&conn, "Few tags".to_string(), None);
+        let many = db_insert_todo(
+            &conn,
+            NewTodo {
+                title: "Many tags".to_string(),
+                description: Some("Details".to_string()),
+                ..NewTodo::default()
+            },
+        )
+        .unwrap();
+        db_add_tag(&conn, few.id, "one");
+        for i in 0..10 {
+            db_add_tag(&conn, many.id, &format!("tag{}", i));
+        }
+
+        let (detail, many_queries) =
+            count_statements(&mut conn, |c| db_get_todo_detail(c, many.id));
+        let (_, few_queries) = count_statements(&mut conn, |c| db_get_todo_detail(c, few.id));
+
+        let detail = detail.unwrap();
+        assert_eq!(detail.todo.description.as_deref(), Some("Details"));
+        assert_eq!(detail.tags.len(), 10);
+        assert_eq!(many_queries, few_queries);
+        assert!(many_queries <= 2);
+        let json = serde_json::to_value(&detail).unwrap();
+        assert_eq!(json["description"], "Details");
+        assert_eq!(json["tags"].as_array().unwrap().len(), 10);
+        assert_eq!(db_get_todo_detail(&conn, 999), None);
+    }
+
+    #[test]
+    fn test_set_color() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, "Colorful".to_string(), None);
+        assert_eq!(todo.color, None);
+
+        assert_eq!(db_set_color(&conn, todo.id, Some("Blue")), Ok(true));
+        assert_eq!(
+            db_get_todo(&conn, todo.id).unwrap().color.as_deref(),
+            Some("blue")
+        );
+
+        assert!(db_set_color(&conn, todo.id, Some("chartreuse")).is_err());
+        assert!(db_set_color(&conn, todo.id, Some("#ff0000")).is_err());
+        assert_eq!(
+            db_get_todo(&conn, todo.id).unwrap().color.as_deref(),
+            Some("blue")
+        );
+
+        assert_eq!(db_set_color(&conn, todo.id, Some("none")), Ok(true));
+        assert_eq!(db_get_todo(&conn, todo.id).unwrap().color, None);
+        db_set_color(&conn, todo.id, Some("red")).unwrap();
+        db_set_color(&conn, todo.id, None).unwrap();
+        assert_eq!(db_get_todo(&conn, todo.id).unwrap().color, None);
+        assert_eq!(db_set_color(&conn, 999, Some("red")), Ok(false));
+    }
+
+    #[test]
+    fn test_hot_queries_use_indexes() {
+        let dir = tempfile::tempdir().unwrap();
+        let conn = Connection::open(dir.path().join("todos.db")).unwrap();
+        init_db(&conn);
+        conn.execute_batch(
+            "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 50000)
+             INSERT INTO todos (title, completed, created_at, deadline)
+             SELECT 'Todo ' || i, i % 3 = 0, '2024-01-01T00:00:00+00:00',
+                    CASE WHEN i % 5 = 0 THEN NULL ELSE date('2024-01-01', '+' || (i % 400) || ' days') END
+             FROM n;
+             ANALYZE;",
+        )
+        .unwrap();
+        let plan = |sql: &str| -> String {
+            let mut stmt = conn
+                .prepare(&format!("EXPLAIN QUERY PLAN {}", sql))
+                .unwrap();
+            let rows = stmt
+                .query_map(["2024-06-01"], |row| row.get::<_, String>(3))
+                .unwrap();
+            rows.map(|r| r.unwrap()).collect::<Vec<_>>().join("\n")
+        };
+
+        for sql in [
+            todos_where_sql(OVERDUE_CANDIDATES),
+            todos_where_sql(BY_COMPLETION),
+        ] {
+            let plan = plan(&sql);
+            assert!(plan.contains("USING INDEX idx_todos_"), "{}", plan);
+            assert!(!plan.contains("SCAN todos"), "{}", plan);
+        }
+
+        let now = Local.with_ymd_and_hms(2024, 6, 1, 12, 0, 0).unwrap();
+        let overdue = db_get_overdue_todos(&conn, now);
+        let expected = db_get_todos(&conn)
+            .into_iter()
+            .filter(|todo| Filter::Overdue.matches(todo, now))
+            .count();
+        assert_eq!(overdue.len(), expected);
+        assert!(!overdue.is_empty());
+    }
+
+    #[test]
+    fn test_overdue_prefilter_handles_offsets() {
+        let conn = setup_test_db();
+        let now = Local::now();
+        let ahead = (now - ChronoDuration::hours(1))
+            .with_timezone(&chrono::FixedOffset::east_opt(14 * 3600).unwrap())
+            .to_rfc3339();
+        let late = db_add_todo(&conn, "Late".to_string(), Some(ahead));
+        db_add_todo(
+            &conn,
+            "Later".to_string(),
+            Some(
+                (now + ChronoDuration::days(3))
+                    .format("%Y-%m-%d")
+                    .to_string(),
+            ),
+        );
+
+        let ids: Vec<u64> = db_get_overdue_todos(&conn, now)
+            .iter()
+            .map(|t| t.id)
+            .collect();
+        assert_eq!(ids, vec![late.id]);
+    }
+
+    #[test]
+    fn test_query_todos_by_single_field() {
+        let conn = setup_test_db();
+        let now = Local.with_ymd_and_hms(2024, 6, 1, 12, 0, 0).unwrap();
+        let report = db_insert_todo(
+            &conn,
+            NewTodo {
+                title: "Write report".to_string(),
+                deadline: Some("2024-05-30".to_string()),
+                ..NewTodo::default()
+            },
+        )
+        .unwrap();
+        let call = db_insert_todo(
+            &conn,
+            NewTodo {
+                title: "Call Ana".to_string(),
+                deadline: Some("2024-06-10T09:00".to_string()),
+                description: Some("About the REPORT".to_string()),
+                ..NewTodo::default()
+            },
+        )
+        .unwrap();
+        let discount = db_add_todo(&conn, "Use 100% _discount_".to_string(), None);
+        db_update_todo(&conn, discount.id, None, Some(true), None, None);
+        db_add_tag(&conn, call.id, "work");
+        let ids = |query: TodoQuery| -> Vec<u64> {
+            db_query_todos(&conn, &query, now)
+                .iter()
+                .map(|t| t.id)
+                .collect()
+        };
+
+        assert_eq!(
+            ids(TodoQuery {
+                text: Some("report".to_string()),
+                ..TodoQuery::default()
+            }),
+            vec![report.id, call.id]
+        );
+        assert_eq!(
+            ids(TodoQuery {
+                text: Some("100%".to_string()),
+                ..TodoQuery::default()
+            }),
+            vec![discount.id]
+        );
+        assert!(ids(TodoQuery {
+            text: Some("0_d".to_string()),
+            ..TodoQuery::default()
+        })
+        .is_empty());
+        assert_eq!(
+            ids(TodoQuery {
+                filter: Filter::Completed,
+                ..TodoQuery::default()
+            }),
+            vec![discount.id]
+        );
+        assert_eq!(
+            ids(TodoQuery {
+                filter: Filter::Overdue,
+                ..TodoQuery::default()
+            }),
+            vec![report.id]
+        );
+        assert_eq!(
+            ids(TodoQuery {
+                tag: Some(" work ".to_string()),
+                ..TodoQuery::default()
+            }),
+            vec![call.id]
+        );
+        assert_eq!(
+            ids(TodoQuery {
+                due_before: Some("2024-06-10".to_string()),
+                ..TodoQuery::default()
+            }),
+            vec![report.id, call.id]
+        );
+        assert_eq!(
+            ids(TodoQuery {
+                due_before: Some("2024-06-10T08:00".to_string()),
+                ..TodoQuery::default()
+            }),
+            vec![report.id]
+        );
+        assert!(ids(TodoQuery {
+            due_before: Some("soon".to_string()),
+            ..TodoQuery::default()
+        })
+        .is_empty());
+    }
+
+    #[test]
+    fn test_old_filter_argument_still_selects() {
+        assert_eq!(
+            TodoQuery::or_filter(None, Some(Filter::Completed)),
+            TodoQuery {
+                filter: Filter::Completed,
+                ..TodoQuery::default()
+            }
+        );
+        let tagged = TodoQuery {
+            tag: Some("work".to_string()),
+            filter: Filter::Active,
+            ..TodoQuery::default()
+        };
+        assert_eq!(TodoQuery::or_filter(Some(tagged.clone()), None), tagged);
+        assert_eq!(
+            TodoQuery::or_filter(Some(tagged.clone()), Some(Filter::Overdue)),
+            TodoQuery {
+                filter: Filter::Overdue,
+                ..tagged
+            }
+        );
+        assert_eq!(TodoQuery::or_filter(None, None), TodoQuery::default());
+    }
+
+    #[test]
+    fn test_query_todos_combined_fields() {
+        let conn = setup_test_db();
+        let now = Local.with_ymd_and_hms(2024, 6, 1, 12, 0, 0).unwrap();
+        let add = |title: &str, deadline: &str, tag: &str| {
+            let todo = db_add_todo(&conn, title.to_string(), Some(deadline.to_string()));
+            db_add_tag(&conn, todo.id, tag);
+            todo.id
+        };
+        let a = add("Plan trip", "2024-06-02", "home");
+        let b = add("Plan sprint", "2024-06-03", "work");
+        let c = add("Plan retro", "2024-07-01", "work");
+        add("Pay rent", "2024-06-02", "home");
+        db_update_todo(&conn, b, None, Some(true), None, None);
+        let query: TodoQuery = serde_json::from_value(serde_json::json!({
+            "text": "plan",
+            "status": "active",
+            "tag": "work",
+        }))
+        .unwrap();
+
+        let ids = |query: &TodoQuery| -> Vec<u64> {
+            db_query_todos(&conn, query, now)
+                .iter()
+                .map(|t| t.id)
+                .collect()
+        };
+
+        assert_eq!(ids(&query), vec![c]);
+        assert_eq!(
+            ids(&TodoQuery {
+                text: Some("plan".to_string()),
+                due_before: Some("2024-06-30".to_string()),
+                ..TodoQuery::default()
+            }),
+            vec![a, b]
+        );
+        assert_eq!(
+            ids(&TodoQuery {
+                text: Some("plan".to_string()),
+                filter: Filter::Active,
+                tag: Some("home".to_string()),
+                due_before: Some("2024-06-02".to_string()),
+                ..TodoQuery::default()
+            }),
+            vec![a]
+        );
+    }
+
+    #[test]
+    fn test_update_outcomes() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, "Outcome".to_string(), None);
+
+        assert_eq!(
+            db_update_todo(&conn, todo.id, None, None, None, None),
+            UpdateOutcome::Unchanged(todo.clone())
+        );
+        let missing = db_update_todo(&conn, 999, Some("x".to_string()), None, None, None);
+        assert_eq!(missing, UpdateOutcome::NotFound);
+        assert_eq!(missing.into_result(999), Err(TodoError::NotFound(999)));
+        assert_eq!(
+            db_update_todo(
+                &conn,
+                todo.id,
+                Some("Outcome".to_string()),
+                Some(false),
+                None,
+                None
+            ),
+            UpdateOutcome::Unchanged(todo.clone())
+        );
+        let UpdateOutcome::Updated(updated) = db_update_todo(
+            &conn,
+            todo.id,
+            None,
+            None,
+            Some("2024-05-01".to_string()),
+            None,
+        ) else {
+            panic!("expected an update");
+        };
+        assert_eq!(updated.deadline.as_deref(), Some("2024-05-01"));
+        assert_eq!(db_get_todo(&conn, todo.id), Some(updated));
+    }
+
+    #[test]
+    fn test_update_returns_saved_todo() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, "Before".to_string(), Some("2024-04-01".to_string()));
+
+        let updated = db_update_todo(
+            &conn,
+            todo.id,
+            Some("After".to_string()),
+            Some(true),
+            Some(String::new()),
+            Some(30),
+        )
+        .into_result(todo.id)
+        .unwrap();
+
+        assert_eq!(updated.title, "After");
+        assert!(updated.completed);
+        assert_eq!(updated.deadline, None);
+        assert_eq!(updated.estimate_minutes, Some(30));
+        assert!(updated.completed_at.is_some());
+        assert_eq!(updated.created_at, todo.created_at);
+        assert_eq!(db_get_todo(&conn, todo.id), Some(updated.clone()));
+
+        let reopened = db_update_todo(&conn, todo.id, None, Some(false), None, None)
+            .into_result(todo.id)
+            .unwrap();
+        assert_eq!(reopened.completed_at, None);
+        assert_eq!(reopened.title, "After");
+    }
+
+    #[test]
+    fn test_multi_field_update_is_atomic() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, "Before".to_string(), None);
+        // Stands in for a CHECK constraint: the history write for the
+        // deadline, which comes after the UPDATE, fails.
+        conn.execute_batch(
+            "CREATE TRIGGER reject_deadline BEFORE INSERT ON todo_history
+             WHEN NEW.field = 'deadline'
+             BEGIN SELECT RAISE(ABORT, 'CHECK constraint failed: deadline'); END;",
+        )
+        .unwrap();
+
+        let result = try_update_todo(
+            &conn,
+            todo.id,
+            None,
+            &TodoChanges {
+                title: Some("After".to_string()),
+                completed: Some(true),
+                deadline: Some("2024-05-01".to_string()),
+                ..TodoChanges::default()
+            },
+        );
+
+        assert!(result.is_err());
+        assert_eq!(db_get_todo(&conn, todo.id), Some(todo.clone()));
+        assert!(db_get_history(&conn, todo.id).is_empty());
+
+        assert!(
+            db_update_todo(&conn, todo.id, Some("After".to_string()), None, None, None).found()
+        );
+        assert_eq!(db_get_todo(&conn, todo.id).unwrap().title, "After");
+    }
+
+    #[test]
+    fn test_estimates() {
+        let conn = setup_test_db();
+        let now = Local::now();
+        let todo = db_insert_todo(
+            &conn,
+            NewTodo {
+                title: "Write report".to_string(),
+                estimate_minutes: Some(90),
+                ..NewTodo::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            db_get_todo(&conn, todo.id).unwrap().estimate_minutes,
+            Some(90)
+        );
+        let short = db_add_todo(&conn, "Reply".to_string(), None);
+        db_update_todo(&conn, short.id, None, None, None, Some(15));
+        db_add_todo(&conn, "No estimate".to_string(), None);
+        let done = db_add_todo(&conn, "Done".to_string(), None);
+        db_update_todo(&conn, done.id, None, Some(true), None, Some(60));
+
+        assert_eq!(db_sum_estimates(&conn, Filter::Active, now), 105);
+        assert_eq!(db_sum_estimates(&conn, Filter::All, now), 165);
+        assert_eq!(db_sum_estimates(&conn, Filter::Overdue, now), 0);
+
+        db_update_todo(&conn, short.id, None, None, None, Some(0));
+        assert_eq!(db_get_todo(&conn, short.id).unwrap().estimate_minutes, None);
+        assert_eq!(db_sum_estimates(&conn, Filter::Active, now), 90);
+    }
+
+    #[test]
+    fn test_stale_version_is_rejected() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, "Shared".to_string(), None);
+        let first = db_update_todo_at_version(
+            &conn,
+            todo.id,
+            Some(0),
+            &TodoChanges {
+                title: Some("First window".to_string()),
+                ..TodoChanges::default()
+            },
+        )
+        .into_result(todo.id)
+        .unwrap();
+        assert_eq!(first.version, 1);
+
+        let stale = db_update_todo_at_version(
+            &conn,
+            todo.id,
+            Some(0),
+            &TodoChanges {
+                title: Some("Second window".to_string()),
+                completed: Some(true),
+                ..TodoChanges::default()
+            },
+        );
+
+        assert_eq!(stale, UpdateOutcome::Conflict(first.clone()));
+        let err = stale.into_result(todo.id).unwrap_err();
+        assert_eq!(err, TodoError::Conflict(Box::new(first.clone())));
+        let payload = serde_json::to_value(&err).unwrap();
+        assert_eq!(payload["kind"], "conflict");
+        assert_eq!(payload["message"]["title"], "First window");
+        assert_eq!(payload["message"]["version"], 1);
+        assert_eq!(db_get_todo(&conn, todo.id), Some(first));
+        assert_eq!(db_get_history(&conn, todo.id).len(), 1);
+    }
+
+    #[test]
+    fn test_matching_version_updates_and_increments() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, "Versioned".to_string(), None);
+        assert_eq!(todo.version, 0);
+
+        let updated = db_update_todo_at_version(
+            &conn,
+            todo.id,
+            Some(todo.version),
+            &TodoChanges {
+                completed: Some(true),
+                ..TodoChanges::default()
+            },
+        )
+        .into_result(todo.id)
+        .unwrap();
+        assert!(updated.completed);
+        assert_eq!(updated.version, 1);
+
+        db_set_pinned(&conn, todo.id, true);
+        db_set_completed_bulk(&conn, &[todo.id], false);
+        assert_eq!(db_get_todo(&conn, todo.id).unwrap().version, 3);
+    }
+
+    #[test]
+    fn test_update_without_expected_version_skips_check() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, "Unchecked".to_string(), None);
+        db_update_todo(
+            &conn,
+            todo.id,
+            Some("Elsewhere".to_string()),
+            None,
+            None,
+            None,
+        );
+
+        let updated = db_update_todo_at_version(
+            &conn,
+            todo.id,
+            None,
+            &TodoChanges {
+                title: Some("Last write wins".to_string()),
+                ..TodoChanges::default()
+            },
+        )
+        .into_result(todo.id)
+        .unwrap();
+
+        assert_eq!(updated.title, "Last write wins");
+        assert_eq!(updated.version, 2);
+    }
+
+    #[test]
+    fn test_bulk_update_hundred_todos() {
+        let conn = setup_test_db();
+        let ids: Vec<u64> = (0..100)
+            .map(|i| db_add_todo(&conn, format!("Todo {}", i), None).id)
+            .collect();
+        db_update_todo(&conn, ids[0], None, Some(true), None, None);
+        let completed_at = db_get_todo(&conn, ids[0]).unwrap().completed_at;
+
+        let results = db_bulk_update_todos(
+            &conn,
+            &ids,
+            &TodoChanges {
+                completed: Some(true),
+                deadline: Some("2024-05-03".to_string()),
+                ..TodoChanges::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(results.len(), 100);
+        assert!(results.iter().all(|r| r.outcome == BulkOutcome::Updated));
+        let todos = db_get_todos(&conn);
+        assert!(todos
+            .iter()
+            .all(|t| t.completed && t.deadline.as_deref() == Some("2024-05-03")));
+        assert_eq!(todos[0].completed_at, completed_at);
+        assert_eq!(todos[0].version, 2);
+        assert_eq!(db_get_history(&conn, ids[0]).len(), 2);
+        assert_eq!(db_get_history(&conn, ids[1]).len(), 2);
+
+        // A failure part-way through leaves every row as it was.
+        conn.execute_batch(
+            "CREATE TRIGGER reject_title BEFORE INSERT ON todo_history
+             WHEN NEW.field = 'title' AND NEW.todo_id = (SELECT MAX(id) FROM todos)
+             BEGIN SELECT RAISE(ABORT, 'rejected'); END;",
+        )
+        .unwrap();
+        let changes = TodoChanges {
+            title: Some("Same".to_string()),
+            ..TodoChanges::default()
+        };
+        assert!(db_bulk_update_todos(&conn, &ids, &changes).is_err());
+        assert_eq!(db_get_todos(&conn), todos);
+    }
+
+    #[test]
+    fn test_bulk_update_reports_missing_and_unchanged() {
+        let conn = setup_test_db();
+        let a = db_add_todo(&conn, "A".to_string(), None);
+        let b = db_add_todo(&conn, "B".to_string(), None);
+        db_update_todo(&conn, b.id, None, None, None, Some(30));
+
+        let results = db_bulk_update_todos(
+            &conn,
+            &[a.id, 999, b.id],
+            &TodoChanges {
+                estimate_minutes: Some(30),
+                ..TodoChanges::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            results,
+            vec![
+                BulkUpdateResult {
+                    id: a.id,
+                    outcome: BulkOutcome::Updated
+                },
+                BulkUpdateResult {
+                    id: 999,
+                    outcome: BulkOutcome::NotFound
+                },
+                BulkUpdateResult {
+                    id: b.id,
+                    outcome: BulkOutcome::Unchanged
+                },
+            ]
+        );
+        assert_eq!(db_get_todo(&conn, a.id).unwrap().estimate_minutes, Some(30));
+    }
+
+    #[test]
+    fn test_clear_deadlines() {
+        let conn = setup_test_db();
+        let ids: Vec<u64> = ["2024-05-01", "2024-05-02", "2024-05-03"]
+            .iter()
+            .map(|d| db_add_todo(&conn, d.to_string(), Some(d.to_string())).id)
+            .collect();
+
+        assert_eq!(db_clear_deadlines(&conn, &[ids[0], ids[2]]), 2);
+        assert_eq!(db_clear_deadlines(&conn, &[]), 0);
+        assert_eq!(db_clear_deadlines(&conn, &[ids[0]]), 0);
+
+        let deadlines: Vec<Option<String>> = ids
+            .iter()
+            .map(|&id| db_get_todo(&conn, id).unwrap().deadline)
+            .collect();
+        assert_eq!(deadlines, vec![None, Some("2024-05-02".to_string()), None]);
+    }
+
+    #[test]
+    fn test_postpone_todos() {
+        let conn = setup_test_db();
+        let date = db_add_todo(&conn, "Date".to_string(), Some("2024-12-30".to_string()));
+        let timed = db_add_todo(
+            &conn,
+            "Timed".to_string(),
+            deadline::normalize("2024-01-31T09:15", &Local),
+        );
+        let undated = db_add_todo(&conn, "Undated".to_string(), None);
+
+        let results = db_postpone_todos(&conn, &[date.id, undated.id, timed.id, 999], 3).unwrap();
+
+        let outcomes: Vec<BulkOutcome> = results.iter().map(|r| r.outcome).collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                BulkOutcome::Updated,
+                BulkOutcome::NoDeadline,
+                BulkOutcome::Updated,
+                BulkOutcome::NotFound
+            ]
+        );
+        let deadline = |id| db_get_todo(&conn, id).unwrap().deadline;
+        assert_eq!(deadline(date.id).as_deref(), Some("2025-01-02"));
+        assert_eq!(
+            deadline(timed.id),
+            deadline::normalize("2024-02-03T09:15", &Local)
+        );
+        assert_eq!(deadline(undated.id), None);
+        let history = db_get_todo_history(&conn, date.id, 10, 0);
+        assert_eq!(history[0].field, "deadline");
+        assert_eq!(history[0].new_value.as_deref(), Some("2025-01-02"));
+
+        db_postpone_todos(&conn, &[date.id], -7).unwrap();
+        assert_eq!(deadline(date.id).as_deref(), Some("2024-12-26"));
+    }
+
+    #[test]
+    fn test_postpone_todos_is_atomic() {
+        let conn = setup_test_db();
+        let first = db_add_todo(&conn, "First".to_string(), Some("2024-05-01".to_string()));
+        let second = db_add_todo(&conn, "Second".to_string(), Some("2024-05-02".to_string()));
+        conn.execute_batch(&format!(
+            "CREATE TEMP TRIGGER fail_second BEFORE UPDATE ON todos WHEN OLD.id = {}
+             BEGIN SELECT RAISE(ABORT, 'refused'); END;",
+            second.id
+        ))
+        .unwrap();
+
+        assert!(db_postpone_todos(&conn, &[first.id, second.id], 1).is_err());
+
+        assert_eq!(
+            db_get_todo(&conn, first.id).unwrap().deadline.as_deref(),
+            Some("2024-05-01")
+        );
+        assert!(db_get_todo_history(&conn, first.id, 10, 0).is_empty());
+    }
+
+    #[test]
+    fn test_bulk_update_rejects_empty_changes() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, "A".to_string(), None);
+
+        assert!(db_bulk_update_todos(&conn, &[todo.id], &TodoChanges::default()).is_err());
+        assert_eq!(db_get_todo(&conn, todo.id), Some(todo));
+        let parsed: Result<TodoChanges, _> = serde_json::from_str(r#"{"list_id":3}"#);
+        assert!(parsed.is_err());
+    }
+
+    #[test]
+    fn test_due_today() {
+        let conn = setup_test_db();
+        let now = Local.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+        let all_day = db_add_todo(&conn, "All day".to_string(), Some("2024-05-01".to_string()));
+        let evening = db_add_todo(
+            &conn,
+            "Evening".to_string(),
+            Some("2024-05-01T18:30".to_string()),
+        );
+        let morning = db_add_todo(
+            &conn,
+            "Morning".to_string(),
+            Some("2024-05-01T09:00".to_string()),
+        );
+        db_add_todo(
+            &conn,
+            "Tomorrow".to_string(),
+            Some("2024-05-02".to_string()),
+        );
+        db_add_todo(
+            &conn,
+            "Yesterday".to_string(),
+            Some("2024-04-30".to_string()),
+        );
+        db_add_todo(&conn, "Unscheduled".to_string(), None);
+        let done = db_add_todo(&conn, "Done".to_string(), Some("2024-05-01".to_string()));
+        db_update_todo(&conn, done.id, None, Some(true), None, None);
+
+        let ids: Vec<u64> = db_get_due_today(&conn, now).iter().map(|t| t.id).collect();
+
+        assert_eq!(ids, vec![morning.id, evening.id, all_day.id]);
+    }
+
+    #[test]
+    fn test_archive_completed() {
+        let conn = setup_test_db();
+        let active = db_add_todo(&conn, "Active".to_string(), None);
+        let done = db_add_todo(&conn, "Done".to_string(), Some("2024-05-01".to_string()));
+        db_add_tag(&conn, done.id, "work");
+        db_add_tag(&conn, done.id, "home");
+        db_update_todo(&conn, done.id, None, Some(true), None, None);
+        let done = db_get_todo(&conn, done.id).unwrap();
+
+        assert_eq!(db_archive_completed(&conn), 1);
+
+        assert_eq!(db_get_todos(&conn), vec![active]);
+        assert!(db_get_tags(&conn, done.id).is_empty());
+        let archive = db_get_archive(&conn);
+        assert_eq!(archive.len(), 1);
+        assert_eq!(archive[0].todo, done);
+        let mut tags = archive[0].tags.clone();
+        tags.sort();
+        assert_eq!(tags, vec!["home", "work"]);
+        assert_eq!(db_archive_completed(&conn), 0);
+    }
+
+    #[test]
+    fn test_unarchive_todo() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(
+            &conn,
+            "Bring back".to_string(),
+            Some("2024-05-01".to_string()),
+        );
+        db_add_tag(&conn, todo.id, "work");
+        db_update_todo(&conn, todo.id, None, Some(true), None, None);
+        db_archive_completed(&conn);
+
+        let restored = db_unarchive_todo(&conn, todo.id).unwrap();
+
+        assert_eq!(restored.id, todo.id);
+        assert!(!restored.completed);
+        assert_eq!(restored.completed_at, None);
+        assert_eq!(restored.deadline.as_deref(), Some("2024-05-01"));
+        assert_eq!(restored.created_at, todo.created_at);
+        assert_eq!(db_get_tags(&conn, todo.id), vec!["work"]);
+        assert_eq!(db_get_todos(&conn), vec![restored]);
+        assert!(db_get_archive(&conn).is_empty());
+        assert_eq!(db_unarchive_todo(&conn, todo.id), None);
+        assert_eq!(db_unarchive_todo(&conn, 999), None);
+    }
+
+    #[test]
+    fn test_unarchive_into_taken_id_gets_fresh_id() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, "Archived".to_string(), None);
+        db_update_todo(&conn, todo.id, None, Some(true), None, None);
+        db_archive_completed(&conn);
+        // E.g. after restoring an older backup over the main table.
+        conn.execute(
+            "INSERT INTO todos (id, title, completed, created_at) VALUES (?1, 'Other', 0, '')",
+            (todo.id,),
+        )
+        .unwrap();
+
+        let restored = db_unarchive_todo(&conn, todo.id).unwrap();
+
+        assert_ne!(restored.id, todo.id);
+        assert_eq!(restored.title, "Archived");
+        assert_eq!(db_get_todo(&conn, todo.id).unwrap().title, "Other");
+    }
+
+    #[test]
+    fn test_urgency_scores() {
+        let now = Local.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+        let base = db_add_todo(&setup_test_db(), "t".to_string(), None);
+        let todo = |priority, deadline: Option<&str>| Todo {
+            priority,
+            deadline: deadline.map(str::to_string),
+            ..base.clone()
+        };
+        let overdue_high = todo(MAX_PRIORITY, Some("2024-04-28"));
+        let far_low = todo(0, Some("2024-08-01"));
+        let undated_high = todo(MAX_PRIORITY, None);
+        let due_soon = todo(0, Some("2024-05-01T15:00"));
+
+        assert!(compute_urgency(&overdue_high, now) > compute_urgency(&far_low, now));
+        assert!(compute_urgency(&overdue_high, now) > compute_urgency(&undated_high, now));
+        assert!(
+            compute_urgency(&todo(0, Some("2024-04-30")), now) > compute_urgency(&due_soon, now)
+        );
+        assert!(compute_urgency(&due_soon, now) > compute_urgency(&far_low, now));
+        assert_eq!(compute_urgency(&todo(0, None), now), 0.0);
+        assert_eq!(
+            compute_urgency(&todo(0, Some("2024-01-01")), now),
+            URGENCY_OVERDUE + URGENCY_MAX_OVERDUE_DAYS
+        );
+    }
+
+    #[test]
+    fn test_todos_by_urgency() {
+        let conn = setup_test_db();
+        let now = Local.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+        let far = db_add_todo(&conn, "Far".to_string(), Some("2024-08-01".to_string()));
+        let overdue = db_insert_todo(
+            &conn,
+            NewTodo {
+                title: "Overdue".to_string(),
+                deadline: Some("2024-04-28".to_string()),
+                priority: MAX_PRIORITY,
+                ..NewTodo::default()
+            },
+        )
+        .unwrap();
+        let undated = db_add_todo(&conn, "Undated".to_string(), None);
+        let done = db_add_todo(&conn, "Done".to_string(), Some("2024-04-01".to_string()));
+        db_update_todo(&conn, done.id, None, Some(true), None, None);
+
+        let ids: Vec<u64> = db_get_todos_by_urgency(&conn, now)
+            .iter()
+            .map(|t| t.id)
+            .collect();
+
+        assert_eq!(ids, vec![overdue.id, far.id, undated.id]);
+    }
+
+    #[test]
+    fn test_focus_returns_top_n() {
+        let conn = setup_test_db();
+        let now = Local.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+        let add = |title: &str, deadline: &str, priority: u8| {
+            db_insert_todo(
+                &conn,
+                NewTodo {
+                    title: title.to_string(),
+                    deadline: Some(deadline.to_string()),
+                    priority,
+                    ..NewTodo::default()
+                },
+            )
+            .unwrap()
+            .id
+        };
+        let far = add("Far", "2024-08-01", 0);
+        let overdue = add("Overdue", "2024-04-28", MAX_PRIORITY);
+        let tomorrow = add("Tomorrow", "2024-05-02", 1);
+        let next_week = add("Next week", "2024-05-08", 1);
+        let pinned = add("Pinned", "2024-07-01", 0);
+        db_set_pinned(&conn, pinned, true);
+        let done = add("Done", "2024-04-01", MAX_PRIORITY);
+        db_update_todo(&conn, done, None, Some(true), None, None);
+        db_archive_completed(&conn);
+
+        let ids = |n| -> Vec<u64> { db_get_focus(&conn, now, n).iter().map(|t| t.id).collect() };
+
+        assert_eq!(ids(3), vec![pinned, overdue, tomorrow]);
+        assert_eq!(ids(0), Vec::<u64>::new());
+        assert_eq!(ids(10), vec![pinned, overdue, tomorrow, next_week, far]);
+    }
+
+    #[test]
+    fn test_pending_reminders() {
+        let conn = setup_test_db();
+        let now = Local.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+        let add = |title: &str, deadline: &str, reminder: u32| {
+            db_insert_todo(
+                &conn,
+                NewTodo {
+                    title: title.to_string(),
+                    deadline: Some(deadline.to_string()),
+                    reminder_minutes: Some(reminder),
+                    ..NewTodo::default()
+                },
+            )
+            .unwrap()
+        };
+        let due = add("Due", "2024-05-01T12:30", 60);
+        add("Not yet", "2024-05-01T14:00", 60);
+        add("Passed", "2024-05-01T11:00", 120);
+        let done = add("Done", "2024-05-01T12:10", 30);
+        db_update_todo(&conn, done.id, None, Some(true), None, None);
+        db_add_todo(
+            &conn,
+            "No reminder".to_string(),
+            Some("2024-05-01T12:05".to_string()),
+        );
+
+        let ids: Vec<u64> = db_get_pending_reminders(&conn, now)
+            .iter()
+            .map(|t| t.id)
+            .collect();
+        assert_eq!(ids, vec![due.id]);
+
+        // The reminder is cleared with 0 minutes.
+        let cleared = TodoChanges {
+            reminder_minutes: Some(0),
+            ..TodoChanges::default()
+        };
+        let todo = db_update_todo_at_version(&conn, due.id, None, &cleared)
+            .into_result(due.id)
+            .unwrap();
+        assert_eq!(todo.reminder_minutes, None);
+        assert!(db_get_pending_reminders(&conn, now).is_empty());
+    }
+
+    #[test]
+    fn test_dedup_tags_collapses_case_variants() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, "Tagged".to_string(), None);
+        let other = db_add_todo(&conn, "Other".to_string(), None);
+        db_add_tag(&conn, todo.id, "Work");
+        db_add_tag(&conn, todo.id, "home");
+        // Tag names are unique only case-sensitively, so these can pile up.
+        for name in ["work", "WORK"] {
+            let tag_id = db_get_or_create_tag(&conn, name);
+            for id in [todo.id, other.id] {
+                conn.execute(
+                    "INSERT INTO todo_tags (todo_id, tag_id) VALUES (?1, ?2)",
+                    (id, tag_id),
+                )
+                .unwrap();
+            }
+        }
+
+        assert_eq!(db_dedup_tags(&conn, todo.id), 2);
+        assert_eq!(db_get_tags(&conn, todo.id), vec!["Work", "home"]);
+        assert_eq!(db_dedup_tags(&conn, todo.id), 0);
+        // Other todos are left alone.
+        assert_eq!(db_get_tags(&conn, other.id), vec!["WORK", "work"]);
+    }
+
+    #[test]
+    fn test_enrich_deadline_fields() {
+        let base = db_add_todo(&setup_test_db(), "t".to_string(), None);
+        let at = |h, m, sec| Local.with_ymd_and_hms(2024, 5, 1, h, m, sec).unwrap();
+        let fields = |deadline: Option<&str>, completed, now| {
+            let todo = enrich(
+                Todo {
+                    deadline: deadline.map(str::to_string),
+                    completed,
+                    ..base.clone()
+                },
+                now,
+            );
+            (todo.is_overdue, todo.is_due_today, todo.days_until_deadline)
+        };
+
+        assert_eq!(fields(None, false, at(12, 0, 0)), (false, false, None));
+        // A date-only deadline lasts the whole day.
+        assert_eq!(
+            fields(Some("2024-05-01"), false, at(23, 59, 0)),
+            (false, true, Some(0))
+        );
+        assert_eq!(
+            fields(Some("2024-04-30"), false, at(0, 0, 0)),
+            (true, false, Some(-1))
+        );
+        // A timed deadline is overdue from that minute, and still due today.
+        assert_eq!(
+            fields(Some("2024-05-01T23:59"), false, at(23, 58, 59)),
+            (false, true, Some(0))
+        );
+        assert_eq!(
+            fields(Some("2024-05-01T23:59"), false, at(23, 59, 30)),
+            (true, true, Some(0))
+        );
+        assert_eq!(
+            fields(Some("2024-05-02T00:00"), false, at(23, 59, 59)),
+            (false, false, Some(1))
+        );
+        // Completed todos are neither, but keep their distance to the deadline.
+        assert_eq!(
+            fields(Some("2024-04-20"), true, at(12, 0, 0)),
+            (false, false, Some(-11))
+        );
+    }
+
+    #[test]
+    fn test_enrich_matches_filters_and_is_not_deserialized() {
+        let conn = setup_test_db();
+        let now = Local.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+        for deadline in ["2024-04-30", "2024-05-01", "2024-05-01T09:00", "2024-05-02"] {
+            db_add_todo(&conn, deadline.to_string(), Some(deadline.to_string()));
+        }
+        let todos = enrich_all(db_get_todos(&conn), now);
+        let ids = |keep: fn(&Todo) -> bool| -> Vec<u64> {
+            todos.iter().filter(|t| keep(t)).map(|t| t.id).collect()
+        };
+        let overdue: Vec<u64> = db_get_overdue_todos(&conn, now)
+            .iter()
+            .map(|t| t.id)
+            .collect();
+        let mut due_today: Vec<u64> = db_get_due_today(&conn, now).iter().map(|t| t.id).collect();
+        due_today.sort();
+        assert_eq!(ids(|t| t.is_overdue), overdue);
+        assert_eq!(ids(|t| t.is_due_today), due_today);
+
+        let json = serde_json::to_value(&todos[0]).unwrap();
+        assert_eq!(json["is_overdue"], true);
+        assert_eq!(json["days_until_deadline"], -1);
+        let read_back: Todo = serde_json::from_value(json).unwrap();
+        assert!(!read_back.is_overdue);
+        assert_eq!(read_back.days_until_deadline, None);
+    }
+
+    #[test]
+    fn test_modified_since_returns_only_later_writes() {
+        let conn = setup_test_db();
+        let ids: Vec<u64> = (1..=4)
+            .map(|i| db_add_todo(&conn, format!("Todo {}", i), None).id)
+            .collect();
+        conn.execute(
+            "UPDATE todos SET updated_at = '2024-01-01T00:00:00+00:00'",
+            [],
+        )
+        .unwrap();
+        let cutoff = "2024-06-01T02:00:00+02:00";
+        assert!(db_get_modified_since(&conn, cutoff).is_empty());
+
+        db_update_todo(&conn, ids[2], Some("Renamed".to_string()), None, None, None);
+        db_set_pinned(&conn, ids[0], true);
+        db_delete_todo(&conn, ids[3]);
+
+        let modified = db_get_modified_since(&conn, cutoff);
+        let mut modified_ids: Vec<u64> = modified.iter().map(|t| t.id).collect();
+        modified_ids.sort();
+        assert_eq!(modified_ids, vec![ids[0], ids[2]]);
+        assert!(modified
+            .windows(2)
+            .all(|w| w[0].updated_at <= w[1].updated_at));
+        let deleted = db_get_deleted_since(&conn, cutoff);
+        assert_eq!(
+            deleted.iter().map(|d| d.id).collect::<Vec<_>>(),
+            vec![ids[3]]
+        );
+        // The latest write is the next cursor.
+        assert!(db_get_modified_since(&conn, &modified.last().unwrap().updated_at).is_empty());
+        assert!(db_get_deleted_since(&conn, &deleted[0].deleted_at).is_empty());
+        assert!(db_get_modified_since(&conn, "yesterday").is_empty());
+    }
+
+    /// A database with only the first `version` migrations applied.
+    fn migrated_to(version: usize) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        for (i, sql) in MIGRATIONS[..version].iter().enumerate() {
+            conn.execute_batch(sql).unwrap();
+            conn.pragma_update(None, "user_version", i + 1).unwrap();
+        }
+        conn
+    }
+
+    #[test]
+    fn test_deleting_todo_removes_tag_links() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, "Tagged".to_string(), None);
+        let other = db_add_todo(&conn, "Other".to_string(), None);
+        db_add_tag(&conn, todo.id, "work");
+        db_add_tag(&conn, todo.id, "home");
+        db_add_tag(&conn, other.id, "work");
+
+        assert!(db_delete_todo(&conn, todo.id));
+
+        let links: Vec<u64> = conn
+            .prepare("SELECT todo_id FROM todo_tags")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .map(|id| id.unwrap())
+            .collect();
+        assert_eq!(links, vec![other.id]);
+        assert_eq!(db_get_tags(&conn, other.id), vec!["work"]);
+    }
+
+    #[test]
+    fn test_tag_link_migration_drops_orphans() {
+        let conn = migrated_to(16);
+        conn.execute_batch(
+            "PRAGMA foreign_keys = OFF;
+             INSERT INTO todos (id, title, completed, created_at) VALUES (1, 't', 0, '2024-01-01');
+             INSERT INTO tags (id, name) VALUES (1, 'work');
+             INSERT INTO todo_tags (todo_id, tag_id) VALUES (1, 1), (2, 1);",
+        )
+        .unwrap();
+
+        init_db(&conn);
+
+        let count: u32 = conn
+            .query_row("SELECT COUNT(*) FROM todo_tags", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 1);
+        let enabled: bool = conn
+            .query_row("PRAGMA foreign_keys", [], |row| row.get(0))
+            .unwrap();
+        assert!(enabled);
+        assert!(db_delete_todo(&conn, 1));
+        assert_eq!(db_get_tags(&conn, 1), Vec::<String>::new());
+        assert_eq!(maintenance::check_integrity(&conn), Ok("ok".to_string()));
+    }
+
+    #[test]
+    fn test_migration_normalizes_deadlines_to_utc() {
+        let conn = migrated_to(15);
+        let deadlines = [
+            "2024-05-01",
+            "2024-05-01T10:30",
+            "2024-05-01T10:30:15",
+            "2024-05-01T10:30:00+02:00",
+            "2024-05-01T23:30:00Z",
+            "garbage",
+        ];
+        for deadline in deadlines {
+            conn.execute(
+                "INSERT INTO todos (title, completed, created_at, deadline)
+                 VALUES ('t', 0, '2024-01-01', ?1)",
+                (deadline,),
+            )
+            .unwrap();
+        }
+
+        init_db(&conn);
+
+        let stored: Vec<String> = db_get_todos(&conn)
+            .into_iter()
+            .map(|t| t.deadline.unwrap())
+            .collect();
+        let local = |naive: &str| deadline::normalize(naive, &Local).unwrap();
+        assert_eq!(
+            stored,
+            vec![
+                "2024-05-01".to_string(),
+                local("2024-05-01T10:30"),
+                local("2024-05-01T10:30:15"),
+                "2024-05-01T08:30:00+00:00".to_string(),
+                "2024-05-01T23:30:00+00:00".to_string(),
+                "garbage".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_deadline_consumers_agree() {
+        let conn = setup_test_db();
+        let now = Local.with_ymd_and_hms(2024, 5, 1, 23, 59, 0).unwrap();
+        let stored = |naive: &str| deadline::normalize(naive, &Local).unwrap();
+        let deadlines = [
+            "2024-04-30".to_string(),
+            "2024-05-01".to_string(),
+            "2024-05-02".to_string(),
+            stored("2024-05-01T00:00"),
+            stored("2024-05-01T23:58"),
+            stored("2024-05-01T23:59:30"),
+            stored("2024-05-02T00:00"),
+        ];
+        for deadline in &deadlines {
+            db_add_todo(&conn, deadline.clone(), Some(deadline.clone()));
+        }
+        let todos = db_get_todos(&conn);
+        let expected = |keep: fn(deadline::DeadlineStatus) -> bool| -> Vec<u64> {
+            todos
+                .iter()
+                .filter(|t| keep(deadline_status(t, now)))
+                .map(|t| t.id)
+                .collect()
+        };
+        let ids = |todos: Vec<Todo>| -> Vec<u64> {
+            let mut ids: Vec<u64> = todos.iter().map(|t| t.id).collect();
+            ids.sort();
+            ids
+        };
+        let overdue = expected(|s| s.overdue);
+        let due_today = expected(|s| s.due_today);
+        assert_eq!(overdue.len(), 3);
+        assert_eq!(due_today.len(), 4);
+
+        assert_eq!(ids(db_get_overdue_todos(&conn, now)), overdue);
+        assert_eq!(
+            ids(db_get_todos_filtered(&conn, Filter::Overdue, now)),
+            overdue
+        );
+        assert_eq!(ids(db_get_due_today(&conn, now)), due_today);
+        let enriched = enrich_all(todos.clone(), now);
+        assert_eq!(
+            ids(enriched.iter().filter(|t| t.is_overdue).cloned().collect()),
+            overdue
+        );
+        assert_eq!(
+            ids(enriched
+                .iter()
+                .filter(|t| t.is_due_today)
+                .cloned()
+                .collect()),
+            due_today
+        );
+        assert_eq!(
+            badge::badge_count(&todos, badge::BadgeMode::Overdue, now),
+            Some(overdue.len() as u64)
+        );
+        // The badge's due today includes earlier days.
+        assert_eq!(
+            badge::badge_count(&todos, badge::BadgeMode::DueToday, now),
+            Some(due_today.len() as u64 + 1)
+        );
+    }
+
+    #[test]
+    fn test_clear_completed_dry_run() {
+        let state = test_state();
+        let events = events::test_emitter::RecordingEmitter::default();
+        let (done, open) = {
+            let conn = state.db.lock().unwrap();
+            let done: Vec<u64> = (0..2)
+                .map(|i| {
+                    let id = db_add_todo(&conn, format!("done {}", i), None).id;
+                    db_set_completed_bulk(&conn, &[id], true);
+                    id
+                })
+                .collect();
+            (done, db_add_todo(&conn, "open".to_string(), None).id)
+        };
+
+        let mut would_remove = clear_completed_todos(&state, &events, true);
+        would_remove.sort();
+        assert_eq!(would_remove, done);
+        assert_eq!(db_get_todos(&state.db.lock().unwrap()).len(), 3);
+        assert!(events.take().is_empty());
+        assert!(!state.history.lock().unwrap().can_undo());
+
+        let mut removed = clear_completed_todos(&state, &events, false);
+        removed.sort();
+        assert_eq!(removed, done);
+        let left = db_get_todos(&state.db.lock().unwrap());
+        assert_eq!(left.iter().map(|t| t.id).collect::<Vec<_>>(), vec![open]);
+        assert_eq!(events.take().len(), 1);
+        assert!(clear_completed_todos(&state, &events, true).is_empty());
+    }
+
+    #[test]
+    fn test_archive_completed_dry_run() {
+        let state = test_state();
+        let events = events::test_emitter::RecordingEmitter::default();
+        let done = {
+            let conn = state.db.lock().unwrap();
+            db_add_todo(&conn, "open".to_string(), None);
+            let id = db_add_todo(&conn, "done".to_string(), None).id;
+            db_set_completed_bulk(&conn, &[id], true);
+            id
+        };
+
+        assert_eq!(archive_completed_todos(&state, &events, true), vec![done]);
+        {
+            let conn = state.db.lock().unwrap();
+            assert_eq!(db_get_todos(&conn).len(), 2);
+            assert!(db_get_archive(&conn).is_empty());
+        }
+        assert!(events.take().is_empty());
+
+        assert_eq!(archive_completed_todos(&state, &events, false), vec![done]);
+        let conn = state.db.lock().unwrap();
+        assert_eq!(db_get_todos(&conn).len(), 1);
+        assert_eq!(db_get_archive(&conn)[0].todo.id, done);
+    }
+
+    #[test]
+    fn test_count_by_priority() {
+        let conn = setup_test_db();
+        assert_eq!(db_count_by_priority(&conn), [0, 0, 0, 0]);
+        let add = |priority: u8, completed: bool| {
+            let todo = db_insert_todo(
+                &conn,
+                NewTodo {
+                    title: format!("p{}", priority),
+                    priority,
+                    ..NewTodo::default()
+                },
+            )
+            .unwrap();
+            if completed {
+                db_set_completed_bulk(&conn, &[todo.id], true);
+            }
+        };
+        for (priority, completed) in [
+            (0, false),
+            (0, false),
+            (1, false),
+            (3, false),
+            (3, false),
+            (3, false),
+            (2, true),
+            (1, true),
+            (7, false),
+        ] {
+            add(priority, completed);
+        }
+        db_archive_completed(&conn);
+        add(2, true);
+        assert_eq!(db_count_by_priority(&conn), [2, 1, 0, 4]);
+    }
+
+    #[test]
+    fn test_validate_new_todo_reports_every_failure() {
+        let new = NewTodo {
+            title: " \u{200B} ".to_string(),
+            deadline: Some("whenever".to_string()),
+            priority: MAX_PRIORITY + 1,
+            ..NewTodo::default()
+        };
+        let errors = validate_new_todo(new, MAX_TITLE_CHARS).unwrap_err();
+        let failed: Vec<(&str, &str)> = errors
+            .iter()
+            .map(|e| (e.field.as_str(), e.code.as_str()))
+            .collect();
+        assert_eq!(
+            failed,
+            [
+                ("title", validation::EMPTY),
+                ("deadline", validation::UNREADABLE),
+                ("priority", validation::OUT_OF_RANGE),
+            ]
+        );
+
+        let ok = validate_new_todo(
+            NewTodo {
+                title: "  Pay   rent ".to_string(),
+                deadline: Some(" 2024-06-01 ".to_string()),
+                estimate_minutes: Some(0),
+                ..NewTodo::default()
+            },
+            MAX_TITLE_CHARS,
+        )
+        .unwrap();
+        assert_eq!(ok.title, "Pay rent");
+        assert_eq!(ok.deadline.as_deref(), Some("2024-06-01"));
+        assert_eq!(ok.estimate_minutes, None);
+    }
+
+    #[test]
+    fn test_validate_update_reports_every_failure() {
+        let changes = TodoChanges {
+            title: Some("x".repeat(11)),
+            deadline: Some("soon".to_string()),
+            completed: Some(true),
+            ..TodoChanges::default()
+        };
+        let errors = validate_update(changes, 10).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].code, validation::TOO_LONG);
+        assert_eq!(errors[1].field, "deadline");
+
+        // Clearing the deadline and leaving the title alone are both fine.
+        let changes = TodoChanges {
+            deadline: Some(String::new()),
+            ..TodoChanges::default()
+        };
+        assert_eq!(
+            validate_update(changes, 10).unwrap().deadline.as_deref(),
+            Some("")
+        );
+    }
+
+    #[test]
+    fn test_validation_error_json_shape() {
+        let errors = validate_new_todo(
+            NewTodo {
+                title: String::new(),
+                deadline: Some("2024-13-01".to_string()),
+                ..NewTodo::default()
+            },
+            MAX_TITLE_CHARS,
+        )
+        .unwrap_err();
+        assert_eq!(
+            serde_json::to_value(TodoError::Validation(errors)).unwrap(),
+            serde_json::json!({
+                "kind": "validation",
+                "message": [
+                    {"field": "title", "code": "empty", "message": "Title is empty"},
+                    {
+                        "field": "deadline",
+                        "code": "unreadable",
+                        "message": "Invalid deadline '2024-13-01'"
+                    }
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn test_reschedule_overdue() {
+        let conn = setup_test_db();
+        let now = Local.with_ymd_and_hms(2024, 5, 10, 15, 0, 0).unwrap();
+        let add = |title: &str, deadline: Option<String>| {
+            db_add_todo(&conn, title.to_string(), deadline).id
+        };
+        let last_week = add("Last week", Some("2024-05-03".to_string()));
+        let this_morning = add(
+            "This morning",
+            deadline::normalize("2024-05-10T09:00", &Local),
+        );
+        let today = add("Today", Some("2024-05-10".to_string()));
+        let tonight = add("Tonight", deadline::normalize("2024-05-10T20:00", &Local));
+        let next_week = add("Next week", Some("2024-05-17".to_string()));
+        let undated = add("Undated", None);
+        let done = add("Done", Some("2024-05-01".to_string()));
+        db_set_completed_bulk(&conn, &[done], true);
+        let before: Vec<Todo> = [today, tonight, next_week, undated, done]
+            .iter()
+            .map(|&id| db_get_todo(&conn, id).unwrap())
+            .collect();
+
+        assert_eq!(db_reschedule_overdue(&conn, now), 2);
+
+        for id in [last_week, this_morning] {
+            assert_eq!(
+                db_get_todo(&conn, id).unwrap().deadline.as_deref(),
+                Some("2024-05-10")
+            );
+        }
+        for todo in before {
+            assert_eq!(db_get_todo(&conn, todo.id).unwrap(), todo);
+        }
+        assert_eq!(db_reschedule_overdue(&conn, now), 0);
+    }
+
+    #[test]
+    fn test_index_of() {
+        let conn = setup_test_db();
+        let add = |title: &str, deadline: Option<&str>| {
+            db_add_todo(&conn, title.to_string(), deadline.map(String::from)).id
+        };
+        let may = add("May", Some("2024-05-01"));
+        let undated = add("Undated", None);
+        let april = add("April", Some("2024-04-01"));
+        let done = add("Done", Some("2024-03-01"));
+        db_set_completed_bulk(&conn, &[done], true);
+        let pinned = add("Pinned", Some("2024-12-01"));
+        db_set_pinned(&conn, pinned, true);
+
+        let index = |id, sort, dir| db_index_of(&conn, id, sort, dir);
+        // Pinned, then April, May, the undated one and the completed one.
+        assert_eq!(index(pinned, SortBy::Deadline, SortDir::Asc), Some(0));
+        assert_eq!(index(april, SortBy::Deadline, SortDir::Asc), Some(1));
+        assert_eq!(index(may, SortBy::Deadline, SortDir::Asc), Some(2));
+        assert_eq!(index(undated, SortBy::Deadline, SortDir::Asc), Some(3));
+        assert_eq!(index(done, SortBy::Deadline, SortDir::Asc), Some(4));
+        // Undated todos stay last when the direction flips.
+        assert_eq!(index(may, SortBy::Deadline, SortDir::Desc), Some(1));
+        assert_eq!(index(undated, SortBy::Deadline, SortDir::Desc), Some(3));
+        assert_eq!(index(999, SortBy::Deadline, SortDir::Asc), None);
+    }
+
+    #[test]
+    fn test_null_created_at_is_tolerated() {
+        logging::test_logger::install();
+        // A table from before `created_at` was required.
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE todos (
+                 id INTEGER PRIMARY KEY AUTOINCREMENT,
+                 title TEXT NOT NULL,
+                 completed BOOLEAN NOT NULL DEFAULT 0,
+                 created_at TEXT,
+                 deadline TEXT
+             );
+             INSERT INTO todos (title, created_at) VALUES ('Legacy row', NULL);",
+        )
+        .unwrap();
+        init_db(&conn);
+
+        let todos = db_get_todos(&conn);
+
+        assert_eq!(todos.len(), 1);
+        assert_eq!(todos[0].title, "Legacy row");
+        assert_eq!(
+            todos[0].created_at,
+            local_timestamp(MISSING_TIMESTAMP.to_string())
+        );
+        assert_eq!(todos[0].updated_at, todos[0].created_at);
+        let id = todos[0].id;
+        assert!(!logging::test_logger::records_containing(&format!(
+            "todo {} has no created_at",
+            id
+        ))
+        .is_empty());
+    }
+
+    #[cfg(feature = "sqlcipher")]
+    #[test]
+    fn test_unlock_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("todos.db");
+        {
+            let mut conn = Connection::open(&path).unwrap();
+            init_db(&conn);
+            db_add_todo(&conn, "Renew passport".to_string(), None);
+            encryption::enable_encryption(&mut conn, Some(&path), "hunter2").unwrap();
+        }
+        let mut state = test_state();
+        state.database.get_mut().unwrap().path = Some(path);
+        state.lock = encryption::Lock::new(true);
+        let events = events::test_emitter::RecordingEmitter::default();
+
+        assert_eq!(
+            db_unlock(&state, &events, "hunter3"),
+            Err(encryption::EncryptionError::WrongPassphrase)
+        );
+        assert!(state.lock.is_locked());
+        assert_eq!(
+            state.lock.check("get_todos"),
+            Err(encryption::EncryptionError::Locked)
+        );
+        assert!(events.take().is_empty());
+
+        db_unlock(&state, &events, "hunter2").unwrap();
+        assert!(!state.lock.is_locked());
+        assert_eq!(state.lock.passphrase().as_deref(), Some("hunter2"));
+        assert_eq!(state.lock.check("get_todos"), Ok(()));
+        let titles: Vec<String> = db_get_todos(&state.db.lock().unwrap())
+            .into_iter()
+            .map(|t| t.title)
+            .collect();
+        assert_eq!(titles, ["Renew passport"]);
+        assert_eq!(events.take(), vec![TodosChanged::reloaded()]);
+    }
+
+    #[test]
+    fn test_switch_profile_keeps_data_apart() {
+        let app_dir = tempfile::tempdir().unwrap();
+        let dir = app_dir.path();
+        let home = profiles::create(dir, "Home").unwrap();
+        profiles::create(dir, "Work").unwrap();
+        let mut state = test_state();
+        let events = events::test_emitter::RecordingEmitter::default();
+        assert!(db_switch_profile(&state, &events, dir, "Home")
+            .unwrap_err()
+            .contains("this run only"));
+
+        *state.database.get_mut().unwrap() =
+            profiles::choice_for(dir, &home, &db_location::main_database(dir));
+        db_switch_profile(&state, &events, dir, "Home").unwrap();
+        db_add_todo(&state.db.lock().unwrap(), "Water plants".to_string(), None);
+        state.history.lock().unwrap().push(undo::Change::created(1));
+
+        let work = db_switch_profile(&state, &events, dir, "work").unwrap();
+        assert_eq!(work.name, "Work");
+        assert!(db_get_todos(&state.db.lock().unwrap()).is_empty());
+        assert!(!state.history.lock().unwrap().can_undo());
+        db_add_todo(&state.db.lock().unwrap(), "File report".to_string(), None);
+        assert_eq!(profiles::load(dir).active, "Work");
+        assert_eq!(
+            state.database.lock().unwrap().path,
+            Some(dir.join(profiles::PROFILES_DIR).join("work.db"))
+        );
+
+        db_switch_profile(&state, &events, dir, "Home").unwrap();
+        let titles: Vec<String> = db_get_todos(&state.db.lock().unwrap())
+            .into_iter()
+            .map(|t| t.title)
+            .collect();
+        assert_eq!(titles, ["Water plants"]);
+        assert_eq!(events.take(), vec![TodosChanged::reloaded(); 3]);
+        assert!(db_switch_profile(&state, &events, dir, "Nobody").is_err());
+        assert!(profiles::delete(dir, "Home", true).is_err());
+    }
+
+    #[test]
+    fn test_uuid_follows_todo_through_archive_and_undo() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, "Keep my uuid".to_string(), None);
+        let uuid = |conn: &Connection| -> Option<String> {
+            conn.query_row(
+                "SELECT uuid FROM todos WHERE title = 'Keep my uuid'",
+                [],
+                |row| row.get(0),
+            )
+            .optional()
+            .unwrap()
+            .flatten()
+        };
+        let original = uuid(&conn).unwrap();
+        assert_eq!(original.len(), 36);
+        assert_eq!(&original[14..15], "4");
+        assert_ne!(db_add_todo(&conn, "Other".to_string(), None).id, todo.id);
+
+        db_set_completed_bulk(&conn, &[todo.id], true);
+        db_archive_completed(&conn);
+        assert_eq!(uuid(&conn), None);
+        db_unarchive_todo(&conn, todo.id).unwrap();
+        assert_eq!(uuid(&conn).as_ref(), Some(&original));
+
+        let (_, change) = undo::record(&conn, &[todo.id], |conn| db_delete_todo(conn, todo.id));
+        let mut history = undo::UndoHistory::default();
+        history.push(change);
+        history.undo(&conn).unwrap();
+        assert_eq!(uuid(&conn).as_ref(), Some(&original));
+
+        // A todo that took the uuid meanwhile keeps it; the archived one
+        // comes back with a new one.
+        db_set_completed_bulk(&conn, &[todo.id], true);
+        db_archive_completed(&conn);
+        conn.execute(
+            "INSERT INTO todos (title, created_at, uuid) VALUES ('Imported', '', ?1)",
+            [&original],
+        )
+        .unwrap();
+        db_unarchive_todo(&conn, todo.id).unwrap();
+        assert_ne!(uuid(&conn), Some(original));
+        assert!(uuid(&conn).is_some());
+    }
+
+    #[test]
+    fn test_http_api_over_a_socket() {
+        use std::io::{Read, Write};
+        let state: &'static AppState = Box::leak(Box::new(test_state()));
+        let events: &'static events::test_emitter::RecordingEmitter = Box::leak(Box::default());
+        let server =
+            http_api::Server::start(0, move |request| api_request(state, events, request)).unwrap();
+        assert!(server.addr().ip().is_loopback());
+        let token = http_api::token(&state.db.lock().unwrap());
+        let send = |method: &str, target: &str, token: Option<&str>, body: &str| {
+            let mut stream = std::net::TcpStream::connect(server.addr()).unwrap();
+            let auth = token
+                .map(|t| format!("Authorization: Bearer {}\r\n", t))
+                .unwrap_or_default();
+            write!(
+                stream,
+                "{} {} HTTP/1.1\r\nHost: localhost\r\n{}Content-Length: {}\r\n\r\n{}",
+                method,
+                target,
+                auth,
+                body.len(),
+                body
+            )
+            .unwrap();
+            let mut reply = String::new();
+            stream.read_to_string(&mut reply).unwrap();
+            let (head, body) = reply.split_once("\r\n\r\n").unwrap();
+            let status: u16 = head.split(' ').nth(1).unwrap().parse().unwrap();
+            let body = match body {
+                "" => serde_json::Value::Null,
+                body => serde_json::from_str(body).unwrap(),
+            };
+            (status, body)
+        };
+        let token = Some(token.as_str());
+
+        assert_eq!(send("GET", "/todos", None, "").0, 401);
+        assert_eq!(send("GET", "/todos", Some("nope"), "").0, 401);
+        assert!(events.take().is_empty());
+
+        let (status, added) = send("POST", "/todos", token, r#"{"title": "Buy milk"}"#);
+        assert_eq!(status, 201);
+        assert_eq!(added["title"], "Buy milk");
+        let id = added["id"].as_u64().unwrap();
+        send("POST", "/todos", token, r#"{"title": "Call mom"}"#);
+        assert_eq!(send("POST", "/todos", token, r#"{"title": ""}"#).0, 422);
+        assert_eq!(send("POST", "/todos", token, "{").0, 400);
+
+        let (status, list) = send("GET", "/todos?text=milk", token, "");
+        assert_eq!(status, 200);
+        assert_eq!(list.as_array().unwrap().len(), 1);
+        assert_eq!(list[0]["id"], id);
+
+        let version = added["version"].as_u64().unwrap();
+        let patch = format!(r#"{{"expected_version": {}, "completed": true}}"#, version);
+        let (status, updated) = send("PATCH", &format!("/todos/{}", id), token, &patch);
+        assert_eq!(status, 200);
+        assert_eq!(updated["completed"], true);
+        let (status, stale) = send("PATCH", &format!("/todos/{}", id), token, &patch);
+        assert_eq!(status, 409);
+        assert_eq!(stale["kind"], "conflict");
+        let (_, active) = send("GET", "/todos?filter=active", token, "");
+        assert_eq!(active.as_array().unwrap().len(), 1);
+
+        assert_eq!(send("DELETE", &format!("/todos/{}", id), token, "").0, 204);
+        assert_eq!(send("DELETE", &format!("/todos/{}", id), token, "").0, 404);
+        assert_eq!(send("GET", "/lists", token, "").0, 404);
+        assert_eq!(send("PUT", "/todos", token, "").0, 405);
+
+        assert_eq!(
+            events.take(),
+            vec![
+                TodosChanged::added(vec![id]),
+                TodosChanged::added(vec![id + 1]),
+                TodosChanged::updated(vec![id]),
+                TodosChanged::deleted(vec![id]),
+            ]
+        );
+        // API changes can be undone like any other.
+        assert!(state.history.lock().unwrap().can_undo());
+    }
+
+    #[test]
+    fn test_deadline_notifications_hide_titles_while_locked() {
+        let state = test_state();
+        let now = parse_deadline("2024-06-07T19:30").unwrap();
+        for (title, deadline) in [
+            ("Call the bank", "2024-06-07T19:40"),
+            ("Pay rent", "2024-06-07T19:35"),
+        ] {
+            db_add_todo(
+                &state.db.lock().unwrap(),
+                title.to_string(),
+                Some(deadline.to_string()),
+            );
+        }
+        let notifier = notifications::test_notifier::RecordingNotifier::default();
+        state.app_lock.configure(true, 0, true, Instant::now());
+
+        assert_eq!(check_deadlines(&state, &notifier, now), Ok(2));
+        let titles: Vec<String> = notifier
+            .take()
+            .into_iter()
+            .map(|(title, _)| title)
+            .collect();
+        assert_eq!(
+            titles,
+            [notifications::HIDDEN_TITLE, notifications::HIDDEN_TITLE]
+        );
     }
 }
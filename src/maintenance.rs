@@ -0,0 +1,246 @@
+use rusqlite::Connection;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct DbInfo {
+    /// Number of todos, the figure most screens want from `row_counts`.
+    pub row_count: u64,
+    pub file_bytes: u64,
+    pub page_count: u64,
+    pub page_size: u64,
+    pub freelist_pages: u64,
+    pub schema_version: u64,
+    pub row_counts: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct MaintenanceReport {
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+    pub reclaimed_bytes: u64,
+}
+
+fn pragma_u64(conn: &Connection, name: &str) -> rusqlite::Result<u64> {
+    conn.query_row(&format!("PRAGMA {}", name), [], |row| row.get(0))
+}
+
+/// Size of the database on disk. In-memory databases have no file, so their
+/// size is derived from the page count instead.
+fn file_bytes(conn: &Connection, page_count: u64, page_size: u64) -> u64 {
+    conn.path()
+        .filter(|p| !p.is_empty())
+        .and_then(|p| std::fs::metadata(p).ok())
+        .map_or(page_count * page_size, |m| m.len())
+}
+
+pub fn get_db_info(conn: &Connection) -> Result<DbInfo, String> {
+    let to_string = |e: rusqlite::Error| e.to_string();
+    let page_count = pragma_u64(conn, "page_count").map_err(to_string)?;
+    let page_size = pragma_u64(conn, "page_size").map_err(to_string)?;
+
+    let mut stmt = conn
+        .prepare("SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%'")
+        .map_err(to_string)?;
+    let tables: Vec<String> = stmt
+        .query_map([], |row| row.get(0))
+        .map_err(to_string)?
+        .collect::<rusqlite::Result<_>>()
+        .map_err(to_string)?;
+    let mut row_counts = BTreeMap::new();
+    for table in tables {
+        let sql = format!("SELECT COUNT(*) FROM \"{}\"", table.replace('"', "\"\""));
+        let count = conn
+            .query_row(&sql, [], |row| row.get(0))
+            .map_err(to_string)?;
+        row_counts.insert(table, count);
+    }
+
+    Ok(DbInfo {
+        row_count: row_counts.get("todos").copied().unwrap_or(0),
+        file_bytes: file_bytes(conn, page_count, page_size),
+        page_count,
+        page_size,
+        freelist_pages: pragma_u64(conn, "freelist_count").map_err(to_string)?,
+        schema_version: pragma_u64(conn, "user_version").map_err(to_string)?,
+        row_counts,
+    })
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct CompactReport {
+    pub pages_before: u64,
+    pub pages_after: u64,
+}
+
+/// Runs `VACUUM` on its own. SQLite refuses to vacuum inside a transaction, so
+/// this checks up front and reports a clear error instead.
+pub fn compact_database(conn: &Connection) -> Result<CompactReport, String> {
+    if !conn.is_autocommit() {
+        return Err("Cannot compact the database while a transaction is open".to_string());
+    }
+    let pages_before = pragma_u64(conn, "page_count").map_err(|e| e.to_string())?;
+    conn.execute_batch("VACUUM").map_err(|e| e.to_string())?;
+    let pages_after = pragma_u64(conn, "page_count").map_err(|e| e.to_string())?;
+    Ok(CompactReport {
+        pages_before,
+        pages_after,
+    })
+}
+
+/// Checkpoints the WAL (a no-op outside WAL mode), then runs `VACUUM` and
+/// `ANALYZE`. Reports how much smaller the file got.
+pub fn maintain_database(conn: &Connection) -> Result<MaintenanceReport, String> {
+    let bytes_before = get_db_info(conn)?.file_bytes;
+    conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))
+        .map_err(|e| e.to_string())?;
+    conn.execute_batch("VACUUM; ANALYZE;")
+        .map_err(|e| e.to_string())?;
+    let bytes_after = get_db_info(conn)?.file_bytes;
+    Ok(MaintenanceReport {
+        bytes_before,
+        bytes_after,
+        reclaimed_bytes: bytes_before.saturating_sub(bytes_after),
+    })
+}
+
+fn integrity_problems(conn: &Connection) -> rusqlite::Result<Vec<String>> {
+    let mut problems: Vec<String> = conn
+        .prepare("PRAGMA integrity_check")?
+        .query_map([], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    problems.retain(|problem| problem != "ok");
+    let mut stmt = conn.prepare("PRAGMA foreign_key_check")?;
+    let violations = stmt.query_map([], |row| {
+        let table: String = row.get(0)?;
+        let rowid: Option<i64> = row.get(1)?;
+        let parent: String = row.get(2)?;
+        Ok(match rowid {
+            Some(rowid) => format!("{} row {} has no matching row in {}", table, rowid, parent),
+            None => format!("{} has a row with no matching row in {}", table, parent),
+        })
+    })?;
+    for violation in violations {
+        problems.push(violation?);
+    }
+    Ok(problems)
+}
+
+/// Runs `PRAGMA integrity_check` and `PRAGMA foreign_key_check`. Returns
+/// `"ok"` if neither finds anything, or every problem they report; a check
+/// that can't run at all is reported as a problem too.
+pub fn check_integrity(conn: &Connection) -> Result<String, Vec<String>> {
+    match integrity_problems(conn) {
+        Ok(problems) if problems.is_empty() => Ok("ok".to_string()),
+        Ok(problems) => Err(problems),
+        Err(e) => Err(vec![e.to_string()]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db_add_tag, db_add_todo, init_db, setup_test_db, MIGRATIONS};
+
+    #[test]
+    fn test_db_info_counts_rows_per_table() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, "One".to_string(), None);
+        db_add_todo(&conn, "Two".to_string(), None);
+        db_add_tag(&conn, todo.id, "work");
+        // Migrations that rebuild a table leave free pages behind.
+        compact_database(&conn).unwrap();
+
+        let info = get_db_info(&conn).unwrap();
+
+        assert_eq!(info.row_count, 2);
+        assert_eq!(info.row_counts["todos"], 2);
+        assert_eq!(info.row_counts["tags"], 1);
+        assert_eq!(info.row_counts["todo_tags"], 1);
+        assert_eq!(info.row_counts["settings"], 0);
+        assert_eq!(info.schema_version, MIGRATIONS.len() as u64);
+        assert_eq!(info.file_bytes, info.page_count * info.page_size);
+        assert_eq!(info.freelist_pages, 0);
+    }
+
+    #[test]
+    fn test_vacuum_shrinks_file_after_deletes() {
+        let dir = tempfile::tempdir().unwrap();
+        let conn = Connection::open(dir.path().join("todos.db")).unwrap();
+        init_db(&conn);
+        let tx = conn.unchecked_transaction().unwrap();
+        for i in 0..2000 {
+            db_add_todo(
+                &tx,
+                format!("Todo number {} with some padding text", i),
+                None,
+            );
+        }
+        tx.commit().unwrap();
+        conn.execute("DELETE FROM todos", []).unwrap();
+
+        let before = get_db_info(&conn).unwrap();
+        assert!(before.freelist_pages > 0);
+
+        let report = maintain_database(&conn).unwrap();
+
+        let after = get_db_info(&conn).unwrap();
+        assert_eq!(after.freelist_pages, 0);
+        assert!(after.page_count < before.page_count);
+        assert_eq!(report.bytes_before, before.file_bytes);
+        assert_eq!(report.bytes_after, after.file_bytes);
+        assert!(report.reclaimed_bytes > 0);
+    }
+
+    #[test]
+    fn test_compact_does_not_grow_page_count() {
+        let conn = setup_test_db();
+        let tx = conn.unchecked_transaction().unwrap();
+        for i in 0..2000 {
+            db_add_todo(&tx, format!("Todo {} padded out a little", i), None);
+        }
+        tx.commit().unwrap();
+        conn.execute("DELETE FROM todos", []).unwrap();
+
+        let report = compact_database(&conn).unwrap();
+
+        assert!(report.pages_after < report.pages_before);
+        assert_eq!(get_db_info(&conn).unwrap().page_count, report.pages_after);
+    }
+
+    #[test]
+    fn test_integrity_check_on_healthy_database() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, "One".to_string(), None);
+        db_add_tag(&conn, todo.id, "work");
+
+        assert_eq!(check_integrity(&conn), Ok("ok".to_string()));
+    }
+
+    #[test]
+    fn test_integrity_check_reports_foreign_key_violations() {
+        let conn = setup_test_db();
+        conn.execute_batch(
+            "PRAGMA foreign_keys = OFF;
+             CREATE TABLE parents (id INTEGER PRIMARY KEY);
+             CREATE TABLE children (parent_id INTEGER REFERENCES parents (id));
+             INSERT INTO children (rowid, parent_id) VALUES (7, 1);",
+        )
+        .unwrap();
+
+        assert_eq!(
+            check_integrity(&conn),
+            Err(vec![
+                "children row 7 has no matching row in parents".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn test_compact_refuses_inside_transaction() {
+        let conn = setup_test_db();
+        let _tx = conn.unchecked_transaction().unwrap();
+
+        assert!(compact_database(&conn).is_err());
+    }
+}
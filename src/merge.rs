@@ -0,0 +1,481 @@
+use crate::bundle::{self, Bundle, BundleTodo};
+use crate::{db_add_tag, now_utc};
+use chrono::DateTime;
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// The fields compared and copied between the two sides, by their names in
+/// the bundle. `tags` is the todo's sorted tag names.
+const MERGED_FIELDS: &[&str] = &[
+    "title",
+    "completed",
+    "deadline",
+    "description",
+    "priority",
+    "completed_at",
+    "pinned",
+    "color",
+    "estimate_minutes",
+    "reminder_minutes",
+    "tags",
+];
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeSide {
+    Local,
+    Remote,
+}
+
+/// A field that differs between this database and the export. Without the
+/// version both started from, either side may have changed it, so each one
+/// is reported; the side written last wins.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct MergeConflict {
+    pub uuid: String,
+    pub title: String,
+    pub field: String,
+    pub local: Value,
+    pub remote: Value,
+    pub winner: MergeSide,
+}
+
+/// What `merge_export` did: todos only in the export were `inserted`, and
+/// `updated` local todos took the export's values. `archived` and `deleted`
+/// todos were left out because they were archived or deleted here.
+#[derive(Debug, Serialize, Clone, PartialEq, Default)]
+pub struct MergeReport {
+    pub inserted: usize,
+    pub updated: usize,
+    pub archived: usize,
+    pub deleted: usize,
+    pub conflicts: Vec<MergeConflict>,
+}
+
+/// The merged fields of each todo in `bundle`, by uuid.
+fn fields_by_uuid(bundle: &Bundle) -> HashMap<String, (BundleTodo, BTreeMap<String, Value>)> {
+    let tag_names: HashMap<u64, &str> = bundle
+        .tags
+        .iter()
+        .map(|tag| (tag.id, tag.name.as_str()))
+        .collect();
+    let mut tags: HashMap<u64, Vec<&str>> = HashMap::new();
+    for link in &bundle.todo_tags {
+        if let Some(name) = tag_names.get(&link.tag_id) {
+            tags.entry(link.todo_id).or_default().push(name);
+        }
+    }
+    bundle
+        .todos
+        .iter()
+        .filter_map(|todo| {
+            let uuid = todo.uuid.clone()?;
+            let Value::Object(mut all) = serde_json::to_value(todo).ok()? else {
+                return None;
+            };
+            let mut names = tags.remove(&todo.id).unwrap_or_default();
+            names.sort_unstable();
+            all.insert("tags".to_string(), Value::from(names));
+            let fields = MERGED_FIELDS
+                .iter()
+                .map(|&field| (field.to_string(), all.remove(field).unwrap_or(Value::Null)))
+                .collect();
+            Some((uuid, (todo.clone(), fields)))
+        })
+        .collect()
+}
+
+/// Whether `remote` was written after `local`. Unreadable or missing
+/// timestamps count as oldest, and a tie keeps the local side.
+fn remote_is_newer(local: &BundleTodo, remote: &BundleTodo) -> bool {
+    let parse = |todo: &BundleTodo| {
+        todo.updated_at
+            .as_deref()
+            .and_then(|stamp| DateTime::parse_from_rfc3339(stamp).ok())
+    };
+    parse(remote) > parse(local)
+}
+
+/// Merges the bundle `json`, exported on another machine, into `conn`, all
+/// in one transaction. Todos are matched by uuid: those only in the export
+/// are added, and for those on both sides the one written last wins, field
+/// by field. Todos only here are kept, so the other machine's deletions
+/// don't carry over, and todos deleted or archived here stay that way.
+pub fn merge_export(conn: &Connection, json: &str) -> Result<MergeReport, String> {
+    let remote = bundle::parse_bundle(json)?;
+    if remote.todos.iter().any(|todo| todo.uuid.is_none()) {
+        return Err(
+            "The export was made before todos had a uuid; export it again to merge it".to_string(),
+        );
+    }
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    let local = fields_by_uuid(&bundle::read_bundle(&tx).map_err(|e| e.to_string())?);
+    let archived = archived_uuids(&tx).map_err(|e| e.to_string())?;
+    let deleted = deleted_uuids(&tx).map_err(|e| e.to_string())?;
+    let mut remote: Vec<_> = fields_by_uuid(&remote).into_iter().collect();
+    // In the export's order, so ids are handed out predictably.
+    remote.sort_by_key(|(_, (todo, _))| todo.id);
+
+    let mut report = MergeReport::default();
+    for (uuid, (theirs, their_fields)) in remote {
+        if archived.contains(&uuid) {
+            report.archived += 1;
+            continue;
+        }
+        let Some((ours, our_fields)) = local.get(&uuid) else {
+            if deleted.contains(&uuid) {
+                report.deleted += 1;
+                continue;
+            }
+            insert(&tx, &theirs, &their_fields).map_err(|e| e.to_string())?;
+            report.inserted += 1;
+            continue;
+        };
+        let winner = if remote_is_newer(ours, &theirs) {
+            MergeSide::Remote
+        } else {
+            MergeSide::Local
+        };
+        let differing: Vec<&str> = MERGED_FIELDS
+            .iter()
+            .copied()
+            .filter(|&field| our_fields[field] != their_fields[field])
+            .collect();
+        if differing.is_empty() {
+            continue;
+        }
+        report
+            .conflicts
+            .extend(differing.iter().map(|&field| MergeConflict {
+                uuid: uuid.clone(),
+                title: ours.title.clone(),
+                field: field.to_string(),
+                local: our_fields[field].clone(),
+                remote: their_fields[field].clone(),
+                winner,
+            }));
+        if winner == MergeSide::Remote {
+            update(&tx, ours.id, &theirs, &their_fields).map_err(|e| e.to_string())?;
+            report.updated += 1;
+        }
+    }
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(report)
+}
+
+fn archived_uuids(conn: &Connection) -> rusqlite::Result<HashSet<String>> {
+    let mut stmt = conn.prepare_cached("SELECT uuid FROM todos_archive WHERE uuid IS NOT NULL")?;
+    let uuids = stmt.query_map([], |row| row.get(0))?;
+    uuids.collect()
+}
+
+fn deleted_uuids(conn: &Connection) -> rusqlite::Result<HashSet<String>> {
+    let mut stmt = conn.prepare_cached("SELECT uuid FROM deleted_todos WHERE uuid IS NOT NULL")?;
+    let uuids = stmt.query_map([], |row| row.get(0))?;
+    uuids.collect()
+}
+
+fn tag_names(fields: &BTreeMap<String, Value>) -> Vec<String> {
+    serde_json::from_value(fields["tags"].clone()).unwrap_or_default()
+}
+
+/// Adds `todo` under a new id, keeping its uuid and timestamps.
+fn insert(
+    conn: &Connection,
+    todo: &BundleTodo,
+    fields: &BTreeMap<String, Value>,
+) -> rusqlite::Result<()> {
+    conn.prepare_cached(
+        "INSERT INTO todos
+             (title, completed, created_at, deadline, description, priority, completed_at,
+              pinned, color, estimate_minutes, version, reminder_minutes, updated_at, uuid)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+    )?
+    .execute(params![
+        todo.title,
+        todo.completed,
+        todo.created_at.clone().unwrap_or_else(now_utc),
+        todo.deadline,
+        todo.description,
+        todo.priority,
+        todo.completed_at,
+        todo.pinned,
+        todo.color,
+        todo.estimate_minutes,
+        todo.version,
+        todo.reminder_minutes,
+        todo.updated_at,
+        todo.uuid,
+    ])?;
+    let id = conn.last_insert_rowid() as u64;
+    for tag in tag_names(fields) {
+        db_add_tag(conn, id, &tag);
+    }
+    Ok(())
+}
+
+/// Overwrites the local todo `id` with the merged fields of `todo`. The
+/// write bumps `version`, so a window editing the old copy is refused, but
+/// `updated_at` is set back to the export's, so merging the other way again
+/// finds both sides the same age.
+fn update(
+    conn: &Connection,
+    id: u64,
+    todo: &BundleTodo,
+    fields: &BTreeMap<String, Value>,
+) -> rusqlite::Result<()> {
+    conn.prepare_cached(
+        "UPDATE todos SET title = ?2, completed = ?3, deadline = ?4, description = ?5,
+             priority = ?6, completed_at = ?7, pinned = ?8, color = ?9, estimate_minutes = ?10,
+             reminder_minutes = ?11, version = version + 1
+         WHERE id = ?1",
+    )?
+    .execute(params![
+        id,
+        todo.title,
+        todo.completed,
+        todo.deadline,
+        todo.description,
+        todo.priority,
+        todo.completed_at,
+        todo.pinned,
+        todo.color,
+        todo.estimate_minutes,
+        todo.reminder_minutes,
+    ])?;
+    conn.prepare_cached("UPDATE todos SET updated_at = ?2 WHERE id = ?1")?
+        .execute(params![id, todo.updated_at])?;
+    conn.prepare_cached("DELETE FROM todo_tags WHERE todo_id = ?1")?
+        .execute((id,))?;
+    for tag in tag_names(fields) {
+        db_add_tag(conn, id, &tag);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db_add_todo, db_archive_completed, db_delete_todo, db_get_tags, db_get_todos,
+        db_set_completed_bulk, db_unarchive_todo, setup_test_db,
+    };
+
+    /// A second machine that started from a copy of `conn`.
+    fn copy_of(conn: &Connection) -> Connection {
+        let copy = setup_test_db();
+        bundle::import_bundle(&copy, &bundle::export_bundle(conn).unwrap()).unwrap();
+        copy
+    }
+
+    fn set_title(conn: &Connection, title: &str, new: &str, updated_at: &str) {
+        conn.execute(
+            "UPDATE todos SET title = ?2, version = version + 1 WHERE title = ?1",
+            (title, new),
+        )
+        .unwrap();
+        conn.execute(
+            "UPDATE todos SET updated_at = ?2 WHERE title = ?1",
+            (new, updated_at),
+        )
+        .unwrap();
+    }
+
+    fn titles(conn: &Connection) -> Vec<String> {
+        db_get_todos(conn).into_iter().map(|t| t.title).collect()
+    }
+
+    #[test]
+    fn test_merge_inserts_todos_only_in_the_export() {
+        let laptop = setup_test_db();
+        let desktop = copy_of(&laptop);
+        let todo = db_add_todo(&laptop, "Book flights".to_string(), None);
+        db_add_tag(&laptop, todo.id, "travel");
+        db_add_todo(&desktop, "Call the bank".to_string(), None);
+
+        let report = merge_export(&desktop, &bundle::export_bundle(&laptop).unwrap()).unwrap();
+        assert_eq!(
+            report,
+            MergeReport {
+                inserted: 1,
+                ..MergeReport::default()
+            }
+        );
+        let mut merged = titles(&desktop);
+        merged.sort();
+        assert_eq!(merged, ["Book flights", "Call the bank"]);
+        let inserted = db_get_todos(&desktop)
+            .into_iter()
+            .find(|t| t.title == "Book flights")
+            .unwrap();
+        assert_eq!(db_get_tags(&desktop, inserted.id), ["travel"]);
+
+        // Merging the same export again changes nothing.
+        let again = merge_export(&desktop, &bundle::export_bundle(&laptop).unwrap()).unwrap();
+        assert_eq!(again, MergeReport::default());
+    }
+
+    #[test]
+    fn test_merge_newer_remote_wins() {
+        let laptop = setup_test_db();
+        db_add_todo(&laptop, "Renew passport".to_string(), None);
+        let desktop = copy_of(&laptop);
+        set_title(
+            &desktop,
+            "Renew passport",
+            "Renew passport (old)",
+            "2024-05-01T08:00:00+00:00",
+        );
+        set_title(
+            &laptop,
+            "Renew passport",
+            "Renew passports",
+            "2024-05-02T08:00:00+00:00",
+        );
+
+        let report = merge_export(&desktop, &bundle::export_bundle(&laptop).unwrap()).unwrap();
+        assert_eq!(report.updated, 1);
+        assert_eq!(report.inserted, 0);
+        assert_eq!(titles(&desktop), ["Renew passports"]);
+        assert_eq!(
+            db_get_todos(&desktop)[0].updated_at,
+            db_get_todos(&laptop)[0].updated_at
+        );
+    }
+
+    #[test]
+    fn test_merge_newer_local_wins() {
+        let laptop = setup_test_db();
+        db_add_todo(&laptop, "Renew passport".to_string(), None);
+        let desktop = copy_of(&laptop);
+        set_title(
+            &laptop,
+            "Renew passport",
+            "Renew passport (old)",
+            "2024-05-01T08:00:00+00:00",
+        );
+        set_title(
+            &desktop,
+            "Renew passport",
+            "Renew passports",
+            "2024-05-02T08:00:00+00:00",
+        );
+
+        let report = merge_export(&desktop, &bundle::export_bundle(&laptop).unwrap()).unwrap();
+        assert_eq!(report.updated, 0);
+        assert_eq!(report.conflicts[0].winner, MergeSide::Local);
+        assert_eq!(titles(&desktop), ["Renew passports"]);
+    }
+
+    #[test]
+    fn test_merge_reports_conflicting_fields() {
+        let laptop = setup_test_db();
+        let todo = db_add_todo(&laptop, "Water plants".to_string(), None);
+        db_add_todo(&laptop, "Untouched".to_string(), None);
+        let desktop = copy_of(&laptop);
+        db_set_completed_bulk(&desktop, &[todo.id], true);
+        desktop
+            .execute(
+                "UPDATE todos SET updated_at = '2024-05-01T08:00:00+00:00'",
+                [],
+            )
+            .unwrap();
+        db_add_tag(&laptop, todo.id, "home");
+        set_title(
+            &laptop,
+            "Water plants",
+            "Water the plants",
+            "2024-05-02T08:00:00+00:00",
+        );
+        let uuid: String = laptop
+            .query_row("SELECT uuid FROM todos WHERE id = ?1", (todo.id,), |row| {
+                row.get(0)
+            })
+            .unwrap();
+
+        let report = merge_export(&desktop, &bundle::export_bundle(&laptop).unwrap()).unwrap();
+        let fields: Vec<&str> = report.conflicts.iter().map(|c| c.field.as_str()).collect();
+        assert_eq!(fields, ["title", "completed", "completed_at", "tags"]);
+        assert!(report
+            .conflicts
+            .iter()
+            .all(|c| c.uuid == uuid && c.title == "Water plants" && c.winner == MergeSide::Remote));
+        assert_eq!(report.conflicts[0].remote, Value::from("Water the plants"));
+        assert_eq!(report.conflicts[1].local, Value::from(true));
+        assert_eq!(report.updated, 1);
+        let merged = db_get_todos(&desktop)
+            .into_iter()
+            .find(|t| t.id == todo.id)
+            .unwrap();
+        assert_eq!(merged.title, "Water the plants");
+        assert!(!merged.completed);
+        assert_eq!(db_get_tags(&desktop, todo.id), ["home"]);
+    }
+
+    #[test]
+    fn test_merge_leaves_todos_archived_here_out() {
+        let laptop = setup_test_db();
+        let todo = db_add_todo(&laptop, "File taxes".to_string(), None);
+        let desktop = copy_of(&laptop);
+        db_set_completed_bulk(&desktop, &[todo.id], true);
+        assert_eq!(db_archive_completed(&desktop), 1);
+
+        let report = merge_export(&desktop, &bundle::export_bundle(&laptop).unwrap()).unwrap();
+        assert_eq!(
+            report,
+            MergeReport {
+                archived: 1,
+                ..MergeReport::default()
+            }
+        );
+        assert!(db_get_todos(&desktop).is_empty());
+
+        let restored = db_unarchive_todo(&desktop, todo.id).unwrap();
+        assert_eq!(restored.title, "File taxes");
+        let uuid = |conn: &Connection| -> String {
+            conn.query_row("SELECT uuid FROM todos", [], |row| row.get(0))
+                .unwrap()
+        };
+        assert_eq!(uuid(&desktop), uuid(&laptop));
+    }
+
+    #[test]
+    fn test_merge_leaves_todos_deleted_here_out() {
+        let laptop = setup_test_db();
+        db_add_todo(&laptop, "Cancel gym".to_string(), None);
+        db_add_todo(&laptop, "Keep me".to_string(), None);
+        let desktop = copy_of(&laptop);
+        let deleted = db_get_todos(&desktop)
+            .into_iter()
+            .find(|t| t.title == "Cancel gym")
+            .unwrap();
+        assert!(db_delete_todo(&desktop, deleted.id));
+
+        let export = bundle::export_bundle(&laptop).unwrap();
+        for _ in 0..2 {
+            let report = merge_export(&desktop, &export).unwrap();
+            assert_eq!(
+                report,
+                MergeReport {
+                    deleted: 1,
+                    ..MergeReport::default()
+                }
+            );
+            assert_eq!(titles(&desktop), ["Keep me"]);
+        }
+    }
+
+    #[test]
+    fn test_merge_refuses_exports_without_uuids() {
+        let conn = setup_test_db();
+        let old = r#"{"version": 1, "todos": [{"id": 1, "title": "Old", "completed": false,
+            "created_at": null, "deadline": null, "description": null, "priority": 0,
+            "completed_at": null, "pinned": false, "color": null, "estimate_minutes": null,
+            "version": 0, "reminder_minutes": null, "updated_at": null}],
+            "tags": [], "todo_tags": []}"#;
+        assert!(merge_export(&conn, old).unwrap_err().contains("uuid"));
+        assert!(db_get_todos(&conn).is_empty());
+    }
+}
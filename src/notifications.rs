@@ -0,0 +1,264 @@
+use crate::{db_get_setting, db_get_todos, db_set_setting, deadline, parse_deadline, Todo};
+use chrono::{DateTime, Duration as ChronoDuration, Local, Utc};
+use rusqlite::Connection;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::time::Duration;
+use tauri::{AppHandle, Runtime};
+use tauri_plugin_notification::NotificationExt;
+
+pub const DEADLINE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+pub const DEFAULT_NOTICE_MINUTES: u32 = 15;
+/// Settings key holding how many minutes before a deadline to notify.
+pub const NOTICE_MINUTES_SETTING: &str = "deadline_notice_minutes";
+/// What a deadline notification says instead of the title while the app
+/// lock hides the list.
+pub const HIDDEN_TITLE: &str = "A todo is due";
+/// Deadlines that passed longer ago than this are not announced, so starting
+/// the app after a break doesn't replay every overdue todo.
+const JUST_PASSED: ChronoDuration = ChronoDuration::minutes(5);
+
+/// Shows a desktop notification. The app uses the notification plugin; tests
+/// record the calls instead.
+pub trait Notifier {
+    fn notify(&self, title: &str, body: &str) -> Result<(), String>;
+}
+
+impl<R: Runtime> Notifier for AppHandle<R> {
+    fn notify(&self, title: &str, body: &str) -> Result<(), String> {
+        self.notification()
+            .builder()
+            .title(title)
+            .body(body)
+            .show()
+            .map_err(|e| e.to_string())
+    }
+}
+
+pub fn notice_minutes(conn: &Connection) -> u32 {
+    db_get_setting(conn, NOTICE_MINUTES_SETTING)
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_NOTICE_MINUTES)
+}
+
+pub fn set_notice_minutes(conn: &Connection, minutes: u32) {
+    db_set_setting(conn, NOTICE_MINUTES_SETTING, &minutes.to_string());
+}
+
+/// `(todo_id, deadline)` pairs that were already announced. Keying on the
+/// deadline value means moving a deadline makes it eligible again.
+fn notified(conn: &Connection) -> rusqlite::Result<HashSet<(u64, String)>> {
+    let mut stmt = conn.prepare_cached("SELECT todo_id, deadline FROM notified_deadlines")?;
+    let pairs = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    pairs.collect()
+}
+
+/// Incomplete todos whose deadline falls within `window` from `now` or passed
+/// moments ago, and that haven't been announced for that deadline yet.
+pub fn due_soon(
+    conn: &Connection,
+    now: DateTime<Local>,
+    window: ChronoDuration,
+) -> Result<Vec<Todo>, String> {
+    let notified = notified(conn).map_err(|e| e.to_string())?;
+    Ok(db_get_todos(conn)
+        .into_iter()
+        .filter(|todo| !todo.completed)
+        .filter(|todo| {
+            let Some(deadline) = todo.deadline.as_deref() else {
+                return false;
+            };
+            let utc = now.with_timezone(&Utc);
+            let due = deadline::deadline_cmp(deadline, utc - JUST_PASSED, &Local)
+                == Some(Ordering::Greater)
+                && deadline::deadline_cmp(deadline, utc + window, &Local)
+                    != Some(Ordering::Greater);
+            due && !notified.contains(&(todo.id, deadline.to_string()))
+        })
+        .collect())
+}
+
+fn mark_notified(conn: &Connection, todo: &Todo, now: DateTime<Local>) -> rusqlite::Result<()> {
+    conn.prepare_cached(
+        "INSERT OR IGNORE INTO notified_deadlines (todo_id, deadline, notified_at)
+         VALUES (?1, ?2, ?3)",
+    )?
+    .execute((
+        todo.id,
+        &todo.deadline,
+        now.with_timezone(&Utc).to_rfc3339(),
+    ))?;
+    Ok(())
+}
+
+/// Forgets that `todo_id` was announced, so its deadline can be announced
+/// again even if it moves back to one that already was.
+pub fn clear_notified(conn: &Connection, todo_id: u64) -> rusqlite::Result<usize> {
+    conn.prepare_cached("DELETE FROM notified_deadlines WHERE todo_id = ?1")?
+        .execute((todo_id,))
+}
+
+/// Announces every todo that is due soon, once per deadline, as
+/// `HIDDEN_TITLE` with `hide_titles`. Returns how many notifications were
+/// shown. A todo whose notification fails is retried on the next check.
+pub fn check_deadlines(
+    conn: &Connection,
+    notifier: &impl Notifier,
+    now: DateTime<Local>,
+    hide_titles: bool,
+) -> Result<usize, String> {
+    let window = ChronoDuration::minutes(notice_minutes(conn).into());
+    let mut shown = 0;
+    for todo in due_soon(conn, now, window)? {
+        let Some(deadline) = todo.deadline.as_deref() else {
+            continue;
+        };
+        let body = match deadline::deadline_cmp(deadline, now.with_timezone(&Utc), &Local) {
+            Some(Ordering::Greater) => match parse_deadline(deadline) {
+                Some(due) => format!("Due at {}", due.format("%H:%M")),
+                None => continue,
+            },
+            Some(_) => "Deadline passed".to_string(),
+            None => continue,
+        };
+        let title = if hide_titles {
+            HIDDEN_TITLE
+        } else {
+            &todo.title
+        };
+        notifier.notify(title, &body)?;
+        mark_notified(conn, &todo, now).map_err(|e| e.to_string())?;
+        shown += 1;
+    }
+    Ok(shown)
+}
+
+#[cfg(test)]
+pub mod test_notifier {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Keeps every notification's title and body so tests can assert on them.
+    #[derive(Default)]
+    pub struct RecordingNotifier {
+        shown: Mutex<Vec<(String, String)>>,
+    }
+
+    impl RecordingNotifier {
+        pub fn take(&self) -> Vec<(String, String)> {
+            std::mem::take(&mut self.shown.lock().unwrap())
+        }
+    }
+
+    impl Notifier for RecordingNotifier {
+        fn notify(&self, title: &str, body: &str) -> Result<(), String> {
+            self.shown
+                .lock()
+                .unwrap()
+                .push((title.to_string(), body.to_string()));
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_notifier::RecordingNotifier;
+    use super::*;
+    use crate::{db_add_todo, db_delete_todo, db_set_deadline, db_update_todo, setup_test_db};
+    use chrono::TimeZone;
+
+    fn noon() -> DateTime<Local> {
+        Local.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap()
+    }
+
+    fn add(conn: &Connection, title: &str, deadline: &str) -> Todo {
+        db_add_todo(conn, title.to_string(), Some(deadline.to_string()))
+    }
+
+    #[test]
+    fn test_due_soon_window() {
+        let conn = setup_test_db();
+        let soon = add(&conn, "Soon", "2024-05-01T12:10");
+        let just_passed = add(&conn, "Just passed", "2024-05-01T11:58");
+        add(&conn, "Later", "2024-05-01T13:00");
+        add(&conn, "Long overdue", "2024-05-01T09:00");
+        let done = add(&conn, "Done", "2024-05-01T12:05");
+        db_update_todo(&conn, done.id, None, Some(true), None, None);
+        db_add_todo(&conn, "Unscheduled".to_string(), None);
+
+        let ids: Vec<u64> = due_soon(&conn, noon(), ChronoDuration::minutes(15))
+            .unwrap()
+            .iter()
+            .map(|t| t.id)
+            .collect();
+
+        assert_eq!(ids, vec![soon.id, just_passed.id]);
+    }
+
+    #[test]
+    fn test_each_deadline_notifies_once() {
+        let conn = setup_test_db();
+        let todo = add(&conn, "Soon", "2024-05-01T12:10");
+        let notifier = RecordingNotifier::default();
+
+        assert_eq!(check_deadlines(&conn, &notifier, noon(), false).unwrap(), 1);
+        assert_eq!(check_deadlines(&conn, &notifier, noon(), false).unwrap(), 0);
+        assert_eq!(
+            notifier.take(),
+            [("Soon".to_string(), "Due at 12:10".to_string())]
+        );
+
+        db_set_deadline(&conn, todo.id, Some("2024-05-01T12:12".to_string()));
+        assert_eq!(check_deadlines(&conn, &notifier, noon(), false).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_hidden_titles() {
+        let conn = setup_test_db();
+        add(&conn, "See the doctor", "2024-05-01T12:10");
+        let notifier = RecordingNotifier::default();
+
+        assert_eq!(check_deadlines(&conn, &notifier, noon(), true).unwrap(), 1);
+        assert_eq!(
+            notifier.take(),
+            [(HIDDEN_TITLE.to_string(), "Due at 12:10".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_completed_todo_is_not_notified() {
+        let conn = setup_test_db();
+        let todo = add(&conn, "Soon", "2024-05-01T12:10");
+        db_update_todo(&conn, todo.id, None, Some(true), None, None);
+        let notifier = RecordingNotifier::default();
+
+        assert_eq!(check_deadlines(&conn, &notifier, noon(), false).unwrap(), 0);
+        assert!(notifier.take().is_empty());
+    }
+
+    #[test]
+    fn test_notice_window_setting() {
+        let conn = setup_test_db();
+        add(&conn, "In an hour", "2024-05-01T13:00");
+        let notifier = RecordingNotifier::default();
+        assert_eq!(notice_minutes(&conn), DEFAULT_NOTICE_MINUTES);
+        assert_eq!(check_deadlines(&conn, &notifier, noon(), false).unwrap(), 0);
+
+        set_notice_minutes(&conn, 60);
+
+        assert_eq!(check_deadlines(&conn, &notifier, noon(), false).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_deleting_todo_clears_its_notifications() {
+        let conn = setup_test_db();
+        let todo = add(&conn, "Soon", "2024-05-01T12:10");
+        check_deadlines(&conn, &RecordingNotifier::default(), noon(), false).unwrap();
+        assert_eq!(notified(&conn).unwrap().len(), 1);
+
+        db_delete_todo(&conn, todo.id);
+
+        assert!(notified(&conn).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,323 @@
+use crate::db_location::{self, DbChoice, DbSource};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// File in the app data dir listing the profiles and which one is active.
+pub const PROFILES_FILE: &str = "profiles.json";
+/// Folder in the app data dir holding the other profiles' databases.
+pub const PROFILES_DIR: &str = "profiles";
+/// The profile using the main database, wherever it was moved to. It is
+/// always there and can't be deleted.
+pub const DEFAULT_PROFILE: &str = "Default";
+pub const MAX_PROFILE_NAME_CHARS: usize = 40;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    /// The database, relative to the app data dir; `None` for the default
+    /// profile.
+    pub file: Option<PathBuf>,
+}
+
+/// What `PROFILES_FILE` holds.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ProfileIndex {
+    pub active: String,
+    pub profiles: Vec<Profile>,
+}
+
+impl Default for ProfileIndex {
+    fn default() -> Self {
+        ProfileIndex {
+            active: DEFAULT_PROFILE.to_string(),
+            profiles: vec![Profile {
+                name: DEFAULT_PROFILE.to_string(),
+                file: None,
+            }],
+        }
+    }
+}
+
+impl ProfileIndex {
+    /// The profile called `name`, ignoring case.
+    pub fn find(&self, name: &str) -> Option<&Profile> {
+        self.profiles
+            .iter()
+            .find(|p| p.name.to_lowercase() == name.trim().to_lowercase())
+    }
+
+    pub fn active_profile(&self) -> &Profile {
+        self.find(&self.active).unwrap_or_else(|| &self.profiles[0])
+    }
+}
+
+fn index_path(app_dir: &Path) -> PathBuf {
+    app_dir.join(PROFILES_FILE)
+}
+
+/// The profile list; just the default profile if there is no readable one.
+pub fn load(app_dir: &Path) -> ProfileIndex {
+    let Ok(text) = fs::read_to_string(index_path(app_dir)) else {
+        return ProfileIndex::default();
+    };
+    match serde_json::from_str::<ProfileIndex>(&text) {
+        Ok(index) if index.find(DEFAULT_PROFILE).is_some() => index,
+        Ok(_) => {
+            tracing::warn!("{} has no default profile, ignoring it", PROFILES_FILE);
+            ProfileIndex::default()
+        }
+        Err(e) => {
+            tracing::warn!("failed to read {}: {}", PROFILES_FILE, e);
+            ProfileIndex::default()
+        }
+    }
+}
+
+/// Writes to a temporary file and renames it over the old one, like the
+/// database pointer, so a crash never leaves half a list.
+fn save(app_dir: &Path, index: &ProfileIndex) -> Result<(), String> {
+    fs::create_dir_all(app_dir).map_err(|e| e.to_string())?;
+    let json = serde_json::to_string_pretty(index).map_err(|e| e.to_string())?;
+    let tmp = app_dir.join(format!("{}.tmp", PROFILES_FILE));
+    fs::write(&tmp, json).map_err(|e| e.to_string())?;
+    fs::rename(&tmp, index_path(app_dir)).map_err(|e| e.to_string())
+}
+
+fn check_name(name: &str) -> Result<&str, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("Profile name is empty".to_string());
+    }
+    if name.chars().count() > MAX_PROFILE_NAME_CHARS {
+        return Err(format!(
+            "Profile names are at most {} characters",
+            MAX_PROFILE_NAME_CHARS
+        ));
+    }
+    if name.chars().any(char::is_control) {
+        return Err("Profile names can't contain control characters".to_string());
+    }
+    Ok(name)
+}
+
+/// A file name for `name`'s database made of lowercase ASCII letters, digits
+/// and dashes, numbered if another profile has it already.
+fn file_for(index: &ProfileIndex, name: &str) -> PathBuf {
+    let slug = name
+        .to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+    let slug = if slug.is_empty() { "profile" } else { &slug };
+    let taken = |file: &Path| {
+        index
+            .profiles
+            .iter()
+            .any(|p| p.file.as_deref() == Some(file))
+    };
+    (1..)
+        .map(|n| match n {
+            1 => format!("{}.db", slug),
+            n => format!("{}-{}.db", slug, n),
+        })
+        .map(|file| Path::new(PROFILES_DIR).join(file))
+        .find(|file| !taken(file))
+        .expect("some numbered name is free")
+}
+
+/// Adds a profile with a new, empty database, created when it is first
+/// switched to.
+pub fn create(app_dir: &Path, name: &str) -> Result<Profile, String> {
+    let name = check_name(name)?;
+    let mut index = load(app_dir);
+    if let Some(existing) = index.find(name) {
+        return Err(format!(
+            "There is already a profile called {}",
+            existing.name
+        ));
+    }
+    let profile = Profile {
+        name: name.to_string(),
+        file: Some(file_for(&index, name)),
+    };
+    index.profiles.push(profile.clone());
+    save(app_dir, &index)?;
+    Ok(profile)
+}
+
+/// The database `profile` uses. `main` is the one the default profile uses.
+pub fn choice_for(app_dir: &Path, profile: &Profile, main: &DbChoice) -> DbChoice {
+    match &profile.file {
+        None => main.clone(),
+        Some(file) => DbChoice {
+            source: DbSource::Profile,
+            ephemeral: false,
+            path: Some(app_dir.join(file)),
+        },
+    }
+}
+
+/// The database to open at startup: that of the active profile, unless this
+/// run was pointed at a database of its own.
+pub fn resolve(app_dir: &Path, main: DbChoice) -> DbChoice {
+    if !main.is_persistent_default() {
+        return main;
+    }
+    let index = load(app_dir);
+    choice_for(app_dir, index.active_profile(), &main)
+}
+
+/// Makes `name` the active profile from the next start on, and returns it.
+pub fn set_active(app_dir: &Path, name: &str) -> Result<Profile, String> {
+    let mut index = load(app_dir);
+    let profile = index
+        .find(name)
+        .cloned()
+        .ok_or_else(|| format!("There is no profile called {}", name))?;
+    index.active = profile.name.clone();
+    save(app_dir, &index)?;
+    Ok(profile)
+}
+
+/// Where the rolling backups of `database` go. Each profile has its own
+/// folder, so one profile's backups never rotate out another's.
+pub fn backups_dir(app_dir: &Path, database: &DbChoice) -> PathBuf {
+    let backups = app_dir.join("backups");
+    match (&database.source, &database.path) {
+        (DbSource::Profile, Some(path)) => match path.file_stem() {
+            Some(stem) => backups.join(stem),
+            None => backups,
+        },
+        _ => backups,
+    }
+}
+
+/// Removes a profile other than the default or the active one, and with
+/// `delete_data` its database and backups too.
+pub fn delete(app_dir: &Path, name: &str, delete_data: bool) -> Result<(), String> {
+    let mut index = load(app_dir);
+    let profile = index
+        .find(name)
+        .cloned()
+        .ok_or_else(|| format!("There is no profile called {}", name))?;
+    let Some(file) = &profile.file else {
+        return Err("The default profile can't be deleted".to_string());
+    };
+    if index.active_profile().name == profile.name {
+        return Err(format!(
+            "{} is the active profile; switch to another before deleting it",
+            profile.name
+        ));
+    }
+    index.profiles.retain(|p| p.name != profile.name);
+    save(app_dir, &index)?;
+    if delete_data {
+        let database = choice_for(app_dir, &profile, &db_location::main_database(app_dir));
+        let backups = backups_dir(app_dir, &database);
+        db_location::remove_database_files(&app_dir.join(file));
+        if backups != app_dir.join("backups") {
+            let _ = fs::remove_dir_all(backups);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(index: &ProfileIndex) -> Vec<&str> {
+        index.profiles.iter().map(|p| p.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_create_and_switch() {
+        let app_dir = tempfile::tempdir().unwrap();
+        let dir = app_dir.path();
+        assert_eq!(load(dir), ProfileIndex::default());
+
+        let work = create(dir, "  Work stuff ").unwrap();
+        assert_eq!(work.name, "Work stuff");
+        assert_eq!(
+            work.file,
+            Some(Path::new(PROFILES_DIR).join("work-stuff.db"))
+        );
+        let other = create(dir, "Work-Stuff!").unwrap();
+        assert_eq!(
+            other.file,
+            Some(Path::new(PROFILES_DIR).join("work-stuff-2.db"))
+        );
+        assert!(create(dir, "work stuff").is_err());
+        assert!(create(dir, "default").is_err());
+        assert!(create(dir, " ").is_err());
+        assert!(create(dir, &"x".repeat(MAX_PROFILE_NAME_CHARS + 1)).is_err());
+        assert_eq!(names(&load(dir)), ["Default", "Work stuff", "Work-Stuff!"]);
+
+        let main = db_location::main_database(dir);
+        assert_eq!(resolve(dir, main.clone()), main);
+        set_active(dir, "WORK STUFF").unwrap();
+        assert_eq!(load(dir).active, "Work stuff");
+        let chosen = resolve(dir, main.clone());
+        assert_eq!(chosen.source, DbSource::Profile);
+        assert_eq!(
+            chosen.path,
+            Some(dir.join(PROFILES_DIR).join("work-stuff.db"))
+        );
+        assert_eq!(
+            backups_dir(dir, &chosen),
+            dir.join("backups").join("work-stuff")
+        );
+        assert_eq!(backups_dir(dir, &main), dir.join("backups"));
+        assert!(set_active(dir, "Nobody").is_err());
+
+        // A run given its own database ignores the profiles.
+        let flag = DbChoice {
+            source: DbSource::Flag,
+            ephemeral: false,
+            path: Some(PathBuf::from("/tmp/e2e.db")),
+        };
+        assert_eq!(resolve(dir, flag.clone()), flag);
+    }
+
+    #[test]
+    fn test_delete() {
+        let app_dir = tempfile::tempdir().unwrap();
+        let dir = app_dir.path();
+        let home = create(dir, "Home").unwrap();
+        create(dir, "Trip").unwrap();
+        let home_db = dir.join(home.file.as_ref().unwrap());
+        fs::create_dir_all(home_db.parent().unwrap()).unwrap();
+        fs::write(&home_db, "data").unwrap();
+        let backups = dir.join("backups").join("home");
+        fs::create_dir_all(&backups).unwrap();
+
+        set_active(dir, "Home").unwrap();
+        let err = delete(dir, "home", true).unwrap_err();
+        assert!(err.contains("active profile"), "{}", err);
+        assert!(home_db.exists());
+        assert!(delete(dir, DEFAULT_PROFILE, false).is_err());
+        assert!(delete(dir, "Nobody", false).is_err());
+
+        set_active(dir, DEFAULT_PROFILE).unwrap();
+        delete(dir, "Trip", false).unwrap();
+        delete(dir, "Home", true).unwrap();
+        assert_eq!(names(&load(dir)), ["Default"]);
+        assert!(!home_db.exists());
+        assert!(!backups.exists());
+    }
+
+    #[test]
+    fn test_unreadable_index_falls_back_to_default() {
+        let app_dir = tempfile::tempdir().unwrap();
+        fs::write(index_path(app_dir.path()), "{not json").unwrap();
+        assert_eq!(load(app_dir.path()), ProfileIndex::default());
+        fs::write(
+            index_path(app_dir.path()),
+            r#"{"active": "A", "profiles": [{"name": "A", "file": "profiles/a.db"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(load(app_dir.path()), ProfileIndex::default());
+    }
+}
@@ -0,0 +1,93 @@
+use chrono::{DateTime, Duration as ChronoDuration, Local};
+
+/// Trailing phrases `parse_quick_add` understands, with how many days ahead
+/// they put the deadline. Deadlines are dates, so they last until the end of
+/// that day.
+const KEYWORDS: [(&str, i64); 3] = [("next week", 7), ("tomorrow", 1), ("today", 0)];
+
+/// Removes `keyword` from the end of `input` as whole words, ignoring case.
+/// Returns what is left before it, if anything.
+fn strip_keyword<'a>(input: &'a str, keyword: &str) -> Option<&'a str> {
+    let mut rest = input;
+    for word in keyword.rsplit(' ') {
+        let (head, last) = rest.rsplit_once(char::is_whitespace)?;
+        if !last.eq_ignore_ascii_case(word) {
+            return None;
+        }
+        rest = head.trim_end();
+    }
+    Some(rest).filter(|r| !r.is_empty())
+}
+
+/// Splits quick-add input like "Buy milk tomorrow" into a title and a
+/// `YYYY-MM-DD` deadline. Only a keyword at the very end counts; input that
+/// is nothing but a keyword is kept as the title.
+pub fn parse_quick_add(input: &str, now: DateTime<Local>) -> (String, Option<String>) {
+    let input = input.trim();
+    for (keyword, days) in KEYWORDS {
+        if let Some(title) = strip_keyword(input, keyword) {
+            let due = now + ChronoDuration::days(days);
+            return (title.to_string(), Some(due.format("%Y-%m-%d").to_string()));
+        }
+    }
+    (input.to_string(), None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn now() -> DateTime<Local> {
+        // A Wednesday.
+        Local.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap()
+    }
+
+    fn parse(input: &str) -> (String, Option<String>) {
+        parse_quick_add(input, now())
+    }
+
+    #[test]
+    fn test_tomorrow() {
+        assert_eq!(
+            parse("Buy milk tomorrow"),
+            ("Buy milk".to_string(), Some("2024-05-02".to_string()))
+        );
+        assert_eq!(parse("Call Ana TOMORROW ").1.as_deref(), Some("2024-05-02"));
+    }
+
+    #[test]
+    fn test_next_week_and_today() {
+        assert_eq!(
+            parse("Plan sprint next week"),
+            ("Plan sprint".to_string(), Some("2024-05-08".to_string()))
+        );
+        assert_eq!(parse("Pay rent today").1.as_deref(), Some("2024-05-01"));
+    }
+
+    #[test]
+    fn test_no_keyword() {
+        assert_eq!(parse("Buy milk"), ("Buy milk".to_string(), None));
+        assert_eq!(parse("  Water plants "), ("Water plants".to_string(), None));
+    }
+
+    #[test]
+    fn test_keyword_must_be_a_trailing_word() {
+        assert_eq!(parse("Tomorrow buy milk").1, None);
+        assert_eq!(parse("Read yesterday").1, None);
+        assert_eq!(parse("Update uptoday").1, None);
+        assert_eq!(parse("Plan week").1, None);
+        assert_eq!(parse("tomorrow"), ("tomorrow".to_string(), None));
+    }
+
+    #[test]
+    fn test_title_spacing_is_kept() {
+        assert_eq!(
+            parse("Zażółć  gęślą jaźń\ttomorrow"),
+            (
+                "Zażółć  gęślą jaźń".to_string(),
+                Some("2024-05-02".to_string())
+            )
+        );
+    }
+}
@@ -0,0 +1,375 @@
+use chrono::Local;
+use rusqlite::types::{Value, ValueRef};
+use rusqlite::{params_from_iter, Connection, OpenFlags};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How `recover_database` got the data out.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RecoveryMethod {
+    /// `VACUUM INTO` read the whole database, so nothing was lost.
+    VacuumInto,
+    /// Rows were copied one at a time, skipping what couldn't be read.
+    RowCopy,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct RecoveryReport {
+    pub method: RecoveryMethod,
+    /// Rows in each table of the recovered copy.
+    pub rows_recovered: BTreeMap<String, u64>,
+    /// Tables where some rows couldn't be read and are missing.
+    pub damaged_tables: Vec<String>,
+    /// Rows dropped because the row they pointed to was lost, such as tag
+    /// links of a todo that couldn't be read.
+    pub orphans_removed: u64,
+    /// Where the damaged file was moved, when recovering at startup.
+    pub damaged_copy: Option<PathBuf>,
+}
+
+impl RecoveryReport {
+    pub fn todos_recovered(&self) -> u64 {
+        self.rows_recovered.get("todos").copied().unwrap_or(0)
+    }
+}
+
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Runs `PRAGMA quick_check`. A file that isn't a database at all fails it
+/// too.
+pub fn quick_check(conn: &Connection) -> Result<(), String> {
+    let problems: Vec<String> = conn
+        .prepare("PRAGMA quick_check")
+        .and_then(|mut stmt| {
+            stmt.query_map([], |row| row.get(0))?
+                .collect::<rusqlite::Result<_>>()
+        })
+        .map_err(|e| e.to_string())?;
+    match problems.as_slice() {
+        [ok] if ok == "ok" => Ok(()),
+        _ => Err(problems.join("; ")),
+    }
+}
+
+fn row_counts(conn: &Connection) -> rusqlite::Result<BTreeMap<String, u64>> {
+    let tables: Vec<String> = conn
+        .prepare(
+            "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%'",
+        )?
+        .query_map([], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    tables
+        .into_iter()
+        .map(|table| {
+            let sql = format!("SELECT COUNT(*) FROM {}", quote(&table));
+            let count = conn.query_row(&sql, [], |row| row.get(0))?;
+            Ok((table, count))
+        })
+        .collect()
+}
+
+/// A value read from a damaged page, whose text may not be valid UTF-8.
+fn salvage_value(value: ValueRef) -> Value {
+    match value {
+        ValueRef::Null => Value::Null,
+        ValueRef::Integer(i) => Value::Integer(i),
+        ValueRef::Real(r) => Value::Real(r),
+        ValueRef::Text(text) => Value::Text(String::from_utf8_lossy(text).into_owned()),
+        ValueRef::Blob(blob) => Value::Blob(blob.to_vec()),
+    }
+}
+
+/// Copies the rows of `table` that can be read, reading forwards by rowid
+/// until the first error and then backwards from the end, so rows on both
+/// sides of a bad page are kept. Returns whether any read failed.
+fn copy_rows(src: &Connection, dst: &Connection, table: &str) -> rusqlite::Result<bool> {
+    let columns: Vec<String> = src
+        .prepare(&format!("PRAGMA table_info({})", quote(table)))?
+        .query_map([], |row| row.get(1))?
+        .collect::<rusqlite::Result<_>>()?;
+    let column_list: Vec<String> = std::iter::once("rowid".to_string())
+        .chain(columns.iter().map(|c| quote(c)))
+        .collect();
+    let placeholders = vec!["?"; column_list.len()].join(", ");
+    let mut insert = dst.prepare(&format!(
+        "INSERT OR IGNORE INTO {} ({}) VALUES ({})",
+        quote(table),
+        column_list.join(", "),
+        placeholders
+    ))?;
+
+    let mut damaged = false;
+    for order in ["ASC", "DESC"] {
+        let sql = format!(
+            "SELECT {} FROM {} ORDER BY rowid {}",
+            column_list.join(", "),
+            quote(table),
+            order
+        );
+        let read = src.prepare(&sql).and_then(|mut select| {
+            let mut rows = select.query([])?;
+            while let Some(row) = rows.next()? {
+                let values = (0..column_list.len())
+                    .map(|i| row.get_ref(i).map(salvage_value))
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                insert.execute(params_from_iter(values))?;
+            }
+            Ok(())
+        });
+        match read {
+            Ok(()) => break,
+            Err(e) if e.sqlite_error_code() == Some(rusqlite::ErrorCode::DatabaseCorrupt) => {
+                damaged = true
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(damaged)
+}
+
+/// Deletes the rows `PRAGMA foreign_key_check` reports, returning how many.
+fn remove_orphans(conn: &Connection) -> rusqlite::Result<u64> {
+    let orphans: Vec<(String, i64)> = conn
+        .prepare("PRAGMA foreign_key_check")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    for (table, rowid) in &orphans {
+        conn.execute(
+            &format!("DELETE FROM {} WHERE rowid = ?1", quote(table)),
+            [rowid],
+        )?;
+    }
+    Ok(orphans.len() as u64)
+}
+
+/// Rebuilds the schema of `src` in `dst` and copies over every row that can
+/// still be read. Indexes and triggers are created after the data, so the
+/// triggers don't fire on the copied rows.
+fn copy_readable_rows(src: &Connection, dst: &Connection) -> rusqlite::Result<(Vec<String>, u64)> {
+    let schema: Vec<(String, String, String)> = src
+        .prepare(
+            "SELECT type, name, sql FROM sqlite_master
+             WHERE sql IS NOT NULL AND name NOT LIKE 'sqlite_%'",
+        )?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    let version: i64 = src.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+
+    dst.pragma_update(None, "foreign_keys", false)?;
+    let tx = dst.unchecked_transaction()?;
+    let (tables, rest): (Vec<_>, Vec<_>) = schema.iter().partition(|(kind, _, _)| kind == "table");
+    let mut damaged = Vec::new();
+    for (_, name, sql) in &tables {
+        tx.execute_batch(sql)?;
+        if copy_rows(src, &tx, name)? {
+            damaged.push(name.clone());
+        }
+    }
+    for (_, _, sql) in &rest {
+        tx.execute_batch(sql)?;
+    }
+    let orphans = remove_orphans(&tx)?;
+    tx.pragma_update(None, "user_version", version)?;
+    tx.commit()?;
+    dst.pragma_update(None, "foreign_keys", true)?;
+    Ok((damaged, orphans))
+}
+
+/// Writes what can be saved of the database at `src` to a new file at
+/// `dst`, which must not exist yet. `VACUUM INTO` is tried first; if it
+/// can't read everything, the readable rows are copied one by one instead.
+pub fn recover_database(src: &Path, dst: &Path) -> Result<RecoveryReport, String> {
+    if dst.exists() {
+        return Err(format!("{} already exists", dst.display()));
+    }
+    let source = Connection::open_with_flags(src, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|e| e.to_string())?;
+
+    let vacuumed = source.execute("VACUUM INTO ?1", [dst.to_string_lossy()]);
+    let (method, damaged_tables, orphans_removed) = match vacuumed {
+        Ok(_) => (RecoveryMethod::VacuumInto, Vec::new(), 0),
+        Err(e) => {
+            tracing::warn!("VACUUM INTO failed, copying rows instead: {}", e);
+            let _ = fs::remove_file(dst);
+            let target = Connection::open(dst).map_err(|e| e.to_string())?;
+            let copied = copy_readable_rows(&source, &target);
+            let (damaged, orphans) = copied.map_err(|e| {
+                drop(target);
+                let _ = fs::remove_file(dst);
+                format!("Nothing could be recovered: {}", e)
+            })?;
+            (RecoveryMethod::RowCopy, damaged, orphans)
+        }
+    };
+
+    let recovered = Connection::open(dst).map_err(|e| e.to_string())?;
+    quick_check(&recovered).map_err(|e| format!("The recovered copy is damaged too: {}", e))?;
+    Ok(RecoveryReport {
+        method,
+        rows_recovered: row_counts(&recovered).map_err(|e| e.to_string())?,
+        damaged_tables,
+        orphans_removed,
+        damaged_copy: None,
+    })
+}
+
+/// Where a damaged database is moved before recovery:
+/// `todos.db.damaged-YYYYMMDD-HHMMSS` next to it.
+fn damaged_copy_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".damaged-{}", Local::now().format("%Y%m%d-%H%M%S")));
+    path.with_file_name(name)
+}
+
+/// Checks the database at `path` before it is opened for use. If it fails
+/// `quick_check`, the file (and its WAL, if any) is moved aside and what
+/// can be salvaged is recovered to `path`. Returns `None` if the database
+/// was fine. On error the damaged file stays moved aside, so the app starts
+/// on an empty database instead of failing.
+pub fn recover_at_startup(path: &Path) -> Result<Option<RecoveryReport>, String> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let problem = {
+        let conn = Connection::open(path).map_err(|e| e.to_string())?;
+        match quick_check(&conn) {
+            Ok(()) => return Ok(None),
+            Err(problem) => problem,
+        }
+    };
+    tracing::error!("database {:?} failed quick_check: {}", path, problem);
+
+    let damaged = damaged_copy_path(path);
+    fs::rename(path, &damaged).map_err(|e| e.to_string())?;
+    for suffix in ["-wal", "-shm"] {
+        let mut from = path.as_os_str().to_os_string();
+        from.push(suffix);
+        let mut to = damaged.as_os_str().to_os_string();
+        to.push(suffix);
+        if Path::new(&from).exists() {
+            fs::rename(&from, &to).map_err(|e| e.to_string())?;
+        }
+    }
+    let report = recover_database(&damaged, path)
+        .map_err(|e| format!("{} (the damaged file is at {})", e, damaged.display()))?;
+    Ok(Some(RecoveryReport {
+        damaged_copy: Some(damaged),
+        ..report
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db_add_tag, db_add_todo, db_get_todos, init_db};
+
+    const TODOS: usize = 400;
+    /// SQLite's default page size.
+    const PAGE_SIZE: usize = 4096;
+
+    /// A database with enough todos to span many pages, closed so that
+    /// everything is in the main file.
+    fn fixture(path: &Path) {
+        let conn = Connection::open(path).unwrap();
+        init_db(&conn);
+        let tx = conn.unchecked_transaction().unwrap();
+        for i in 0..TODOS {
+            let todo = db_add_todo(&tx, format!("Todo {} {}", i, "x".repeat(200)), None);
+            if i % 10 == 0 {
+                db_add_tag(&tx, todo.id, "tenth");
+            }
+        }
+        tx.commit().unwrap();
+    }
+
+    /// `fixture`, with the `todos` pages holding the middle rows overwritten.
+    /// They are found by content, as indexes share the file with the table.
+    fn damaged_fixture(path: &Path) {
+        fixture(path);
+        let mut bytes = fs::read(path).unwrap();
+        let needle = format!("Todo {} x", TODOS / 2);
+        let at = bytes
+            .windows(needle.len())
+            .position(|w| w == needle.as_bytes())
+            .unwrap();
+        let page = at / PAGE_SIZE * PAGE_SIZE;
+        bytes[page..page + 2 * PAGE_SIZE].fill(0xAB);
+        fs::write(path, bytes).unwrap();
+    }
+
+    #[test]
+    fn test_healthy_database_is_copied_whole() {
+        let dir = tempfile::tempdir().unwrap();
+        let (src, dst) = (dir.path().join("todos.db"), dir.path().join("copy.db"));
+        fixture(&src);
+
+        let report = recover_database(&src, &dst).unwrap();
+
+        assert_eq!(report.method, RecoveryMethod::VacuumInto);
+        assert_eq!(report.todos_recovered(), TODOS as u64);
+        assert!(report.damaged_tables.is_empty());
+        assert!(recover_database(&src, &dst).is_err());
+    }
+
+    #[test]
+    fn test_damaged_database_salvages_readable_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        let (src, dst) = (dir.path().join("todos.db"), dir.path().join("copy.db"));
+        damaged_fixture(&src);
+        assert!(quick_check(&Connection::open(&src).unwrap()).is_err());
+
+        let report = recover_database(&src, &dst).unwrap();
+
+        assert_eq!(report.method, RecoveryMethod::RowCopy);
+        assert!(report.damaged_tables.contains(&"todos".to_string()));
+        let salvaged = report.todos_recovered();
+        assert!(salvaged > 0 && salvaged < TODOS as u64, "{}", salvaged);
+        // Rows from both ends of the table survive.
+        let conn = Connection::open(&dst).unwrap();
+        init_db(&conn);
+        let titles: Vec<String> = db_get_todos(&conn).into_iter().map(|t| t.title).collect();
+        assert!(titles.first().unwrap().starts_with("Todo 0 "));
+        assert!(titles
+            .last()
+            .unwrap()
+            .starts_with(&format!("Todo {} ", TODOS - 1)));
+        assert!(crate::maintenance::check_integrity(&conn).is_ok());
+    }
+
+    #[test]
+    fn test_file_that_is_not_a_database_cant_be_recovered() {
+        let dir = tempfile::tempdir().unwrap();
+        let (src, dst) = (dir.path().join("todos.db"), dir.path().join("copy.db"));
+        fs::write(&src, "not a database at all, just some text").unwrap();
+
+        assert!(recover_database(&src, &dst).is_err());
+        assert!(!dst.exists());
+    }
+
+    #[test]
+    fn test_recover_at_startup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("todos.db");
+        assert_eq!(recover_at_startup(&path), Ok(None));
+        fixture(&path);
+        assert_eq!(recover_at_startup(&path), Ok(None));
+
+        damaged_fixture(&dir.path().join("other.db"));
+        fs::rename(dir.path().join("other.db"), &path).unwrap();
+        let report = recover_at_startup(&path).unwrap().unwrap();
+
+        let damaged = report.damaged_copy.unwrap();
+        assert!(damaged.exists());
+        assert!(damaged
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with("todos.db.damaged-"));
+        assert_eq!(quick_check(&Connection::open(&path).unwrap()), Ok(()));
+    }
+}
@@ -0,0 +1,388 @@
+use crate::app_lock;
+use crate::badge::{self, BadgeMode};
+use crate::http_api;
+use crate::logging::{self, LogLevel};
+use crate::{
+    backup, check_max_title_chars, db_get_setting, db_set_setting, max_title_chars, notifications,
+    set_max_title_chars, tray, MAX_TITLE_CHARS,
+};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+
+/// Settings key holding the color scheme.
+pub const THEME_SETTING: &str = "theme";
+pub const MAX_NOTICE_MINUTES: u32 = 24 * 60;
+/// Keys set through `set_ui` are stored under this prefix, apart from the
+/// typed settings, so the frontend can't overwrite those (or the app lock
+/// hash) by name.
+pub const UI_SETTING_PREFIX: &str = "ui.";
+pub const MAX_UI_KEY_CHARS: usize = 64;
+pub const MAX_UI_VALUE_CHARS: usize = 4096;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Theme {
+    /// Follow the OS.
+    #[default]
+    System,
+    Light,
+    Dark,
+}
+
+impl Theme {
+    fn as_str(self) -> &'static str {
+        match self {
+            Theme::System => "system",
+            Theme::Light => "light",
+            Theme::Dark => "dark",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        [Theme::System, Theme::Light, Theme::Dark]
+            .into_iter()
+            .find(|theme| theme.as_str() == value)
+    }
+}
+
+/// Every plain preference in one place. Each field is stored under its own
+/// key in the `settings` table, so rows this version doesn't know about are
+/// left alone. The quick-add shortcut and autostart also change OS state and
+/// keep their own commands.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct Settings {
+    pub backups_to_keep: usize,
+    pub deadline_notice_minutes: u32,
+    pub close_to_tray: bool,
+    pub badge_mode: BadgeMode,
+    pub theme: Theme,
+    pub max_title_chars: usize,
+    pub log_level: LogLevel,
+    /// Idle minutes before the app lock engages; 0 never.
+    pub auto_lock_minutes: u32,
+    /// Serve the local HTTP API on `127.0.0.1:http_api_port`.
+    pub http_api_enabled: bool,
+    pub http_api_port: u16,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            backups_to_keep: backup::DEFAULT_BACKUPS_TO_KEEP,
+            deadline_notice_minutes: notifications::DEFAULT_NOTICE_MINUTES,
+            close_to_tray: true,
+            badge_mode: BadgeMode::default(),
+            theme: Theme::default(),
+            max_title_chars: MAX_TITLE_CHARS,
+            log_level: LogLevel::default(),
+            auto_lock_minutes: app_lock::DEFAULT_AUTO_LOCK_MINUTES,
+            http_api_enabled: false,
+            http_api_port: http_api::DEFAULT_PORT,
+        }
+    }
+}
+
+/// Fields to change; `None` leaves a setting as it is.
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SettingsUpdate {
+    pub backups_to_keep: Option<usize>,
+    pub deadline_notice_minutes: Option<u32>,
+    pub close_to_tray: Option<bool>,
+    pub badge_mode: Option<BadgeMode>,
+    pub theme: Option<Theme>,
+    pub max_title_chars: Option<usize>,
+    pub log_level: Option<LogLevel>,
+    pub auto_lock_minutes: Option<u32>,
+    pub http_api_enabled: Option<bool>,
+    pub http_api_port: Option<u16>,
+}
+
+/// Reads all settings. Missing or unreadable values fall back to defaults.
+pub fn load(conn: &Connection) -> Settings {
+    Settings {
+        backups_to_keep: backup::backups_to_keep(conn),
+        deadline_notice_minutes: notifications::notice_minutes(conn),
+        close_to_tray: tray::close_to_tray(conn),
+        badge_mode: badge::badge_mode(conn),
+        theme: db_get_setting(conn, THEME_SETTING)
+            .and_then(|v| Theme::parse(&v))
+            .unwrap_or_default(),
+        max_title_chars: max_title_chars(conn),
+        log_level: logging::log_level(conn),
+        auto_lock_minutes: app_lock::auto_lock_minutes(conn),
+        http_api_enabled: http_api::enabled(conn),
+        http_api_port: http_api::port(conn),
+    }
+}
+
+fn validate(update: &SettingsUpdate) -> Result<(), String> {
+    if let Some(keep) = update.backups_to_keep {
+        backup::check_backups_to_keep(keep)?;
+    }
+    if let Some(max) = update.max_title_chars {
+        check_max_title_chars(max)?;
+    }
+    if let Some(minutes) = update.auto_lock_minutes {
+        app_lock::check_auto_lock_minutes(minutes)?;
+    }
+    if let Some(port) = update.http_api_port {
+        http_api::check_port(port)?;
+    }
+    if let Some(minutes) = update.deadline_notice_minutes {
+        if minutes > MAX_NOTICE_MINUTES {
+            return Err(format!(
+                "Deadline notice must be at most {} minutes",
+                MAX_NOTICE_MINUTES
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Validates every supplied field, then writes them in one transaction, so
+/// either all of the update is saved or none of it. Returns the settings as
+/// they are afterwards.
+pub fn update(conn: &Connection, update: SettingsUpdate) -> Result<Settings, String> {
+    validate(&update)?;
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    if let Some(keep) = update.backups_to_keep {
+        backup::set_backups_to_keep(&tx, keep)?;
+    }
+    if let Some(minutes) = update.deadline_notice_minutes {
+        notifications::set_notice_minutes(&tx, minutes);
+    }
+    if let Some(enabled) = update.close_to_tray {
+        tray::set_close_to_tray(&tx, enabled);
+    }
+    if let Some(mode) = update.badge_mode {
+        badge::set_badge_mode(&tx, mode);
+    }
+    if let Some(theme) = update.theme {
+        db_set_setting(&tx, THEME_SETTING, theme.as_str());
+    }
+    if let Some(max) = update.max_title_chars {
+        set_max_title_chars(&tx, max)?;
+    }
+    if let Some(level) = update.log_level {
+        logging::set_log_level(&tx, level);
+    }
+    if let Some(minutes) = update.auto_lock_minutes {
+        app_lock::set_auto_lock_minutes(&tx, minutes);
+    }
+    if let Some(enabled) = update.http_api_enabled {
+        http_api::set_enabled(&tx, enabled);
+    }
+    if let Some(port) = update.http_api_port {
+        http_api::set_port(&tx, port);
+    }
+    tx.commit().map_err(|e| e.to_string())?;
+    if let Some(level) = update.log_level {
+        logging::apply_level(level);
+    }
+    Ok(load(conn))
+}
+
+/// The stored key for the frontend's `key`: lowercase letters, digits, `_`,
+/// `-` and `.`, at most `MAX_UI_KEY_CHARS` of them.
+fn ui_key(key: &str) -> Result<String, String> {
+    let valid = key
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '_' | '-' | '.'));
+    if key.is_empty() || key.len() > MAX_UI_KEY_CHARS || !valid {
+        return Err(format!(
+            "Setting names are 1 to {} of a-z, 0-9, '_', '-' and '.'",
+            MAX_UI_KEY_CHARS
+        ));
+    }
+    Ok(format!("{}{}", UI_SETTING_PREFIX, key))
+}
+
+/// A value the frontend stored with `set_ui`, as it was given; `None` if it
+/// never set one.
+pub fn get_ui(conn: &Connection, key: &str) -> Result<Option<String>, String> {
+    Ok(db_get_setting(conn, &ui_key(key)?))
+}
+
+/// Stores a frontend preference, such as the default sort, replacing any
+/// earlier value. The backend doesn't read it; the frontend serializes it
+/// however it likes.
+pub fn set_ui(conn: &Connection, key: &str, value: &str) -> Result<(), String> {
+    let key = ui_key(key)?;
+    if value.chars().count() > MAX_UI_VALUE_CHARS {
+        return Err(format!(
+            "Setting values are at most {} characters",
+            MAX_UI_VALUE_CHARS
+        ));
+    }
+    db_set_setting(conn, &key, value);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::setup_test_db;
+
+    fn rows(conn: &Connection) -> Vec<(String, String)> {
+        let mut stmt = conn
+            .prepare("SELECT key, value FROM settings ORDER BY key")
+            .unwrap();
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap();
+        rows.map(|r| r.unwrap()).collect()
+    }
+
+    #[test]
+    fn test_defaults_on_empty_table() {
+        let conn = setup_test_db();
+        assert_eq!(load(&conn), Settings::default());
+    }
+
+    #[test]
+    fn test_partial_update_only_touches_supplied_fields() {
+        let conn = setup_test_db();
+
+        let settings = update(
+            &conn,
+            SettingsUpdate {
+                backups_to_keep: Some(12),
+                theme: Some(Theme::Dark),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            settings,
+            Settings {
+                backups_to_keep: 12,
+                theme: Theme::Dark,
+                ..Settings::default()
+            }
+        );
+        assert_eq!(
+            rows(&conn),
+            vec![
+                ("backups_to_keep".to_string(), "12".to_string()),
+                ("theme".to_string(), "dark".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_update_persists_nothing() {
+        let conn = setup_test_db();
+
+        for bad in [
+            SettingsUpdate {
+                theme: Some(Theme::Light),
+                backups_to_keep: Some(0),
+                ..Default::default()
+            },
+            SettingsUpdate {
+                close_to_tray: Some(false),
+                backups_to_keep: Some(backup::MAX_BACKUPS_TO_KEEP + 1),
+                ..Default::default()
+            },
+            SettingsUpdate {
+                badge_mode: Some(BadgeMode::Off),
+                deadline_notice_minutes: Some(MAX_NOTICE_MINUTES + 1),
+                ..Default::default()
+            },
+            SettingsUpdate {
+                theme: Some(Theme::Dark),
+                max_title_chars: Some(0),
+                ..Default::default()
+            },
+            SettingsUpdate {
+                close_to_tray: Some(false),
+                auto_lock_minutes: Some(app_lock::MAX_AUTO_LOCK_MINUTES + 1),
+                ..Default::default()
+            },
+            SettingsUpdate {
+                http_api_enabled: Some(true),
+                http_api_port: Some(80),
+                ..Default::default()
+            },
+        ] {
+            assert!(update(&conn, bad).is_err());
+        }
+
+        assert!(rows(&conn).is_empty());
+    }
+
+    #[test]
+    fn test_unknown_keys_are_preserved() {
+        let conn = setup_test_db();
+        db_set_setting(&conn, "from_a_newer_version", "42");
+
+        update(
+            &conn,
+            SettingsUpdate {
+                close_to_tray: Some(false),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            db_get_setting(&conn, "from_a_newer_version").as_deref(),
+            Some("42")
+        );
+        assert!(!load(&conn).close_to_tray);
+    }
+
+    #[test]
+    fn test_ui_settings() {
+        let conn = setup_test_db();
+        assert_eq!(get_ui(&conn, "default_sort").unwrap(), None);
+
+        set_ui(
+            &conn,
+            "default_sort",
+            r#"{"field":"deadline","direction":"asc"}"#,
+        )
+        .unwrap();
+        set_ui(
+            &conn,
+            "default_sort",
+            r#"{"field":"created","direction":"desc"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            get_ui(&conn, "default_sort").unwrap().as_deref(),
+            Some(r#"{"field":"created","direction":"desc"}"#)
+        );
+
+        // Typed settings can't be reached by name.
+        set_ui(&conn, THEME_SETTING, "not a theme").unwrap();
+        assert_eq!(load(&conn).theme, Theme::System);
+        assert_eq!(
+            rows(&conn)
+                .iter()
+                .map(|(k, _)| k.as_str())
+                .collect::<Vec<_>>(),
+            ["ui.default_sort", "ui.theme"]
+        );
+
+        for bad in [
+            "",
+            "Default Sort",
+            "x".repeat(MAX_UI_KEY_CHARS + 1).as_str(),
+        ] {
+            assert!(set_ui(&conn, bad, "1").is_err(), "{:?}", bad);
+            assert!(get_ui(&conn, bad).is_err());
+        }
+        assert!(set_ui(&conn, "big", &"x".repeat(MAX_UI_VALUE_CHARS + 1)).is_err());
+    }
+
+    #[test]
+    fn test_update_rejects_unknown_fields() {
+        let parsed: Result<SettingsUpdate, _> =
+            serde_json::from_str(r#"{"theme":"dark","colour":"red"}"#);
+        assert!(parsed.is_err());
+    }
+}
@@ -0,0 +1,227 @@
+use crate::{db_get_setting, db_set_setting};
+use rusqlite::Connection;
+use serde::Serialize;
+use std::fmt;
+use tauri::{AppHandle, Runtime};
+use tauri_plugin_global_shortcut::{GlobalShortcutExt, Modifiers, Shortcut};
+
+/// Settings key holding the accelerator that opens the quick-add window.
+pub const QUICK_ADD_SHORTCUT_SETTING: &str = "quick_add_shortcut";
+pub const DEFAULT_QUICK_ADD_SHORTCUT: &str = "Ctrl+Shift+Space";
+
+/// Why a shortcut could not be used. Serialized as `{"kind", "message"}` so the
+/// frontend can tell a typo from a combination the OS refused.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+#[serde(tag = "kind", content = "message", rename_all = "snake_case")]
+pub enum ShortcutError {
+    /// The accelerator string doesn't describe a usable shortcut.
+    Invalid(String),
+    /// The OS refused to register it, usually because another app owns it.
+    Rejected(String),
+}
+
+impl fmt::Display for ShortcutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShortcutError::Invalid(msg) => write!(f, "invalid shortcut: {}", msg),
+            ShortcutError::Rejected(msg) => write!(f, "shortcut rejected: {}", msg),
+        }
+    }
+}
+
+/// Registers global shortcuts. The app goes through the global-shortcut
+/// plugin; tests record the calls instead.
+pub trait ShortcutRegistrar {
+    fn register(&self, shortcut: Shortcut) -> Result<(), String>;
+    fn unregister(&self, shortcut: Shortcut) -> Result<(), String>;
+}
+
+impl<R: Runtime> ShortcutRegistrar for AppHandle<R> {
+    fn register(&self, shortcut: Shortcut) -> Result<(), String> {
+        self.global_shortcut()
+            .register(shortcut)
+            .map_err(|e| e.to_string())
+    }
+
+    fn unregister(&self, shortcut: Shortcut) -> Result<(), String> {
+        self.global_shortcut()
+            .unregister(shortcut)
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Parses an accelerator such as `Ctrl+Shift+Space`. A modifier is required
+/// so the shortcut can't swallow ordinary typing in other apps.
+pub fn parse_accelerator(accel: &str) -> Result<Shortcut, ShortcutError> {
+    let shortcut: Shortcut = accel
+        .trim()
+        .parse()
+        .map_err(|e| ShortcutError::Invalid(format!("{}", e)))?;
+    let modifiers = Modifiers::CONTROL | Modifiers::ALT | Modifiers::SHIFT | Modifiers::SUPER;
+    if !shortcut.mods.intersects(modifiers) {
+        return Err(ShortcutError::Invalid(format!(
+            "'{}' needs at least one of Ctrl, Alt, Shift or Super",
+            accel.trim()
+        )));
+    }
+    Ok(shortcut)
+}
+
+pub fn quick_add_shortcut(conn: &Connection) -> String {
+    db_get_setting(conn, QUICK_ADD_SHORTCUT_SETTING)
+        .unwrap_or_else(|| DEFAULT_QUICK_ADD_SHORTCUT.to_string())
+}
+
+/// Registers the saved shortcut at startup.
+pub fn register_saved(
+    conn: &Connection,
+    registrar: &impl ShortcutRegistrar,
+) -> Result<(), ShortcutError> {
+    let shortcut = parse_accelerator(&quick_add_shortcut(conn))?;
+    registrar
+        .register(shortcut)
+        .map_err(ShortcutError::Rejected)
+}
+
+/// Swaps the quick-add shortcut for `accel` and saves it. If the OS rejects
+/// the new one, the previous shortcut is registered again and the setting
+/// is left alone.
+pub fn set_quick_add_shortcut(
+    conn: &Connection,
+    registrar: &impl ShortcutRegistrar,
+    accel: &str,
+) -> Result<(), ShortcutError> {
+    let new = parse_accelerator(accel)?;
+    let old = parse_accelerator(&quick_add_shortcut(conn)).ok();
+    if let Some(old) = old {
+        // The old one may never have registered (e.g. it was taken at startup).
+        let _ = registrar.unregister(old);
+    }
+    if let Err(e) = registrar.register(new) {
+        if let Some(old) = old {
+            let _ = registrar.register(old);
+        }
+        return Err(ShortcutError::Rejected(e));
+    }
+    db_set_setting(conn, QUICK_ADD_SHORTCUT_SETTING, accel.trim());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::setup_test_db;
+    use std::cell::RefCell;
+
+    /// Tracks what is currently registered; refuses anything in `taken`.
+    #[derive(Default)]
+    struct FakeRegistrar {
+        registered: RefCell<Vec<Shortcut>>,
+        taken: Vec<Shortcut>,
+    }
+
+    impl ShortcutRegistrar for FakeRegistrar {
+        fn register(&self, shortcut: Shortcut) -> Result<(), String> {
+            if self.taken.contains(&shortcut) {
+                return Err("already registered by another application".to_string());
+            }
+            self.registered.borrow_mut().push(shortcut);
+            Ok(())
+        }
+
+        fn unregister(&self, shortcut: Shortcut) -> Result<(), String> {
+            self.registered.borrow_mut().retain(|s| *s != shortcut);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_parse_accelerator() {
+        assert!(parse_accelerator(DEFAULT_QUICK_ADD_SHORTCUT).is_ok());
+        assert!(parse_accelerator(" Alt+T ").is_ok());
+        assert_eq!(
+            parse_accelerator("ctrl+shift+space").unwrap(),
+            parse_accelerator("Ctrl+Shift+Space").unwrap()
+        );
+
+        for bad in ["", "Ctrl+", "Ctrl+Nope", "Hyperdrive+T"] {
+            assert!(
+                matches!(parse_accelerator(bad), Err(ShortcutError::Invalid(_))),
+                "{:?} should be invalid",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn test_bare_key_is_rejected() {
+        let err = parse_accelerator("T").unwrap_err();
+        assert!(matches!(err, ShortcutError::Invalid(ref msg) if msg.contains("at least one")));
+    }
+
+    #[test]
+    fn test_default_is_used_until_changed() {
+        let conn = setup_test_db();
+        let registrar = FakeRegistrar::default();
+        assert_eq!(quick_add_shortcut(&conn), DEFAULT_QUICK_ADD_SHORTCUT);
+
+        register_saved(&conn, &registrar).unwrap();
+
+        assert_eq!(
+            registrar.registered.borrow().as_slice(),
+            [parse_accelerator(DEFAULT_QUICK_ADD_SHORTCUT).unwrap()]
+        );
+    }
+
+    #[test]
+    fn test_set_shortcut_replaces_and_persists() {
+        let conn = setup_test_db();
+        let registrar = FakeRegistrar::default();
+        register_saved(&conn, &registrar).unwrap();
+
+        set_quick_add_shortcut(&conn, &registrar, "Alt+Shift+A").unwrap();
+
+        assert_eq!(quick_add_shortcut(&conn), "Alt+Shift+A");
+        assert_eq!(
+            registrar.registered.borrow().as_slice(),
+            [parse_accelerator("Alt+Shift+A").unwrap()]
+        );
+    }
+
+    #[test]
+    fn test_rejected_shortcut_keeps_previous() {
+        let conn = setup_test_db();
+        let registrar = FakeRegistrar {
+            taken: vec![parse_accelerator("Alt+Tab").unwrap()],
+            ..Default::default()
+        };
+        register_saved(&conn, &registrar).unwrap();
+
+        let err = set_quick_add_shortcut(&conn, &registrar, "Alt+Tab").unwrap_err();
+
+        assert!(matches!(err, ShortcutError::Rejected(_)));
+        assert_eq!(quick_add_shortcut(&conn), DEFAULT_QUICK_ADD_SHORTCUT);
+        assert_eq!(
+            registrar.registered.borrow().as_slice(),
+            [parse_accelerator(DEFAULT_QUICK_ADD_SHORTCUT).unwrap()]
+        );
+    }
+
+    #[test]
+    fn test_invalid_shortcut_is_not_saved() {
+        let conn = setup_test_db();
+        let registrar = FakeRegistrar::default();
+
+        let err = set_quick_add_shortcut(&conn, &registrar, "Ctrl+Nope").unwrap_err();
+
+        assert!(matches!(err, ShortcutError::Invalid(_)));
+        assert!(db_get_setting(&conn, QUICK_ADD_SHORTCUT_SETTING).is_none());
+        assert!(registrar.registered.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_error_serializes_kind_and_message() {
+        let json = serde_json::to_string(&ShortcutError::Rejected("taken".to_string())).unwrap();
+        assert_eq!(json, r#"{"kind":"rejected","message":"taken"}"#);
+    }
+}
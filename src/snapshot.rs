@@ -0,0 +1,103 @@
+use crate::{db_get_todos, Todo};
+use rusqlite::Connection;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// How the todo list changed between two snapshots, matched by id.
+#[derive(Debug, Serialize, Clone, Default, PartialEq)]
+pub struct Diff {
+    pub added: Vec<Todo>,
+    pub removed: Vec<u64>,
+    /// Each todo before and after, for ids present in both with any field
+    /// different.
+    pub changed: Vec<(Todo, Todo)>,
+}
+
+/// The whole todo list as it is now.
+pub fn snapshot(conn: &Connection) -> Vec<Todo> {
+    db_get_todos(conn)
+}
+
+/// Compares two snapshots. Every list in the result is in id order, whatever
+/// order the snapshots were in.
+pub fn diff_snapshots(before: &[Todo], after: &[Todo]) -> Diff {
+    let before: BTreeMap<u64, &Todo> = before.iter().map(|t| (t.id, t)).collect();
+    let after: BTreeMap<u64, &Todo> = after.iter().map(|t| (t.id, t)).collect();
+    let mut diff = Diff::default();
+    for (id, &new) in &after {
+        match before.get(id) {
+            None => diff.added.push(new.clone()),
+            Some(&old) if old != new => diff.changed.push((old.clone(), new.clone())),
+            Some(_) => {}
+        }
+    }
+    diff.removed = before
+        .keys()
+        .filter(|id| !after.contains_key(id))
+        .copied()
+        .collect();
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db_add_todo, db_delete_todo, db_update_todo, setup_test_db, test_todo};
+
+    #[test]
+    fn test_identical_snapshots_have_no_diff() {
+        let list = vec![test_todo(1, "a"), test_todo(2, "b")];
+        assert_eq!(diff_snapshots(&list, &list), Diff::default());
+        assert_eq!(diff_snapshots(&[], &[]), Diff::default());
+    }
+
+    #[test]
+    fn test_add_delete_and_change() {
+        let before = vec![
+            test_todo(1, "Keep"),
+            test_todo(2, "Delete"),
+            test_todo(3, "Rename"),
+        ];
+        let after = vec![
+            test_todo(4, "New"),
+            test_todo(3, "Renamed"),
+            Todo {
+                pinned: true,
+                ..test_todo(1, "Keep")
+            },
+        ];
+
+        let diff = diff_snapshots(&before, &after);
+
+        assert_eq!(diff.added, vec![test_todo(4, "New")]);
+        assert_eq!(diff.removed, vec![2]);
+        assert_eq!(
+            diff.changed,
+            vec![
+                (test_todo(1, "Keep"), after[2].clone()),
+                (test_todo(3, "Rename"), test_todo(3, "Renamed")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_snapshots_of_database() {
+        let conn = setup_test_db();
+        let kept = db_add_todo(&conn, "Kept".to_string(), None);
+        let deleted = db_add_todo(&conn, "Deleted".to_string(), None);
+        let before = snapshot(&conn);
+
+        db_delete_todo(&conn, deleted.id);
+        db_update_todo(&conn, kept.id, None, Some(true), None, None);
+        let added = db_add_todo(&conn, "Added".to_string(), None);
+        let diff = diff_snapshots(&before, &snapshot(&conn));
+
+        assert_eq!(
+            diff.added.iter().map(|t| t.id).collect::<Vec<_>>(),
+            vec![added.id]
+        );
+        assert_eq!(diff.removed, vec![deleted.id]);
+        assert_eq!(diff.changed.len(), 1);
+        assert!(!diff.changed[0].0.completed && diff.changed[0].1.completed);
+    }
+}
@@ -0,0 +1,192 @@
+use crate::deadline::deadline_in;
+use crate::deadline_text::coming;
+use chrono::{DateTime, Days, Duration as ChronoDuration, NaiveDate, NaiveTime, TimeZone, Weekday};
+use serde::Deserialize;
+
+const LATER_TODAY: ChronoDuration = ChronoDuration::hours(3);
+/// The furthest `Hours` can snooze: a year.
+pub const MAX_SNOOZE_HOURS: i64 = 366 * 24;
+
+/// How far `snooze_todo` pushes a deadline. Sent as `"later_today"` and so on,
+/// or `{"hours": 2}`.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SnoozePreset {
+    /// Three hours from now, but no later than the end of today.
+    LaterToday,
+    /// 09:00 tomorrow.
+    TomorrowMorning,
+    /// The next Saturday at 10:00, today if that's still to come.
+    ThisWeekend,
+    /// Monday at 09:00, a week on if today is Monday.
+    NextWeek,
+    /// This many hours past the later of the current deadline and now.
+    Hours(i64),
+}
+
+impl SnoozePreset {
+    /// An error for `Hours` outside 1 to `MAX_SNOOZE_HOURS`.
+    pub fn check(self) -> Result<(), String> {
+        match self {
+            SnoozePreset::Hours(hours) if !(1..=MAX_SNOOZE_HOURS).contains(&hours) => Err(format!(
+                "Snooze hours must be between 1 and {}",
+                MAX_SNOOZE_HOURS
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// `hour`:00 on `day` in `tz`, or an hour later if the clocks skip it.
+fn at<Tz: TimeZone>(tz: &Tz, day: NaiveDate, hour: u32) -> Option<DateTime<Tz>> {
+    let naive = day.and_time(NaiveTime::from_hms_opt(hour, 0, 0)?);
+    tz.from_local_datetime(&naive).earliest().or_else(|| {
+        tz.from_local_datetime(&(naive + ChronoDuration::hours(1)))
+            .earliest()
+    })
+}
+
+/// The new deadline for snoozing a todo with `deadline` at `now`. The presets
+/// count from `now` in its time zone; only `Hours` looks at the old deadline.
+/// `None` where the local time doesn't exist, or `Hours` runs past the dates
+/// chrono can hold.
+pub fn snoozed_until<Tz: TimeZone>(
+    preset: SnoozePreset,
+    deadline: Option<&str>,
+    now: DateTime<Tz>,
+) -> Option<DateTime<Tz>> {
+    let tz = now.timezone();
+    let today = now.date_naive();
+    match preset {
+        SnoozePreset::LaterToday => {
+            let end_of_day = today
+                .and_hms_opt(23, 59, 59)
+                .and_then(|naive| tz.from_local_datetime(&naive).latest())?;
+            Some((now.clone() + LATER_TODAY).min(end_of_day.max(now)))
+        }
+        SnoozePreset::TomorrowMorning => at(&tz, today + Days::new(1), 9),
+        SnoozePreset::ThisWeekend => {
+            let saturday = at(&tz, coming(today, Weekday::Sat, false), 10)?;
+            if saturday > now {
+                Some(saturday)
+            } else {
+                at(&tz, coming(today, Weekday::Sat, true), 10)
+            }
+        }
+        SnoozePreset::NextWeek => at(&tz, coming(today, Weekday::Mon, true), 9),
+        SnoozePreset::Hours(hours) => {
+            let anchor = deadline
+                .and_then(|d| deadline_in(d, &tz))
+                .map_or(now.clone(), |d| d.max(now));
+            anchor.checked_add_signed(ChronoDuration::try_hours(hours)?)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::FixedOffset;
+
+    fn local(value: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(value).unwrap()
+    }
+
+    fn snooze(preset: SnoozePreset, now: &str) -> String {
+        snoozed_until(preset, None, local(now))
+            .unwrap()
+            .to_rfc3339()
+    }
+
+    #[test]
+    fn test_later_today_stops_at_midnight() {
+        assert_eq!(
+            snooze(SnoozePreset::LaterToday, "2024-05-01T12:15:00+02:00"),
+            "2024-05-01T15:15:00+02:00"
+        );
+        assert_eq!(
+            snooze(SnoozePreset::LaterToday, "2024-05-01T22:00:00+02:00"),
+            "2024-05-01T23:59:59+02:00"
+        );
+    }
+
+    #[test]
+    fn test_friday_evening() {
+        let friday = "2024-06-07T19:30:00+02:00";
+        assert_eq!(
+            snooze(SnoozePreset::TomorrowMorning, friday),
+            "2024-06-08T09:00:00+02:00"
+        );
+        assert_eq!(
+            snooze(SnoozePreset::ThisWeekend, friday),
+            "2024-06-08T10:00:00+02:00"
+        );
+        assert_eq!(
+            snooze(SnoozePreset::NextWeek, friday),
+            "2024-06-10T09:00:00+02:00"
+        );
+    }
+
+    #[test]
+    fn test_weekend_presets() {
+        // Saturday before and after 10:00.
+        assert_eq!(
+            snooze(SnoozePreset::ThisWeekend, "2024-06-08T08:00:00+02:00"),
+            "2024-06-08T10:00:00+02:00"
+        );
+        assert_eq!(
+            snooze(SnoozePreset::ThisWeekend, "2024-06-08T10:00:00+02:00"),
+            "2024-06-15T10:00:00+02:00"
+        );
+        let sunday = "2024-06-09T11:00:00+02:00";
+        assert_eq!(
+            snooze(SnoozePreset::ThisWeekend, sunday),
+            "2024-06-15T10:00:00+02:00"
+        );
+        assert_eq!(
+            snooze(SnoozePreset::NextWeek, sunday),
+            "2024-06-10T09:00:00+02:00"
+        );
+        // On a Monday, next week is the Monday after.
+        assert_eq!(
+            snooze(SnoozePreset::NextWeek, "2024-06-10T08:00:00+02:00"),
+            "2024-06-17T09:00:00+02:00"
+        );
+    }
+
+    #[test]
+    fn test_hours_are_bounded() {
+        assert_eq!(SnoozePreset::Hours(1).check(), Ok(()));
+        assert_eq!(SnoozePreset::Hours(MAX_SNOOZE_HOURS).check(), Ok(()));
+        for hours in [0, -1, MAX_SNOOZE_HOURS + 1, i64::MAX, i64::MIN] {
+            assert!(SnoozePreset::Hours(hours).check().is_err(), "{}", hours);
+        }
+        assert_eq!(SnoozePreset::NextWeek.check(), Ok(()));
+        // Unchecked, an overflow is no result rather than a panic.
+        let now = local("2024-06-07T19:30:00+02:00");
+        assert_eq!(
+            snoozed_until(SnoozePreset::Hours(i64::MAX), None, now),
+            None
+        );
+        assert_eq!(
+            snoozed_until(SnoozePreset::Hours(1_000_000_000_000), None, now),
+            None
+        );
+    }
+
+    #[test]
+    fn test_presets_ignore_old_deadline() {
+        let now = local("2024-06-07T19:30:00+02:00");
+        let far = Some("2024-07-01T08:00:00+00:00");
+        assert_eq!(
+            snoozed_until(SnoozePreset::TomorrowMorning, far, now),
+            snoozed_until(SnoozePreset::TomorrowMorning, None, now)
+        );
+        assert_eq!(
+            snoozed_until(SnoozePreset::Hours(1), far, now)
+                .unwrap()
+                .to_rfc3339(),
+            "2024-07-01T11:00:00+02:00"
+        );
+    }
+}
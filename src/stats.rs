@@ -0,0 +1,398 @@
+use chrono::{Datelike, Duration as ChronoDuration, NaiveDate};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Longest range `completion_stats` accepts, so a typo in a year can't ask
+/// for millions of buckets.
+pub const MAX_RANGE_DAYS: i64 = 10 * 366;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Bucket {
+    Day,
+    /// Weeks start on Monday.
+    Week,
+}
+
+impl Bucket {
+    /// SQLite modifiers that turn a stored UTC timestamp into the local date
+    /// the bucket starts on. `localtime` uses the same zone as `Local`.
+    fn sql_modifiers(self) -> &'static str {
+        match self {
+            Bucket::Day => "'localtime'",
+            // `weekday 0` moves on to the Sunday ending the week.
+            Bucket::Week => "'localtime', 'weekday 0', '-6 days'",
+        }
+    }
+
+    fn start_of(self, day: NaiveDate) -> NaiveDate {
+        match self {
+            Bucket::Day => day,
+            Bucket::Week => day - ChronoDuration::days(day.weekday().num_days_from_monday() as i64),
+        }
+    }
+
+    fn step(self) -> ChronoDuration {
+        match self {
+            Bucket::Day => ChronoDuration::days(1),
+            Bucket::Week => ChronoDuration::weeks(1),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct CompletionStat {
+    /// `YYYY-MM-DD`, local time.
+    pub bucket_start: String,
+    pub created_count: u64,
+    pub completed_count: u64,
+}
+
+/// Counts per bucket start of `column` over active and archived todos, for
+/// local dates from `start` to `end` inclusive.
+fn counts_by_bucket(
+    conn: &Connection,
+    column: &str,
+    bucket: Bucket,
+    start: NaiveDate,
+    end: NaiveDate,
+) -> rusqlite::Result<HashMap<String, u64>> {
+    let sql = format!(
+        "SELECT date({column}, {modifiers}) AS bucket, COUNT(*) FROM (
+             SELECT {column} FROM todos
+             UNION ALL SELECT {column} FROM todos_archive
+         )
+         WHERE date({column}, 'localtime') BETWEEN ?1 AND ?2
+         GROUP BY bucket",
+        column = column,
+        modifiers = bucket.sql_modifiers()
+    );
+    let mut stmt = conn.prepare_cached(&sql)?;
+    let rows = stmt.query_map(
+        (
+            start.format("%Y-%m-%d").to_string(),
+            end.format("%Y-%m-%d").to_string(),
+        ),
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    rows.collect()
+}
+
+/// Every bucket from the one holding `start` through the one holding `end`,
+/// with zeroes where `created` and `completed` have no count.
+fn dense_series(
+    bucket: Bucket,
+    start: NaiveDate,
+    end: NaiveDate,
+    created: &HashMap<String, u64>,
+    completed: &HashMap<String, u64>,
+) -> Vec<CompletionStat> {
+    let mut series = Vec::new();
+    let mut day = bucket.start_of(start);
+    while day <= end {
+        let key = day.format("%Y-%m-%d").to_string();
+        series.push(CompletionStat {
+            created_count: created.get(&key).copied().unwrap_or(0),
+            completed_count: completed.get(&key).copied().unwrap_or(0),
+            bucket_start: key,
+        });
+        day += bucket.step();
+    }
+    series
+}
+
+/// How many todos were created and completed in each day or week from
+/// `start` to `end` (local dates, inclusive), archived ones included. Every
+/// bucket in the range is present, so the series can be charted directly.
+pub fn completion_stats(
+    conn: &Connection,
+    start: NaiveDate,
+    end: NaiveDate,
+    bucket: Bucket,
+) -> Result<Vec<CompletionStat>, String> {
+    if end < start {
+        return Err("The range ends before it starts".to_string());
+    }
+    if (end - start).num_days() > MAX_RANGE_DAYS {
+        return Err(format!("The range is longer than {} days", MAX_RANGE_DAYS));
+    }
+    let created =
+        counts_by_bucket(conn, "created_at", bucket, start, end).map_err(|e| e.to_string())?;
+    let completed =
+        counts_by_bucket(conn, "completed_at", bucket, start, end).map_err(|e| e.to_string())?;
+    Ok(dense_series(bucket, start, end, &created, &completed))
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Default)]
+pub struct Streak {
+    pub current_streak_days: u32,
+    pub longest_streak_days: u32,
+    /// Whether something was completed today. Until then a streak that
+    /// ended yesterday still counts as current.
+    pub today_counted: bool,
+}
+
+/// Works out the streaks from the distinct days with a completion, in
+/// ascending order. Days after `today` (a clock that was ahead) are ignored.
+pub fn streak_from_days(days: &[NaiveDate], today: NaiveDate) -> Streak {
+    let mut streak = Streak::default();
+    let mut run = 0;
+    let mut previous: Option<NaiveDate> = None;
+    for &day in days.iter().take_while(|&&day| day <= today) {
+        run = match previous {
+            Some(p) if day - p == ChronoDuration::days(1) => run + 1,
+            _ => 1,
+        };
+        streak.longest_streak_days = streak.longest_streak_days.max(run);
+        previous = Some(day);
+    }
+    let yesterday = today - ChronoDuration::days(1);
+    streak.today_counted = previous == Some(today);
+    if previous == Some(today) || previous == Some(yesterday) {
+        streak.current_streak_days = run;
+    }
+    streak
+}
+
+/// The local dates with at least one completion, active and archived todos
+/// alike, in ascending order.
+fn completion_days(conn: &Connection) -> rusqlite::Result<Vec<NaiveDate>> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT date(completed_at, 'localtime') AS day FROM (
+             SELECT completed_at FROM todos
+             UNION ALL SELECT completed_at FROM todos_archive
+         )
+         WHERE day IS NOT NULL GROUP BY day ORDER BY day",
+    )?;
+    let days = stmt.query_map([], |row| row.get::<_, String>(0))?;
+    days.map(|day| day.map(|day| NaiveDate::parse_from_str(&day, "%Y-%m-%d").ok()))
+        .filter_map(Result::transpose)
+        .collect()
+}
+
+/// Current and longest run of days with a completion, as of `today`.
+pub fn streak(conn: &Connection, today: NaiveDate) -> Result<Streak, String> {
+    let days = completion_days(conn).map_err(|e| e.to_string())?;
+    Ok(streak_from_days(&days, today))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::setup_test_db;
+    use chrono::{Local, TimeZone, Utc};
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    /// Stored the way the app stores timestamps: UTC RFC 3339.
+    fn stamp(y: i32, m: u32, d: u32, h: u32, min: u32) -> String {
+        Local
+            .with_ymd_and_hms(y, m, d, h, min, 0)
+            .unwrap()
+            .with_timezone(&Utc)
+            .to_rfc3339()
+    }
+
+    fn insert(conn: &Connection, created_at: &str, completed_at: Option<&str>) {
+        conn.execute(
+            "INSERT INTO todos (title, completed, created_at, completed_at)
+             VALUES ('t', ?1, ?2, ?3)",
+            (completed_at.is_some(), created_at, completed_at),
+        )
+        .unwrap();
+    }
+
+    /// May 2024: the 1st is a Wednesday.
+    fn fixture(conn: &Connection) {
+        insert(
+            conn,
+            &stamp(2024, 5, 1, 9, 0),
+            Some(&stamp(2024, 5, 2, 10, 0)),
+        );
+        insert(conn, &stamp(2024, 5, 1, 23, 59), None);
+        insert(
+            conn,
+            &stamp(2024, 5, 2, 0, 1),
+            Some(&stamp(2024, 5, 6, 0, 30)),
+        );
+        insert(
+            conn,
+            &stamp(2024, 5, 5, 23, 30),
+            Some(&stamp(2024, 5, 31, 12, 0)),
+        );
+        insert(conn, &stamp(2024, 5, 20, 8, 0), None);
+        insert(
+            conn,
+            &stamp(2024, 4, 30, 23, 59),
+            Some(&stamp(2024, 6, 1, 0, 0)),
+        );
+    }
+
+    fn counts(stats: &[CompletionStat]) -> Vec<(&str, u64, u64)> {
+        stats
+            .iter()
+            .filter(|s| s.created_count + s.completed_count > 0)
+            .map(|s| (s.bucket_start.as_str(), s.created_count, s.completed_count))
+            .collect()
+    }
+
+    #[test]
+    fn test_daily_buckets() {
+        let conn = setup_test_db();
+        fixture(&conn);
+
+        let stats =
+            completion_stats(&conn, date(2024, 5, 1), date(2024, 5, 31), Bucket::Day).unwrap();
+
+        assert_eq!(stats.len(), 31);
+        assert_eq!(stats[0].bucket_start, "2024-05-01");
+        assert_eq!(stats[30].bucket_start, "2024-05-31");
+        assert_eq!(
+            counts(&stats),
+            vec![
+                ("2024-05-01", 2, 0),
+                ("2024-05-02", 1, 1),
+                ("2024-05-05", 1, 0),
+                ("2024-05-06", 0, 1),
+                ("2024-05-20", 1, 0),
+                ("2024-05-31", 0, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_weekly_buckets_start_on_monday() {
+        let conn = setup_test_db();
+        fixture(&conn);
+
+        let stats =
+            completion_stats(&conn, date(2024, 5, 1), date(2024, 5, 31), Bucket::Week).unwrap();
+
+        let starts: Vec<&str> = stats.iter().map(|s| s.bucket_start.as_str()).collect();
+        assert_eq!(
+            starts,
+            vec![
+                "2024-04-29",
+                "2024-05-06",
+                "2024-05-13",
+                "2024-05-20",
+                "2024-05-27"
+            ]
+        );
+        // The todo created on Tuesday 30 April is before the range.
+        assert_eq!(
+            counts(&stats),
+            vec![
+                ("2024-04-29", 4, 1),
+                ("2024-05-06", 0, 1),
+                ("2024-05-20", 1, 0),
+                ("2024-05-27", 0, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_empty_range_is_all_zero() {
+        let conn = setup_test_db();
+        fixture(&conn);
+
+        let stats =
+            completion_stats(&conn, date(2023, 1, 1), date(2023, 1, 7), Bucket::Day).unwrap();
+
+        assert_eq!(stats.len(), 7);
+        assert!(counts(&stats).is_empty());
+        assert!(completion_stats(&conn, date(2024, 5, 2), date(2024, 5, 1), Bucket::Day).is_err());
+        assert!(completion_stats(&conn, date(2000, 1, 1), date(2024, 1, 1), Bucket::Week).is_err());
+    }
+
+    #[test]
+    fn test_archived_todos_still_count() {
+        let conn = setup_test_db();
+        fixture(&conn);
+        crate::db_archive_completed(&conn);
+
+        let stats =
+            completion_stats(&conn, date(2024, 5, 1), date(2024, 5, 31), Bucket::Week).unwrap();
+
+        assert_eq!(counts(&stats)[0], ("2024-04-29", 4, 1));
+    }
+
+    fn days(list: &[(u32, u32)]) -> Vec<NaiveDate> {
+        list.iter().map(|&(m, d)| date(2024, m, d)).collect()
+    }
+
+    #[test]
+    fn test_streak_with_gaps() {
+        let completions = days(&[
+            (4, 1),
+            (4, 2),
+            (4, 3),
+            (4, 4),
+            (4, 10),
+            (4, 29),
+            (4, 30),
+            (5, 1),
+        ]);
+
+        assert_eq!(
+            streak_from_days(&completions, date(2024, 5, 1)),
+            Streak {
+                current_streak_days: 3,
+                longest_streak_days: 4,
+                today_counted: true
+            }
+        );
+        // Nothing yet on the 2nd: the streak stays current until midnight.
+        assert_eq!(
+            streak_from_days(&completions, date(2024, 5, 2)),
+            Streak {
+                current_streak_days: 3,
+                longest_streak_days: 4,
+                today_counted: false
+            }
+        );
+        assert_eq!(
+            streak_from_days(&completions, date(2024, 5, 3)).current_streak_days,
+            0
+        );
+    }
+
+    #[test]
+    fn test_single_day_and_empty_streaks() {
+        assert_eq!(streak_from_days(&[], date(2024, 5, 1)), Streak::default());
+        assert_eq!(
+            streak_from_days(&days(&[(5, 1)]), date(2024, 5, 1)),
+            Streak {
+                current_streak_days: 1,
+                longest_streak_days: 1,
+                today_counted: true
+            }
+        );
+        // A day the clock was ahead doesn't count.
+        let streak = streak_from_days(&days(&[(4, 30), (5, 9)]), date(2024, 5, 1));
+        assert_eq!(streak.current_streak_days, 1);
+        assert!(!streak.today_counted);
+    }
+
+    #[test]
+    fn test_streak_from_database_counts_days_once() {
+        let conn = setup_test_db();
+        for (d, h) in [(29, 9), (29, 18), (30, 0), (30, 23)] {
+            let at = stamp(2024, 4, d, h, 30);
+            insert(&conn, &at, Some(&at));
+        }
+        insert(&conn, &stamp(2024, 5, 1, 8, 0), None);
+
+        assert_eq!(
+            streak(&conn, date(2024, 5, 1)),
+            Ok(Streak {
+                current_streak_days: 2,
+                longest_streak_days: 2,
+                today_counted: false
+            })
+        );
+    }
+}
@@ -0,0 +1,323 @@
+use crate::{db_get_setting, db_get_todos, db_set_setting, parse_deadline, AppState, Todo};
+use chrono::{DateTime, Local};
+use rusqlite::Connection;
+use std::time::Instant;
+use tauri::menu::{Menu, MenuEvent, MenuItem, PredefinedMenuItem};
+use tauri::tray::{MouseButton, MouseButtonState, TrayIcon, TrayIconBuilder, TrayIconEvent};
+use tauri::{AppHandle, Emitter, Manager, Runtime, WebviewUrl, WebviewWindowBuilder};
+
+pub const TRAY_ID: &str = "main-tray";
+pub const MAIN_WINDOW: &str = "main";
+pub const QUICK_ADD_WINDOW: &str = "quick-add";
+/// Asks the main window to scroll to and highlight a todo; carries its id.
+pub const FOCUS_TODO_EVENT: &str = "focus-todo";
+/// How many upcoming todos the tray menu lists.
+const DUE_SOON_ITEMS: usize = 5;
+const MAX_LABEL_CHARS: usize = 40;
+/// Settings key: whether closing the main window hides it to the tray.
+pub const CLOSE_TO_TRAY_SETTING: &str = "close_to_tray";
+
+const SHOW_ID: &str = "show";
+const ADD_ID: &str = "add";
+const QUIT_ID: &str = "quit";
+const TODO_ID_PREFIX: &str = "todo:";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TrayEntry {
+    Item { id: String, label: String },
+    Separator,
+}
+
+/// Everything the tray shows, independent of the tray API so it can be tested.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrayMenu {
+    pub tooltip: String,
+    pub entries: Vec<TrayEntry>,
+}
+
+fn item(id: impl Into<String>, label: impl Into<String>) -> TrayEntry {
+    TrayEntry::Item {
+        id: id.into(),
+        label: label.into(),
+    }
+}
+
+fn truncate(title: &str) -> String {
+    if title.chars().count() <= MAX_LABEL_CHARS {
+        return title.to_string();
+    }
+    let mut short: String = title.chars().take(MAX_LABEL_CHARS - 1).collect();
+    short.push('…');
+    short
+}
+
+/// The tooltip counts pending todos; the menu lists the incomplete todos with
+/// the nearest deadlines (overdue ones first) between the fixed actions.
+pub fn describe_menu(todos: &[Todo], now: DateTime<Local>) -> TrayMenu {
+    let pending = todos.iter().filter(|t| !t.completed).count();
+    let tooltip = match pending {
+        0 => "No pending todos".to_string(),
+        1 => "1 pending todo".to_string(),
+        n => format!("{} pending todos", n),
+    };
+
+    let mut upcoming: Vec<(DateTime<Local>, &Todo)> = todos
+        .iter()
+        .filter(|t| !t.completed)
+        .filter_map(|t| Some((t.deadline.as_deref().and_then(parse_deadline)?, t)))
+        .collect();
+    upcoming.sort_by_key(|(due, _)| *due);
+
+    let mut entries = vec![item(SHOW_ID, "Show window"), item(ADD_ID, "Add todo…")];
+    if !upcoming.is_empty() {
+        entries.push(TrayEntry::Separator);
+        for (due, todo) in upcoming.into_iter().take(DUE_SOON_ITEMS) {
+            let when = if due < now {
+                "overdue".to_string()
+            } else {
+                due.format("%b %-d %H:%M").to_string()
+            };
+            entries.push(item(
+                format!("{}{}", TODO_ID_PREFIX, todo.id),
+                format!("{} ({})", truncate(&todo.title), when),
+            ));
+        }
+    }
+    entries.push(TrayEntry::Separator);
+    entries.push(item(QUIT_ID, "Quit"));
+    TrayMenu { tooltip, entries }
+}
+
+pub fn close_to_tray(conn: &Connection) -> bool {
+    db_get_setting(conn, CLOSE_TO_TRAY_SETTING).is_none_or(|v| v == "true")
+}
+
+pub fn set_close_to_tray(conn: &Connection, enabled: bool) {
+    db_set_setting(conn, CLOSE_TO_TRAY_SETTING, &enabled.to_string());
+}
+
+fn build_menu<R: Runtime>(app: &AppHandle<R>, menu: &TrayMenu) -> tauri::Result<Menu<R>> {
+    let built = Menu::new(app)?;
+    for entry in &menu.entries {
+        match entry {
+            TrayEntry::Item { id, label } => {
+                built.append(&MenuItem::with_id(app, id, label, true, None::<&str>)?)?
+            }
+            TrayEntry::Separator => built.append(&PredefinedMenuItem::separator(app)?)?,
+        }
+    }
+    Ok(built)
+}
+
+pub fn show_main_window<R: Runtime>(app: &AppHandle<R>) {
+    if let Some(window) = app.get_webview_window(MAIN_WINDOW) {
+        let _ = window.show();
+        let _ = window.unminimize();
+        let _ = window.set_focus();
+    }
+}
+
+fn toggle_main_window<R: Runtime>(app: &AppHandle<R>) {
+    let Some(window) = app.get_webview_window(MAIN_WINDOW) else {
+        return;
+    };
+    if window.is_visible().unwrap_or(false) {
+        let _ = window.hide();
+    } else {
+        show_main_window(app);
+    }
+}
+
+/// Brings the main window to the front and highlights todo `id` in it.
+pub fn reveal_todo<R: Runtime>(app: &AppHandle<R>, id: u64) -> tauri::Result<()> {
+    show_main_window(app);
+    app.emit_to(MAIN_WINDOW, FOCUS_TODO_EVENT, id)
+}
+
+/// Shows the small always-on-top quick-add window, creating it on first use.
+pub fn show_quick_add<R: Runtime>(app: &AppHandle<R>) -> tauri::Result<()> {
+    if let Some(window) = app.get_webview_window(QUICK_ADD_WINDOW) {
+        window.show()?;
+        return window.set_focus();
+    }
+    WebviewWindowBuilder::new(
+        app,
+        QUICK_ADD_WINDOW,
+        WebviewUrl::App("quick-add.html".into()),
+    )
+    .title("Add todo")
+    .inner_size(420.0, 70.0)
+    .resizable(false)
+    .always_on_top(true)
+    .skip_taskbar(true)
+    .center()
+    .build()?;
+    Ok(())
+}
+
+fn on_menu_event<R: Runtime>(app: &AppHandle<R>, event: MenuEvent) {
+    let id = event.id().as_ref();
+    let result = match id {
+        SHOW_ID => {
+            show_main_window(app);
+            Ok(())
+        }
+        ADD_ID => show_quick_add(app),
+        QUIT_ID => {
+            app.exit(0);
+            Ok(())
+        }
+        _ => match id
+            .strip_prefix(TODO_ID_PREFIX)
+            .and_then(|n| n.parse::<u64>().ok())
+        {
+            Some(todo_id) => reveal_todo(app, todo_id),
+            None => Ok(()),
+        },
+    };
+    if let Err(e) = result {
+        tracing::warn!("tray action {} failed: {}", id, e);
+    }
+}
+
+fn on_tray_icon_event<R: Runtime>(tray: &TrayIcon<R>, event: TrayIconEvent) {
+    if let TrayIconEvent::Click {
+        button: MouseButton::Left,
+        button_state: MouseButtonState::Up,
+        ..
+    } = event
+    {
+        toggle_main_window(tray.app_handle());
+    }
+}
+
+pub fn create<R: Runtime>(app: &AppHandle<R>) -> tauri::Result<()> {
+    let mut builder = TrayIconBuilder::with_id(TRAY_ID)
+        .show_menu_on_left_click(false)
+        .on_menu_event(on_menu_event)
+        .on_tray_icon_event(on_tray_icon_event);
+    if let Some(icon) = app.default_window_icon() {
+        builder = builder.icon(icon.clone());
+    }
+    builder.build(app)?;
+    refresh(app)
+}
+
+/// Rebuilds the tray menu and tooltip from the current todos.
+pub fn refresh<R: Runtime>(app: &AppHandle<R>) -> tauri::Result<()> {
+    let Some(tray) = app.tray_by_id(TRAY_ID) else {
+        return Ok(());
+    };
+    // Titles stay out of the menu while the app is locked.
+    let todos = {
+        let state = app.state::<AppState>();
+        if state.app_lock.is_locked(Instant::now()) {
+            Vec::new()
+        } else {
+            db_get_todos(&state.db.lock().unwrap())
+        }
+    };
+    let menu = describe_menu(&todos, Local::now());
+    tray.set_menu(Some(build_menu(app, &menu)?))?;
+    tray.set_tooltip(Some(&menu.tooltip))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_todo;
+    use chrono::TimeZone;
+
+    fn todo(id: u64, title: &str, completed: bool, deadline: Option<&str>) -> Todo {
+        Todo {
+            completed,
+            deadline: deadline.map(String::from),
+            ..test_todo(id, title)
+        }
+    }
+
+    fn labels(menu: &TrayMenu) -> Vec<&str> {
+        menu.entries
+            .iter()
+            .map(|e| match e {
+                TrayEntry::Item { label, .. } => label.as_str(),
+                TrayEntry::Separator => "---",
+            })
+            .collect()
+    }
+
+    fn now() -> DateTime<Local> {
+        Local.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_menu_without_deadlines_has_only_actions() {
+        let menu = describe_menu(&[todo(1, "Someday", false, None)], now());
+
+        assert_eq!(menu.tooltip, "1 pending todo");
+        assert_eq!(
+            labels(&menu),
+            vec!["Show window", "Add todo…", "---", "Quit"]
+        );
+    }
+
+    #[test]
+    fn test_menu_lists_five_nearest_incomplete_deadlines() {
+        let mut todos = vec![
+            todo(1, "Done", true, Some("2024-05-01T12:30")),
+            todo(2, "Late", false, Some("2024-04-30T09:00")),
+            todo(3, "Unscheduled", false, None),
+        ];
+        for (i, day) in (3..=8).enumerate() {
+            let deadline = format!("2024-05-{:02}T09:00", day);
+            todos.push(todo(
+                10 + i as u64,
+                &format!("Day {}", day),
+                false,
+                Some(&deadline),
+            ));
+        }
+
+        let menu = describe_menu(&todos, now());
+
+        assert_eq!(menu.tooltip, "8 pending todos");
+        assert_eq!(
+            labels(&menu),
+            vec![
+                "Show window",
+                "Add todo…",
+                "---",
+                "Late (overdue)",
+                "Day 3 (May 3 09:00)",
+                "Day 4 (May 4 09:00)",
+                "Day 5 (May 5 09:00)",
+                "Day 6 (May 6 09:00)",
+                "---",
+                "Quit",
+            ]
+        );
+        assert_eq!(menu.entries[3], item("todo:2", "Late (overdue)"));
+    }
+
+    #[test]
+    fn test_long_titles_are_truncated() {
+        let title = "x".repeat(60);
+        let menu = describe_menu(&[todo(1, &title, false, Some("2024-05-02T09:00"))], now());
+
+        let TrayEntry::Item { label, .. } = &menu.entries[3] else {
+            panic!("expected an item");
+        };
+        assert!(label.starts_with(&format!("{}…", "x".repeat(MAX_LABEL_CHARS - 1))));
+    }
+
+    #[test]
+    fn test_close_to_tray_defaults_on() {
+        let conn = crate::setup_test_db();
+        assert!(close_to_tray(&conn));
+
+        set_close_to_tray(&conn, false);
+
+        assert!(!close_to_tray(&conn));
+    }
+}
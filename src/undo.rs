@@ -0,0 +1,406 @@
+use crate::{db_add_tag, db_get_tags, db_get_todo, to_utc, Todo};
+use rusqlite::{Connection, OptionalExtension};
+use std::collections::VecDeque;
+
+/// How many operations `undo` can step back through.
+pub const UNDO_LIMIT: usize = 20;
+
+/// A todo row as it was at some point, including its tags, its edit history
+/// and the uuid `merge_export` knows it by.
+#[derive(Debug, Clone, PartialEq)]
+struct Snapshot {
+    todo: Todo,
+    tags: Vec<String>,
+    uuid: Option<String>,
+    history: Vec<HistoryRow>,
+}
+
+/// A `todo_history` row as stored. Deleting a todo drops its history, so
+/// undoing the delete has to put it back.
+#[derive(Debug, Clone, PartialEq)]
+struct HistoryRow {
+    id: u64,
+    changed_at: String,
+    field: String,
+    old_value: Option<String>,
+    new_value: Option<String>,
+}
+
+/// The state some todos were in before an operation. `None` means the todo
+/// didn't exist yet, so undoing deletes it again.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    rows: Vec<(u64, Option<Snapshot>)>,
+}
+
+impl Change {
+    /// The change made by inserting the todo `id`.
+    pub fn created(id: u64) -> Self {
+        Change::created_all(&[id])
+    }
+
+    /// The change made by inserting the todos `ids` at once, as an import does.
+    pub fn created_all(ids: &[u64]) -> Self {
+        Change {
+            rows: ids.iter().map(|&id| (id, None)).collect(),
+        }
+    }
+}
+
+fn history_rows(conn: &Connection, id: u64) -> rusqlite::Result<Vec<HistoryRow>> {
+    conn.prepare_cached(
+        "SELECT id, changed_at, field, old_value, new_value FROM todo_history
+         WHERE todo_id = ?1
+         ORDER BY id",
+    )?
+    .query_map((id,), |row| {
+        Ok(HistoryRow {
+            id: row.get(0)?,
+            changed_at: row.get(1)?,
+            field: row.get(2)?,
+            old_value: row.get(3)?,
+            new_value: row.get(4)?,
+        })
+    })?
+    .collect()
+}
+
+fn snapshot(conn: &Connection, id: u64) -> Option<Snapshot> {
+    db_get_todo(conn, id).map(|todo| Snapshot {
+        todo,
+        tags: db_get_tags(conn, id),
+        uuid: conn
+            .prepare_cached("SELECT uuid FROM todos WHERE id = ?1")
+            .and_then(|mut stmt| stmt.query_row((id,), |row| row.get(0)).optional())
+            .ok()
+            .flatten()
+            .flatten(),
+        history: history_rows(conn, id).unwrap_or_default(),
+    })
+}
+
+/// Puts the todo `id` back into the given state. Deleted rows are reinserted
+/// with their original id and `created_at`.
+fn restore(conn: &Connection, id: u64, state: Option<&Snapshot>) -> rusqlite::Result<()> {
+    conn.prepare_cached("DELETE FROM todo_tags WHERE todo_id = ?1")?
+        .execute((id,))?;
+    let Some(Snapshot {
+        todo,
+        tags,
+        uuid,
+        history,
+    }) = state
+    else {
+        conn.prepare_cached("DELETE FROM todos WHERE id = ?1")?
+            .execute((id,))?;
+        return Ok(());
+    };
+    // Snapshots hold timestamps as read, i.e. converted to the local zone.
+    let stored = |stamp: &String| to_utc(stamp).unwrap_or_else(|| stamp.clone());
+    conn.prepare_cached(
+        "INSERT INTO todos
+             (id, title, completed, created_at, deadline, description, priority, completed_at,
+              pinned, color, estimate_minutes, version, reminder_minutes, uuid)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)
+         ON CONFLICT(id) DO UPDATE SET
+             title = excluded.title,
+             completed = excluded.completed,
+             created_at = excluded.created_at,
+             deadline = excluded.deadline,
+             description = excluded.description,
+             priority = excluded.priority,
+             completed_at = excluded.completed_at,
+             pinned = excluded.pinned,
+             color = excluded.color,
+             estimate_minutes = excluded.estimate_minutes,
+             reminder_minutes = excluded.reminder_minutes,
+             version = todos.version + 1",
+    )?
+    .execute((
+        todo.id,
+        &todo.title,
+        todo.completed,
+        stored(&todo.created_at),
+        &todo.deadline,
+        &todo.description,
+        todo.priority,
+        todo.completed_at.as_ref().map(stored),
+        todo.pinned,
+        &todo.color,
+        todo.estimate_minutes,
+        todo.version,
+        todo.reminder_minutes,
+        uuid,
+    ))?;
+    for tag in tags {
+        db_add_tag(conn, id, tag);
+    }
+    for entry in history {
+        conn.prepare_cached(
+            "INSERT OR IGNORE INTO todo_history
+                 (id, todo_id, changed_at, field, old_value, new_value)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )?
+        .execute((
+            entry.id,
+            id,
+            &entry.changed_at,
+            &entry.field,
+            &entry.old_value,
+            &entry.new_value,
+        ))?;
+    }
+    Ok(())
+}
+
+/// Runs `mutate` in a transaction after capturing the current state of `ids`,
+/// so the returned `Change` always describes the rows as they were right
+/// before the mutation committed.
+pub fn record<T>(
+    conn: &Connection,
+    ids: &[u64],
+    mutate: impl FnOnce(&Connection) -> T,
+) -> (T, Change) {
+    let tx = conn.unchecked_transaction().unwrap();
+    let rows = ids.iter().map(|&id| (id, snapshot(&tx, id))).collect();
+    let out = mutate(&tx);
+    tx.commit().unwrap();
+    (out, Change { rows })
+}
+
+/// Reverts `change` and returns the change that would redo it.
+fn apply(conn: &Connection, change: &Change) -> rusqlite::Result<Change> {
+    let tx = conn.unchecked_transaction()?;
+    let mut inverse = Vec::with_capacity(change.rows.len());
+    for (id, state) in &change.rows {
+        inverse.push((*id, snapshot(&tx, *id)));
+        restore(&tx, *id, state.as_ref())?;
+    }
+    tx.commit()?;
+    Ok(Change { rows: inverse })
+}
+
+/// Bounded undo stack with a matching redo stack. Recording a new change
+/// discards anything that could have been redone.
+#[derive(Debug, Default)]
+pub struct UndoHistory {
+    undo: VecDeque<Change>,
+    redo: Vec<Change>,
+}
+
+impl UndoHistory {
+    pub fn push(&mut self, change: Change) {
+        self.redo.clear();
+        self.push_undo(change);
+    }
+
+    fn push_undo(&mut self, change: Change) {
+        if self.undo.len() == UNDO_LIMIT {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(change);
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Forgets everything, e.g. after the database was replaced.
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+
+    /// Reverts the most recent change. Returns `false` if there was none.
+    pub fn undo(&mut self, conn: &Connection) -> Result<bool, String> {
+        let Some(change) = self.undo.pop_back() else {
+            return Ok(false);
+        };
+        match apply(conn, &change) {
+            Ok(inverse) => {
+                self.redo.push(inverse);
+                Ok(true)
+            }
+            Err(e) => {
+                self.undo.push_back(change);
+                Err(e.to_string())
+            }
+        }
+    }
+
+    /// Re-applies the most recently undone change. Returns `false` if there
+    /// was none.
+    pub fn redo(&mut self, conn: &Connection) -> Result<bool, String> {
+        let Some(change) = self.redo.pop() else {
+            return Ok(false);
+        };
+        match apply(conn, &change) {
+            Ok(inverse) => {
+                self.push_undo(inverse);
+                Ok(true)
+            }
+            Err(e) => {
+                self.redo.push(change);
+                Err(e.to_string())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db_add_todo, db_clear_completed, db_delete_todo, db_get_todos, db_update_todo,
+        setup_test_db,
+    };
+
+    /// A restore is a new write as far as sync is concerned, so `updated_at`
+    /// is the one field it doesn't bring back.
+    fn without_updated_at(todo: Todo) -> Todo {
+        Todo {
+            updated_at: String::new(),
+            ..todo
+        }
+    }
+
+    #[test]
+    fn test_undo_delete_restores_identical_row() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, "Keep me".to_string(), Some("2024-05-01".to_string()));
+        db_add_tag(&conn, todo.id, "work");
+        let mut history = UndoHistory::default();
+
+        let (deleted, change) = record(&conn, &[todo.id], |c| db_delete_todo(c, todo.id));
+        history.push(change);
+        assert!(deleted);
+        assert_eq!(db_get_todo(&conn, todo.id), None);
+
+        assert!(history.undo(&conn).unwrap());
+
+        assert_eq!(
+            db_get_todo(&conn, todo.id).map(without_updated_at),
+            Some(without_updated_at(todo.clone()))
+        );
+        assert_eq!(db_get_tags(&conn, todo.id), vec!["work"]);
+        assert!(!history.can_undo());
+
+        assert!(history.redo(&conn).unwrap());
+        assert_eq!(db_get_todo(&conn, todo.id), None);
+        assert!(history.can_undo());
+    }
+
+    #[test]
+    fn test_undo_delete_restores_history() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, "Draft".to_string(), None);
+        db_update_todo(&conn, todo.id, Some("Final".to_string()), None, None, None);
+        let rows = history_rows(&conn, todo.id).unwrap();
+        assert_eq!(rows.len(), 1);
+        let mut history = UndoHistory::default();
+
+        let (_, change) = record(&conn, &[todo.id], |c| db_delete_todo(c, todo.id));
+        history.push(change);
+        assert!(history_rows(&conn, todo.id).unwrap().is_empty());
+
+        assert!(history.undo(&conn).unwrap());
+        assert_eq!(history_rows(&conn, todo.id).unwrap(), rows);
+    }
+
+    #[test]
+    fn test_undo_title_update_restores_old_title() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, "Old".to_string(), None);
+        let mut history = UndoHistory::default();
+
+        let (_, change) = record(&conn, &[todo.id], |c| {
+            db_update_todo(c, todo.id, Some("New".to_string()), None, None, None)
+        });
+        history.push(change);
+
+        assert!(history.undo(&conn).unwrap());
+        assert_eq!(db_get_todo(&conn, todo.id).unwrap().title, "Old");
+        assert!(history.redo(&conn).unwrap());
+        assert_eq!(db_get_todo(&conn, todo.id).unwrap().title, "New");
+    }
+
+    #[test]
+    fn test_undo_clear_completed_restores_all_rows() {
+        let conn = setup_test_db();
+        let mut done = Vec::new();
+        for title in ["A", "B", "C"] {
+            let todo = db_add_todo(&conn, title.to_string(), None);
+            db_update_todo(&conn, todo.id, None, Some(true), None, None);
+            done.push(db_get_todo(&conn, todo.id).unwrap());
+        }
+        db_add_todo(&conn, "Open".to_string(), None);
+        let rows = |conn: &Connection| -> Vec<Todo> {
+            db_get_todos(conn)
+                .into_iter()
+                .map(without_updated_at)
+                .collect()
+        };
+        let before = rows(&conn);
+        let ids: Vec<u64> = done.iter().map(|t| t.id).collect();
+        let mut history = UndoHistory::default();
+
+        let (removed, change) = record(&conn, &ids, db_clear_completed);
+        history.push(change);
+        assert_eq!(removed, 3);
+        assert_eq!(db_get_todos(&conn).len(), 1);
+
+        assert!(history.undo(&conn).unwrap());
+        assert_eq!(rows(&conn), before);
+    }
+
+    #[test]
+    fn test_undo_undoes_insert() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, "Oops".to_string(), None);
+        let mut history = UndoHistory::default();
+        history.push(Change::created(todo.id));
+
+        assert!(history.undo(&conn).unwrap());
+        assert!(db_get_todos(&conn).is_empty());
+    }
+
+    #[test]
+    fn test_undo_stack_is_capped() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, "0".to_string(), None);
+        let mut history = UndoHistory::default();
+        for i in 1..=UNDO_LIMIT + 5 {
+            let (_, change) = record(&conn, &[todo.id], |c| {
+                db_update_todo(c, todo.id, Some(i.to_string()), None, None, None)
+            });
+            history.push(change);
+        }
+
+        let mut undone = 0;
+        while history.undo(&conn).unwrap() {
+            undone += 1;
+        }
+
+        assert_eq!(undone, UNDO_LIMIT);
+        assert_eq!(db_get_todo(&conn, todo.id).unwrap().title, "5");
+    }
+
+    #[test]
+    fn test_new_change_clears_redo() {
+        let conn = setup_test_db();
+        let todo = db_add_todo(&conn, "Task".to_string(), None);
+        let mut history = UndoHistory::default();
+        let (_, change) = record(&conn, &[todo.id], |c| db_delete_todo(c, todo.id));
+        history.push(change);
+        history.undo(&conn).unwrap();
+        assert!(history.can_redo());
+
+        history.push(Change::created(todo.id));
+
+        assert!(!history.can_redo());
+    }
+}
@@ -0,0 +1,119 @@
+use serde::Serialize;
+use std::fmt;
+
+/// Nothing was left once whitespace was trimmed.
+pub const EMPTY: &str = "empty";
+/// Longer than the configured limit.
+pub const TOO_LONG: &str = "too_long";
+/// Couldn't be read, such as a deadline in an unknown format.
+pub const UNREADABLE: &str = "unreadable";
+/// A number outside the range allowed for the field.
+pub const OUT_OF_RANGE: &str = "out_of_range";
+/// Not one of a fixed set of values, such as an unknown color.
+pub const UNKNOWN: &str = "unknown";
+/// Clashes with another field given in the same call.
+pub const CONFLICT: &str = "conflict";
+
+/// One field a command rejected. Serialized as
+///
+/// ```json
+/// {"field": "title", "code": "too_long", "message": "Title is 12 characters long; the limit is 10"}
+/// ```
+///
+/// `field` is the name of the argument as the frontend sends it and `code`
+/// one of the constants in this module; both are stable and meant to be
+/// matched on. `message` is for showing to the user and may change.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct ValidationError {
+    pub field: String,
+    pub code: String,
+    pub message: String,
+}
+
+impl ValidationError {
+    pub fn new(field: &str, code: &str, message: impl Into<String>) -> Self {
+        ValidationError {
+            field: field.to_string(),
+            code: code.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// The messages of `errors` as one line, for callers that report a string.
+pub fn describe(errors: &[ValidationError]) -> String {
+    errors
+        .iter()
+        .map(|e| e.message.as_str())
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Collects the failures of several checks, so they can be reported
+/// together instead of stopping at the first.
+#[derive(Debug, Default)]
+pub struct Checks {
+    errors: Vec<ValidationError>,
+}
+
+impl Checks {
+    /// The checked value, or a default after noting the error.
+    pub fn check<T: Default>(&mut self, result: Result<T, ValidationError>) -> T {
+        result.unwrap_or_else(|e| {
+            self.errors.push(e);
+            T::default()
+        })
+    }
+
+    /// `check` for a result that can fail on several fields.
+    pub fn check_all<T: Default>(&mut self, result: Result<T, Vec<ValidationError>>) -> T {
+        result.unwrap_or_else(|errors| {
+            self.errors.extend(errors);
+            T::default()
+        })
+    }
+
+    /// `value` if every check passed, otherwise all the errors, in the order
+    /// they were checked.
+    pub fn finish<T>(self, value: T) -> Result<T, Vec<ValidationError>> {
+        if self.errors.is_empty() {
+            Ok(value)
+        } else {
+            Err(self.errors)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checks_collect_every_failure() {
+        let mut checks = Checks::default();
+        let title: String =
+            checks.check(Err(ValidationError::new("title", EMPTY, "Title is empty")));
+        let priority: u8 = checks.check(Ok(2));
+        let deadline: Option<String> = checks.check_all(Err(vec![
+            ValidationError::new("deadline", UNREADABLE, "Invalid deadline 'soon'"),
+            ValidationError::new("deadline_text", CONFLICT, "Pass one"),
+        ]));
+        assert_eq!((title.as_str(), priority, deadline), ("", 2, None));
+        let errors = checks.finish(()).unwrap_err();
+        assert_eq!(
+            errors.iter().map(|e| e.field.as_str()).collect::<Vec<_>>(),
+            ["title", "deadline", "deadline_text"]
+        );
+        assert_eq!(
+            describe(&errors),
+            "Title is empty; Invalid deadline 'soon'; Pass one"
+        );
+        assert_eq!(Checks::default().finish(7), Ok(7));
+    }
+}
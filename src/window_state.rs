@@ -0,0 +1,275 @@
+use crate::{db_get_setting, db_set_setting};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::{PhysicalPosition, PhysicalSize, Runtime, WebviewWindow};
+
+/// Geometry is saved once a window has stopped moving or resizing this long.
+pub const SAVE_DEBOUNCE: Duration = Duration::from_millis(500);
+/// Settings keys are this prefix followed by the window label.
+const GEOMETRY_SETTING_PREFIX: &str = "window_geometry.";
+/// Smallest size a restored window is given, so a bad value can't make it
+/// vanish.
+const MIN_SIZE: u32 = 200;
+
+/// A rectangle in physical pixels.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    fn overlap(&self, other: &Rect) -> u64 {
+        let span = |a: i32, a_len: u32, b: i32, b_len: u32| {
+            let start = a.max(b) as i64;
+            let end = (a as i64 + a_len as i64).min(b as i64 + b_len as i64);
+            (end - start).max(0) as u64
+        };
+        span(self.x, self.width, other.x, other.width)
+            * span(self.y, self.height, other.y, other.height)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct WindowGeometry {
+    /// Position and size while not maximized.
+    pub rect: Rect,
+    pub maximized: bool,
+}
+
+fn setting_key(label: &str) -> String {
+    format!("{}{}", GEOMETRY_SETTING_PREFIX, label)
+}
+
+pub fn load(conn: &Connection, label: &str) -> Option<WindowGeometry> {
+    db_get_setting(conn, &setting_key(label)).and_then(|v| serde_json::from_str(&v).ok())
+}
+
+pub fn store(conn: &Connection, label: &str, geometry: &WindowGeometry) {
+    let value = serde_json::to_string(geometry).unwrap();
+    db_set_setting(conn, &setting_key(label), &value);
+}
+
+/// Fits a saved window onto the monitors available now (their work areas,
+/// primary first). It stays on the monitor it overlaps most; if it overlaps
+/// none, such as when it was saved on a monitor that has since been
+/// disconnected, it moves to the primary one. It is shrunk to fit if needed
+/// and then moved fully inside. With no monitors reported the saved rect is
+/// used as is.
+pub fn clamp_to_monitors(saved: Rect, monitors: &[Rect]) -> Rect {
+    let Some(monitor) = monitors
+        .iter()
+        .filter(|m| m.overlap(&saved) > 0)
+        .max_by_key(|m| m.overlap(&saved))
+        .or(monitors.first())
+    else {
+        return saved;
+    };
+    let width = saved
+        .width
+        .clamp(MIN_SIZE.min(monitor.width), monitor.width);
+    let height = saved
+        .height
+        .clamp(MIN_SIZE.min(monitor.height), monitor.height);
+    let max_x = monitor.x + (monitor.width - width) as i32;
+    let max_y = monitor.y + (monitor.height - height) as i32;
+    Rect {
+        x: saved.x.clamp(monitor.x, max_x),
+        y: saved.y.clamp(monitor.y, max_y),
+        width,
+        height,
+    }
+}
+
+/// Windows that moved or resized recently, by label, with when that last
+/// happened. `due` hands each one back once it has been still for
+/// `SAVE_DEBOUNCE`.
+#[derive(Default)]
+pub struct PendingSaves(Mutex<HashMap<String, Instant>>);
+
+impl PendingSaves {
+    pub fn touch(&self, label: &str, now: Instant) {
+        self.0.lock().unwrap().insert(label.to_string(), now);
+    }
+
+    pub fn cancel(&self, label: &str) {
+        self.0.lock().unwrap().remove(label);
+    }
+
+    pub fn due(&self, now: Instant) -> Vec<String> {
+        let mut pending = self.0.lock().unwrap();
+        let due: Vec<String> = pending
+            .iter()
+            .filter(|(_, &at)| now.duration_since(at) >= SAVE_DEBOUNCE)
+            .map(|(label, _)| label.clone())
+            .collect();
+        for label in &due {
+            pending.remove(label);
+        }
+        due
+    }
+}
+
+/// Saves where `window` is now. While it is maximized only the flag is
+/// updated, so un-maximizing after the next launch returns to the old size;
+/// a minimized window reports a meaningless position and isn't saved.
+pub fn save<R: Runtime>(conn: &Connection, window: &WebviewWindow<R>) -> tauri::Result<()> {
+    if window.is_minimized()? {
+        return Ok(());
+    }
+    let label = window.label();
+    let geometry = if window.is_maximized()? {
+        let Some(previous) = load(conn, label) else {
+            return Ok(());
+        };
+        WindowGeometry {
+            maximized: true,
+            ..previous
+        }
+    } else {
+        let position = window.outer_position()?;
+        let size = window.inner_size()?;
+        WindowGeometry {
+            rect: Rect {
+                x: position.x,
+                y: position.y,
+                width: size.width,
+                height: size.height,
+            },
+            maximized: false,
+        }
+    };
+    store(conn, label, &geometry);
+    Ok(())
+}
+
+/// Puts `window` back where it was last saved, fitted to the current
+/// monitors. Windows without saved geometry keep their configured defaults.
+pub fn restore<R: Runtime>(conn: &Connection, window: &WebviewWindow<R>) -> tauri::Result<()> {
+    let Some(geometry) = load(conn, window.label()) else {
+        return Ok(());
+    };
+    let monitors: Vec<Rect> = window
+        .primary_monitor()?
+        .into_iter()
+        .chain(window.available_monitors()?)
+        .map(|m| {
+            let area = m.work_area();
+            Rect {
+                x: area.position.x,
+                y: area.position.y,
+                width: area.size.width,
+                height: area.size.height,
+            }
+        })
+        .collect();
+    let rect = clamp_to_monitors(geometry.rect, &monitors);
+    window.set_size(PhysicalSize::new(rect.width, rect.height))?;
+    window.set_position(PhysicalPosition::new(rect.x, rect.y))?;
+    if geometry.maximized {
+        window.maximize()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::setup_test_db;
+
+    fn rect(x: i32, y: i32, width: u32, height: u32) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Work areas of a 1920x1080 primary monitor with a taskbar and a
+    /// 1280x1024 one to its right.
+    fn monitors() -> Vec<Rect> {
+        vec![rect(0, 0, 1920, 1040), rect(1920, 0, 1280, 1024)]
+    }
+
+    #[test]
+    fn test_window_inside_a_monitor_is_unchanged() {
+        let saved = rect(2000, 100, 800, 600);
+        assert_eq!(clamp_to_monitors(saved, &monitors()), saved);
+    }
+
+    #[test]
+    fn test_window_from_disconnected_monitor_moves_to_primary() {
+        let saved = rect(3500, 200, 800, 600);
+
+        assert_eq!(
+            clamp_to_monitors(saved, &monitors()),
+            rect(1120, 200, 800, 600)
+        );
+        assert_eq!(
+            clamp_to_monitors(rect(-1500, -900, 800, 600), &monitors()),
+            rect(0, 0, 800, 600)
+        );
+    }
+
+    #[test]
+    fn test_window_straddling_stays_on_monitor_it_mostly_covers() {
+        let saved = rect(1700, 50, 800, 600);
+        assert_eq!(
+            clamp_to_monitors(saved, &monitors()),
+            rect(1920, 50, 800, 600)
+        );
+    }
+
+    #[test]
+    fn test_oversized_window_is_shrunk_to_fit() {
+        let saved = rect(-10, -10, 4000, 3000);
+        assert_eq!(
+            clamp_to_monitors(saved, &monitors()),
+            rect(0, 0, 1920, 1040)
+        );
+        assert_eq!(
+            clamp_to_monitors(rect(100, 100, 0, 0), &monitors()),
+            rect(100, 100, MIN_SIZE, MIN_SIZE)
+        );
+    }
+
+    #[test]
+    fn test_no_monitors_keeps_saved_rect() {
+        let saved = rect(5000, 5000, 800, 600);
+        assert_eq!(clamp_to_monitors(saved, &[]), saved);
+    }
+
+    #[test]
+    fn test_geometry_is_stored_per_label() {
+        let conn = setup_test_db();
+        let main = WindowGeometry {
+            rect: rect(10, 20, 800, 600),
+            maximized: true,
+        };
+        store(&conn, "main", &main);
+
+        assert_eq!(load(&conn, "main"), Some(main));
+        assert_eq!(load(&conn, "quick-add"), None);
+        db_set_setting(&conn, "window_geometry.broken", "{");
+        assert_eq!(load(&conn, "broken"), None);
+    }
+
+    #[test]
+    fn test_pending_saves_wait_for_quiet_period() {
+        let pending = PendingSaves::default();
+        let start = Instant::now();
+        pending.touch("main", start);
+        pending.touch("quick-add", start);
+        pending.touch("main", start + SAVE_DEBOUNCE / 2);
+
+        assert_eq!(pending.due(start + SAVE_DEBOUNCE), vec!["quick-add"]);
+        pending.cancel("main");
+        assert!(pending.due(start + SAVE_DEBOUNCE * 2).is_empty());
+    }
+}